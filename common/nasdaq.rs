@@ -1,31 +1,50 @@
+use std::cmp::Ordering;
+use std::error::Error;
 use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+mod serde_impl;
 
 #[repr(transparent)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Symbol([u8; 8]);
 
 impl Symbol {
-    /// A valid new firm is one that is 8 bytes long and contains all caps or spaces.
-    pub fn new<B: AsRef<[u8]>(bar: B) -> Result<Self, B> {
+    /// A valid new symbol is one that is 8 bytes long and contains all caps, left-justified and
+    /// padded on the right with spaces.
+    pub fn new<B: AsRef<[u8]>>(bar: B) -> Result<Self, B> {
         let bytes = bar.as_ref();
         if bytes.len() != 8 {
             return Err(bar);
         }
-        let mut in_letters = false;
-        for i in 0..8 {
-            match bytes[i] {
-                b'A'..=b'Z' => in_letters = true,
-                b' ' => {
-                    if in_letters {
+        let mut in_padding = false;
+        for &b in bytes {
+            match b {
+                b'A'..=b'Z' => {
+                    if in_padding {
                         return Err(bar);
                     }
-                },
+                }
+                b' ' => in_padding = true,
                 _ => return Err(bar),
             }
         }
         Ok(Self(bytes.try_into().unwrap()))
     }
 
+    /// Pads the symbol with spaces to 8 bytes. Errors the same as `new`, or if the symbol is
+    /// longer than 8 bytes.
+    pub fn new_padded<B: AsRef<[u8]>>(bar: B) -> Result<Self, B> {
+        let bytes = bar.as_ref();
+        if bytes.len() > 8 {
+            return Err(bar);
+        }
+        let mut arr = [b' '; 8];
+        arr[..bytes.len()].copy_from_slice(bytes);
+        Self::new(arr).map_err(|_| bar)
+    }
+
     #[inline(always)]
     pub const fn empty() -> Self {
         Self([b' '; 8])
@@ -50,12 +69,13 @@ impl Default for Symbol {
 
 impl fmt::Display for Symbol {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!("{}", self.as_str().unwrap_or("????????"))
+        write!(f, "{}", self.as_str().unwrap_or("????????"))
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OrderSide {
     Buy = b'B',
     Sell = b'S',
@@ -63,48 +83,89 @@ pub enum OrderSide {
     SellShortExempt = b'E',
 }
 
+impl OrderSide {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'B' => Some(OrderSide::Buy),
+            b'S' => Some(OrderSide::Sell),
+            b'T' => Some(OrderSide::SellShort),
+            b'E' => Some(OrderSide::SellShortExempt),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        self as _
+    }
+
+    pub const fn is_buy(self) -> bool {
+        matches!(self, OrderSide::Buy)
+    }
+}
+
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Price(u64);
 
+// The digits are grouped to mark off the 4 implied decimal places.
+#[allow(clippy::inconsistent_digit_grouping)]
 impl Price {
     pub const MAX: Self = Self(199_999_9900);
     pub const MAX_F64: f64 = 199_999.9900;
-    //pub const MAX_U64: u64 = 199_999_9900;
+    pub const MAX_U64: u64 = 199_999_9900;
     pub const MARKET: Self = Self(200_000_0000);
     pub const MARKET_CROSS: Self = Self(214_748_3647);
 
+    /// Creates a price from its raw value, which has 4 implied decimal places.
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    pub const fn to_raw(self) -> u64 {
+        self.0
+    }
+
+    /// From the big-endian encoding.
+    pub const fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self(u64::from_be_bytes(bytes))
+    }
+
+    /// The big-endian encoding.
+    pub const fn to_bytes(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+
     pub fn from_f64(f: f64) -> Option<Self> {
-        if f > Self::MAX_F64 || f < 0.0 {
+        if !(0.0..=Self::MAX_F64).contains(&f) {
             return None;
         }
-        Some(Self(f as u64 * 10_000 + ((f.fract() * 10_000.0))))
+        Some(Self((f * 10_000.0).round() as u64))
     }
 
     pub fn to_f64(self) -> f64 {
         // NOTE: max is representable as f64
-        self.0 as f64 / 10_000
+        self.0 as f64 / 10_000.0
     }
 
     pub fn to_f64_opt(self) -> Option<f64> {
         if self.0 <= Self::MAX_U64 {
-            Some(self.0 as f64 / 10_000)
+            Some(self.0 as f64 / 10_000.0)
         } else {
             None
         }
     }
 
-    pub fn to_parts(self) -> (u32, u32) {
+    pub fn to_parts(self) -> (u64, u64) {
         (self.0 / 10_000, self.0 % 10_000)
     }
 
     pub const fn is_market(self) -> bool {
         // FIXME: should check for market cross too?
-        self == Self::MARKET || self == Self::MARKET_CROSS || self.0 == u64::MAX
+        self.0 == Self::MARKET.0 || self.0 == Self::MARKET_CROSS.0 || self.0 == u64::MAX
     }
 
     pub const fn is_market_cross(self) -> bool {
-        self == Self::MARKET_CROSS
+        self.0 == Self::MARKET_CROSS.0
     }
 }
 
@@ -120,7 +181,7 @@ impl PartialEq for Price {
 
 impl PartialOrd for Price {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.0 <= Self::MAX.0 && other <= Self::MAX.0 {
+        if self.0 <= Self::MAX.0 && other.0 <= Self::MAX.0 {
             self.0.partial_cmp(&other.0)
         } else {
             None
@@ -135,15 +196,39 @@ impl fmt::Display for Price {
             write!(f, "MARKET ORDER")
         } else {
             let (dollars, cents) = self.to_parts();
-            write!(f, "{dollars}.{cents:04}");
+            write!(f, "{dollars}.{cents:04}")
+        }
+    }
+}
+
+impl FromStr for Price {
+    type Err = ParsePriceError;
+
+    /// Parses a decimal with up to 4 decimal places, or "MARKET ORDER" (or "MARKET") and
+    /// "MARKET CROSS".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "MARKET ORDER" | "MARKET" => return Ok(Self::MARKET),
+            "MARKET CROSS" => return Ok(Self::MARKET_CROSS),
+            _ => (),
+        }
+        if s.starts_with('-') {
+            return Err(ParsePriceError);
+        }
+        let raw = parse_raw(s)?;
+        if raw > Self::MAX_U64 as i64 {
+            return Err(ParsePriceError);
         }
+        Ok(Self(raw as u64))
     }
 }
 
 #[repr(transparent)]
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct SignedPrice(i32);
 
+// The digits are grouped to mark off the 4 implied decimal places.
+#[allow(clippy::inconsistent_digit_grouping)]
 impl SignedPrice {
     pub const MIN: Self = Self(-199_999_9900);
     pub const MIN_F64: f64 = -199_999.9900;
@@ -152,28 +237,50 @@ impl SignedPrice {
     pub const MARKET: Self = Self(200_000_0000);
     pub const MARKET_CROSS: Self = Self(214_748_3647);
 
+    /// Creates a price from its raw value, which has 4 implied decimal places.
+    pub const fn from_raw(raw: i32) -> Self {
+        Self(raw)
+    }
+
+    pub const fn to_raw(self) -> i32 {
+        self.0
+    }
+
+    /// From the big-endian encoding.
+    pub const fn from_bytes(bytes: [u8; 4]) -> Self {
+        Self(i32::from_be_bytes(bytes))
+    }
+
+    /// The big-endian encoding.
+    pub const fn to_bytes(self) -> [u8; 4] {
+        self.0.to_be_bytes()
+    }
+
     pub fn from_f64(f: f64) -> Option<Self> {
-        if f > Self::MAX_F64 || f < Self::MIN_F64 {
+        if !(Self::MIN_F64..=Self::MAX_F64).contains(&f) {
             None
-        } else if f >= 0.0 {
-            Some(Self(f as i32 * 10_000 + (f.fract() as i32 * 10_000)))
         } else {
-            Some(Self(f as i32 * 10_000 - (f.fract() as i32 * 10_000)))
+            Some(Self((f * 10_000.0).round() as i32))
         }
     }
 
     pub fn to_f64(self) -> f64 {
         // NOTE: max is representable as f64
-        self.0 as f64 / 10_000
+        self.0 as f64 / 10_000.0
+    }
+
+    /// The whole and fractional (4 digit) parts. Both are negative for negative prices.
+    pub fn to_parts(self) -> (i32, i32) {
+        (self.0 / 10_000, self.0 % 10_000)
     }
 
     pub const fn is_market(self) -> bool {
         // FIXME: should check for market cross too?
-        self == Self::MARKET || self == Self::MARKET_CROSS || self.0 == i32::MAX
+        self.0 == Self::MARKET.0 || self.0 == Self::MARKET_CROSS.0 || self.0 == i32::MAX
     }
 
     pub const fn is_market_cross(self) -> bool {
-        self == Self::MARKET_CROSS
+        self.0 == Self::MARKET_CROSS.0
     }
 }
 
@@ -189,7 +296,7 @@ impl PartialEq for SignedPrice {
 
 impl PartialOrd for SignedPrice {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.0 <= Self::MAX.0 && other <= Self::MAX.0 {
+        if self.0 <= Self::MAX.0 && other.0 <= Self::MAX.0 {
             self.0.partial_cmp(&other.0)
         } else {
             None
@@ -204,7 +311,58 @@ impl fmt::Display for SignedPrice {
             write!(f, "MARKET ORDER")
         } else {
             let (dollars, cents) = self.to_parts();
-            write!(f, "{dollars}.{cents:04}");
+            let sign = if self.0 < 0 { "-" } else { "" };
+            write!(f, "{sign}{}.{:04}", dollars.abs(), cents.abs())
+        }
+    }
+}
+
+impl FromStr for SignedPrice {
+    type Err = ParsePriceError;
+
+    /// Parses a decimal with up to 4 decimal places, or "MARKET ORDER" (or "MARKET") and
+    /// "MARKET CROSS".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "MARKET ORDER" | "MARKET" => return Ok(Self::MARKET),
+            "MARKET CROSS" => return Ok(Self::MARKET_CROSS),
+            _ => (),
+        }
+        let (neg, abs) = match s.strip_prefix('-') {
+            Some(abs) => (true, abs),
+            None => (false, s),
+        };
+        let raw = parse_raw(abs)?;
+        if raw > Self::MAX.0 as i64 {
+            return Err(ParsePriceError);
         }
+        Ok(Self(if neg { -raw } else { raw } as i32))
+    }
+}
+
+/// Parses an unsigned decimal with up to 4 decimal places into its raw value, without going
+/// through a float.
+fn parse_raw(s: &str) -> Result<i64, ParsePriceError> {
+    let (whole, frac) = s.split_once('.').unwrap_or((s, ""));
+    if whole.is_empty() && frac.is_empty() || frac.len() > 4 {
+        return Err(ParsePriceError);
     }
+    let digits = |d: &str| d.bytes().all(|b| b.is_ascii_digit());
+    if !digits(whole) || !digits(frac) || whole.len() > 6 {
+        return Err(ParsePriceError);
+    }
+    let whole: i64 = if whole.is_empty() { 0 } else { whole.parse().unwrap() };
+    let frac: i64 = format!("{frac:0<4}").parse().unwrap();
+    Ok(whole * 10_000 + frac)
 }
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ParsePriceError;
+
+impl fmt::Display for ParsePriceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid price")
+    }
+}
+
+impl Error for ParsePriceError {}
@@ -0,0 +1,139 @@
+//! Symbols are (de)serialized as strings without padding, and prices as decimal strings (numbers
+//! are also accepted when deserializing).
+
+use super::{Price, SignedPrice, Symbol};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.to_string().trim_end())
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        Symbol::new_padded(s.as_bytes())
+            .map_err(|_| de::Error::custom(format!("invalid symbol: {s:?}")))
+    }
+}
+
+impl Serialize for Price {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        if self.is_market_cross() {
+            s.serialize_str("MARKET CROSS")
+        } else {
+            s.collect_str(self)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Price {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_any(PriceVisitor)
+    }
+}
+
+struct PriceVisitor;
+
+impl Visitor<'_> for PriceVisitor {
+    type Value = Price;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a price")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Price, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Price, E> {
+        Price::from_f64(v).ok_or_else(|| E::custom("invalid price"))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Price, E> {
+        self.visit_f64(v as f64)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Price, E> {
+        self.visit_f64(v as f64)
+    }
+}
+
+impl Serialize for SignedPrice {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        if self.is_market_cross() {
+            s.serialize_str("MARKET CROSS")
+        } else {
+            s.collect_str(self)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SignedPrice {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_any(SignedPriceVisitor)
+    }
+}
+
+struct SignedPriceVisitor;
+
+impl Visitor<'_> for SignedPriceVisitor {
+    type Value = SignedPrice;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a signed price")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<SignedPrice, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<SignedPrice, E> {
+        SignedPrice::from_f64(v).ok_or_else(|| E::custom("invalid price"))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<SignedPrice, E> {
+        self.visit_f64(v as f64)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<SignedPrice, E> {
+        self.visit_f64(v as f64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn symbol() {
+        let sym = Symbol::new_padded("AAPL").unwrap();
+        assert_eq!(serde_json::to_string(&sym).unwrap(), r#""AAPL""#);
+        assert_eq!(serde_json::from_str::<Symbol>(r#""AAPL""#).unwrap(), sym);
+        assert!(serde_json::from_str::<Symbol>(r#""TOOLONGSYM""#).is_err());
+    }
+
+    #[test]
+    fn prices() {
+        let px = Price::from_raw(1_872_500);
+        assert_eq!(serde_json::to_string(&px).unwrap(), r#""187.2500""#);
+        assert_eq!(serde_json::from_str::<Price>(r#""187.2500""#).unwrap(), px);
+        assert_eq!(serde_json::from_str::<Price>("187.25").unwrap(), px);
+        assert_eq!(serde_json::from_str::<Price>("5").unwrap(), Price::from_raw(50_000));
+        assert!(serde_json::from_str::<Price>(r#""-1.00""#).is_err());
+
+        let json = serde_json::to_string(&Price::MARKET_CROSS).unwrap();
+        assert_eq!(json, r#""MARKET CROSS""#);
+        assert!(serde_json::from_str::<Price>(&json).unwrap().is_market_cross());
+
+        let px = SignedPrice::from_raw(-12_500);
+        assert_eq!(serde_json::to_string(&px).unwrap(), r#""-1.2500""#);
+        assert_eq!(serde_json::from_str::<SignedPrice>(r#""-1.2500""#).unwrap(), px);
+        assert_eq!(serde_json::from_str::<SignedPrice>("-1.25").unwrap(), px);
+        let json = serde_json::to_string(&SignedPrice::MARKET_CROSS).unwrap();
+        assert!(serde_json::from_str::<SignedPrice>(&json).unwrap().is_market_cross());
+    }
+}
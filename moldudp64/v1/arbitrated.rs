@@ -0,0 +1,154 @@
+use super::receiver::*;
+use super::stats::ReceiverStats;
+use super::types::SessionId;
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+
+/// Receives the same stream on two lines (A and B), delivering each message exactly once. A gap
+/// on one line is filled from the other if it can be, and is only re-requested once both lines
+/// have passed it or the options' arbitration window runs out.
+#[derive(Clone)]
+pub struct ArbitratedReceiver(pub(crate) Arc<InnerReceiver>);
+
+impl ArbitratedReceiver {
+    pub fn options() -> ReceiverOptions {
+        ReceiverOptions::default()
+    }
+
+    pub fn connect(
+        line_a: impl Into<SocketAddr>,
+        line_b: impl Into<SocketAddr>,
+        handler: PacketHandler,
+    ) -> Result<Self, ReceiverError> {
+        Self::options().connect_arbitrated(line_a, line_b, handler)
+    }
+
+    pub fn request_messages(&self, start: u64, num: u16) -> io::Result<()> {
+        self.0.request_messages(start, num)
+    }
+
+    pub fn request_messages_from(
+        &self,
+        addr: impl ToSocketAddrs,
+        start: u64, num: u16,
+    ) -> io::Result<()> {
+        self.0.request_messages_from(addr, start, num)
+    }
+
+    /// The session being received, if one has been adopted yet.
+    pub fn session(&self) -> Option<SessionId> {
+        self.0.session()
+    }
+
+    /// The sequence number of the next message that will be passed to the handler.
+    pub fn curr_seq_num(&self) -> u64 {
+        self.0.curr_seq_num()
+    }
+
+    /// One past the highest sequence number either line is known to have sent.
+    pub fn next_expected_seq_num(&self) -> u64 {
+        self.0.next_expected_seq_num()
+    }
+
+    /// The statistics for both lines together.
+    pub fn stats(&self) -> ReceiverStats {
+        self.0.stats()
+    }
+
+    /// The statistics for lines A and B, in that order.
+    pub fn line_stats(&self) -> [LineStats; 2] {
+        let stats = self.0.line_stats();
+        [stats[0], stats[1]]
+    }
+
+    pub fn auto_rerequest(&self) -> bool {
+        self.0.auto_rerequest()
+    }
+
+    pub fn set_auto_rerequest(&self, b: bool) {
+        self.0.set_auto_rerequest(b)
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.0.buffer_size()
+    }
+
+    pub fn set_buffer_size(&self, size: usize) {
+        self.0.set_buffer_size(size);
+    }
+
+    pub fn opts(&self) -> &ReceiverOptions {
+        self.0.opts()
+    }
+
+    /// Stops the receiver. The listening threads exit the next time they wake up.
+    pub fn close(&self) {
+        self.0.close_with_err(ReceiverError::Closed);
+    }
+
+    pub fn close_err(&self) -> Option<ArcReceiverError> {
+        self.0.close_err()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::v1::types::*;
+    use std::net::UdpSocket;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn line_b_fills_gap() {
+        let session = SessionId::new_trunc("A");
+        let conn_a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn_b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (addr_a, addr_b) = (conn_a.local_addr().unwrap(), conn_b.local_addr().unwrap());
+        let (tx, got) = mpsc::channel();
+        let rx = ArbitratedReceiver::options()
+            .with_session(session)
+            .build_arbitrated_with_sockets(
+                (conn_a, addr_a.ip()),
+                (conn_b, addr_b.ip()),
+                Arc::new(move |_, seq_num, _| {
+                    let _ = tx.send(seq_num);
+                }),
+            )
+            .unwrap();
+
+        let send = |addr, seq_num: u64, count: u64| {
+            let blocks = (seq_num..seq_num + count)
+                .map(|n| MessageBlock::new(vec![n as u8]).unwrap())
+                .collect();
+            let pkt = DownstreamPacket::new(Header::new(session, seq_num, 0), blocks).unwrap();
+            UdpSocket::bind("127.0.0.1:0").unwrap().send_to(&pkt.serialize(), addr).unwrap();
+        };
+        let timeout = Duration::from_secs(5);
+        send(addr_a, 1, 1);
+        assert_eq!(got.recv_timeout(timeout).unwrap(), 1);
+        // Skips 2 on line A, then line B has everything.
+        send(addr_a, 3, 1);
+        let deadline = Instant::now() + timeout;
+        while rx.line_stats()[0].packets < 2 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(1));
+        }
+        send(addr_b, 1, 3);
+        assert_eq!(got.recv_timeout(timeout).unwrap(), 2);
+        assert_eq!(got.recv_timeout(timeout).unwrap(), 3);
+
+        let [a, b] = rx.line_stats();
+        assert_eq!((a.packets, a.messages, a.first, a.duplicates, a.dropped), (2, 2, 2, 0, 1));
+        assert_eq!((b.packets, b.messages, b.first, b.duplicates, b.dropped), (1, 3, 1, 2, 0));
+        let stats = rx.stats();
+        assert_eq!((stats.messages, stats.gaps, stats.lost), (3, 1, 0));
+    }
+}
@@ -0,0 +1,723 @@
+pub use crate::v1::receiver::{
+    ArcReceiverError, EventHandler, ReceiverError, ReceiverEvent, DEFAULT_BUFFER_SIZE,
+    DEFAULT_MAX_PENDING,
+};
+pub use crate::v1::recovery::{
+    DEFAULT_MAX_REQUEST_MESSAGES, DEFAULT_MAX_REQUEST_RETRIES, DEFAULT_REQUEST_TIMEOUT,
+};
+use crate::v1::liveness::Liveness;
+use crate::v1::receiver::{Dispatch, State, StateOptions, POLL_INTERVAL};
+use crate::v1::recovery::RecoveryManager;
+pub use crate::v1::session::{LateJoin, SessionPolicy};
+use crate::v1::socket::SocketOptions;
+use crate::v1::stats::ReceiverStats;
+use crate::v1::store::{ArcMessageStore, MessageStore};
+use crate::v1::types::*;
+
+use futures_core::Stream;
+use jtutils::atomic_value::{Ordering, AAV};
+use std::future::poll_fn;
+use std::io::{self, Error as IoError, ErrorKind as IoErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::mpsc::{self, Receiver as MpscReceiver, Sender};
+use tokio::time::timeout;
+
+/// A message block along with the address it came from and its sequence number.
+pub type Message = (SocketAddr, u64, MessageBlock);
+
+/// The default number of messages (and events) queued to be yielded.
+pub const DEFAULT_QUEUE_LEN: usize = 4096;
+
+/// What the listening task sends to the receiver. Events are handled as they're reached so
+/// they stay in order with the messages.
+enum Delivery {
+    Message(Message),
+    Event(ReceiverEvent),
+}
+
+#[derive(Clone)]
+pub struct ReceiverOptions {
+    session: SessionId,
+    sequence_number: u64,
+    request_addrs: Vec<SocketAddr>,
+    auto_rerequest: bool,
+    request_timeout: Duration,
+    max_request_retries: u32,
+    max_request_messages: u16,
+    max_pending: usize,
+    queue_len: usize,
+    socket: SocketOptions,
+    buffer_size: usize,
+    server_timeout: Duration,
+    close_on_server_timeout: bool,
+    session_policy: SessionPolicy,
+    late_join: LateJoin,
+    event_handler: Option<EventHandler>,
+    journal: Option<ArcMessageStore>,
+}
+
+impl Default for ReceiverOptions {
+    fn default() -> Self {
+        Self {
+            session: SessionId::default(),
+            sequence_number: 1,
+            request_addrs: Vec::new(),
+            auto_rerequest: false,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_request_retries: DEFAULT_MAX_REQUEST_RETRIES,
+            max_request_messages: DEFAULT_MAX_REQUEST_MESSAGES,
+            max_pending: DEFAULT_MAX_PENDING,
+            queue_len: DEFAULT_QUEUE_LEN,
+            socket: SocketOptions::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            server_timeout: Duration::from_secs(0),
+            close_on_server_timeout: false,
+            session_policy: SessionPolicy::default(),
+            late_join: LateJoin::default(),
+            event_handler: None,
+            journal: None,
+        }
+    }
+}
+
+impl ReceiverOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_session(mut self, session: SessionId) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// The sequence number of the first message expected. Defaults to 1.
+    pub fn with_sequence_number(mut self, seq_num: u64) -> Self {
+        self.sequence_number = seq_num;
+        self
+    }
+
+    /// How sessions other than the one set with `with_session` are handled. Defaults to
+    /// `SessionPolicy::Fixed`.
+    pub fn with_session_policy(mut self, policy: SessionPolicy) -> Self {
+        self.session_policy = policy;
+        self
+    }
+
+    /// Where delivery starts when joining a stream (or a new session) that's already going.
+    /// Defaults to `LateJoin::Recover`.
+    pub fn with_late_join(mut self, late_join: LateJoin) -> Self {
+        self.late_join = late_join;
+        self
+    }
+
+    /// Events are passed to the handler from within `recv` (or the stream), between the
+    /// messages they came between.
+    pub fn with_event_handler(mut self, handler: EventHandler) -> Self {
+        self.event_handler = Some(handler);
+        self
+    }
+
+    /// Writes every message to the store just before it's delivered, so the stream can be
+    /// replayed or served to others later. If writing fails, the receiver closes with the error.
+    pub fn with_journal(mut self, store: impl MessageStore + 'static) -> Self {
+        self.journal = Some(Arc::new(store));
+        self
+    }
+
+    /// The addresses retransmission requests are sent to.
+    pub fn with_request_addrs(mut self, addrs: Vec<SocketAddr>) -> Self {
+        self.request_addrs = addrs;
+        self
+    }
+
+    pub fn add_request_addr(mut self, addr: SocketAddr) -> Self {
+        self.request_addrs.push(addr);
+        self
+    }
+
+    /// Whether gaps are automatically re-requested.
+    pub fn with_auto_rerequest(mut self, b: bool) -> Self {
+        self.auto_rerequest = b;
+        self
+    }
+
+    /// How long to wait for a request to be answered before asking again.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// How many times a request is retried before the receiver gives up and closes.
+    pub fn with_max_request_retries(mut self, retries: u32) -> Self {
+        self.max_request_retries = retries;
+        self
+    }
+
+    /// The maximum number of messages asked for in a single request. Larger gaps are split up.
+    pub fn with_max_request_messages(mut self, num: u16) -> Self {
+        self.max_request_messages = num;
+        self
+    }
+
+    /// The most messages buffered while waiting on a gap to be filled. Past that, the gap is
+    /// given up on (see `ReceiverEvent::GapAbandoned`) so memory stays bounded when messages
+    /// can't be recovered. Zero means no limit.
+    pub fn with_max_pending(mut self, num: usize) -> Self {
+        self.max_pending = num;
+        self
+    }
+
+    /// The most messages (and events) queued to be yielded. Once that many are waiting, the
+    /// listening task stops reading packets until some are taken, so a slow consumer falls
+    /// behind (and relies on recovery) rather than using more and more memory. Zero means the
+    /// default, `DEFAULT_QUEUE_LEN`.
+    pub fn with_queue_len(mut self, len: usize) -> Self {
+        self.queue_len = len;
+        self
+    }
+
+    /// How long the server can go without sending anything, heartbeats included, before a
+    /// `ReceiverEvent::ServerStale` is sent. Zero (the default) disables the check.
+    pub fn with_server_timeout(mut self, timeout: Duration) -> Self {
+        self.server_timeout = timeout;
+        self
+    }
+
+    /// Close with `ReceiverError::ServerTimedOut` instead of just sending an event when the
+    /// server times out.
+    pub fn with_close_on_server_timeout(mut self, b: bool) -> Self {
+        self.close_on_server_timeout = b;
+        self
+    }
+
+    /// Shorthand for setting the socket options' IPv4 multicast interface.
+    pub fn with_multicast_interface(mut self, intf: Ipv4Addr) -> Self {
+        self.socket = self.socket.with_multicast_interface_v4(intf);
+        self
+    }
+
+    /// Options for the socket created by `connect`. The multicast interfaces are also used for
+    /// sockets passed to `build_with_socket`.
+    pub fn with_socket_options(mut self, opts: SocketOptions) -> Self {
+        self.socket = opts;
+        self
+    }
+
+    pub fn with_buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /* GETTERS */
+
+    pub fn session(&self) -> SessionId {
+        self.session
+    }
+
+    pub fn sequence_number(&self) -> u64 {
+        self.sequence_number
+    }
+
+    pub fn request_addrs(&self) -> &[SocketAddr] {
+        &self.request_addrs
+    }
+
+    pub fn session_policy(&self) -> SessionPolicy {
+        self.session_policy
+    }
+
+    pub fn late_join(&self) -> LateJoin {
+        self.late_join
+    }
+
+    pub fn event_handler(&self) -> Option<&EventHandler> {
+        self.event_handler.as_ref()
+    }
+
+    pub fn journal(&self) -> Option<&ArcMessageStore> {
+        self.journal.as_ref()
+    }
+
+    pub fn auto_rerequest(&self) -> bool {
+        self.auto_rerequest
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    pub fn max_request_retries(&self) -> u32 {
+        self.max_request_retries
+    }
+
+    pub fn max_request_messages(&self) -> u16 {
+        self.max_request_messages
+    }
+
+    pub fn max_pending(&self) -> usize {
+        self.max_pending
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.queue_len
+    }
+
+    pub fn server_timeout(&self) -> Duration {
+        self.server_timeout
+    }
+
+    pub fn close_on_server_timeout(&self) -> bool {
+        self.close_on_server_timeout
+    }
+
+    pub fn multicast_interface(&self) -> Ipv4Addr {
+        self.socket.multicast_interface_v4()
+    }
+
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.socket
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Binds to the port of `addr` and joins the multicast group at its IP. If the IP isn't a
+    /// multicast group, binds to `addr` and receives unicast packets instead.
+    pub async fn connect(self, addr: impl Into<SocketAddr>) -> Result<Receiver, ReceiverError> {
+        let addr = addr.into();
+        let conn = self.socket.bind_receiver(addr)?;
+        conn.set_nonblocking(true)?;
+        self.build_with_socket(UdpSocket::from_std(conn)?, addr.ip())
+    }
+
+    /// Joins the group on the socket unless it isn't a multicast address. Must be called from
+    /// within a tokio runtime.
+    pub fn build_with_socket(
+        self,
+        conn: UdpSocket,
+        group: impl Into<IpAddr>,
+    ) -> Result<Receiver, ReceiverError> {
+        let group = group.into();
+        self.socket.join(&conn, group)?;
+        let queue_len = if self.queue_len != 0 { self.queue_len } else { DEFAULT_QUEUE_LEN };
+        let (tx, rx) = mpsc::channel(queue_len);
+        let state = State::new(
+            StateOptions {
+                session: self.session,
+                sequence_number: self.sequence_number,
+                session_policy: self.session_policy,
+                late_join: self.late_join,
+                max_pending: self.max_pending,
+                journal: self.journal.clone(),
+            },
+            RecoveryManager::new(
+                self.request_addrs.clone(),
+                self.request_timeout,
+                self.max_request_retries,
+                self.max_request_messages,
+            ),
+            Liveness::new(self.server_timeout, Instant::now()),
+            1,
+        );
+        let inner = Arc::new(InnerReceiver {
+            conn,
+            group,
+            state: Mutex::new(state),
+            curr_seq_num: AtomicU64::new(self.sequence_number),
+            next_expected_seq_num: AtomicU64::new(self.sequence_number),
+            auto_rerequest: AtomicBool::new(self.auto_rerequest),
+            close_err: AAV::empty(),
+            opts: self,
+        });
+        tokio::spawn(Arc::clone(&inner).listen_packets(tx));
+        Ok(Receiver {
+            inner,
+            msgs: rx,
+            done: false,
+        })
+    }
+}
+
+/// Receives a MoldUDP64 stream, yielding messages in sequence order. Messages that arrive ahead
+/// of a gap are held back until the gap is filled.
+///
+/// Once the receiver closes, the error it closed with (unless it was closed with `close`) is
+/// yielded after any messages still buffered, and then the stream ends.
+pub struct Receiver {
+    inner: Arc<InnerReceiver>,
+    msgs: MpscReceiver<Delivery>,
+    done: bool,
+}
+
+impl Receiver {
+    pub fn options() -> ReceiverOptions {
+        ReceiverOptions::default()
+    }
+
+    pub async fn connect(addr: impl Into<SocketAddr>) -> Result<Self, ReceiverError> {
+        Self::options().connect(addr).await
+    }
+
+    pub fn build_with_socket(
+        conn: UdpSocket,
+        group: impl Into<IpAddr>,
+    ) -> Result<Self, ReceiverError> {
+        Self::options().build_with_socket(conn, group)
+    }
+
+    /// Returns the next message in sequence, or `None` once the receiver has closed and every
+    /// message has been returned.
+    pub async fn recv(&mut self) -> Option<Result<Message, ArcReceiverError>> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message, ArcReceiverError>>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        loop {
+            return match self.msgs.poll_recv(cx) {
+                Poll::Ready(Some(Delivery::Message(msg))) => Poll::Ready(Some(Ok(msg))),
+                Poll::Ready(Some(Delivery::Event(event))) => {
+                    if let Some(handler) = &self.inner.opts.event_handler {
+                        handler(event);
+                    }
+                    continue;
+                }
+                Poll::Ready(None) => {
+                    self.done = true;
+                    match self.inner.close_err() {
+                        Some(err) if !matches!(*err, ReceiverError::Closed) => {
+                            Poll::Ready(Some(Err(err)))
+                        }
+                        _ => Poll::Ready(None),
+                    }
+                }
+                Poll::Pending => Poll::Pending,
+            };
+        }
+    }
+
+    pub async fn request_messages(&self, start: u64, num: u16) -> io::Result<()> {
+        let pkt = RequestPacket::new(self.inner.request_session(), start, num);
+        let mut res = Err(IoError::new(
+            IoErrorKind::InvalidInput, "no request address specified",
+        ));
+        for addr in &self.inner.opts.request_addrs {
+            res = self.inner.conn.send_to(pkt.as_slice(), addr).await.map(|_| ());
+            if res.is_ok() {
+                break;
+            }
+        }
+        res
+    }
+
+    pub async fn request_messages_from(
+        &self,
+        addr: impl ToSocketAddrs,
+        start: u64, num: u16,
+    ) -> io::Result<()> {
+        let pkt = RequestPacket::new(self.inner.request_session(), start, num);
+        self.inner.conn.send_to(pkt.as_slice(), addr).await.map(|_| ())
+    }
+
+    /// The session being received, if one has been adopted yet.
+    pub fn session(&self) -> Option<SessionId> {
+        self.inner.state.lock().unwrap().session.current()
+    }
+
+    /// Messages count as delivered once they're queued to be yielded.
+    pub fn stats(&self) -> ReceiverStats {
+        let state = self.inner.state.lock().unwrap();
+        state.stats.snapshot(self.curr_seq_num(), self.next_expected_seq_num())
+    }
+
+    /// The sequence number of the next message that will be yielded.
+    pub fn curr_seq_num(&self) -> u64 {
+        self.inner.curr_seq_num.load(Ordering::SeqCst)
+    }
+
+    /// One past the highest sequence number the server is known to have sent.
+    pub fn next_expected_seq_num(&self) -> u64 {
+        self.inner.next_expected_seq_num.load(Ordering::SeqCst)
+    }
+
+    pub fn auto_rerequest(&self) -> bool {
+        self.inner.auto_rerequest()
+    }
+
+    pub fn set_auto_rerequest(&self, b: bool) {
+        self.inner.auto_rerequest.store(b, Ordering::Relaxed);
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.conn.local_addr()
+    }
+
+    pub fn opts(&self) -> &ReceiverOptions {
+        &self.inner.opts
+    }
+
+    /// Stops the receiver. The listening task exits the next time it wakes up.
+    pub fn close(&self) {
+        self.inner.close_with_err(ReceiverError::Closed);
+    }
+
+    pub fn close_err(&self) -> Option<ArcReceiverError> {
+        self.inner.close_err()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+}
+
+impl Stream for Receiver {
+    type Item = Result<Message, ArcReceiverError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+struct InnerReceiver {
+    conn: UdpSocket,
+    group: IpAddr,
+    opts: ReceiverOptions,
+    state: Mutex<State>,
+
+    curr_seq_num: AtomicU64,
+    next_expected_seq_num: AtomicU64,
+    auto_rerequest: AtomicBool,
+
+    close_err: AAV<ReceiverError>,
+}
+
+impl InnerReceiver {
+    fn auto_rerequest(&self) -> bool {
+        self.auto_rerequest.load(Ordering::Relaxed)
+    }
+
+    fn request_session(&self) -> SessionId {
+        self.state.lock().unwrap().session.current().unwrap_or(self.opts.session)
+    }
+
+    fn close_err(&self) -> Option<ArcReceiverError> {
+        self.close_err.load(Ordering::Relaxed)
+    }
+
+    fn is_closed(&self) -> bool {
+        !self.close_err.is_empty(Ordering::Relaxed)
+    }
+
+    fn close_with_err(&self, err: impl Into<ReceiverError>) -> ArcReceiverError {
+        let _ = self.close_err.store_if_empty(err.into(), Ordering::Relaxed, Ordering::Relaxed);
+        self.close_err.load(Ordering::Relaxed).unwrap()
+    }
+
+    /// Receives packets and sends the messages that are in order to `tx`. The receiver is
+    /// closed (and `tx` dropped, ending the stream) when this returns.
+    async fn listen_packets(self: Arc<Self>, tx: Sender<Delivery>) {
+        let buffer_size = if self.opts.buffer_size != 0 {
+            self.opts.buffer_size
+        } else {
+            DEFAULT_BUFFER_SIZE
+        };
+        let mut b = vec![0u8; buffer_size];
+        let mut out = Vec::new();
+        'listen: loop {
+            if self.is_closed() || tx.is_closed() {
+                break;
+            }
+            let wait = match self.recover().await {
+                Ok(wait) => wait,
+                Err(lost) => {
+                    self.state.lock().unwrap().stats.lost(lost.end - lost.start);
+                    self.close_with_err(ReceiverError::MessagesLost {
+                        start: lost.start,
+                        count: lost.end - lost.start,
+                    });
+                    break;
+                }
+            };
+            let now = Instant::now();
+            let (stale, deadline) = {
+                let mut state = self.state.lock().unwrap();
+                (state.liveness.poll(now), state.liveness.deadline())
+            };
+            if let Some(silent_for) = stale {
+                let event = ReceiverEvent::ServerStale { silent_for };
+                if tx.send(Delivery::Event(event)).await.is_err() {
+                    break;
+                }
+                if self.opts.close_on_server_timeout {
+                    self.close_with_err(ReceiverError::ServerTimedOut);
+                    break;
+                }
+            }
+            let wait = match deadline {
+                Some(deadline) => wait.min(deadline.saturating_duration_since(now)),
+                None => wait,
+            };
+            let (bytes, addr) = match timeout(wait, self.conn.recv_from(&mut b)).await {
+                Ok(Ok((n, addr))) => (&b[..n], addr),
+                Ok(Err(e)) => {
+                    self.close_with_err(e);
+                    break;
+                }
+                Err(_) => continue,
+            };
+            let packet = match DownstreamPacketRef::parse(bytes) {
+                Ok(p) => p,
+                Err(_) => {
+                    self.state.lock().unwrap().stats.malformed();
+                    continue;
+                }
+            };
+            let res = {
+                let mut state = self.state.lock().unwrap();
+                let res = state.packet(0, bytes.len(), packet, Instant::now(), &mut out);
+                self.curr_seq_num.store(state.seqr.next_seq_num(), Ordering::SeqCst);
+                self.next_expected_seq_num.store(state.seqr.high_seq_num(), Ordering::SeqCst);
+                res
+            };
+            for d in out.drain(..) {
+                let d = match d {
+                    Dispatch::Event(event) => Delivery::Event(event),
+                    Dispatch::Block(seq_num, block) => Delivery::Message((addr, seq_num, block)),
+                };
+                // Waits for room, so a slow consumer holds up reading rather than piling up
+                // messages. An error means the receiver was dropped.
+                if tx.send(d).await.is_err() {
+                    break 'listen;
+                }
+            }
+            if let Err(e) = res {
+                self.close_with_err(e);
+                break;
+            }
+        }
+        self.close_with_err(ReceiverError::Closed);
+    }
+
+    /// Sends any retransmission requests needed for the current gaps, returning how long to
+    /// wait for a packet before checking again.
+    async fn recover(&self) -> Result<Duration, Range<u64>> {
+        let now = Instant::now();
+        let mut reqs = Vec::new();
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+            let recovery = &mut state.recovery;
+            if !recovery.has_requests() && (!self.auto_rerequest() || !state.seqr.has_gap()) {
+                return Ok(POLL_INTERVAL);
+            }
+            if self.auto_rerequest() {
+                let session = state.session.current().unwrap_or(self.opts.session);
+                recovery.poll(now, &state.seqr.missing(), |addr, start, num| {
+                    reqs.push((addr, RequestPacket::new(session, start, num)));
+                })?;
+            } else {
+                recovery.clear();
+            }
+            match recovery.next_deadline() {
+                Some(deadline) => deadline
+                    .saturating_duration_since(now)
+                    .clamp(Duration::from_millis(1), POLL_INTERVAL),
+                None => POLL_INTERVAL,
+            }
+        };
+        for (addr, pkt) in reqs {
+            // An error here just means the request times out and is sent elsewhere.
+            let _ = self.conn.send_to(pkt.as_slice(), addr).await;
+        }
+        Ok(wait)
+    }
+}
+
+impl Drop for InnerReceiver {
+    fn drop(&mut self) {
+        let _ = self.opts.socket.leave(&self.conn, self.group);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket as StdUdpSocket;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+    }
+
+    #[test]
+    fn fills_gap_in_order() {
+        runtime().block_on(async {
+            let session = SessionId::new_trunc("A");
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let events2 = Arc::clone(&events);
+            let mut rx = Receiver::options()
+                .with_session(session)
+                .with_queue_len(1)
+                .with_event_handler(Arc::new(move |event| {
+                    events2.lock().unwrap().push(format!("{event:?}"));
+                }))
+                .connect("127.0.0.1:0".parse::<SocketAddr>().unwrap())
+                .await
+                .unwrap();
+            let addr = rx.local_addr().unwrap();
+            let conn = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+            let send = |seq_num: u64, count: u64| {
+                let blocks = (seq_num..seq_num + count)
+                    .map(|n| MessageBlock::new(vec![n as u8]).unwrap())
+                    .collect();
+                let header = Header::new(session, seq_num, 0);
+                let pkt = DownstreamPacket::new(header, blocks).unwrap();
+                conn.send_to(&pkt.serialize(), addr).unwrap();
+            };
+
+            send(1, 1);
+            // Messages 2 and 3 are missing, so 4 and 5 are held back.
+            send(4, 2);
+            conn.send_to(b"not a packet", addr).unwrap();
+            send(2, 2);
+            for want in 1..=5u64 {
+                let (_, seq_num, block) = timeout(Duration::from_secs(5), rx.recv())
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(seq_num, want);
+                assert_eq!(MessageBlock::into_inner(block), [want as u8]);
+            }
+
+            // The fill event is queued after the messages, so it's handled on the way to the
+            // end of the stream.
+            rx.close();
+            assert!(timeout(Duration::from_secs(5), rx.recv()).await.unwrap().is_none());
+            let events = events.lock().unwrap();
+            assert_eq!(events.len(), 2, "{events:?}");
+            assert_eq!(events[0], "GapOpened { start: 2, end: 4 }");
+            assert!(events[1].starts_with("GapFilled { start: 2, end: 4,"));
+            let stats = rx.stats();
+            assert_eq!((stats.messages, stats.malformed, stats.recovered), (5, 1, 2));
+        });
+    }
+}
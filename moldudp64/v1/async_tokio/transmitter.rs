@@ -1,13 +1,66 @@
+pub use crate::v1::pacing::{Pacing, DEFAULT_SCHEDULE_TOLERANCE};
+pub use crate::v1::transmitter::{
+    ArcTransmitterError, TransmitterError, TransmitterEvent, TransmitterEventHandler,
+    DEFAULT_END_SESSION_INTERVAL, DEFAULT_END_SESSION_REPEATS, DEFAULT_FLUSH_INTERVAL,
+    DEFAULT_HEARTBEAT_INTERVAL,
+};
+use crate::v1::pacing::{Pacer, ReplayClock};
+use crate::v1::receiver::POLL_INTERVAL;
+use crate::v1::socket::SocketOptions;
+use crate::v1::store::{ArcMessageStore, MessageStore};
+use crate::v1::transmitter::MAX_PACKET_BLOCKS;
+use crate::v1::types::*;
+
+use jtutils::atomic_value::{Ordering, AAV};
+use std::io::{self, Error as IoError, ErrorKind as IoErrorKind};
+use std::net::SocketAddr;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::net::{lookup_host, ToSocketAddrs, UdpSocket};
+use tokio::sync::{Mutex, MutexGuard, Notify};
+use tokio::time::{sleep, sleep_until, timeout, Instant};
+
 // TODO: what to on non-full writes/sends?
 #[derive(Clone)]
 pub struct TransmitterOptions {
     session: SessionId,
     sequence_number: u64,
+    mtu: usize,
+    flush_interval: Duration,
+    heartbeat_interval: Duration,
+    end_session_repeats: u32,
+    end_session_interval: Duration,
+    store: Option<ArcMessageStore>,
+    socket: SocketOptions,
+    pacing: Pacing,
+    replay_speed: f64,
+    schedule_tolerance: Duration,
+    event_handler: Option<TransmitterEventHandler>,
+}
+
+impl Default for TransmitterOptions {
+    fn default() -> Self {
+        Self {
+            session: SessionId::default(),
+            sequence_number: 1,
+            mtu: DEFAULT_MTU,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            end_session_repeats: DEFAULT_END_SESSION_REPEATS,
+            end_session_interval: DEFAULT_END_SESSION_INTERVAL,
+            store: None,
+            socket: SocketOptions::default(),
+            pacing: Pacing::default(),
+            replay_speed: 0.0,
+            schedule_tolerance: DEFAULT_SCHEDULE_TOLERANCE,
+            event_handler: None,
+        }
+    }
 }
 
 impl TransmitterOptions {
     pub fn new() -> Self {
-        Self::default(),
+        Self::default()
     }
 
     pub fn with_session(mut self, session: SessionId) -> Self {
@@ -15,61 +68,521 @@ impl TransmitterOptions {
         self
     }
 
+    /// The sequence number given to the first message sent. Defaults to 1.
     pub fn with_sequence_number(mut self, seq_num: u64) -> Self {
         self.sequence_number = seq_num;
         self
     }
 
+    /// The maximum size of a packet, including the IP and UDP headers.
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    /// How long a message can wait for more to be batched with it. A zero interval sends every
+    /// call immediately.
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// How long to go without sending before a heartbeat is sent. Zero disables heartbeats.
+    pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// How many times the end of session packet is sent, and the time between each.
+    pub fn with_end_session_repeats(mut self, repeats: u32, interval: Duration) -> Self {
+        self.end_session_repeats = repeats;
+        self.end_session_interval = interval;
+        self
+    }
+
+    /// Every message sent is recorded in the store so it can be retransmitted.
+    pub fn with_store(mut self, store: impl MessageStore + 'static) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Options for the socket created by `connect`.
+    pub fn with_socket_options(mut self, opts: SocketOptions) -> Self {
+        self.socket = opts;
+        self
+    }
+
+    /// Limits how fast packets are sent, so fast senders (like replays) don't overrun
+    /// receivers' socket buffers. Sending waits, holding up whoever is queueing messages, until
+    /// the limit allows the next packet. Defaults to `Pacing::Unlimited`.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Sends messages queued with a timestamp (`send_message_at`) with the same relative timing
+    /// they originally had, sped up by `speed` (2.0 is twice as fast). Timing starts with the
+    /// first such message. Zero (the default) ignores the timestamps.
+    pub fn with_replay_speed(mut self, speed: f64) -> Self {
+        self.replay_speed = speed;
+        self
+    }
+
+    /// How late a replayed message can be queued before `TransmitterEvent::BehindSchedule` is
+    /// sent.
+    pub fn with_schedule_tolerance(mut self, tolerance: Duration) -> Self {
+        self.schedule_tolerance = tolerance;
+        self
+    }
+
+    pub fn with_event_handler(mut self, handler: TransmitterEventHandler) -> Self {
+        self.event_handler = Some(handler);
+        self
+    }
+
+    pub fn session(&self) -> SessionId {
+        self.session
+    }
+
+    pub fn sequence_number(&self) -> u64 {
+        self.sequence_number
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.heartbeat_interval
+    }
+
+    pub fn end_session_repeats(&self) -> u32 {
+        self.end_session_repeats
+    }
+
+    pub fn end_session_interval(&self) -> Duration {
+        self.end_session_interval
+    }
+
+    pub fn store(&self) -> Option<&ArcMessageStore> {
+        self.store.as_ref()
+    }
+
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.socket
+    }
+
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    pub fn replay_speed(&self) -> f64 {
+        self.replay_speed
+    }
+
+    pub fn schedule_tolerance(&self) -> Duration {
+        self.schedule_tolerance
+    }
+
+    pub fn event_handler(&self) -> Option<&TransmitterEventHandler> {
+        self.event_handler.as_ref()
+    }
+
+    /// Sends to `addr`, which can be a multicast group or a unicast address.
     pub async fn connect(self, addr: impl ToSocketAddrs) -> Result<Transmitter, TransmitterError> {
-        let conn = UdpSocket::bind("0.0.0.0:0").await?
-        conn.connect(addr).await?;
-        Ok(self.build_with_socket(conn))
+        let addr = lookup_host(addr).await?.next().ok_or_else(|| {
+            IoError::new(IoErrorKind::InvalidInput, "no address to connect to")
+        })?;
+        let conn = self.socket.connect_transmitter(addr)?;
+        conn.set_nonblocking(true)?;
+        self.build_with_socket(UdpSocket::from_std(conn)?)
     }
 
-    pub fn build_with_socket(self, conn: UdpSocket) -> Transmitter {
-        Transmitter {
-            conn,
-            session: self.session,
-            next_seq_num: AtomicU64::new(self.sequence_number),
+    /// The socket must already be connected to the address packets are sent to. Must be called
+    /// from within a tokio runtime.
+    pub fn build_with_socket(self, conn: UdpSocket) -> Result<Transmitter, TransmitterError> {
+        if self.mtu < IP_UDP_HEADER_LEN + HEADER_LEN + 2 {
+            return Err(TransmitterError::Io(IoError::new(
+                IoErrorKind::InvalidInput,
+                "mtu too small to fit a message",
+            )));
         }
+        let inner = Arc::new(InnerTransmitter {
+            conn,
+            batch: Mutex::new(Batch {
+                next_seq_num: self.sequence_number,
+                blocks: Vec::new(),
+                len: HEADER_LEN,
+                first_at: None,
+                last_sent: Instant::now(),
+                pacer: Pacer::new(self.pacing),
+                replay: ReplayClock::new(self.replay_speed, self.schedule_tolerance),
+            }),
+            notify: Notify::new(),
+            opts: self,
+            close_err: AAV::empty(),
+        });
+        tokio::spawn(InnerTransmitter::run(Arc::downgrade(&inner)));
+        Ok(Transmitter(inner))
     }
 }
 
-pub struct Transmitter {
-    conn: UdpSocket,
-    session: SessionId,
-    next_seq_num: AtomicU64,
-}
+/// Sends messages as MoldUDP64 downstream packets. Messages are stamped with sequence numbers
+/// and batched into packets up to the MTU, and heartbeats are sent while idle.
+#[derive(Clone)]
+pub struct Transmitter(Arc<InnerTransmitter>);
 
 impl Transmitter {
-    pub async fn new(addr: impl ToSocketAddrs, session: SessionId) -> io::Result<Self> {
-        let conn = UdpSocket::bind("0.0.0.0:0").await?
-        conn.connect(addr).await?;
-        Self::from_socket(conn, session)
+    pub fn options() -> TransmitterOptions {
+        TransmitterOptions::new()
     }
 
-    pub async fn from_socket(conn: UdpSocket, session: SessionId) -> Self {
-        Self {
-            conn,
-            session,
-            next_seq_num
-        }
+    pub async fn new(
+        addr: impl ToSocketAddrs,
+        session: SessionId,
+    ) -> Result<Self, TransmitterError> {
+        Self::options().with_session(session).connect(addr).await
+    }
+
+    pub fn from_socket(conn: UdpSocket, session: SessionId) -> Result<Self, TransmitterError> {
+        Self::options().with_session(session).build_with_socket(conn)
     }
 
-    pub async fn send(&self, packet: DownstreamPacket) -> Result<(), TransmitterError> {
-        self.conn.send(packet.as_slice()).map(|_| ())
+    /// Queues a message to be sent, returning the sequence number it was given.
+    pub async fn send_message(&self, msg: impl Into<Vec<u8>>) -> Result<u64, TransmitterError> {
+        let block = MessageBlock::new(msg.into())
+            .map_err(|msg| TransmitterError::MessageTooLarge(msg.len()))?;
+        self.0.send_message_blocks(vec![block], None).await
     }
 
+    /// Queues a message originally sent at `at` (measured from any fixed point, like midnight),
+    /// first waiting until it's due if replaying. Otherwise the same as `send_message`.
+    pub async fn send_message_at(
+        &self,
+        msg: impl Into<Vec<u8>>,
+        at: Duration,
+    ) -> Result<u64, TransmitterError> {
+        let block = MessageBlock::new(msg.into())
+            .map_err(|msg| TransmitterError::MessageTooLarge(msg.len()))?;
+        self.0.send_message_blocks(vec![block], Some(at)).await
+    }
+
+    /// Queues the message blocks to be sent in order, returning the sequence number given to
+    /// the first. If sending fails partway, `TransmitterError::Unsent` says how many were queued.
     pub async fn send_message_blocks(
         &self,
-        blocks: Vec<MessageBlocks>,
-    ) -> Result<(), TransmitterError> {
-        self.conn.send(packet.as_slice()).map(|_| ())
+        blocks: Vec<MessageBlock>,
+    ) -> Result<u64, TransmitterError> {
+        self.0.send_message_blocks(blocks, None).await
+    }
+
+    /// Queues message blocks originally sent at `at`, like `send_message_at`.
+    pub async fn send_message_blocks_at(
+        &self,
+        blocks: Vec<MessageBlock>,
+        at: Duration,
+    ) -> Result<u64, TransmitterError> {
+        self.0.send_message_blocks(blocks, Some(at)).await
+    }
+
+    /// Sends anything that's been queued.
+    pub async fn flush(&self) -> Result<(), TransmitterError> {
+        self.0.flush().await
+    }
+
+    pub async fn send_heartbeat(&self) -> Result<(), TransmitterError> {
+        self.0.send_heartbeat().await
+    }
+
+    /// Flushes anything queued, sends the end of session packet (repeatedly, as configured), and
+    /// closes the transmitter.
+    pub async fn send_end_session(&self) -> Result<(), TransmitterError> {
+        self.0.send_end_session().await
+    }
+
+    /// The sequence number the next message queued will be given.
+    pub async fn next_seq_num(&self) -> u64 {
+        let batch = self.0.batch.lock().await;
+        batch.next_seq_num + batch.blocks.len() as u64
+    }
+
+    /// How late the last replayed message was queued compared to its original timing.
+    pub async fn schedule_lag(&self) -> Duration {
+        let batch = self.0.batch.lock().await;
+        batch.replay.as_ref().map(|r| r.lag()).unwrap_or_default()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.conn.local_addr()
+    }
+
+    pub fn opts(&self) -> &TransmitterOptions {
+        &self.0.opts
+    }
+
+    pub fn store(&self) -> Option<&ArcMessageStore> {
+        self.0.opts.store()
+    }
+
+    /// Flushes anything queued and stops the transmitter without ending the session.
+    pub async fn close(&self) -> Result<(), TransmitterError> {
+        let res = self.0.flush().await;
+        self.0.close_with_err(TransmitterError::Closed);
+        res
+    }
+
+    pub fn close_err(&self) -> Option<ArcTransmitterError> {
+        self.0.close_err.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+}
+
+struct InnerTransmitter {
+    conn: UdpSocket,
+    opts: TransmitterOptions,
+    batch: Mutex<Batch>,
+    notify: Notify,
+
+    close_err: AAV<TransmitterError>,
+}
+
+struct Batch {
+    /// The sequence number of the first block in `blocks`.
+    next_seq_num: u64,
+    blocks: Vec<MessageBlock>,
+    /// The length of the packet the blocks would be sent in.
+    len: usize,
+    /// When the first block in `blocks` was queued.
+    first_at: Option<Instant>,
+    last_sent: Instant,
+    pacer: Pacer,
+    /// Set if replaying.
+    replay: Option<ReplayClock>,
+}
+
+impl InnerTransmitter {
+    fn max_packet_len(&self) -> usize {
+        self.opts.mtu - IP_UDP_HEADER_LEN
+    }
+
+    async fn send_message_blocks(
+        &self,
+        blocks: Vec<MessageBlock>,
+        at: Option<Duration>,
+    ) -> Result<u64, TransmitterError> {
+        let max_len = self.max_packet_len();
+        if let Some(block) = blocks.iter().find(|b| HEADER_LEN + b.as_slice().len() > max_len) {
+            return Err(TransmitterError::MessageTooLarge(block.len()));
+        }
+        let mut batch = self.lock_open().await?;
+        let due = match (at, &mut batch.replay) {
+            (Some(at), Some(replay)) => Some(replay.due(at, Instant::now().into_std())),
+            _ => None,
+        };
+        if let Some(due) = due {
+            let due = Instant::from_std(due);
+            if due > Instant::now() {
+                // Anything queued was due earlier, so it shouldn't wait for the flush interval.
+                self.flush_locked(&mut batch).await?;
+                drop(batch);
+                sleep_until(due).await;
+                batch = self.lock_open().await?;
+            }
+        }
+        let first_seq_num = batch.next_seq_num + batch.blocks.len() as u64;
+        let count = blocks.len();
+        let unsent = |queued, e| TransmitterError::unsent(first_seq_num, queued, e);
+        for (queued, block) in blocks.into_iter().enumerate() {
+            let block_len = block.as_slice().len();
+            if batch.len + block_len > max_len || batch.blocks.len() >= MAX_PACKET_BLOCKS {
+                self.flush_locked(&mut batch).await.map_err(|e| unsent(queued, e))?;
+            }
+            if batch.first_at.is_none() {
+                batch.first_at = Some(Instant::now());
+                self.notify.notify_one();
+            }
+            batch.len += block_len;
+            batch.blocks.push(block);
+        }
+        if self.opts.flush_interval.is_zero() {
+            self.flush_locked(&mut batch).await.map_err(|e| unsent(count, e))?;
+        }
+        let now = Instant::now().into_std();
+        let event = match (due, &mut batch.replay) {
+            (Some(due), Some(replay)) => replay.queued(due, now, first_seq_num),
+            _ => None,
+        };
+        drop(batch);
+        if let (Some(event), Some(handler)) = (event, &self.opts.event_handler) {
+            handler(event);
+        }
+        Ok(first_seq_num)
     }
 
-    pub fn send_heartbeat(&self) -> Result<(), TransmitterError> {
+    async fn flush(&self) -> Result<(), TransmitterError> {
+        let mut batch = self.lock_open().await?;
+        self.flush_locked(&mut batch).await
     }
 
-    pub fn send_end_session(&self) -> Result<(), TransmitterError> {
+    async fn flush_locked(&self, batch: &mut Batch) -> Result<(), TransmitterError> {
+        if batch.blocks.is_empty() {
+            return Ok(());
+        }
+        if let Some(store) = &self.opts.store {
+            store.insert_blocks(self.opts.session, batch.next_seq_num, &batch.blocks)?;
+        }
+        let blocks = std::mem::take(&mut batch.blocks);
+        let header = Header::new(self.opts.session, batch.next_seq_num, 0);
+        // The number of blocks is kept under the max when they're queued, so this can't fail.
+        let packet = DownstreamPacket::new(header, blocks).unwrap();
+        let res = self.send_packet(batch, &packet.serialize()).await;
+        let (_, blocks) = packet.into_parts();
+        if let Err(e) = res {
+            // Left queued so they're sent again, with the same sequence numbers, next time.
+            batch.blocks = blocks;
+            return Err(e.into());
+        }
+        batch.next_seq_num += blocks.len() as u64;
+        batch.len = HEADER_LEN;
+        batch.first_at = None;
+        batch.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Sends the packet once pacing allows it.
+    async fn send_packet(&self, batch: &mut Batch, packet: &[u8]) -> io::Result<()> {
+        let delay = batch.pacer.delay(packet.len(), Instant::now().into_std());
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+        batch.pacer.sent(packet.len(), Instant::now().into_std());
+        // NOTE: "partial write are not possible until buffer sizes above i32::MAX."
+        self.conn.send(packet).await?;
+        Ok(())
+    }
+
+    async fn send_heartbeat(&self) -> Result<(), TransmitterError> {
+        let mut batch = self.lock_open().await?;
+        self.send_heartbeat_locked(&mut batch).await
+    }
+
+    async fn send_heartbeat_locked(&self, batch: &mut Batch) -> Result<(), TransmitterError> {
+        // Anything queued has to go out first, otherwise the heartbeat's sequence number would
+        // be behind.
+        self.flush_locked(batch).await?;
+        let header = Header::heartbeat(self.opts.session, batch.next_seq_num);
+        batch.last_sent = Instant::now();
+        self.send_packet(batch, header.as_slice()).await?;
+        Ok(())
+    }
+
+    async fn send_end_session(&self) -> Result<(), TransmitterError> {
+        let mut batch = self.lock_open().await?;
+        self.flush_locked(&mut batch).await?;
+        let header = Header::end_session(self.opts.session, batch.next_seq_num);
+        for i in 0..self.opts.end_session_repeats.max(1) {
+            if i != 0 {
+                sleep(self.opts.end_session_interval).await;
+            }
+            self.send_packet(&mut batch, header.as_slice()).await?;
+        }
+        batch.last_sent = Instant::now();
+        self.close_with_err(TransmitterError::SessionEnded);
+        Ok(())
+    }
+
+    async fn lock_open(&self) -> Result<MutexGuard<'_, Batch>, TransmitterError> {
+        let batch = self.batch.lock().await;
+        if self.is_closed() {
+            return Err(TransmitterError::Closed);
+        }
+        Ok(batch)
+    }
+
+    fn is_closed(&self) -> bool {
+        !self.close_err.is_empty(Ordering::Relaxed)
+    }
+
+    fn close_with_err(&self, err: impl Into<TransmitterError>) -> ArcTransmitterError {
+        let _ = self.close_err.store_if_empty(err.into(), Ordering::Relaxed, Ordering::Relaxed);
+        self.notify.notify_one();
+        self.close_err.load(Ordering::Relaxed).unwrap()
+    }
+
+    /// Flushes batches that have waited long enough and sends heartbeats while idle, until the
+    /// transmitter is closed or dropped.
+    async fn run(weak: Weak<Self>) {
+        loop {
+            let Some(inner) = weak.upgrade() else {
+                break;
+            };
+            let (flush_interval, hb_interval) =
+                (inner.opts.flush_interval, inner.opts.heartbeat_interval);
+            let deadline = {
+                let mut batch = inner.batch.lock().await;
+                if inner.is_closed() {
+                    break;
+                }
+                let now = Instant::now();
+                match batch.first_at {
+                    Some(first_at) => {
+                        if now >= first_at + flush_interval {
+                            if let Err(e) = inner.flush_locked(&mut batch).await {
+                                // Closes with the error, which `close_err` returns.
+                                inner.close_with_err(e);
+                                break;
+                            }
+                            continue;
+                        }
+                        Some(first_at + flush_interval)
+                    }
+                    None if !hb_interval.is_zero() => {
+                        if now >= batch.last_sent + hb_interval {
+                            if let Err(e) = inner.send_heartbeat_locked(&mut batch).await {
+                                inner.close_with_err(e);
+                                break;
+                            }
+                            continue;
+                        }
+                        Some(batch.last_sent + hb_interval)
+                    }
+                    None => None,
+                }
+            };
+            // Wakes up at least every poll interval to notice the transmitter being dropped.
+            let wait = deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+                .unwrap_or(POLL_INTERVAL)
+                .min(POLL_INTERVAL);
+            let _ = timeout(wait, inner.notify.notified()).await;
+        }
+    }
+}
+
+impl Drop for InnerTransmitter {
+    fn drop(&mut self) {
+        // Anything still queued is sent (without pacing, since this can't wait) rather than
+        // lost.
+        if self.is_closed() {
+            return;
+        }
+        let batch = self.batch.get_mut();
+        if batch.blocks.is_empty() {
+            return;
+        }
+        if let Some(store) = &self.opts.store {
+            let _ = store.insert_blocks(self.opts.session, batch.next_seq_num, &batch.blocks);
+        }
+        let header = Header::new(self.opts.session, batch.next_seq_num, 0);
+        let blocks = std::mem::take(&mut batch.blocks);
+        let packet = DownstreamPacket::new(header, blocks).unwrap();
+        let _ = self.conn.try_send(&packet.serialize());
     }
 }
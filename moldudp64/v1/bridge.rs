@@ -0,0 +1,458 @@
+//! Republishing a SoupBinTCP session's sequenced data as a MoldUDP64 stream.
+
+use crate::v1::request_server::*;
+use crate::v1::store::{ArcMessageStore, MemoryStore};
+use crate::v1::transmitter::*;
+use crate::v1::types::*;
+
+use jtutils::atomic_value::{Ordering, AAV};
+use soupbintcp::client::{ArcClientError, Client, ClientError, ClientOptions};
+use soupbintcp::PacketType;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Error as IoError};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Instant;
+
+#[derive(Clone, Default)]
+pub struct SoupBinBridgeOptions {
+    client: ClientOptions,
+    transmitter: TransmitterOptions,
+    request_server: RequestServerOptions,
+    request_addr: Option<SocketAddr>,
+}
+
+impl SoupBinBridgeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Options used to log into the upstream SoupBinTCP server.
+    pub fn with_client_options(mut self, opts: ClientOptions) -> Self {
+        self.client = opts;
+        self
+    }
+
+    /// Options for the MoldUDP64 transmitter. The session and sequence number are replaced with
+    /// those the login was accepted with. If no store is set, one is created.
+    pub fn with_transmitter_options(mut self, opts: TransmitterOptions) -> Self {
+        self.transmitter = opts;
+        self
+    }
+
+    /// Options for the retransmission server. The session is replaced with the bridge's.
+    pub fn with_request_server_options(mut self, opts: RequestServerOptions) -> Self {
+        self.request_server = opts;
+        self
+    }
+
+    /// The address to answer retransmission requests on. No request server is run if this isn't
+    /// set.
+    pub fn with_request_addr(mut self, addr: SocketAddr) -> Self {
+        self.request_addr = Some(addr);
+        self
+    }
+
+    pub fn client_options(&self) -> &ClientOptions {
+        &self.client
+    }
+
+    pub fn transmitter_options(&self) -> &TransmitterOptions {
+        &self.transmitter
+    }
+
+    pub fn request_server_options(&self) -> &RequestServerOptions {
+        &self.request_server
+    }
+
+    pub fn request_addr(&self) -> Option<SocketAddr> {
+        self.request_addr
+    }
+
+    /// Logs into the SoupBinTCP server at `upstream` and starts republishing its sequenced data
+    /// to `downstream`, which can be a multicast group or a unicast address.
+    pub fn connect(
+        self,
+        upstream: impl ToSocketAddrs,
+        downstream: impl ToSocketAddrs,
+    ) -> Result<SoupBinBridge, BridgeError> {
+        let client = self.client.clone().connect(upstream, None)?;
+        let session = SessionId::new_trunc(client.session());
+        // A SoupBinTCP server gives the sequence number of the next message it'll send, which
+        // is also where the MoldUDP64 stream picks up.
+        let seq_num = client.sequence_number().to_u64().max(1);
+
+        let store = match self.transmitter.store() {
+            Some(store) => Arc::clone(store),
+            None => Arc::new(MemoryStore::new()),
+        };
+        let transmitter = self
+            .transmitter
+            .clone()
+            .with_session(session)
+            .with_sequence_number(seq_num)
+            .with_store(Arc::clone(&store))
+            .connect(downstream);
+        let transmitter = match transmitter {
+            Ok(t) => t,
+            Err(e) => {
+                let _ = client.logout();
+                return Err(e.into());
+            }
+        };
+        let request_server = match self.request_addr {
+            Some(addr) => {
+                let res = self
+                    .request_server
+                    .clone()
+                    .with_session(session)
+                    .bind(addr, Arc::clone(&store));
+                match res {
+                    Ok(server) => Some(server),
+                    Err(e) => {
+                        let _ = client.logout();
+                        let _ = transmitter.close();
+                        return Err(e.into());
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let inner = Arc::new(InnerBridge {
+            client,
+            transmitter,
+            request_server,
+            store,
+            session,
+            opts: self,
+            close_err: AAV::empty(),
+        });
+        let (client, weak) = (inner.client.clone(), Arc::downgrade(&inner));
+        thread::spawn(move || InnerBridge::forward(client, weak));
+        Ok(SoupBinBridge(inner))
+    }
+}
+
+/// Logs into a SoupBinTCP session and sends every sequenced message on as MoldUDP64, keeping its
+/// sequence number. The MoldUDP64 session ID is the SoupBinTCP one. Messages sent are kept so
+/// retransmission requests can be answered.
+#[derive(Clone)]
+pub struct SoupBinBridge(Arc<InnerBridge>);
+
+impl SoupBinBridge {
+    pub fn options() -> SoupBinBridgeOptions {
+        SoupBinBridgeOptions::new()
+    }
+
+    /// The MoldUDP64 session the messages are sent on.
+    pub fn session(&self) -> SessionId {
+        self.0.session
+    }
+
+    /// The sequence number the next message will be sent with.
+    pub fn next_seq_num(&self) -> u64 {
+        self.0.transmitter.next_seq_num()
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.0.client
+    }
+
+    pub fn transmitter(&self) -> &Transmitter {
+        &self.0.transmitter
+    }
+
+    pub fn request_server(&self) -> Option<&RequestServer> {
+        self.0.request_server.as_ref()
+    }
+
+    /// The address retransmission requests are answered on, if a request server is running.
+    pub fn request_addr(&self) -> Option<io::Result<SocketAddr>> {
+        self.0.request_server.as_ref().map(|s| s.local_addr())
+    }
+
+    pub fn store(&self) -> &ArcMessageStore {
+        &self.0.store
+    }
+
+    pub fn opts(&self) -> &SoupBinBridgeOptions {
+        &self.0.opts
+    }
+
+    /// Logs out upstream and stops sending without ending the MoldUDP64 session. The request
+    /// server is stopped too.
+    pub fn close(&self) {
+        self.0.close_with_err(BridgeError::Closed);
+    }
+
+    pub fn close_err(&self) -> Option<ArcBridgeError> {
+        self.0.close_err.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.0.close_err.is_empty(Ordering::Relaxed)
+    }
+}
+
+struct InnerBridge {
+    client: Client,
+    transmitter: Transmitter,
+    request_server: Option<RequestServer>,
+    store: ArcMessageStore,
+    session: SessionId,
+    opts: SoupBinBridgeOptions,
+
+    close_err: AAV<BridgeError>,
+}
+
+impl InnerBridge {
+    /// Only the client is held while waiting for a packet. Dropping the bridge logs it out, which
+    /// ends the read.
+    fn forward(client: Client, weak: Weak<Self>) {
+        loop {
+            let res = client.read_packet();
+            let Some(inner) = weak.upgrade() else {
+                break;
+            };
+            let packet = match res {
+                Some(Ok(packet)) => packet,
+                Some(Err(e)) => {
+                    inner.close_with_err(BridgeError::Client(e));
+                    break;
+                }
+                None => {
+                    let err = client.close_err().unwrap_or_else(|| {
+                        Arc::new(ClientError::LoggedOut)
+                    });
+                    inner.close_with_err(BridgeError::Client(err));
+                    break;
+                }
+            };
+            if !inner.close_err.is_empty(Ordering::Relaxed) {
+                break;
+            }
+            client.set_last_server_heartbeat(Instant::now());
+            match packet.packet_type() {
+                PacketType::SequencedData => {
+                    if let Err(e) = inner.transmitter.send_message(packet.payload()) {
+                        inner.close_with_err(e);
+                        break;
+                    }
+                }
+                PacketType::EndOfSession => {
+                    if let Err(e) = inner.transmitter.send_end_session() {
+                        inner.close_with_err(e);
+                    } else {
+                        inner.close_with_err(BridgeError::SessionEnded);
+                    }
+                    break;
+                }
+                // Heartbeats are sent by the transmitter while idle, and nothing else is
+                // sequenced.
+                _ => (),
+            }
+        }
+    }
+
+    fn close_with_err(&self, err: impl Into<BridgeError>) -> ArcBridgeError {
+        let stored = self
+            .close_err
+            .store_if_empty(err.into(), Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok();
+        if stored {
+            let _ = self.client.logout();
+            let _ = self.transmitter.close();
+            if let Some(server) = &self.request_server {
+                server.close();
+            }
+        }
+        self.close_err.load(Ordering::Relaxed).unwrap()
+    }
+}
+
+impl Drop for InnerBridge {
+    fn drop(&mut self) {
+        if self.close_err.is_empty(Ordering::Relaxed) {
+            let _ = self.client.logout();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BridgeError {
+    /// The upstream session ended. The MoldUDP64 session was ended too.
+    SessionEnded,
+    Closed,
+    Client(ArcClientError),
+    Transmitter(TransmitterError),
+    RequestServer(RequestServerError),
+    Io(IoError),
+}
+pub type ArcBridgeError = Arc<BridgeError>;
+
+impl From<IoError> for BridgeError {
+    fn from(e: IoError) -> Self {
+        BridgeError::Io(e)
+    }
+}
+
+impl From<ClientError> for BridgeError {
+    fn from(e: ClientError) -> Self {
+        BridgeError::Client(Arc::new(e))
+    }
+}
+
+impl From<TransmitterError> for BridgeError {
+    fn from(e: TransmitterError) -> Self {
+        BridgeError::Transmitter(e)
+    }
+}
+
+impl From<RequestServerError> for BridgeError {
+    fn from(e: RequestServerError) -> Self {
+        BridgeError::RequestServer(e)
+    }
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BridgeError::SessionEnded => write!(f, "session ended"),
+            BridgeError::Closed => write!(f, "bridge closed"),
+            BridgeError::Client(ref e) => write!(f, "soupbintcp client error: {e}"),
+            BridgeError::Transmitter(ref e) => write!(f, "transmitter error: {e}"),
+            BridgeError::RequestServer(ref e) => write!(f, "request server error: {e}"),
+            BridgeError::Io(ref e) => write!(f, "io error: {e}"),
+        }
+    }
+}
+
+impl Error for BridgeError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::v1::receiver::Receiver;
+    use soupbintcp::client::ClientHandler;
+    use soupbintcp::{Packet, Payload, SequenceNumber};
+    use std::io::Write;
+    use std::net::{TcpListener, UdpSocket};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Accepts a login, waits for `go`, then writes `packets` and returns the type of the first
+    /// packet the client sends after that other than a heartbeat.
+    fn serve(
+        packets: Vec<Packet>,
+        go: mpsc::Receiver<()>,
+    ) -> (SocketAddr, thread::JoinHandle<PacketType>) {
+        let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = ln.local_addr().unwrap();
+        let h = thread::spawn(move || {
+            let (mut s, _) = ln.accept().unwrap();
+            Packet::read_from(&mut s).unwrap();
+            let session = soupbintcp::SessionId::new_trunc("SESS");
+            let accepted = Packet::login_accepted(session, SequenceNumber::from_u64(1));
+            s.write_all(accepted.as_slice()).unwrap();
+            go.recv().unwrap();
+            for p in packets {
+                s.write_all(p.as_slice()).unwrap();
+            }
+            loop {
+                let pt = Packet::read_from(&mut s).unwrap().packet_type();
+                if pt != PacketType::ClientHeartbeat {
+                    return pt;
+                }
+            }
+        });
+        (addr, h)
+    }
+
+    fn sequenced(data: &[u8]) -> Packet {
+        Packet::sequenced_data(Payload::new(data.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn republishes_session() {
+        // Longer than 255 bytes so the length prefix's byte order matters.
+        let (long, short) = (vec![7u8; 300], b"short".to_vec());
+        let packets = vec![sequenced(&long), sequenced(&short), Packet::end_of_session()];
+        let (go, wait) = mpsc::channel();
+        let (upstream, server) = serve(packets, wait);
+
+        let (tx, got) = mpsc::channel();
+        let conn = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let downstream = conn.local_addr().unwrap();
+        let receiver = Receiver::options()
+            .with_session(SessionId::new_trunc("SESS"))
+            .build_with_socket(
+                conn,
+                downstream.ip(),
+                Arc::new(move |_, seq_num, block| {
+                    let _ = tx.send((seq_num, block.to_vec()));
+                }),
+            )
+            .unwrap();
+
+        let bridge = SoupBinBridge::options().connect(upstream, downstream).unwrap();
+        assert_eq!(bridge.session(), SessionId::new_trunc("SESS"));
+        assert!(!bridge.client().is_closed());
+        go.send(()).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(got.recv_timeout(timeout).unwrap(), (1, long));
+        assert_eq!(got.recv_timeout(timeout).unwrap(), (2, short));
+        // The client logs out once the session ends.
+        assert_eq!(server.join().unwrap(), PacketType::LogoutRequest);
+        assert!(matches!(*bridge.close_err().unwrap(), BridgeError::SessionEnded));
+        assert!(bridge.client().is_closed());
+        assert_eq!(bridge.next_seq_num(), 3);
+        let stored = bridge.store().range(bridge.session(), 1, 10).unwrap();
+        assert_eq!(stored.len(), 2);
+        drop(receiver);
+    }
+
+    #[test]
+    fn drop_stops_thread() {
+        let (go, wait) = mpsc::channel();
+        let (upstream, server) = serve(Vec::new(), wait);
+        let conn = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let bridge = SoupBinBridge::options()
+            .connect(upstream, conn.local_addr().unwrap())
+            .unwrap();
+        let weak = Arc::downgrade(&bridge.0);
+        go.send(()).unwrap();
+        drop(bridge);
+        assert_eq!(server.join().unwrap(), PacketType::LogoutRequest);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn client_handler_reads_each_packet() {
+        let (long, short) = (vec![7u8; 300], b"short".to_vec());
+        let packets = vec![sequenced(&long), sequenced(&short)];
+        let (go, wait) = mpsc::channel();
+        let (upstream, server) = serve(packets, wait);
+
+        let (tx, got) = mpsc::channel();
+        let handler: ClientHandler = Arc::new(move |p: Packet| {
+            let _ = tx.send(p);
+        });
+        let client = ClientOptions::new().connect(upstream, Some(handler)).unwrap();
+        go.send(()).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        for want in [long, short] {
+            let p = got.recv_timeout(timeout).unwrap();
+            assert_eq!(p.payload(), &want[..]);
+            let parsed = Packet::parse_as(PacketType::SequencedData, p.as_slice()).unwrap();
+            assert_eq!(parsed.payload(), &want[..]);
+        }
+        client.logout().unwrap();
+        assert_eq!(server.join().unwrap(), PacketType::LogoutRequest);
+        assert!(client.is_closed());
+    }
+}
@@ -0,0 +1,421 @@
+use super::store::MessageStore;
+use super::types::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{
+    self, prelude::*, BufReader, BufWriter, Error as IoError, ErrorKind as IoErrorKind, SeekFrom,
+};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The default number of records per session between entries in a `FileStore`'s index.
+pub const DEFAULT_INDEX_INTERVAL: u64 = 256;
+
+const MAGIC: &[u8; 8] = b"MOLDJNL1";
+/// A record is the session, the sequence number, then the message block (length included).
+const RECORD_HEADER_LEN: usize = 10 + 8 + 2;
+
+#[derive(Clone, Debug)]
+pub struct FileStoreOptions {
+    index_interval: u64,
+    sync_on_write: bool,
+}
+
+impl Default for FileStoreOptions {
+    fn default() -> Self {
+        Self {
+            index_interval: DEFAULT_INDEX_INTERVAL,
+            sync_on_write: false,
+        }
+    }
+}
+
+impl FileStoreOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many records of a session there are between entries in the index. Smaller intervals
+    /// make lookups faster at the cost of memory.
+    pub fn with_index_interval(mut self, interval: u64) -> Self {
+        self.index_interval = interval.max(1);
+        self
+    }
+
+    /// Flushes and syncs the file to disk after every insert. Otherwise, writes are buffered
+    /// until `sync` is called, a read needs them, or the store is dropped.
+    pub fn with_sync_on_write(mut self, b: bool) -> Self {
+        self.sync_on_write = b;
+        self
+    }
+
+    pub fn index_interval(&self) -> u64 {
+        self.index_interval
+    }
+
+    pub fn sync_on_write(&self) -> bool {
+        self.sync_on_write
+    }
+
+    /// Opens the journal at `path`, creating it if it doesn't exist. An existing journal is
+    /// scanned to rebuild the index, and a partially written record at the end (from a crash)
+    /// is cut off.
+    pub fn open(self, path: impl AsRef<Path>) -> io::Result<FileStore> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        // The reader is opened separately so it has its own offset; seeking it for a read must
+        // not move where the writer appends.
+        let reader = OpenOptions::new().read(true).open(&path)?;
+        let mut files = Files {
+            writer: BufWriter::new(file),
+            reader,
+            end: MAGIC.len() as u64,
+            dirty: false,
+            sessions: HashMap::new(),
+        };
+        files.load(self.index_interval)?;
+        Ok(FileStore(Arc::new(InnerFileStore {
+            path,
+            opts: self,
+            files: Mutex::new(files),
+        })))
+    }
+}
+
+/// An append-only journal of messages on disk. Every session's messages are written to the same
+/// file in the order they're inserted, and a sparse index of each session's records is kept in
+/// memory so any message can be found by reading at most `index_interval` records.
+///
+/// Messages for a session must be inserted in sequence order (with or without gaps); any with
+/// sequence numbers below the highest stored for the session are ignored. Clones share the same
+/// file, so a `Transmitter` and a `RequestServer` can be given the same store.
+#[derive(Clone)]
+pub struct FileStore(Arc<InnerFileStore>);
+
+impl FileStore {
+    pub fn options() -> FileStoreOptions {
+        FileStoreOptions::new()
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::options().open(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0.path
+    }
+
+    pub fn opts(&self) -> &FileStoreOptions {
+        &self.0.opts
+    }
+
+    /// The sessions that have messages stored.
+    pub fn sessions(&self) -> Vec<SessionId> {
+        self.0.files.lock().unwrap().sessions.keys().copied().collect()
+    }
+
+    /// The number of messages stored for the session.
+    pub fn len(&self, session: SessionId) -> u64 {
+        let files = self.0.files.lock().unwrap();
+        files.sessions.get(&session).map(|s| s.count).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self, session: SessionId) -> bool {
+        self.len(session) == 0
+    }
+
+    /// One past the highest sequence number stored for the session, if any are.
+    pub fn next_seq_num(&self, session: SessionId) -> Option<u64> {
+        let files = self.0.files.lock().unwrap();
+        files.sessions.get(&session).map(|s| s.next_seq_num)
+    }
+
+    /// Flushes buffered writes and syncs the file to disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.0.files.lock().unwrap().sync()
+    }
+}
+
+impl MessageStore for FileStore {
+    fn insert_blocks(
+        &self,
+        session: SessionId,
+        start: u64,
+        blocks: &[MessageBlock],
+    ) -> io::Result<()> {
+        let mut files = self.0.files.lock().unwrap();
+        files.append(session, start, blocks, self.0.opts.index_interval)?;
+        if self.0.opts.sync_on_write {
+            files.sync()?;
+        }
+        Ok(())
+    }
+
+    fn range(&self, session: SessionId, start: u64, max: usize) -> io::Result<Vec<MessageBlock>> {
+        self.0.files.lock().unwrap().range(session, start, max)
+    }
+}
+
+struct InnerFileStore {
+    path: PathBuf,
+    opts: FileStoreOptions,
+    files: Mutex<Files>,
+}
+
+struct Files {
+    writer: BufWriter<File>,
+    reader: File,
+    /// Where the next record will be written.
+    end: u64,
+    /// Whether there are writes that haven't been flushed.
+    dirty: bool,
+    sessions: HashMap<SessionId, SessionIndex>,
+}
+
+struct SessionIndex {
+    /// One past the highest sequence number stored.
+    next_seq_num: u64,
+    count: u64,
+    /// The offsets of every `index_interval`th record (and the first), by sequence number.
+    offsets: BTreeMap<u64, u64>,
+    since_indexed: u64,
+}
+
+impl SessionIndex {
+    fn new() -> Self {
+        Self {
+            next_seq_num: 0,
+            count: 0,
+            offsets: BTreeMap::new(),
+            since_indexed: 0,
+        }
+    }
+
+    fn add(&mut self, seq_num: u64, offset: u64, interval: u64) {
+        if self.since_indexed == 0 || self.since_indexed >= interval {
+            self.offsets.insert(seq_num, offset);
+            self.since_indexed = 0;
+        }
+        self.since_indexed += 1;
+        self.count += 1;
+        self.next_seq_num = seq_num + 1;
+    }
+}
+
+impl Files {
+    /// Checks the magic (writing it to a new file) and rebuilds the index from the records.
+    fn load(&mut self, interval: u64) -> io::Result<()> {
+        let len = self.reader.metadata()?.len();
+        if len == 0 {
+            self.writer.write_all(MAGIC)?;
+            self.writer.flush()?;
+            return Ok(());
+        }
+        let mut r = BufReader::new(&mut self.reader);
+        let mut magic = [0u8; 8];
+        if len < MAGIC.len() as u64 || r.read_exact(&mut magic).is_err() || &magic != MAGIC {
+            return Err(IoError::new(IoErrorKind::InvalidData, "not a message journal"));
+        }
+        let mut offset = MAGIC.len() as u64;
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        loop {
+            if offset + RECORD_HEADER_LEN as u64 > len {
+                break;
+            }
+            r.read_exact(&mut header)?;
+            let (session, seq_num, data_len) = parse_record_header(&header);
+            let record_end = offset + (RECORD_HEADER_LEN + data_len) as u64;
+            if record_end > len {
+                break;
+            }
+            r.seek_relative(data_len as i64)?;
+            let index = self.sessions.entry(session).or_insert_with(SessionIndex::new);
+            index.add(seq_num, offset, interval);
+            offset = record_end;
+        }
+        if offset < len {
+            // The last record was only partly written.
+            self.writer.get_ref().set_len(offset)?;
+        }
+        self.end = offset;
+        self.writer.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    fn append(
+        &mut self,
+        session: SessionId,
+        start: u64,
+        blocks: &[MessageBlock],
+        interval: u64,
+    ) -> io::Result<()> {
+        let index = self.sessions.entry(session).or_insert_with(SessionIndex::new);
+        for (seq_num, block) in (start..).zip(blocks) {
+            if index.count != 0 && seq_num < index.next_seq_num {
+                continue;
+            }
+            let bytes = block.as_slice();
+            self.writer.write_all(&session)?;
+            self.writer.write_all(&seq_num.to_be_bytes())?;
+            self.writer.write_all(bytes)?;
+            index.add(seq_num, self.end, interval);
+            self.end += (RECORD_HEADER_LEN - 2 + bytes.len()) as u64;
+            self.dirty = true;
+        }
+        if index.count == 0 {
+            self.sessions.remove(&session);
+        }
+        Ok(())
+    }
+
+    fn range(
+        &mut self,
+        session: SessionId,
+        start: u64,
+        max: usize,
+    ) -> io::Result<Vec<MessageBlock>> {
+        let Some(index) = self.sessions.get(&session) else {
+            return Ok(Vec::new());
+        };
+        if max == 0 || start >= index.next_seq_num {
+            return Ok(Vec::new());
+        }
+        let offset = match index.offsets.range(..=start).next_back() {
+            Some((_, &offset)) => offset,
+            None => return Ok(Vec::new()),
+        };
+        if self.dirty {
+            self.writer.flush()?;
+            self.dirty = false;
+        }
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut r = BufReader::new(&mut self.reader);
+        let (mut offset, mut want) = (offset, start);
+        let mut blocks = Vec::new();
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        while offset < self.end && blocks.len() < max {
+            r.read_exact(&mut header)?;
+            let (rec_session, seq_num, data_len) = parse_record_header(&header);
+            offset += (RECORD_HEADER_LEN + data_len) as u64;
+            if rec_session != session || seq_num < want {
+                r.seek_relative(data_len as i64)?;
+                continue;
+            }
+            if seq_num > want {
+                break;
+            }
+            let mut data = vec![0u8; data_len];
+            r.read_exact(&mut data)?;
+            blocks.push(MessageBlock::from_data(&data));
+            want += 1;
+        }
+        Ok(blocks)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.dirty = false;
+        self.writer.get_ref().sync_data()
+    }
+}
+
+fn parse_record_header(header: &[u8; RECORD_HEADER_LEN]) -> (SessionId, u64, usize) {
+    let session = SessionId::new_trunc(&header[..10]);
+    let seq_num = u64::from_be_bytes(header[10..18].try_into().unwrap());
+    let data_len = u16::from_be_bytes([header[18], header[19]]) as usize;
+    (session, seq_num, data_len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}.jnl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn block(n: u64) -> MessageBlock {
+        MessageBlock::from_data(&n.to_be_bytes())
+    }
+
+    fn blocks(range: std::ops::Range<u64>) -> Vec<MessageBlock> {
+        range.map(block).collect()
+    }
+
+    #[test]
+    fn reopen() {
+        let path = temp_path("file_store_reopen");
+        let (a, b) = (SessionId::new_trunc("A"), SessionId::new_trunc("B"));
+        {
+            let store = FileStore::options().with_index_interval(4).open(&path).unwrap();
+            store.insert_blocks(a, 1, &blocks(1..11)).unwrap();
+            store.insert_blocks(b, 5, &blocks(5..8)).unwrap();
+            // Already stored, so ignored.
+            store.insert_blocks(a, 3, &blocks(3..5)).unwrap();
+            store.sync().unwrap();
+        }
+        let store = FileStore::options().with_index_interval(4).open(&path).unwrap();
+        assert_eq!(store.len(a), 10);
+        assert_eq!(store.len(b), 3);
+        assert_eq!(store.next_seq_num(a), Some(11));
+        assert_eq!(store.next_seq_num(b), Some(8));
+
+        let got = store.range(a, 6, 100).unwrap();
+        assert_eq!(got.len(), 5);
+        for (n, blk) in (6u64..).zip(&got) {
+            assert_eq!(&**blk, &n.to_be_bytes());
+        }
+        assert_eq!(&*store.get(b, 7).unwrap().unwrap(), &7u64.to_be_bytes());
+        assert!(store.get(b, 4).unwrap().is_none());
+        assert!(store.range(a, 11, 1).unwrap().is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn truncates_partial_record() {
+        let path = temp_path("file_store_partial");
+        let sess = SessionId::new_trunc("A");
+        {
+            let store = FileStore::open(&path).unwrap();
+            store.insert_blocks(sess, 1, &blocks(1..4)).unwrap();
+            store.sync().unwrap();
+        }
+        let len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[0; 5]).unwrap();
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(sess), 3);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn range_between_appends() {
+        let path = temp_path("file_store_interleaved");
+        let sess = SessionId::new_trunc("A");
+        {
+            let store = FileStore::open(&path).unwrap();
+            store.insert_blocks(sess, 1, &blocks(1..1001)).unwrap();
+            assert_eq!(store.range(sess, 1, 2).unwrap().len(), 2);
+            store.insert_blocks(sess, 1001, &blocks(1001..1011)).unwrap();
+            assert_eq!(store.range(sess, 500, 1).unwrap().len(), 1);
+            store.insert_blocks(sess, 1011, &blocks(1011..1021)).unwrap();
+            store.sync().unwrap();
+        }
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.len(sess), 1020);
+        let got = store.range(sess, 1, 2000).unwrap();
+        assert_eq!(got.len(), 1020);
+        for (n, blk) in (1u64..).zip(&got) {
+            assert_eq!(&**blk, &n.to_be_bytes());
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
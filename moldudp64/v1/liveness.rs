@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+/// Tracks when packets (including heartbeats) last arrived to tell a dead feed from a quiet one.
+pub(crate) struct Liveness {
+    timeout: Duration,
+    last_packet: Instant,
+    stale: bool,
+}
+
+impl Liveness {
+    /// A zero timeout disables the checks.
+    pub(crate) fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            last_packet: now,
+            stale: false,
+        }
+    }
+
+    /// Records a packet arriving, returning how long the feed was silent if it had gone stale.
+    pub(crate) fn packet(&mut self, now: Instant) -> Option<Duration> {
+        let silent_for = now.saturating_duration_since(self.last_packet);
+        self.last_packet = now;
+        std::mem::replace(&mut self.stale, false).then_some(silent_for)
+    }
+
+    /// Returns how long the feed has been silent if it just went stale.
+    pub(crate) fn poll(&mut self, now: Instant) -> Option<Duration> {
+        if self.timeout.is_zero() || self.stale {
+            return None;
+        }
+        let silent_for = now.saturating_duration_since(self.last_packet);
+        if silent_for < self.timeout {
+            return None;
+        }
+        self.stale = true;
+        Some(silent_for)
+    }
+
+    /// When the feed goes stale if nothing arrives before then.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        if self.timeout.is_zero() || self.stale {
+            None
+        } else {
+            Some(self.last_packet + self.timeout)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timeout_and_recovery() {
+        let timeout = Duration::from_secs(1);
+        let t0 = Instant::now();
+        let mut liveness = Liveness::new(timeout, t0);
+        assert_eq!(liveness.poll(t0 + timeout / 2), None);
+        assert_eq!(liveness.packet(t0 + timeout / 2), None);
+        assert_eq!(liveness.poll(t0 + timeout), None);
+
+        let t1 = t0 + timeout / 2;
+        assert_eq!(liveness.poll(t1 + timeout * 2), Some(timeout * 2));
+        // Going stale is only reported once.
+        assert_eq!(liveness.poll(t1 + timeout * 3), None);
+        assert_eq!(liveness.packet(t1 + timeout * 4), Some(timeout * 4));
+        assert_eq!(liveness.packet(t1 + timeout * 4), None);
+        assert_eq!(liveness.poll(t1 + timeout * 5), Some(timeout));
+    }
+
+    #[test]
+    fn zero_timeout_disables() {
+        let t0 = Instant::now();
+        let mut liveness = Liveness::new(Duration::ZERO, t0);
+        assert_eq!(liveness.poll(t0 + Duration::from_secs(60)), None);
+        assert_eq!(liveness.packet(t0 + Duration::from_secs(60)), None);
+    }
+}
@@ -1 +1,34 @@
 pub mod types;
+pub mod pcap;
+#[cfg(feature = "tokio")]
+pub mod async_tokio;
+#[cfg(feature = "soupbintcp")]
+pub mod bridge;
+mod arbitrated;
+pub use arbitrated::*;
+mod file_store;
+pub use file_store::*;
+mod liveness;
+mod pacing;
+pub use pacing::{Pacing, DEFAULT_SCHEDULE_TOLERANCE};
+mod receiver;
+pub use receiver::*;
+mod recovery;
+pub use recovery::{
+    DEFAULT_MAX_REQUEST_MESSAGES, DEFAULT_MAX_REQUEST_RETRIES, DEFAULT_REQUEST_TIMEOUT,
+};
+mod rate_limit;
+mod request_server;
+pub use request_server::*;
+mod sequencer;
+pub use sequencer::*;
+mod session;
+pub use session::{LateJoin, SessionPolicy};
+mod socket;
+pub use socket::*;
+mod stats;
+pub use stats::*;
+mod store;
+pub use store::*;
+mod transmitter;
+pub use transmitter::*;
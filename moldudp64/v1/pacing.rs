@@ -0,0 +1,180 @@
+use super::rate_limit::TokenBucket;
+use super::transmitter::TransmitterEvent;
+
+use std::time::{Duration, Instant};
+
+/// The default amount a replay can lag its schedule before `TransmitterEvent::BehindSchedule` is
+/// sent.
+pub const DEFAULT_SCHEDULE_TOLERANCE: Duration = Duration::from_millis(10);
+
+/// Limits how fast a transmitter sends packets. Heartbeats and end of session packets count
+/// toward the limit too. A zero rate is unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pacing {
+    #[default]
+    Unlimited,
+    /// At most `rate` packets per second, with bursts of up to `burst` packets.
+    Packets { rate: u64, burst: u64 },
+    /// At most `rate` bytes (of UDP payload) per second, with bursts of up to `burst` bytes.
+    Bytes { rate: u64, burst: u64 },
+}
+
+/// Applies a `Pacing` to the packets sent.
+pub(crate) struct Pacer {
+    bucket: Option<TokenBucket>,
+    bytes: bool,
+}
+
+impl Pacer {
+    pub(crate) fn new(pacing: Pacing) -> Self {
+        let (rate, burst, bytes) = match pacing {
+            Pacing::Unlimited => (0, 0, false),
+            Pacing::Packets { rate, burst } => (rate, burst, false),
+            Pacing::Bytes { rate, burst } => (rate, burst, true),
+        };
+        let bucket = (rate != 0).then(|| TokenBucket::new(rate as f64, burst.max(1) as f64));
+        Self { bucket, bytes }
+    }
+
+    fn cost(&self, len: usize) -> u64 {
+        if self.bytes {
+            len as u64
+        } else {
+            1
+        }
+    }
+
+    /// How long to wait before a packet of `len` bytes can be sent.
+    pub(crate) fn delay(&mut self, len: usize, now: Instant) -> Duration {
+        let cost = self.cost(len);
+        match &mut self.bucket {
+            Some(bucket) => bucket.delay(cost, now),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Records that a packet of `len` bytes was sent. A packet bigger than the burst puts the
+    /// bucket in debt, so the ones after it wait longer.
+    pub(crate) fn sent(&mut self, len: usize, now: Instant) {
+        let cost = self.cost(len);
+        if let Some(bucket) = &mut self.bucket {
+            bucket.spend(cost, now);
+        }
+    }
+}
+
+/// Maps the original timestamps of replayed messages to when they should be sent.
+pub(crate) struct ReplayClock {
+    speed: f64,
+    tolerance: Duration,
+    /// The first timestamp replayed and when it was.
+    origin: Option<(Duration, Instant)>,
+    lag: Duration,
+    behind: bool,
+}
+
+impl ReplayClock {
+    /// Returns `None` if the speed doesn't enable replay.
+    pub(crate) fn new(speed: f64, tolerance: Duration) -> Option<Self> {
+        (speed > 0.0 && speed.is_finite()).then_some(Self {
+            speed,
+            tolerance,
+            origin: None,
+            lag: Duration::ZERO,
+            behind: false,
+        })
+    }
+
+    /// When a message originally at `at` is due. The first message is due `now`.
+    pub(crate) fn due(&mut self, at: Duration, now: Instant) -> Instant {
+        let (origin_at, origin) = *self.origin.get_or_insert((at, now));
+        origin + at.saturating_sub(origin_at).div_f64(self.speed)
+    }
+
+    /// Records that the message due at `due` was queued at `now`, returning an event if that
+    /// put the replay behind schedule or back on it.
+    pub(crate) fn queued(
+        &mut self,
+        due: Instant,
+        now: Instant,
+        seq_num: u64,
+    ) -> Option<TransmitterEvent> {
+        self.lag = now.saturating_duration_since(due);
+        let behind = self.lag > self.tolerance;
+        if behind == self.behind {
+            return None;
+        }
+        self.behind = behind;
+        Some(if behind {
+            TransmitterEvent::BehindSchedule {
+                seq_num,
+                behind: self.lag,
+            }
+        } else {
+            TransmitterEvent::BackOnSchedule { seq_num }
+        })
+    }
+
+    /// How late the last message replayed was queued.
+    pub(crate) fn lag(&self) -> Duration {
+        self.lag
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pacer() {
+        let ms = Duration::from_millis;
+        let mut p = Pacer::new(Pacing::Unlimited);
+        let now = Instant::now();
+        p.sent(1 << 20, now);
+        assert_eq!(p.delay(1 << 20, now), Duration::ZERO);
+
+        let mut p = Pacer::new(Pacing::Packets { rate: 4, burst: 0 });
+        let now = Instant::now();
+        assert_eq!(p.delay(1000, now), Duration::ZERO);
+        p.sent(1000, now);
+        assert_eq!(p.delay(10, now), ms(250));
+        assert_eq!(p.delay(10, now + ms(250)), Duration::ZERO);
+
+        // A packet bigger than the burst leaves the bucket in debt.
+        let mut p = Pacer::new(Pacing::Bytes { rate: 1024, burst: 512 });
+        let now = Instant::now();
+        assert_eq!(p.delay(1024, now), Duration::ZERO);
+        p.sent(1024, now);
+        assert_eq!(p.delay(256, now), ms(750));
+        assert_eq!(p.delay(256, now + ms(500)), ms(250));
+    }
+
+    #[test]
+    fn replay_clock() {
+        let ms = Duration::from_millis;
+        assert!(ReplayClock::new(0.0, ms(10)).is_none());
+        assert!(ReplayClock::new(f64::INFINITY, ms(10)).is_none());
+
+        let mut c = ReplayClock::new(2.0, ms(10)).unwrap();
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        assert_eq!(c.due(secs(10), start + ms(3)), start + ms(3));
+        // Twice as fast, from the first message.
+        assert_eq!(c.due(secs(14), start + secs(1)), start + ms(2003));
+        // Earlier than the first message is due right away.
+        assert_eq!(c.due(secs(9), start + secs(1)), start + ms(3));
+
+        let due = start + secs(2);
+        assert!(c.queued(due, due + ms(5), 1).is_none());
+        let event = c.queued(due, due + ms(20), 2);
+        assert!(matches!(
+            event,
+            Some(TransmitterEvent::BehindSchedule { seq_num: 2, behind }) if behind == ms(20)
+        ));
+        assert_eq!(c.lag(), ms(20));
+        assert!(c.queued(due, due + ms(30), 3).is_none());
+        let event = c.queued(due, due, 4);
+        assert!(matches!(event, Some(TransmitterEvent::BackOnSchedule { seq_num: 4 })));
+        assert_eq!(c.lag(), Duration::ZERO);
+    }
+}
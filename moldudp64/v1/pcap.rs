@@ -0,0 +1,822 @@
+//! Reading MoldUDP64 traffic back out of `.pcap` and `.pcapng` captures.
+
+use super::sequencer::Sequencer;
+use super::types::*;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Error as IoError, ErrorKind as IoErrorKind, Read};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The default number of messages buffered behind a gap before the gap is skipped. More than a
+/// receiver buffers by default, since a replay has nothing to re-request from.
+pub const DEFAULT_REPLAY_MAX_PENDING: usize = 100_000;
+
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+/// The largest frame read from a capture. This is tcpdump's maximum snapshot length; lengths
+/// in a capture are only trusted up to it so a corrupt file can't cause a huge allocation.
+pub const MAX_FRAME_LEN: usize = 256 * 1024;
+/// Room for the fixed fields and options around a frame in a pcapng block.
+const MAX_BLOCK_OVERHEAD: usize = 4096;
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B23C4D;
+const PCAPNG_SHB: u32 = 0x0A0D0D0A;
+const PCAPNG_IDB: u32 = 0x00000001;
+const PCAPNG_SPB: u32 = 0x00000003;
+const PCAPNG_EPB: u32 = 0x00000006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+/// A single captured link-layer frame.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub timestamp: SystemTime,
+    pub link_type: u32,
+    pub data: Vec<u8>,
+}
+
+/// A UDP datagram pulled out of a captured frame.
+#[derive(Clone, Debug)]
+pub struct UdpDatagram {
+    pub timestamp: SystemTime,
+    pub src: SocketAddrV4,
+    pub dst: SocketAddrV4,
+    pub payload: Vec<u8>,
+}
+
+enum Format {
+    Pcap {
+        big_endian: bool,
+        /// Units per second of the sub-second part of timestamps.
+        ts_units: u64,
+        link_type: u32,
+        /// The largest frame that can be in the capture.
+        snaplen: usize,
+    },
+    PcapNg {
+        big_endian: bool,
+        interfaces: Vec<Interface>,
+        last_timestamp: SystemTime,
+    },
+}
+
+struct Interface {
+    link_type: u32,
+    /// Timestamp units per second.
+    ts_units: u64,
+}
+
+/// Reads frames from a pcap or pcapng capture. The format is detected from the first bytes.
+pub struct PcapReader<R> {
+    r: R,
+    format: Format,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, PcapError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut r: R) -> Result<Self, PcapError> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if u32::from_be_bytes(magic) == PCAPNG_SHB {
+            let mut reader = Self {
+                r,
+                format: Format::PcapNg {
+                    big_endian: false,
+                    interfaces: Vec::new(),
+                    last_timestamp: UNIX_EPOCH,
+                },
+            };
+            reader.read_section_header()?;
+            return Ok(reader);
+        }
+
+        let (big_endian, ts_units) = match (u32::from_be_bytes(magic), u32::from_le_bytes(magic)) {
+            (PCAP_MAGIC_MICROS, _) => (true, 1_000_000),
+            (_, PCAP_MAGIC_MICROS) => (false, 1_000_000),
+            (PCAP_MAGIC_NANOS, _) => (true, 1_000_000_000),
+            (_, PCAP_MAGIC_NANOS) => (false, 1_000_000_000),
+            _ => return Err(PcapError::Format("unknown file magic")),
+        };
+        // Version, timezone, sigfigs, snaplen, link type.
+        let mut header = [0u8; 20];
+        r.read_exact(&mut header)?;
+        let link_type = read_u32(&header[16..], big_endian) & 0x0FFF_FFFF;
+        // Some writers leave the snapshot length zero or set it to more than they ever capture.
+        let snaplen = match read_u32(&header[12..], big_endian) as usize {
+            0 => MAX_FRAME_LEN,
+            snaplen => snaplen.min(MAX_FRAME_LEN),
+        };
+        Ok(Self {
+            r,
+            format: Format::Pcap {
+                big_endian,
+                ts_units,
+                link_type,
+                snaplen,
+            },
+        })
+    }
+
+    /// Returns the next captured frame, or `None` at the end of the capture.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, PcapError> {
+        match self.format {
+            Format::Pcap { big_endian, ts_units, link_type, snaplen } => {
+                let mut header = [0u8; 16];
+                if !read_exact_or_eof(&mut self.r, &mut header)? {
+                    return Ok(None);
+                }
+                let secs = read_u32(&header[0..], big_endian) as u64;
+                let units = read_u32(&header[4..], big_endian) as u64;
+                let incl_len = read_u32(&header[8..], big_endian) as usize;
+                if incl_len > snaplen {
+                    return Err(PcapError::Format("captured length over snapshot length"));
+                }
+                let mut data = vec![0u8; incl_len];
+                self.r.read_exact(&mut data)?;
+                Ok(Some(Frame {
+                    timestamp: UNIX_EPOCH
+                        + Duration::from_secs(secs)
+                        + Duration::from_nanos(units * 1_000_000_000 / ts_units),
+                    link_type,
+                    data,
+                }))
+            }
+            Format::PcapNg { .. } => self.next_pcapng_frame(),
+        }
+    }
+
+    /// Returns the next UDP over IPv4 datagram in the capture, skipping everything else.
+    pub fn next_udp(&mut self) -> Result<Option<UdpDatagram>, PcapError> {
+        while let Some(frame) = self.next_frame()? {
+            if let Some((src, dst, payload)) = parse_udp(frame.link_type, &frame.data) {
+                return Ok(Some(UdpDatagram {
+                    timestamp: frame.timestamp,
+                    src,
+                    dst,
+                    payload: payload.to_vec(),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_pcapng_frame(&mut self) -> Result<Option<Frame>, PcapError> {
+        loop {
+            let mut header = [0u8; 8];
+            if !read_exact_or_eof(&mut self.r, &mut header)? {
+                return Ok(None);
+            }
+            if u32::from_be_bytes(header[..4].try_into().unwrap()) == PCAPNG_SHB {
+                self.read_section_header_body(header[4..].try_into().unwrap())?;
+                continue;
+            }
+            let Format::PcapNg { big_endian, ref mut interfaces, ref mut last_timestamp } =
+                self.format
+            else {
+                unreachable!()
+            };
+            let block_type = read_u32(&header, big_endian);
+            let total_len = read_u32(&header[4..], big_endian) as usize;
+            if total_len < 12 || !total_len.is_multiple_of(4) {
+                return Err(PcapError::Format("bad block length"));
+            }
+            if !matches!(block_type, PCAPNG_IDB | PCAPNG_EPB | PCAPNG_SPB) {
+                io::copy(&mut (&mut self.r).take((total_len - 8) as u64), &mut io::sink())?;
+                continue;
+            }
+            if total_len > MAX_FRAME_LEN + MAX_BLOCK_OVERHEAD {
+                return Err(PcapError::Format("block too long"));
+            }
+            let mut body = vec![0u8; total_len - 8];
+            self.r.read_exact(&mut body)?;
+            // Drop the trailing copy of the length.
+            body.truncate(total_len - 12);
+
+            match block_type {
+                PCAPNG_IDB => {
+                    if body.len() < 8 {
+                        return Err(PcapError::Format("short interface description block"));
+                    }
+                    let link_type = read_u16(&body, big_endian) as u32;
+                    let mut ts_units = 1_000_000;
+                    for (code, value) in options(&body[8..], big_endian) {
+                        // if_tsresol
+                        if code == 9 && value.len() == 1 {
+                            let exp = (value[0] & 0x7F) as u32;
+                            ts_units = if value[0] & 0x80 == 0 {
+                                10u64.checked_pow(exp)
+                            } else {
+                                2u64.checked_pow(exp)
+                            }
+                            .ok_or(PcapError::Format("bad timestamp resolution"))?;
+                        }
+                    }
+                    interfaces.push(Interface { link_type, ts_units });
+                }
+                PCAPNG_EPB => {
+                    if body.len() < 20 {
+                        return Err(PcapError::Format("short enhanced packet block"));
+                    }
+                    let intf = read_u32(&body, big_endian) as usize;
+                    let ts = (read_u32(&body[4..], big_endian) as u64) << 32
+                        | read_u32(&body[8..], big_endian) as u64;
+                    let cap_len = read_u32(&body[12..], big_endian) as usize;
+                    let Some(intf) = interfaces.get(intf) else {
+                        return Err(PcapError::Format("unknown interface"));
+                    };
+                    let data = body
+                        .get(20..20 + cap_len)
+                        .ok_or(PcapError::Format("bad captured length"))?;
+                    let nanos = ts as u128 * 1_000_000_000 / intf.ts_units as u128;
+                    let timestamp = UNIX_EPOCH
+                        + Duration::new(
+                            (nanos / 1_000_000_000) as u64,
+                            (nanos % 1_000_000_000) as u32,
+                        );
+                    *last_timestamp = timestamp;
+                    return Ok(Some(Frame {
+                        timestamp,
+                        link_type: intf.link_type,
+                        data: data.to_vec(),
+                    }));
+                }
+                PCAPNG_SPB => {
+                    let Some(intf) = interfaces.first() else {
+                        return Err(PcapError::Format("unknown interface"));
+                    };
+                    if body.len() < 4 {
+                        return Err(PcapError::Format("short simple packet block"));
+                    }
+                    let orig_len = read_u32(&body, big_endian) as usize;
+                    let data = &body[4..];
+                    // Simple packet blocks have no timestamp so they're given the previous
+                    // packet's.
+                    return Ok(Some(Frame {
+                        timestamp: *last_timestamp,
+                        link_type: intf.link_type,
+                        data: data[..orig_len.min(data.len())].to_vec(),
+                    }));
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Reads the section header block after its type has been read.
+    fn read_section_header(&mut self) -> Result<(), PcapError> {
+        let mut len = [0u8; 4];
+        self.r.read_exact(&mut len)?;
+        self.read_section_header_body(len)
+    }
+
+    fn read_section_header_body(&mut self, len: [u8; 4]) -> Result<(), PcapError> {
+        let mut magic = [0u8; 4];
+        self.r.read_exact(&mut magic)?;
+        let big_endian = match u32::from_be_bytes(magic) {
+            PCAPNG_BYTE_ORDER_MAGIC => true,
+            m if m.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => false,
+            _ => return Err(PcapError::Format("bad byte order magic")),
+        };
+        let total_len = read_u32(&len, big_endian) as usize;
+        if total_len < 28 || !total_len.is_multiple_of(4) {
+            return Err(PcapError::Format("bad block length"));
+        }
+        // Skip the rest of the block; nothing in it is needed.
+        io::copy(&mut (&mut self.r).take((total_len - 12) as u64), &mut io::sink())?;
+        // Interfaces are scoped to the section.
+        self.format = Format::PcapNg {
+            big_endian,
+            interfaces: Vec::new(),
+            last_timestamp: UNIX_EPOCH,
+        };
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<UdpDatagram, PcapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_udp().transpose()
+    }
+}
+
+/// Pulls the source and destination addresses and payload out of a UDP over IPv4 frame.
+/// Returns `None` for anything else, including IP fragments.
+pub fn parse_udp(link_type: u32, data: &[u8]) -> Option<(SocketAddrV4, SocketAddrV4, &[u8])> {
+    let ip = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes(data.get(12..14)?.try_into().unwrap());
+            let mut off = 14;
+            // Skip any VLAN tags.
+            while ethertype == 0x8100 || ethertype == 0x88A8 {
+                ethertype = u16::from_be_bytes(data.get(off + 2..off + 4)?.try_into().unwrap());
+                off += 4;
+            }
+            if ethertype != 0x0800 {
+                return None;
+            }
+            &data[off..]
+        }
+        LINKTYPE_LINUX_SLL => {
+            if data.get(14..16)? != [0x08, 0x00] {
+                return None;
+            }
+            &data[16..]
+        }
+        LINKTYPE_LINUX_SLL2 => {
+            if data.get(0..2)? != [0x08, 0x00] || data.len() < 20 {
+                return None;
+            }
+            &data[20..]
+        }
+        LINKTYPE_NULL => {
+            // The family is in the capturing host's byte order; AF_INET is 2 everywhere.
+            let family = data.get(0..4)?;
+            if family != [2, 0, 0, 0] && family != [0, 0, 0, 2] {
+                return None;
+            }
+            &data[4..]
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 => data,
+        _ => return None,
+    };
+
+    if ip.first()? >> 4 != 4 {
+        return None;
+    }
+    let ihl = (ip[0] & 0x0F) as usize * 4;
+    if ihl < 20 || ip.len() < ihl {
+        return None;
+    }
+    let total_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+    let flags_frag = u16::from_be_bytes([ip[6], ip[7]]);
+    // More fragments set or a non-zero offset.
+    if flags_frag & 0x3FFF != 0 || ip[9] != 17 {
+        return None;
+    }
+    let src_ip = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+    let dst_ip = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+    let udp = ip.get(ihl..total_len.min(ip.len()))?;
+    if udp.len() < 8 {
+        return None;
+    }
+    let src_port = u16::from_be_bytes([udp[0], udp[1]]);
+    let dst_port = u16::from_be_bytes([udp[2], udp[3]]);
+    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    let payload = udp.get(8..udp_len)?;
+    Some((
+        SocketAddrV4::new(src_ip, src_port),
+        SocketAddrV4::new(dst_ip, dst_port),
+        payload,
+    ))
+}
+
+/// A message replayed from a capture.
+#[derive(Clone)]
+pub struct ReplayedMessage {
+    /// When the packet carrying the message was captured.
+    pub timestamp: SystemTime,
+    pub src: SocketAddrV4,
+    pub seq_num: u64,
+    pub block: MessageBlock,
+}
+
+/// What happened during a replay.
+#[derive(Clone, Debug, Default)]
+pub struct ReplayStats {
+    /// Downstream packets that matched the filters, including heartbeats.
+    pub packets: u64,
+    /// Messages delivered to the handler.
+    pub messages: u64,
+    /// Messages dropped because they had already been seen.
+    pub duplicates: u64,
+    /// Datagrams that matched the filters but weren't valid downstream packets.
+    pub malformed: u64,
+    /// Ranges of sequence numbers that were never seen and were skipped over.
+    pub gaps: Vec<Range<u64>>,
+    /// Whether an end of session packet was seen.
+    pub session_ended: bool,
+}
+
+#[derive(Clone)]
+pub struct ReplayOptions {
+    session: Option<SessionId>,
+    sequence_number: u64,
+    group: Option<Ipv4Addr>,
+    port: Option<u16>,
+    max_pending: usize,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            session: None,
+            sequence_number: 1,
+            group: None,
+            port: None,
+            max_pending: DEFAULT_REPLAY_MAX_PENDING,
+        }
+    }
+}
+
+impl ReplayOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only packets for the session are replayed. If not set, the session of the first packet
+    /// is used.
+    pub fn with_session(mut self, session: SessionId) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// The sequence number of the first message expected. Defaults to 1.
+    pub fn with_sequence_number(mut self, seq_num: u64) -> Self {
+        self.sequence_number = seq_num;
+        self
+    }
+
+    /// Only datagrams sent to the group (destination address) are replayed.
+    pub fn with_group(mut self, group: Ipv4Addr) -> Self {
+        self.group = Some(group);
+        self
+    }
+
+    /// Only datagrams sent to the port are replayed.
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// How many messages can be buffered behind a gap before the gap is given up on and
+    /// skipped. Gaps still open at the end of the capture are always skipped.
+    pub fn with_max_pending(mut self, max: usize) -> Self {
+        self.max_pending = max;
+        self
+    }
+
+    pub fn session(&self) -> Option<SessionId> {
+        self.session
+    }
+
+    pub fn sequence_number(&self) -> u64 {
+        self.sequence_number
+    }
+
+    pub fn group(&self) -> Option<Ipv4Addr> {
+        self.group
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    pub fn max_pending(&self) -> usize {
+        self.max_pending
+    }
+
+    pub fn replay_file(
+        self,
+        path: impl AsRef<Path>,
+        handler: impl FnMut(ReplayedMessage),
+    ) -> Result<ReplayStats, PcapError> {
+        self.replay(PcapReader::open(path)?, handler)
+    }
+
+    /// Replays the capture, calling `handler` for every message in sequence order. Since there
+    /// is no one to re-request from, gaps are skipped (and recorded in the stats) once they
+    /// can't be filled by later packets, such as ones captured from the other line.
+    pub fn replay<R: Read>(
+        self,
+        mut reader: PcapReader<R>,
+        mut handler: impl FnMut(ReplayedMessage),
+    ) -> Result<ReplayStats, PcapError> {
+        let mut stats = ReplayStats::default();
+        let mut session = self.session;
+        let mut seqr = Sequencer::new(self.sequence_number);
+        let mut messages = 0;
+        let mut deliver = |seq_num, (timestamp, src, block)| {
+            messages += 1;
+            handler(ReplayedMessage { timestamp, src, seq_num, block });
+        };
+
+        while let Some(dgram) = reader.next_udp()? {
+            if self.group.map(|g| g != *dgram.dst.ip()).unwrap_or(false)
+                || self.port.map(|p| p != dgram.dst.port()).unwrap_or(false)
+            {
+                continue;
+            }
+            let Ok(packet) = DownstreamPacketRef::parse(&dgram.payload) else {
+                stats.malformed += 1;
+                continue;
+            };
+            let header = packet.header();
+            if *session.get_or_insert(header.session()) != header.session() {
+                continue;
+            }
+            stats.packets += 1;
+
+            let start = header.sequence_number();
+            if header.is_heartbeat() || header.is_end_session() {
+                stats.session_ended |= header.is_end_session();
+                seqr.observe(start);
+                continue;
+            }
+            for (seq_num, data) in (start..).zip(packet.message_blocks()) {
+                if seqr.contains(seq_num) {
+                    stats.duplicates += 1;
+                    continue;
+                }
+                let item = (dgram.timestamp, dgram.src, MessageBlock::from_data(data));
+                seqr.push(seq_num, item, &mut deliver);
+            }
+            while seqr.pending_len() > self.max_pending {
+                stats.gaps.extend(seqr.skip_gap(&mut deliver));
+            }
+        }
+        while seqr.pending_len() != 0 {
+            stats.gaps.extend(seqr.skip_gap(&mut deliver));
+        }
+        if seqr.has_gap() {
+            stats.gaps.push(seqr.next_seq_num()..seqr.high_seq_num());
+        }
+        stats.messages = messages;
+        Ok(stats)
+    }
+}
+
+fn read_exact_or_eof(r: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(IoError::from(IoErrorKind::UnexpectedEof)),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == IoErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Iterates over pcapng options, stopping at the end of options marker.
+fn options(mut b: &[u8], big_endian: bool) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if b.len() < 4 {
+            return None;
+        }
+        let code = read_u16(b, big_endian);
+        let len = read_u16(&b[2..], big_endian) as usize;
+        if code == 0 {
+            return None;
+        }
+        let value = b.get(4..4 + len)?;
+        b = b.get(4 + len.div_ceil(4) * 4..).unwrap_or(&[]);
+        Some((code, value))
+    })
+}
+
+fn read_u16(b: &[u8], big_endian: bool) -> u16 {
+    let b = b[..2].try_into().unwrap();
+    if big_endian {
+        u16::from_be_bytes(b)
+    } else {
+        u16::from_le_bytes(b)
+    }
+}
+
+fn read_u32(b: &[u8], big_endian: bool) -> u32 {
+    let b = b[..4].try_into().unwrap();
+    if big_endian {
+        u32::from_be_bytes(b)
+    } else {
+        u32::from_le_bytes(b)
+    }
+}
+
+#[derive(Debug)]
+pub enum PcapError {
+    /// The capture is malformed or not a pcap/pcapng file.
+    Format(&'static str),
+    Io(IoError),
+}
+
+impl From<IoError> for PcapError {
+    fn from(e: IoError) -> Self {
+        PcapError::Io(e)
+    }
+}
+
+impl fmt::Display for PcapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PcapError::Format(msg) => write!(f, "bad capture: {msg}"),
+            PcapError::Io(ref e) => write!(f, "io error: {e}"),
+        }
+    }
+}
+
+impl Error for PcapError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn pcap(snaplen: u32, incl_len: u32) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend(PCAP_MAGIC_MICROS.to_le_bytes());
+        b.extend([2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        b.extend(snaplen.to_le_bytes());
+        b.extend(LINKTYPE_RAW.to_le_bytes());
+        b.extend([0; 8]);
+        b.extend(incl_len.to_le_bytes());
+        b.extend(incl_len.to_le_bytes());
+        b.extend(vec![0; incl_len.min(64) as usize]);
+        b
+    }
+
+    fn pcapng_block(block_type: u32, total_len: u32, body: &[u8]) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend(block_type.to_le_bytes());
+        b.extend(total_len.to_le_bytes());
+        b.extend(body);
+        b
+    }
+
+    fn pcapng(blocks: &[Vec<u8>]) -> Vec<u8> {
+        let mut b = pcapng_block(PCAPNG_SHB, 28, &PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        b.extend([1, 0, 0, 0]);
+        b.extend(u64::MAX.to_le_bytes());
+        b.extend(28u32.to_le_bytes());
+        // Ethernet interface.
+        b.extend(pcapng_block(PCAPNG_IDB, 20, &[1, 0, 0, 0, 0, 0, 0, 0, 20, 0, 0, 0]));
+        b.extend(blocks.concat());
+        b
+    }
+
+    #[test]
+    fn pcap_frame_len() {
+        let mut r = PcapReader::new(Cursor::new(pcap(64, 64))).unwrap();
+        assert_eq!(r.next_frame().unwrap().unwrap().data.len(), 64);
+        assert!(r.next_frame().unwrap().is_none());
+
+        let mut r = PcapReader::new(Cursor::new(pcap(64, 65))).unwrap();
+        assert!(matches!(r.next_frame(), Err(PcapError::Format(_))));
+        let mut r = PcapReader::new(Cursor::new(pcap(0, u32::MAX))).unwrap();
+        assert!(matches!(r.next_frame(), Err(PcapError::Format(_))));
+    }
+
+    #[test]
+    fn pcapng_block_len() {
+        let epb = pcapng_block(PCAPNG_EPB, u32::MAX - 3, &[0; 20]);
+        let mut r = PcapReader::new(Cursor::new(pcapng(&[epb]))).unwrap();
+        assert!(matches!(r.next_frame(), Err(PcapError::Format(_))));
+
+        // Blocks that aren't needed are skipped without being read into memory.
+        let mut nrb = pcapng_block(4, 1 << 20, &[]);
+        nrb.resize(1 << 20, 0);
+        let mut epb = pcapng_block(PCAPNG_EPB, 40, &[0; 12]);
+        epb.extend(8u32.to_le_bytes());
+        epb.extend(8u32.to_le_bytes());
+        epb.extend([0xAB; 8]);
+        epb.extend(40u32.to_le_bytes());
+        let mut r = PcapReader::new(Cursor::new(pcapng(&[nrb, epb]))).unwrap();
+        assert_eq!(r.next_frame().unwrap().unwrap().data, [0xAB; 8]);
+        assert!(r.next_frame().unwrap().is_none());
+    }
+
+    /// An IPv4 UDP datagram from 10.0.0.1:1000 to `dst`.
+    fn ipv4_udp(dst: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+        let udp_len = 8 + payload.len() as u16;
+        let mut b = vec![0x45, 0];
+        b.extend((20 + udp_len).to_be_bytes());
+        b.extend([0, 0, 0x40, 0, 64, 17, 0, 0, 10, 0, 0, 1]);
+        b.extend(dst.ip().octets());
+        b.extend(1000u16.to_be_bytes());
+        b.extend(dst.port().to_be_bytes());
+        b.extend(udp_len.to_be_bytes());
+        b.extend([0, 0]);
+        b.extend(payload);
+        b
+    }
+
+    /// A pcap capture of raw IPv4 frames, one a second.
+    fn capture(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut b = pcap(65535, 0);
+        b.truncate(24);
+        for (i, frame) in frames.iter().enumerate() {
+            b.extend((i as u32).to_le_bytes());
+            b.extend(0u32.to_le_bytes());
+            b.extend((frame.len() as u32).to_le_bytes());
+            b.extend((frame.len() as u32).to_le_bytes());
+            b.extend(frame);
+        }
+        b
+    }
+
+    #[test]
+    fn parse_udp_link_types() {
+        let src = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1000);
+        let dst = SocketAddrV4::new(Ipv4Addr::new(239, 1, 2, 3), 2000);
+        let ip = ipv4_udp(dst, b"data");
+        let framed = |prefix: &[u8]| [prefix, &ip].concat();
+
+        let mut eth = vec![0; 12];
+        eth.extend([0x08, 0x00]);
+        let mut vlan = vec![0; 12];
+        vlan.extend([0x81, 0x00, 0, 5, 0x88, 0xA8, 0, 6, 0x08, 0x00]);
+        let mut sll = vec![0; 14];
+        sll.extend([0x08, 0x00]);
+        let mut sll2 = vec![0x08, 0x00];
+        sll2.extend([0; 18]);
+        let cases = [
+            (LINKTYPE_ETHERNET, framed(&eth)),
+            (LINKTYPE_ETHERNET, framed(&vlan)),
+            (LINKTYPE_LINUX_SLL, framed(&sll)),
+            (LINKTYPE_LINUX_SLL2, framed(&sll2)),
+            (LINKTYPE_NULL, framed(&[2, 0, 0, 0])),
+            (LINKTYPE_NULL, framed(&[0, 0, 0, 2])),
+            (LINKTYPE_RAW, ip.clone()),
+            (LINKTYPE_IPV4, ip.clone()),
+        ];
+        for (link_type, frame) in &cases {
+            let got = parse_udp(*link_type, frame);
+            assert_eq!(got, Some((src, dst, &b"data"[..])), "link type {link_type}");
+        }
+
+        // Not IPv4, a fragment, and an unknown link type.
+        eth[13] = 0xDD;
+        assert_eq!(parse_udp(LINKTYPE_ETHERNET, &framed(&eth)), None);
+        let mut frag = ip.clone();
+        frag[6] = 0x20;
+        assert_eq!(parse_udp(LINKTYPE_RAW, &frag), None);
+        assert_eq!(parse_udp(LINKTYPE_NULL, &framed(&[24, 0, 0, 0])), None);
+        assert_eq!(parse_udp(2, &ip), None);
+    }
+
+    #[test]
+    fn replay() {
+        let (a, b) = (SessionId::new_trunc("A"), SessionId::new_trunc("B"));
+        let group = SocketAddrV4::new(Ipv4Addr::new(239, 0, 0, 1), 5000);
+        let other_port = SocketAddrV4::new(*group.ip(), 6000);
+        let other_group = SocketAddrV4::new(Ipv4Addr::new(239, 0, 0, 2), 5000);
+        let packet = |session, seq_num: u64, count: u64| {
+            let blocks = (seq_num..seq_num + count)
+                .map(|n| MessageBlock::new(vec![n as u8]).unwrap())
+                .collect();
+            DownstreamPacket::new(Header::new(session, seq_num, 0), blocks).unwrap().serialize()
+        };
+        let frames = [
+            ipv4_udp(group, &packet(a, 1, 1)),
+            ipv4_udp(other_port, &packet(a, 2, 1)),
+            ipv4_udp(other_group, &packet(a, 2, 1)),
+            ipv4_udp(group, &packet(b, 2, 1)),
+            ipv4_udp(group, b"garbage"),
+            // Opens a gap at 2, filled after a duplicate of 1.
+            ipv4_udp(group, &packet(a, 3, 2)),
+            ipv4_udp(group, &packet(a, 1, 1)),
+            ipv4_udp(group, &packet(a, 2, 1)),
+            // 5 is never seen.
+            ipv4_udp(group, &packet(a, 6, 1)),
+            ipv4_udp(group, Header::end_session(a, 7).as_slice()),
+        ];
+
+        let reader = PcapReader::new(Cursor::new(capture(&frames))).unwrap();
+        let mut got = Vec::new();
+        let stats = ReplayOptions::new()
+            .with_group(*group.ip())
+            .with_port(group.port())
+            .replay(reader, |msg| got.push((msg.seq_num, msg.block[0], msg.timestamp)))
+            .unwrap();
+
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(
+            got,
+            [(1, 1, at(0)), (2, 2, at(7)), (3, 3, at(5)), (4, 4, at(5)), (6, 6, at(8))]
+        );
+        assert_eq!(
+            (stats.packets, stats.messages, stats.duplicates, stats.malformed),
+            (6, 5, 1, 1)
+        );
+        assert_eq!(stats.gaps, vec![5..6]);
+        assert!(stats.session_ended);
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+/// A token bucket that refills at `rate` tokens per second up to `burst` tokens.
+pub(crate) struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+
+    /// How many whole tokens are available.
+    pub(crate) fn available(&mut self, now: Instant) -> u64 {
+        self.refill(now);
+        self.tokens.max(0.0).floor() as u64
+    }
+
+    /// Takes `n` tokens even if there aren't that many, leaving the bucket in debt.
+    pub(crate) fn spend(&mut self, n: u64, now: Instant) {
+        self.refill(now);
+        self.tokens -= n as f64;
+    }
+
+    /// How long until `n` tokens are available, or the bucket is full if it can't hold that
+    /// many.
+    pub(crate) fn delay(&mut self, n: u64, now: Instant) -> Duration {
+        self.refill(now);
+        let need = (n as f64).min(self.burst);
+        if self.tokens >= need {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((need - self.tokens) / self.rate)
+    }
+
+    /// Whether the bucket has been refilled all the way since it was last used.
+    pub(crate) fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens + elapsed * self.rate >= self.burst
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn refill_and_debt() {
+        let ms = Duration::from_millis;
+        let mut b = TokenBucket::new(4.0, 2.0);
+        let start = b.last;
+        assert_eq!(b.available(start), 2);
+        assert!(b.is_full(start));
+        b.spend(2, start);
+        assert_eq!(b.available(start), 0);
+        assert_eq!(b.delay(1, start), ms(250));
+        assert_eq!(b.delay(1, start + ms(125)), ms(125));
+        assert!(!b.is_full(start + ms(250)));
+        assert!(b.is_full(start + ms(500)));
+        // More than the burst only waits for a full bucket.
+        assert_eq!(b.delay(10, start + ms(250)), ms(250));
+
+        // Only refills up to the burst, and can go into debt.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(b.available(later), 2);
+        b.spend(3, later);
+        assert_eq!(b.available(later), 0);
+        assert_eq!(b.delay(1, later), ms(500));
+    }
+}
@@ -1,13 +1,74 @@
-use std::io;
-use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::time::Duration;
+use super::arbitrated::ArbitratedReceiver;
+use super::liveness::Liveness;
+use super::recovery::*;
+use super::sequencer::*;
+use super::session::*;
+use super::socket::SocketOptions;
+use super::stats::{ReceiverStats, StatsState};
+use super::store::{ArcMessageStore, MessageStore};
 use super::types::*;
 
-pub type PacketHandler = Arc<dyn Fn(SocketADdr, DownstreamPacket) + Send + Sync + 'static>;
+use jtutils::atomic_value::{Ordering, AAV};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Error as IoError, ErrorKind as IoErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Called once for every message block, in sequence order, with the address the block came from
+/// and the block's sequence number.
+pub type PacketHandler = Arc<dyn Fn(SocketAddr, u64, MessageBlock) + Send + Sync + 'static>;
+/// Called with events that aren't messages, in order with the calls to the packet handler.
+pub type EventHandler = Arc<dyn Fn(ReceiverEvent) + Send + Sync + 'static>;
+
+/// Something that happened to the stream other than a message arriving.
+#[derive(Clone, Debug)]
+pub enum ReceiverEvent {
+    /// The receiver switched sessions (see `SessionPolicy`), or adopted its first session, in
+    /// which case `from` is `None`. `next_seq_num` is where delivery restarts.
+    SessionChanged {
+        from: Option<SessionId>,
+        to: SessionId,
+        next_seq_num: u64,
+    },
+    /// The server ended the session. Unless following sessions, the receiver then closes with
+    /// `ReceiverError::SessionEnded`.
+    SessionEnded {
+        session: SessionId,
+        next_seq_num: u64,
+    },
+    /// Messages `start..end` were skipped over by a packet.
+    GapOpened { start: u64, end: u64 },
+    /// All the messages in a gap were received, `latency` after it was opened.
+    GapFilled {
+        start: u64,
+        end: u64,
+        latency: Duration,
+    },
+    /// No packets, not even heartbeats, have arrived for the options' server timeout. If set to
+    /// close on timeout, the receiver then closes with `ReceiverError::ServerTimedOut`.
+    ServerStale { silent_for: Duration },
+    /// Packets started arriving again after the feed went stale.
+    ServerRecovered { silent_for: Duration },
+    /// A heartbeat (or end of session) showed the server sent messages `start..end` after the
+    /// last ones received. This opens a gap like `GapOpened` does.
+    TailGap { start: u64, end: u64 },
+    /// More messages were buffered than the options' max pending while waiting on messages
+    /// `start..end`, so those were skipped and counted as lost.
+    GapAbandoned { start: u64, end: u64 },
+}
 
 pub const DEFAULT_BUFFER_SIZE: usize = 4096;
+/// The default number of messages buffered while waiting on a gap before it is given up on.
+pub const DEFAULT_MAX_PENDING: usize = 1 << 16;
+/// The default time a gap is given to be filled by another line before it is re-requested.
+pub const DEFAULT_ARBITRATION_WINDOW: Duration = Duration::from_millis(5);
+/// How often the listening thread wakes up to check if it should stop when no packets are
+/// coming in.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Clone)]
 pub struct ReceiverOptions {
@@ -15,21 +76,41 @@ pub struct ReceiverOptions {
     sequence_number: u64,
     request_addrs: Vec<SocketAddr>,
     auto_rerequest: bool,
+    request_timeout: Duration,
+    max_request_retries: u32,
+    max_request_messages: u16,
+    max_pending: usize,
+    arbitration_window: Duration,
     server_timeout: Duration,
-    multicast_interface: Ipv4Addr,
+    close_on_server_timeout: bool,
+    socket: SocketOptions,
     buffer_size: usize,
+    session_policy: SessionPolicy,
+    late_join: LateJoin,
+    event_handler: Option<EventHandler>,
+    journal: Option<ArcMessageStore>,
 }
 
 impl Default for ReceiverOptions {
     fn default() -> Self {
         Self {
             session: SessionId::default(),
-            sequence_number: 0,
+            sequence_number: 1,
             request_addrs: Vec::new(),
             auto_rerequest: false,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_request_retries: DEFAULT_MAX_REQUEST_RETRIES,
+            max_request_messages: DEFAULT_MAX_REQUEST_MESSAGES,
+            max_pending: DEFAULT_MAX_PENDING,
+            arbitration_window: DEFAULT_ARBITRATION_WINDOW,
             server_timeout: Duration::from_secs(0),
-            multicast_interface: Ipv4Addr::UNSPECIFIED,
+            close_on_server_timeout: false,
+            socket: SocketOptions::default(),
             buffer_size: DEFAULT_BUFFER_SIZE,
+            session_policy: SessionPolicy::default(),
+            late_join: LateJoin::default(),
+            event_handler: None,
+            journal: None,
         }
     }
 }
@@ -44,18 +125,45 @@ impl ReceiverOptions {
         self
     }
 
+    /// The sequence number of the first message to deliver. Defaults to 1.
     pub fn with_sequence_number(mut self, seq_num: u64) -> Self {
         self.sequence_number = seq_num;
         self
     }
 
+    /// How sessions other than the one set with `with_session` are handled. Defaults to
+    /// `SessionPolicy::Fixed`.
+    pub fn with_session_policy(mut self, policy: SessionPolicy) -> Self {
+        self.session_policy = policy;
+        self
+    }
+
+    /// Where delivery starts when joining a stream (or a new session) that's already going.
+    /// Defaults to `LateJoin::Recover`.
+    pub fn with_late_join(mut self, late_join: LateJoin) -> Self {
+        self.late_join = late_join;
+        self
+    }
+
+    pub fn with_event_handler(mut self, handler: EventHandler) -> Self {
+        self.event_handler = Some(handler);
+        self
+    }
+
+    /// Writes every message to the store just before it's delivered, so the stream can be
+    /// replayed or served to others later. If writing fails, the receiver closes with the error.
+    pub fn with_journal(mut self, store: impl MessageStore + 'static) -> Self {
+        self.journal = Some(Arc::new(store));
+        self
+    }
+
     pub fn with_request_addrs(mut self, addrs: Vec<SocketAddr>) -> Self {
         self.request_addrs = addrs;
         self
     }
 
     pub fn add_request_addr(mut self, addr: SocketAddr) -> Self {
-        self.request_addr.push(addr);
+        self.request_addrs.push(addr);
         self
     }
 
@@ -64,13 +172,65 @@ impl ReceiverOptions {
         self
     }
 
+    /// How long to wait for a retransmission request to be answered before asking the next
+    /// request server.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// How many times a retransmission request is retried before the messages are considered
+    /// lost.
+    pub fn with_max_request_retries(mut self, retries: u32) -> Self {
+        self.max_request_retries = retries;
+        self
+    }
+
+    /// The maximum number of messages asked for in a single request. Larger gaps are split up.
+    pub fn with_max_request_messages(mut self, num: u16) -> Self {
+        self.max_request_messages = num;
+        self
+    }
+
+    /// The most messages buffered while waiting on a gap to be filled. Past that, the gap is
+    /// given up on (see `ReceiverEvent::GapAbandoned`) so memory stays bounded when messages
+    /// can't be recovered. Zero means no limit.
+    pub fn with_max_pending(mut self, num: usize) -> Self {
+        self.max_pending = num;
+        self
+    }
+
+    /// How long a gap on one line is given to be filled by another line before it is
+    /// re-requested. Only used when receiving on more than one line.
+    pub fn with_arbitration_window(mut self, window: Duration) -> Self {
+        self.arbitration_window = window;
+        self
+    }
+
+    /// How long the server can go without sending anything, heartbeats included, before a
+    /// `ReceiverEvent::ServerStale` is sent. Zero (the default) disables the check.
     pub fn with_server_timeout(mut self, timeout: Duration) -> Self {
         self.server_timeout = timeout;
         self
     }
 
+    /// Close with `ReceiverError::ServerTimedOut` instead of just sending an event when the
+    /// server times out.
+    pub fn with_close_on_server_timeout(mut self, b: bool) -> Self {
+        self.close_on_server_timeout = b;
+        self
+    }
+
+    /// Shorthand for setting the socket options' IPv4 multicast interface.
     pub fn with_multicast_interface(mut self, intf: Ipv4Addr) -> Self {
-        self.multicast_interface = intf;
+        self.socket = self.socket.with_multicast_interface_v4(intf);
+        self
+    }
+
+    /// Options for the sockets created by `connect`. The multicast interfaces are also used
+    /// for sockets passed to `build_with_socket`.
+    pub fn with_socket_options(mut self, opts: SocketOptions) -> Self {
+        self.socket = opts;
         self
     }
 
@@ -93,45 +253,153 @@ impl ReceiverOptions {
         &self.request_addrs
     }
 
+    pub fn session_policy(&self) -> SessionPolicy {
+        self.session_policy
+    }
+
+    pub fn late_join(&self) -> LateJoin {
+        self.late_join
+    }
+
+    pub fn event_handler(&self) -> Option<&EventHandler> {
+        self.event_handler.as_ref()
+    }
+
+    pub fn journal(&self) -> Option<&ArcMessageStore> {
+        self.journal.as_ref()
+    }
+
     pub fn auto_rerequest(&self) -> bool {
         self.auto_rerequest
     }
 
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    pub fn max_request_retries(&self) -> u32 {
+        self.max_request_retries
+    }
+
+    pub fn max_request_messages(&self) -> u16 {
+        self.max_request_messages
+    }
+
+    pub fn max_pending(&self) -> usize {
+        self.max_pending
+    }
+
+    pub fn arbitration_window(&self) -> Duration {
+        self.arbitration_window
+    }
+
     pub fn server_timeout(&self) -> Duration {
         self.server_timeout
     }
 
-    pub fn multicast_interface(self) -> Ipv4Addr {
-        self.multicast_interface
+    pub fn close_on_server_timeout(&self) -> bool {
+        self.close_on_server_timeout
+    }
+
+    pub fn multicast_interface(&self) -> Ipv4Addr {
+        self.socket.multicast_interface_v4()
+    }
+
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.socket
     }
 
     pub fn buffer_size(&self) -> usize {
         self.buffer_size
     }
 
+    /// Binds to the port of `addr` and joins the multicast group at its IP. If the IP isn't a
+    /// multicast group, binds to `addr` and receives unicast packets instead.
     pub fn connect(
         self,
-        multiaddr: Ipv4Addr,
+        addr: impl Into<SocketAddr>,
         handler: PacketHandler,
-    ) -> Result<Reciever, ReceiverError> {
-        let conn = UdpSocket::bind("0.0.0.0:0")?;
-        self.build_with_socket(conn, multiaddr, handler)
+    ) -> Result<Receiver, ReceiverError> {
+        let addr = addr.into();
+        let conn = self.socket.bind_receiver(addr)?;
+        self.build_with_socket(conn, addr.ip(), handler)
     }
 
+    /// Joins the group on the socket unless it isn't a multicast address.
     pub fn build_with_socket(
         self,
         conn: UdpSocket,
-        multiaddr: Ipv4Addr,
+        group: impl Into<IpAddr>,
         handler: PacketHandler,
     ) -> Result<Receiver, ReceiverError> {
-        conn.join_multicast_v4(multiaddr, &self.multicast_interface)?;
+        let inner = self.build_inner(vec![(conn, group.into())], handler)?;
+        Ok(Receiver(inner))
+    }
+
+    /// Binds to the ports of both lines and joins both multicast groups. Each sequence number is
+    /// delivered once, from whichever line it arrives on first.
+    pub fn connect_arbitrated(
+        self,
+        line_a: impl Into<SocketAddr>,
+        line_b: impl Into<SocketAddr>,
+        handler: PacketHandler,
+    ) -> Result<ArbitratedReceiver, ReceiverError> {
+        let (line_a, line_b) = (line_a.into(), line_b.into());
+        let conn_a = self.socket.bind_receiver(line_a)?;
+        let conn_b = self.socket.bind_receiver(line_b)?;
+        self.build_arbitrated_with_sockets((conn_a, line_a.ip()), (conn_b, line_b.ip()), handler)
+    }
+
+    /// Retransmission requests are sent on the first line's socket.
+    pub fn build_arbitrated_with_sockets(
+        self,
+        line_a: (UdpSocket, IpAddr),
+        line_b: (UdpSocket, IpAddr),
+        handler: PacketHandler,
+    ) -> Result<ArbitratedReceiver, ReceiverError> {
+        let inner = self.build_inner(vec![line_a, line_b], handler)?;
+        Ok(ArbitratedReceiver(inner))
+    }
 
+    /// Joins the multicast group (if any) on every line and starts a listening thread for each.
+    pub(crate) fn build_inner(
+        self,
+        conns: Vec<(UdpSocket, IpAddr)>,
+        handler: PacketHandler,
+    ) -> Result<Arc<InnerReceiver>, ReceiverError> {
+        let mut lines = Vec::with_capacity(conns.len());
+        for (conn, group) in conns {
+            self.socket.join(&conn, group)?;
+            conn.set_read_timeout(Some(POLL_INTERVAL))?;
+            lines.push(Line { conn, group });
+        }
+
+        let state = State::new(
+            StateOptions {
+                session: self.session,
+                sequence_number: self.sequence_number,
+                session_policy: self.session_policy,
+                late_join: self.late_join,
+                max_pending: self.max_pending,
+                journal: self.journal.clone(),
+            },
+            RecoveryManager::new(
+                self.request_addrs.clone(),
+                self.request_timeout,
+                self.max_request_retries,
+                self.max_request_messages,
+            ),
+            Liveness::new(self.server_timeout, Instant::now()),
+            lines.len(),
+        );
         let inner = Arc::new(InnerReceiver {
-            conn,
+            lines,
+            handler,
+            state: Mutex::new(state),
+            dispatch: Mutex::new(()),
 
-            //last_server_msg: todo!(),
-            curr_seq_num: AtomicU64::new(todo!()),
-            next_expected_seq_num: AtomicU64::new(0),
+            curr_seq_num: AtomicU64::new(self.sequence_number),
+            next_expected_seq_num: AtomicU64::new(self.sequence_number),
             auto_rerequest: AtomicBool::new(self.auto_rerequest),
             buffer_size: AtomicUsize::new(self.buffer_size),
 
@@ -139,12 +407,12 @@ impl ReceiverOptions {
 
             opts: self,
         });
-        //
-        std::thread::spawn(async move {
-            inner2.listen_packets();
-        });
+        for line in 0..inner.lines.len() {
+            let weak = Arc::downgrade(&inner);
+            thread::spawn(move || InnerReceiver::listen_packets(weak, line));
+        }
 
-        Ok(Receiver(inner))
+        Ok(inner)
     }
 }
 
@@ -157,22 +425,22 @@ impl Receiver {
     }
 
     pub fn connect(
-        multiaddr: Ipv4Addr,
+        addr: impl Into<SocketAddr>,
         packet_handler: PacketHandler,
     ) -> Result<Self, ReceiverError> {
-        Self::options().connect(multiaddr, packet_handler)
+        Self::options().connect(addr, packet_handler)
     }
 
     pub fn build_with_socket(
         conn: UdpSocket,
-        multiaddr: Ipv4Addr,
+        group: impl Into<IpAddr>,
         handler: PacketHandler,
     ) -> Result<Self, ReceiverError> {
-        Self::options().build_with_socket(conn, multiaddr, handler)
+        Self::options().build_with_socket(conn, group, handler)
     }
 
     pub fn request_messages(&self, start: u64, num: u16) -> io::Result<()> {
-        self.0.request_message(start, num)
+        self.0.request_messages(start, num)
     }
 
     pub fn request_messages_from(
@@ -180,7 +448,26 @@ impl Receiver {
         addr: impl ToSocketAddrs,
         start: u64, num: u16,
     ) -> io::Result<()> {
-        self.0.request_message_from(addr, start, num)
+        self.0.request_messages_from(addr, start, num)
+    }
+
+    /// The session being received, if one has been adopted yet.
+    pub fn session(&self) -> Option<SessionId> {
+        self.0.session()
+    }
+
+    /// The sequence number of the next message that will be passed to the handler.
+    pub fn curr_seq_num(&self) -> u64 {
+        self.0.curr_seq_num()
+    }
+
+    pub fn stats(&self) -> ReceiverStats {
+        self.0.stats()
+    }
+
+    /// One past the highest sequence number the server is known to have sent.
+    pub fn next_expected_seq_num(&self) -> u64 {
+        self.0.next_expected_seq_num()
     }
 
     pub fn auto_rerequest(&self) -> bool {
@@ -202,28 +489,306 @@ impl Receiver {
     pub fn opts(&self) -> &ReceiverOptions {
         self.0.opts()
     }
+
+    /// Stops the receiver. The listening thread exits the next time it wakes up.
+    pub fn close(&self) {
+        self.0.close_with_err(ReceiverError::Closed);
+    }
+
+    pub fn close_err(&self) -> Option<ArcReceiverError> {
+        self.0.close_err()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
 }
 
-struct InnerReceiver {
-    conn: UdpSocket,
+pub(crate) struct InnerReceiver {
+    lines: Vec<Line>,
     opts: ReceiverOptions,
+    handler: PacketHandler,
+    state: Mutex<State>,
+    /// Held while calling the handlers, which is done without holding `state`.
+    dispatch: Mutex<()>,
 
-    //last_server_msg: AV<Instant>,
     curr_seq_num: AtomicU64,
     next_expected_seq_num: AtomicU64,
     auto_rerequest: AtomicBool,
     buffer_size: AtomicUsize,
 
-    close_err: AAV<ReceiverERror>,
+    close_err: AAV<ReceiverError>,
+}
+
+/// Something to be passed to a handler.
+pub(crate) enum Dispatch {
+    Event(ReceiverEvent),
+    Block(u64, MessageBlock),
+}
+
+struct Line {
+    conn: UdpSocket,
+    group: IpAddr,
+}
+
+/// The options `State` needs, taken from either receiver's options.
+pub(crate) struct StateOptions {
+    pub(crate) session: SessionId,
+    pub(crate) sequence_number: u64,
+    pub(crate) session_policy: SessionPolicy,
+    pub(crate) late_join: LateJoin,
+    pub(crate) max_pending: usize,
+    pub(crate) journal: Option<ArcMessageStore>,
+}
+
+/// Everything a receiver keeps track of about the stream. Both the sync and async receivers
+/// feed their packets through `State::packet`.
+pub(crate) struct State {
+    opts: StateOptions,
+    pub(crate) seqr: Sequencer<MessageBlock>,
+    pub(crate) recovery: RecoveryManager,
+    /// When the current gap (if any) was first seen.
+    gap_since: Option<Instant>,
+    lines: Vec<LineState>,
+    pub(crate) session: SessionState,
+    /// Whether a packet has been accepted yet.
+    started: bool,
+    pub(crate) liveness: Liveness,
+    pub(crate) stats: StatsState,
+}
+
+impl State {
+    pub(crate) fn new(
+        opts: StateOptions,
+        recovery: RecoveryManager,
+        liveness: Liveness,
+        lines: usize,
+    ) -> Self {
+        Self {
+            seqr: Sequencer::new(opts.sequence_number),
+            recovery,
+            gap_since: None,
+            lines: vec![LineState::new(opts.sequence_number); lines],
+            session: SessionState::new(opts.session, opts.session_policy),
+            started: false,
+            liveness,
+            stats: StatsState::default(),
+            opts,
+        }
+    }
+
+    /// Updates the state with a packet that arrived on `line`, collecting what's to be passed on
+    /// (in order) in `out`.
+    pub(crate) fn packet(
+        &mut self,
+        line: usize,
+        len: usize,
+        packet: DownstreamPacketRef<'_>,
+        now: Instant,
+        out: &mut Vec<Dispatch>,
+    ) -> Result<(), ReceiverError> {
+        self.stats.packet(len);
+        if let Some(silent_for) = self.liveness.packet(now) {
+            out.push(Dispatch::Event(ReceiverEvent::ServerRecovered { silent_for }));
+        }
+        let header = packet.header();
+        let start = header.sequence_number();
+        match self.session.check(header.session()) {
+            SessionCheck::Accept => (),
+            SessionCheck::Ignore => return Ok(()),
+            SessionCheck::Unexpected { want } => {
+                return Err(ReceiverError::UnexpectedSession {
+                    want,
+                    got: header.session(),
+                });
+            }
+            SessionCheck::Changed { from } => {
+                if from.is_some() || self.opts.late_join == LateJoin::Live {
+                    self.restart(start);
+                }
+                self.started = true;
+                out.push(Dispatch::Event(ReceiverEvent::SessionChanged {
+                    from,
+                    to: header.session(),
+                    next_seq_num: self.seqr.next_seq_num(),
+                }));
+            }
+        }
+        if !self.started {
+            self.started = true;
+            if self.opts.late_join == LateJoin::Live {
+                self.restart(start);
+            }
+        }
+
+        let line_state = &mut self.lines[line];
+        line_state.stats.packets += 1;
+        if start > line_state.next_seq_num {
+            line_state.stats.dropped += start - line_state.next_seq_num;
+        }
+        if header.is_heartbeat() || header.is_end_session() {
+            line_state.next_seq_num = line_state.next_seq_num.max(start);
+            let high = self.seqr.high_seq_num();
+            if start > high {
+                self.stats.gap_opened(high..start, now);
+                out.push(Dispatch::Event(ReceiverEvent::TailGap { start: high, end: start }));
+            }
+            self.seqr.observe(start);
+            if header.is_end_session() && self.session.end() {
+                out.push(Dispatch::Event(ReceiverEvent::SessionEnded {
+                    session: header.session(),
+                    next_seq_num: start,
+                }));
+                if self.opts.session_policy != SessionPolicy::Follow {
+                    return Err(ReceiverError::SessionEnded);
+                }
+            }
+        } else {
+            let blocks = packet.message_blocks();
+            let count = blocks.len() as u64;
+            line_state.next_seq_num = line_state.next_seq_num.max(start.saturating_add(count));
+            line_state.stats.messages += count;
+            let high = self.seqr.high_seq_num();
+            if start > high {
+                self.stats.gap_opened(high..start, now);
+                out.push(Dispatch::Event(ReceiverEvent::GapOpened { start: high, end: start }));
+            }
+            let store = &self.opts.journal;
+            let mut journal_err = None;
+            for (seq_num, data) in (start..).zip(blocks) {
+                // Only messages that haven't been seen are copied out of the buffer.
+                if self.seqr.contains(seq_num) {
+                    line_state.stats.duplicates += 1;
+                    self.stats.duplicate();
+                    continue;
+                }
+                line_state.stats.first += 1;
+                if seq_num < self.seqr.high_seq_num() {
+                    self.stats.recovered();
+                }
+                let stats = &mut self.stats;
+                self.seqr.push(seq_num, MessageBlock::from_data(data), |seq_num, block| {
+                    if journal_err.is_none() {
+                        journal_err = journal(store, header.session(), seq_num, &block).err();
+                    }
+                    stats.delivered();
+                    out.push(Dispatch::Block(seq_num, block));
+                });
+            }
+            let max_pending = self.opts.max_pending;
+            while max_pending != 0 && self.seqr.pending_len() > max_pending {
+                let stats = &mut self.stats;
+                let skipped = self.seqr.skip_gap(|seq_num, block| {
+                    if journal_err.is_none() {
+                        journal_err = journal(store, header.session(), seq_num, &block).err();
+                    }
+                    stats.delivered();
+                    out.push(Dispatch::Block(seq_num, block));
+                });
+                let Some(skipped) = skipped else { break };
+                out.push(Dispatch::Event(ReceiverEvent::GapAbandoned {
+                    start: skipped.start,
+                    end: skipped.end,
+                }));
+                self.stats.abandon_gap(skipped);
+            }
+            self.stats.gaps_filled(self.seqr.next_seq_num(), now, |gap, latency| {
+                out.push(Dispatch::Event(ReceiverEvent::GapFilled {
+                    start: gap.start,
+                    end: gap.end,
+                    latency,
+                }));
+            });
+            if let Some(e) = journal_err {
+                return Err(ReceiverError::Io(e));
+            }
+        }
+        if !self.seqr.has_gap() {
+            self.gap_since = None;
+        } else if self.gap_since.is_none() {
+            self.gap_since = Some(now);
+        }
+        Ok(())
+    }
+
+    /// Drops all sequencing state so delivery starts over, from `live_seq_num` if joining live
+    /// and from the options' sequence number otherwise.
+    fn restart(&mut self, live_seq_num: u64) {
+        let next_seq_num = match self.opts.late_join {
+            LateJoin::Live => live_seq_num,
+            LateJoin::Recover => self.opts.sequence_number,
+        };
+        self.stats.abandon_gaps(&self.seqr.missing());
+        self.seqr.reset(next_seq_num);
+        self.recovery.clear();
+        self.gap_since = None;
+        for line in &mut self.lines {
+            line.next_seq_num = next_seq_num;
+        }
+    }
+}
+
+/// Writes the block to the journal, if there is one.
+fn journal(
+    journal: &Option<ArcMessageStore>,
+    session: SessionId,
+    seq_num: u64,
+    block: &MessageBlock,
+) -> io::Result<()> {
+    match journal {
+        Some(journal) => journal.insert_blocks(session, seq_num, std::slice::from_ref(block)),
+        None => Ok(()),
+    }
+}
+
+#[derive(Clone)]
+struct LineState {
+    /// One past the highest sequence number seen on the line.
+    next_seq_num: u64,
+    stats: LineStats,
+}
+
+impl LineState {
+    fn new(next_seq_num: u64) -> Self {
+        Self {
+            next_seq_num,
+            stats: LineStats::default(),
+        }
+    }
+}
+
+/// Statistics for a single line (multicast group) of a receiver.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LineStats {
+    /// Downstream packets received, including heartbeats.
+    pub packets: u64,
+    /// Messages received.
+    pub messages: u64,
+    /// Messages that arrived on this line before any other.
+    pub first: u64,
+    /// Messages that were dropped because they had already been received.
+    pub duplicates: u64,
+    /// Messages this line skipped over, whether or not another line had them.
+    pub dropped: u64,
 }
 
 impl InnerReceiver {
-    fn request_messages(&self, start: u64, num: u16) -> io::Result<()> {
-        let pkt = RequestPacket::new(self.opts.session, start, num);
-        let mut res = Ok(())
-        for addr in &self.socket_addrs {
+    pub(crate) fn session(&self) -> Option<SessionId> {
+        self.state.lock().unwrap().session.current()
+    }
+
+    fn request_session(&self) -> SessionId {
+        self.session().unwrap_or(self.opts.session)
+    }
+
+    pub(crate) fn request_messages(&self, start: u64, num: u16) -> io::Result<()> {
+        let pkt = RequestPacket::new(self.request_session(), start, num);
+        let mut res = Err(IoError::new(
+            IoErrorKind::InvalidInput, "no request address specified",
+        ));
+        for addr in &self.opts.request_addrs {
             // NOTE: "partial write are not possible until buffer sizes above i32::MAX."
-            res = self.conn.send_to(pkt.as_slice(), addr).map(|_| ());
+            res = self.lines[0].conn.send_to(pkt.as_slice(), addr).map(|_| ());
             if res.is_ok() {
                 break;
             }
@@ -231,121 +796,250 @@ impl InnerReceiver {
         res
     }
 
-    fn request_messages_from(
+    pub(crate) fn request_messages_from(
         &self,
         addr: impl ToSocketAddrs,
         start: u64, num: u16,
     ) -> io::Result<()> {
-        let pkt = RequestPacket::new(self.opts.session, start, num);
+        let pkt = RequestPacket::new(self.request_session(), start, num);
         // NOTE: "partial write are not possible until buffer sizes above i32::MAX."
-        self.conn.send_to(pkt.as_slice(), addr).map(|_| ())
+        self.lines[0].conn.send_to(pkt.as_slice(), addr).map(|_| ())
+    }
+
+    pub(crate) fn curr_seq_num(&self) -> u64 {
+        self.curr_seq_num.load(Ordering::SeqCst)
     }
 
-    fn auto_rerequest(&self) -> bool {
+    pub(crate) fn next_expected_seq_num(&self) -> u64 {
+        self.next_expected_seq_num.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn auto_rerequest(&self) -> bool {
         self.auto_rerequest.load(Ordering::Relaxed)
     }
 
-    fn set_auto_rerequest(&self, b: bool) {
+    pub(crate) fn set_auto_rerequest(&self, b: bool) {
         self.auto_rerequest.store(b, Ordering::Relaxed);
     }
 
-    fn buffer_size(&self) -> usize {
+    pub(crate) fn buffer_size(&self) -> usize {
         self.buffer_size.load(Ordering::Relaxed)
     }
 
-    fn set_buffer_size(&self, size: usize) {
+    pub(crate) fn set_buffer_size(&self, size: usize) {
         self.buffer_size.store(
             if size != 0 { size } else { DEFAULT_BUFFER_SIZE },
             Ordering::Relaxed,
         );
     }
 
-    fn opts(&self) -> &ReceiverOptions {
+    pub(crate) fn opts(&self) -> &ReceiverOptions {
         &self.opts
     }
 
-    fn close_with_err(&self, err: impl Into<ReceiverError>) -> ArcReceiverError {
+    pub(crate) fn stats(&self) -> ReceiverStats {
+        let state = self.state.lock().unwrap();
+        state.stats.snapshot(self.curr_seq_num(), self.next_expected_seq_num())
+    }
+
+    pub(crate) fn line_stats(&self) -> Vec<LineStats> {
+        self.state.lock().unwrap().lines.iter().map(|l| l.stats).collect()
+    }
+
+    pub(crate) fn close_err(&self) -> Option<ArcReceiverError> {
+        self.close_err.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        !self.close_err.is_empty(Ordering::Relaxed)
+    }
+
+    pub(crate) fn close_with_err(&self, err: impl Into<ReceiverError>) -> ArcReceiverError {
         let _ = self.close_err.store_if_empty(err.into(), Ordering::Relaxed, Ordering::Relaxed);
         self.close_err.load(Ordering::Relaxed).unwrap()
     }
 
-    fn listen_packets(self: Arc<Self>) {
-        // TODO: finish (rerequests/counting)
+    /// Listens for packets on the given line until the receiver is closed or dropped.
+    /// Retransmission requests are sent (and so are answered) on the first line, so its thread
+    /// also takes care of recovery.
+    fn listen_packets(weak: Weak<Self>, line: usize) {
+        let mut b = Vec::new();
         loop {
-            let mut b = vec![0u8; self.buffer_size()];
-            let (bytes, addr) = match ac.recv_from(&mut b) {
-                Ok((0, _)) => {
+            // Only held for one read, which times out within the poll interval.
+            let Some(inner) = weak.upgrade() else {
+                break;
+            };
+            if inner.is_closed() {
+                break;
+            }
+            let conn = &inner.lines[line].conn;
+            if line == 0 {
+                if let Err(lost) = inner.recover() {
+                    inner.state.lock().unwrap().stats.lost(lost.end - lost.start);
+                    inner.close_with_err(ReceiverError::MessagesLost {
+                        start: lost.start,
+                        count: lost.end - lost.start,
+                    });
                     break;
                 }
-                Ok((n, addr)) => &b[..n],
+                if let Err(e) = inner.check_liveness() {
+                    inner.close_with_err(e);
+                    break;
+                }
+            }
+            if b.len() != inner.buffer_size() {
+                b.resize(inner.buffer_size(), 0);
+            }
+            let (bytes, addr) = match conn.recv_from(&mut b) {
+                Ok((n, addr)) => (&b[..n], addr),
+                Err(e) if is_timeout(&e) => continue,
                 Err(e) => {
-                    self.close_with_err(e);
+                    inner.close_with_err(e);
                     break;
                 }
             };
-            let packet = match DownstreamPacket::parse(&b) {
+            let packet = match DownstreamPacketRef::parse(bytes) {
                 Ok(p) => p,
-                // TODO
-                Err(_) => continue,
+                Err(_) => {
+                    inner.state.lock().unwrap().stats.malformed();
+                    continue;
+                }
             };
-            if packet.header.session() != self.opts.session {
-                self.close_with_err(ReceiverError::UnexpectedSession {
-                    want: self.opts.session,
-                    got: packet.header.session(),
-                });
-                break;
-            } else if packet.header.is_heartbeat() {
-                self.next_expected_seq_num.store(
-                    packet.header.sequence_number(),
-                    Ordering::SeqCst,
-                );
-            } else if packet.header.is_end_session() {
-                self.next_expected_seq_num.store(
-                    packet.header.sequence_number(),
-                    Ordering::SeqCst,
-                );
-                self.close_with_err(ReceiverError::SessionEnded);
-                // TODO
-                (packet_handler)(addr, packet);
+            if let Err(e) = inner.handle_packet(line, addr, bytes.len(), packet) {
+                inner.close_with_err(e);
                 break;
-            } else {
-                let seq_num = packet.header.sequence_number();
-                let next = self.curr_seq_num.load(Ordering::SeqCst) + 1;
-                let next_ex = self.next_expected_seq_num.load(Ordering::SeqCst);
-                if seq_num == next {
-                } else {
-                }
             }
+        }
+    }
 
-            (packet_handler)(addr, packet);
+    fn handle_packet(
+        &self,
+        line: usize,
+        addr: SocketAddr,
+        len: usize,
+        packet: DownstreamPacketRef<'_>,
+    ) -> Result<(), ReceiverError> {
+        // Held while dispatching so the lines' threads call the handlers in order. It's taken
+        // before the state lock so the handlers can call methods that lock the state.
+        let _dispatch = self.dispatch.lock().unwrap();
+        let mut out = Vec::new();
+        let res = self.sequence_packet(line, len, packet, &mut out);
+        for d in out {
+            match d {
+                Dispatch::Event(event) => self.send_event(event),
+                Dispatch::Block(seq_num, block) => (self.handler)(addr, seq_num, block),
+            }
         }
+        res
     }
 
-    fn run_auto_rerequest(&self, start: u64, end: u64) -> io::Result<()> {
-        if !self.auto_rerequest.load(Ordering::SeqCst) {
+    /// Updates the state with the packet, collecting what's to be passed to the handlers in
+    /// `out`.
+    fn sequence_packet(
+        &self,
+        line: usize,
+        len: usize,
+        packet: DownstreamPacketRef<'_>,
+        out: &mut Vec<Dispatch>,
+    ) -> Result<(), ReceiverError> {
+        let mut state = self.state.lock().unwrap();
+        // Checked again now that the lock is held so nothing is delivered after closing.
+        if self.is_closed() {
             return Ok(());
-        } else if start > end {
+        }
+        let res = state.packet(line, len, packet, Instant::now(), out);
+        self.curr_seq_num.store(state.seqr.next_seq_num(), Ordering::SeqCst);
+        self.next_expected_seq_num.store(state.seqr.high_seq_num(), Ordering::SeqCst);
+        res
+    }
+
+    fn send_event(&self, event: ReceiverEvent) {
+        if let Some(handler) = &self.opts.event_handler {
+            handler(event);
+        }
+    }
+
+    /// Sends any retransmission requests needed for the current gaps and sets the read timeout
+    /// so the listening thread wakes up in time to retry them.
+    fn recover(&self) -> Result<(), std::ops::Range<u64>> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let recovery = &mut state.recovery;
+        if !recovery.has_requests() && (!self.auto_rerequest() || !state.seqr.has_gap()) {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if self.auto_rerequest() {
+            let mut missing = state.seqr.missing();
+            // With more than one line, give the other lines a chance to fill a gap before asking
+            // for a retransmission.
+            let waited = state
+                .gap_since
+                .map(|t| now.saturating_duration_since(t) >= self.opts.arbitration_window)
+                .unwrap_or(true);
+            if self.lines.len() > 1 && !waited {
+                let all_past = state.lines.iter().map(|l| l.next_seq_num).min().unwrap_or(0);
+                missing.retain(|range| range.start < all_past);
+                for range in &mut missing {
+                    range.end = range.end.min(all_past);
+                }
+            }
+            let conn = &self.lines[0].conn;
+            let session = state.session.current().unwrap_or(self.opts.session);
+            recovery.poll(now, &missing, |addr, start, num| {
+                let pkt = RequestPacket::new(session, start, num);
+                // An error here just means the request times out and is sent elsewhere.
+                let _ = conn.send_to(pkt.as_slice(), addr);
+            })?;
+        } else {
+            recovery.clear();
+        }
+        let timeout = match recovery.next_deadline() {
+            Some(deadline) => deadline
+                .saturating_duration_since(now)
+                .clamp(Duration::from_millis(1), POLL_INTERVAL),
+            None => POLL_INTERVAL,
+        };
+        let _ = self.lines[0].conn.set_read_timeout(Some(timeout));
+        Ok(())
+    }
+
+    /// Sends a `ServerStale` event if nothing has arrived within the server timeout, failing if
+    /// the receiver should close because of it.
+    fn check_liveness(&self) -> Result<(), ReceiverError> {
+        let _dispatch = self.dispatch.lock().unwrap();
+        let silent_for = self.state.lock().unwrap().liveness.poll(Instant::now());
+        let Some(silent_for) = silent_for else {
             return Ok(());
+        };
+        self.send_event(ReceiverEvent::ServerStale { silent_for });
+        if self.opts.close_on_server_timeout {
+            return Err(ReceiverError::ServerTimedOut);
         }
-        let num = end - start;
-        self.request_messages(start, num)
+        Ok(())
     }
 }
 
 impl Drop for InnerReceiver {
     fn drop(&mut self) {
-        let _ = conn.leave_multicast_v4(&self.multiaddr, &self.opts.multicast_interface);
-        // This should trigger a read timeout error in the listening thread, which will cause it to
-        // exit.
-        let _ = conn.set_read_timeout(Some(Duration::from_nanos(1)));
+        for line in &self.lines {
+            let _ = self.opts.socket.leave(&line.conn, line.group);
+        }
     }
 }
 
+pub(crate) fn is_timeout(e: &IoError) -> bool {
+    matches!(e.kind(), IoErrorKind::WouldBlock | IoErrorKind::TimedOut)
+}
+
 #[derive(Debug)]
 pub enum ReceiverError {
     UnexpectedSession { want: SessionId, got: SessionId },
     ServerTimedOut,
     SessionEnded,
+    /// Retransmission requests for the messages went unanswered.
+    MessagesLost { start: u64, count: u64 },
+    Closed,
     Io(IoError),
 }
 pub type ArcReceiverError = Arc<ReceiverError>;
@@ -364,7 +1058,123 @@ impl fmt::Display for ReceiverError {
             }
             ReceiverError::ServerTimedOut => write!(f, "server timed out"),
             ReceiverError::SessionEnded => write!(f, "session ended"),
+            ReceiverError::MessagesLost { start, count } => {
+                write!(f, "lost {count} message(s) starting at sequence number {start}")
+            }
+            ReceiverError::Closed => write!(f, "receiver closed"),
             ReceiverError::Io(ref e) => write!(f, "io error: {e}"),
         }
     }
 }
+
+impl Error for ReceiverError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use std::sync::OnceLock;
+
+    #[test]
+    fn handlers_can_call_receiver() {
+        let rx: Arc<OnceLock<Receiver>> = Arc::new(OnceLock::new());
+        let (tx, seen) = mpsc::channel();
+        let (rx2, rx3) = (Arc::clone(&rx), Arc::clone(&rx));
+        let events_tx = tx.clone();
+        let session = SessionId::new_trunc("A");
+        let receiver = Receiver::options()
+            .with_session(session)
+            .with_event_handler(Arc::new(move |_| {
+                if let Some(rx) = rx3.get() {
+                    let _ = events_tx.send(rx.stats().messages);
+                }
+            }))
+            .connect(
+                "127.0.0.1:0".parse::<SocketAddr>().unwrap(),
+                Arc::new(move |_, _, _| {
+                    let rx = rx2.get().unwrap();
+                    let _ = tx.send(rx.stats().messages);
+                    let _ = rx.session();
+                }),
+            )
+            .unwrap();
+        let addr = receiver.0.lines[0].conn.local_addr().unwrap();
+        let _ = rx.set(receiver);
+
+        let send = |seq_num: u64| {
+            let block = MessageBlock::new(vec![seq_num as u8]).unwrap();
+            let pkt = DownstreamPacket::new(Header::new(session, seq_num, 0), vec![block]).unwrap();
+            UdpSocket::bind("127.0.0.1:0").unwrap().send_to(&pkt.serialize(), addr).unwrap();
+        };
+        send(1);
+        // Opens a gap, so the event handler is called too.
+        send(3);
+        for _ in 0..2 {
+            seen.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+    }
+
+    fn state(policy: SessionPolicy, late_join: LateJoin) -> State {
+        let opts = StateOptions {
+            session: SessionId::BLANK,
+            sequence_number: 1,
+            session_policy: policy,
+            late_join,
+            max_pending: DEFAULT_MAX_PENDING,
+            journal: None,
+        };
+        let recovery = RecoveryManager::new(Vec::new(), DEFAULT_REQUEST_TIMEOUT, 0, 1);
+        State::new(opts, recovery, Liveness::new(Duration::ZERO, Instant::now()), 1)
+    }
+
+    /// Feeds `state` a packet of `count` messages, returning the sequence numbers delivered.
+    fn feed(state: &mut State, session: SessionId, seq_num: u64, count: u8) -> Vec<u64> {
+        let blocks = (0..count).map(|i| MessageBlock::new(vec![i]).unwrap()).collect();
+        let pkt = DownstreamPacket::new(Header::new(session, seq_num, 0), blocks).unwrap();
+        let b = pkt.serialize();
+        let mut out = Vec::new();
+        let packet = DownstreamPacketRef::parse(&b).unwrap();
+        state.packet(0, b.len(), packet, Instant::now(), &mut out).unwrap();
+        out.into_iter()
+            .filter_map(|d| match d {
+                Dispatch::Block(seq_num, _) => Some(seq_num),
+                Dispatch::Event(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn late_join() {
+        let (a, b) = (SessionId::new_trunc("A"), SessionId::new_trunc("B"));
+        let mut live = state(SessionPolicy::AdoptFirst, LateJoin::Live);
+        assert_eq!(feed(&mut live, a, 100, 2), vec![100, 101]);
+        assert!(live.seqr.missing().is_empty());
+
+        let mut recover = state(SessionPolicy::AdoptFirst, LateJoin::Recover);
+        assert_eq!(feed(&mut recover, a, 100, 2), vec![]);
+        assert_eq!(recover.seqr.missing(), vec![1..100]);
+        assert_eq!(feed(&mut recover, a, 1, 99).len(), 101);
+
+        // Following a new session starts its sequencing over, counting what's missing as lost.
+        let mut follow = state(SessionPolicy::Follow, LateJoin::Live);
+        assert_eq!(feed(&mut follow, a, 5, 1), vec![5]);
+        assert_eq!(feed(&mut follow, a, 8, 1), vec![]);
+        assert_eq!(feed(&mut follow, b, 3, 1), vec![3]);
+        assert_eq!(follow.session.current(), Some(b));
+        assert_eq!(feed(&mut follow, a, 6, 2), vec![]);
+        assert_eq!(follow.stats.snapshot(0, 0).lost, 2);
+    }
+
+    #[test]
+    fn drop_stops_threads() {
+        let receiver = Receiver::connect(
+            "127.0.0.1:0".parse::<SocketAddr>().unwrap(),
+            Arc::new(|_, _, _| {}),
+        )
+        .unwrap();
+        let weak = Arc::downgrade(&receiver.0);
+        drop(receiver);
+        thread::sleep(POLL_INTERVAL * 3);
+        assert!(weak.upgrade().is_none());
+    }
+}
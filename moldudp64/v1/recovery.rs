@@ -0,0 +1,212 @@
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+pub const DEFAULT_MAX_REQUEST_RETRIES: u32 = 5;
+/// The default maximum number of messages asked for in a single request packet. Keeping this
+/// small means the response is likely to fit in a single downstream packet.
+pub const DEFAULT_MAX_REQUEST_MESSAGES: u16 = 64;
+
+/// Keeps track of outstanding retransmission requests for the gaps in a stream.
+///
+/// Gaps are split into chunks of at most `max_messages` messages and each new chunk is sent to
+/// the next request server in round-robin order. A chunk that isn't filled within `timeout` is
+/// re-requested from the next server in the list, and is given up on once it has been retried
+/// `max_retries` times.
+pub(crate) struct RecoveryManager {
+    addrs: Vec<SocketAddr>,
+    next_addr: usize,
+    timeout: Duration,
+    max_retries: u32,
+    max_messages: u16,
+    requests: Vec<Request>,
+}
+
+struct Request {
+    range: Range<u64>,
+    addr_idx: usize,
+    sent_at: Instant,
+    retries: u32,
+}
+
+impl RecoveryManager {
+    pub(crate) fn new(
+        addrs: Vec<SocketAddr>,
+        timeout: Duration,
+        max_retries: u32,
+        max_messages: u16,
+    ) -> Self {
+        Self {
+            addrs,
+            next_addr: 0,
+            timeout,
+            max_retries,
+            max_messages: max_messages.max(1),
+            requests: Vec::new(),
+        }
+    }
+
+    pub(crate) fn has_requests(&self) -> bool {
+        !self.requests.is_empty()
+    }
+
+    /// The earliest time an outstanding request will time out.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.requests.iter().map(|r| r.sent_at + self.timeout).min()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.requests.clear();
+    }
+
+    /// Updates the outstanding requests against the currently missing ranges. `send` is called
+    /// with the address, first sequence number, and message count of every request that needs
+    /// to be (re)sent. If a request has run out of retries, the range it was for is returned as
+    /// an error.
+    pub(crate) fn poll(
+        &mut self,
+        now: Instant,
+        missing: &[Range<u64>],
+        mut send: impl FnMut(SocketAddr, u64, u16),
+    ) -> Result<(), Range<u64>> {
+        if self.addrs.is_empty() {
+            return Ok(());
+        }
+
+        // Drop filled requests and shrink partially filled ones down to what's still missing.
+        self.requests.retain_mut(|req| match intersect(&req.range, missing) {
+            Some(range) => {
+                req.range = range;
+                true
+            }
+            None => false,
+        });
+
+        // Retry timed out requests with the next server.
+        for req in &mut self.requests {
+            if now < req.sent_at + self.timeout {
+                continue;
+            }
+            if req.retries >= self.max_retries {
+                return Err(req.range.clone());
+            }
+            req.retries += 1;
+            req.addr_idx = (req.addr_idx + 1) % self.addrs.len();
+            req.sent_at = now;
+            send(self.addrs[req.addr_idx], req.range.start, range_len(&req.range));
+        }
+
+        // Request anything that isn't covered yet.
+        for range in missing {
+            let mut start = range.start;
+            while start < range.end {
+                if let Some(req) = self.requests.iter().find(|r| r.range.contains(&start)) {
+                    start = req.range.end;
+                    continue;
+                }
+                let mut end = range.end.min(start + self.max_messages as u64);
+                if let Some(next) = self
+                    .requests
+                    .iter()
+                    .map(|r| r.range.start)
+                    .filter(|&s| s > start)
+                    .min()
+                {
+                    end = end.min(next);
+                }
+                let addr_idx = self.next_addr;
+                self.next_addr = (self.next_addr + 1) % self.addrs.len();
+                send(self.addrs[addr_idx], start, (end - start) as u16);
+                self.requests.push(Request {
+                    range: start..end,
+                    addr_idx,
+                    sent_at: now,
+                    retries: 0,
+                });
+                start = end;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the smallest range covering every part of `range` that is in `missing`.
+fn intersect(range: &Range<u64>, missing: &[Range<u64>]) -> Option<Range<u64>> {
+    let (mut start, mut end) = (u64::MAX, 0);
+    for m in missing {
+        let (s, e) = (m.start.max(range.start), m.end.min(range.end));
+        if s < e {
+            start = start.min(s);
+            end = end.max(e);
+        }
+    }
+    (start < end).then_some(start..end)
+}
+
+fn range_len(range: &Range<u64>) -> u16 {
+    (range.end - range.start).min(u16::MAX as u64) as u16
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod test {
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec!["127.0.0.1:1000".parse().unwrap(), "127.0.0.1:2000".parse().unwrap()]
+    }
+
+    fn poll(
+        rm: &mut RecoveryManager,
+        now: Instant,
+        missing: &[Range<u64>],
+    ) -> Result<Vec<(SocketAddr, u64, u16)>, Range<u64>> {
+        let mut sent = Vec::new();
+        rm.poll(now, missing, |addr, seq, count| sent.push((addr, seq, count)))?;
+        Ok(sent)
+    }
+
+    #[test]
+    fn chunks_round_robin() {
+        let a = addrs();
+        let mut rm = RecoveryManager::new(a.clone(), Duration::from_millis(100), 3, 64);
+        let now = Instant::now();
+        assert_eq!(
+            poll(&mut rm, now, &[1..150]).unwrap(),
+            vec![(a[0], 1, 64), (a[1], 65, 64), (a[0], 129, 21)]
+        );
+        assert_eq!(rm.next_deadline(), Some(now + Duration::from_millis(100)));
+
+        // Already requested ranges aren't requested again, new gaps are.
+        assert_eq!(poll(&mut rm, now, &[1..150, 200..202]).unwrap(), vec![(a[1], 200, 2)]);
+
+        // Filled requests are dropped.
+        assert_eq!(poll(&mut rm, now, &[]).unwrap(), vec![]);
+        assert!(!rm.has_requests());
+        assert_eq!(rm.next_deadline(), None);
+    }
+
+    #[test]
+    fn retry_and_give_up() {
+        let a = addrs();
+        let timeout = Duration::from_millis(100);
+        let mut rm = RecoveryManager::new(a.clone(), timeout, 1, 64);
+        let t0 = Instant::now();
+        assert_eq!(poll(&mut rm, t0, &[5..10]).unwrap(), vec![(a[0], 5, 5)]);
+        assert_eq!(poll(&mut rm, t0 + timeout / 2, &[5..10]).unwrap(), vec![]);
+
+        // A timed out request goes to the next server, shrunk to what's still missing.
+        assert_eq!(poll(&mut rm, t0 + timeout, &[7..10]).unwrap(), vec![(a[1], 7, 3)]);
+        assert_eq!(rm.next_deadline(), Some(t0 + timeout * 2));
+
+        assert_eq!(poll(&mut rm, t0 + timeout * 2, &[7..10]), Err(7..10));
+    }
+
+    #[test]
+    fn no_servers() {
+        let mut rm = RecoveryManager::new(Vec::new(), Duration::from_millis(100), 1, 64);
+        assert_eq!(poll(&mut rm, Instant::now(), &[1..10]).unwrap(), vec![]);
+        assert!(!rm.has_requests());
+    }
+}
@@ -0,0 +1,347 @@
+use super::rate_limit::TokenBucket;
+use super::receiver::{is_timeout, POLL_INTERVAL};
+use super::store::{ArcMessageStore, MessageStore};
+use super::types::*;
+
+use jtutils::atomic_value::{Ordering, AAV};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Error as IoError, ErrorKind as IoErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default number of messages per second a single requester can be sent.
+pub const DEFAULT_REQUESTER_RATE_LIMIT: u32 = 50_000;
+/// The most requesters whose rate limits are tracked at once. Requests from others are ignored
+/// until some go idle.
+const MAX_TRACKED_REQUESTERS: usize = 1024;
+/// How often requesters that have gone idle are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct RequestServerOptions {
+    session: SessionId,
+    mtu: usize,
+    rate_limit: u32,
+}
+
+impl Default for RequestServerOptions {
+    fn default() -> Self {
+        Self {
+            session: SessionId::default(),
+            mtu: DEFAULT_MTU,
+            rate_limit: DEFAULT_REQUESTER_RATE_LIMIT,
+        }
+    }
+}
+
+impl RequestServerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests for any other session are ignored.
+    pub fn with_session(mut self, session: SessionId) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// The maximum size of a response packet, including the IP and UDP headers.
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    /// The maximum number of messages per second sent to a single requester. Requests over the
+    /// limit are cut short (or dropped entirely) and the requester will need to ask again. A
+    /// limit of 0 means no limit.
+    pub fn with_rate_limit(mut self, msgs_per_sec: u32) -> Self {
+        self.rate_limit = msgs_per_sec;
+        self
+    }
+
+    pub fn session(&self) -> SessionId {
+        self.session
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    pub fn rate_limit(&self) -> u32 {
+        self.rate_limit
+    }
+
+    pub fn bind(
+        self,
+        addr: impl ToSocketAddrs,
+        store: impl MessageStore + 'static,
+    ) -> Result<RequestServer, RequestServerError> {
+        let conn = UdpSocket::bind(addr)?;
+        self.build_with_socket(conn, store)
+    }
+
+    pub fn build_with_socket(
+        self,
+        conn: UdpSocket,
+        store: impl MessageStore + 'static,
+    ) -> Result<RequestServer, RequestServerError> {
+        if self.mtu < IP_UDP_HEADER_LEN + HEADER_LEN + 2 {
+            return Err(RequestServerError::Io(IoError::new(
+                IoErrorKind::InvalidInput,
+                "mtu too small to fit a message",
+            )));
+        }
+        conn.set_read_timeout(Some(POLL_INTERVAL))?;
+        let inner = Arc::new(InnerRequestServer {
+            conn,
+            store: Arc::new(store),
+            opts: self,
+            close_err: AAV::empty(),
+        });
+        let weak = Arc::downgrade(&inner);
+        thread::spawn(move || InnerRequestServer::serve(weak));
+        Ok(RequestServer(inner))
+    }
+}
+
+/// Answers MoldUDP64 retransmission requests out of a `MessageStore`.
+#[derive(Clone)]
+pub struct RequestServer(Arc<InnerRequestServer>);
+
+impl RequestServer {
+    pub fn options() -> RequestServerOptions {
+        RequestServerOptions::new()
+    }
+
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        session: SessionId,
+        store: impl MessageStore + 'static,
+    ) -> Result<Self, RequestServerError> {
+        Self::options().with_session(session).bind(addr, store)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.conn.local_addr()
+    }
+
+    pub fn store(&self) -> &ArcMessageStore {
+        &self.0.store
+    }
+
+    pub fn opts(&self) -> &RequestServerOptions {
+        &self.0.opts
+    }
+
+    /// Stops the server. The serving thread exits the next time it wakes up, as it also does
+    /// once every handle to the server is dropped.
+    pub fn close(&self) {
+        self.0.close_with_err(RequestServerError::Closed);
+    }
+
+    pub fn close_err(&self) -> Option<ArcRequestServerError> {
+        self.0.close_err.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+}
+
+struct InnerRequestServer {
+    conn: UdpSocket,
+    store: ArcMessageStore,
+    opts: RequestServerOptions,
+
+    close_err: AAV<RequestServerError>,
+}
+
+impl InnerRequestServer {
+    fn is_closed(&self) -> bool {
+        !self.close_err.is_empty(Ordering::Relaxed)
+    }
+
+    fn close_with_err(&self, err: impl Into<RequestServerError>) -> ArcRequestServerError {
+        let _ = self.close_err.store_if_empty(err.into(), Ordering::Relaxed, Ordering::Relaxed);
+        self.close_err.load(Ordering::Relaxed).unwrap()
+    }
+
+    /// Answers requests until the server is closed or dropped.
+    fn serve(weak: Weak<Self>) {
+        let mut limiters: HashMap<SocketAddr, TokenBucket> = HashMap::new();
+        let mut last_pruned = Instant::now();
+        let mut b = [0u8; HEADER_LEN];
+        loop {
+            // Only held for one read, which times out within the poll interval.
+            let Some(inner) = weak.upgrade() else {
+                break;
+            };
+            if inner.is_closed() {
+                break;
+            }
+            let (n, addr) = match inner.conn.recv_from(&mut b) {
+                Ok(res) => res,
+                Err(e) if is_timeout(&e) => continue,
+                Err(e) => {
+                    inner.close_with_err(e);
+                    break;
+                }
+            };
+            let Ok(req) = RequestPacket::parse(&b[..n]) else {
+                continue;
+            };
+            if req.session() != inner.opts.session || req.message_count() == 0 {
+                continue;
+            }
+
+            let mut count = req.message_count() as u64;
+            let now = Instant::now();
+            let limiter = match inner.opts.rate_limit {
+                0 => None,
+                rate => {
+                    if now.saturating_duration_since(last_pruned) >= PRUNE_INTERVAL {
+                        // A full bucket is the same as a new one, so forgetting it loses nothing.
+                        limiters.retain(|_, l| !l.is_full(now));
+                        last_pruned = now;
+                    }
+                    if limiters.len() >= MAX_TRACKED_REQUESTERS && !limiters.contains_key(&addr) {
+                        continue;
+                    }
+                    let rate = rate as f64;
+                    let limiter = limiters
+                        .entry(addr)
+                        .or_insert_with(|| TokenBucket::new(rate, rate));
+                    count = count.min(limiter.available(now));
+                    Some(limiter)
+                }
+            };
+            if count == 0 {
+                continue;
+            }
+            // A failed read just means the requester will have to ask again.
+            let start = req.sequence_number();
+            let Ok(blocks) = inner.store.range(req.session(), start, count as usize) else {
+                continue;
+            };
+            let sent = inner.respond(addr, start, blocks);
+            // Only what was actually sent is charged for.
+            if let Some(limiter) = limiter {
+                limiter.spend(sent, now);
+            }
+        }
+    }
+
+    /// Sends the blocks to the address in as many packets as needed to stay under the MTU,
+    /// returning how many were sent. A failed send just means the requester will have to ask
+    /// again, so sending stops there.
+    fn respond(&self, addr: SocketAddr, start: u64, blocks: Vec<MessageBlock>) -> u64 {
+        let max_len = self.opts.mtu - IP_UDP_HEADER_LEN;
+        let mut seq_num = start;
+        let mut packet_blocks = Vec::new();
+        let mut packet_len = HEADER_LEN;
+        for block in blocks {
+            let block_len = block.as_slice().len();
+            if packet_len + block_len > max_len {
+                if packet_blocks.is_empty() {
+                    // The block can never fit so stop here and let the requester deal with it.
+                    break;
+                }
+                let count = packet_blocks.len() as u64;
+                if self.send_blocks(addr, seq_num, std::mem::take(&mut packet_blocks)).is_err() {
+                    return seq_num - start;
+                }
+                seq_num += count;
+                packet_len = HEADER_LEN;
+                if packet_len + block_len > max_len {
+                    break;
+                }
+            }
+            packet_len += block_len;
+            packet_blocks.push(block);
+        }
+        let count = packet_blocks.len() as u64;
+        if count != 0 && self.send_blocks(addr, seq_num, packet_blocks).is_ok() {
+            seq_num += count;
+        }
+        seq_num - start
+    }
+
+    fn send_blocks(
+        &self,
+        addr: SocketAddr,
+        seq_num: u64,
+        blocks: Vec<MessageBlock>,
+    ) -> io::Result<()> {
+        let header = Header::new(self.opts.session, seq_num, 0);
+        // The number of blocks is bounded by the request's message count, so this can't fail.
+        let packet = DownstreamPacket::new(header, blocks).unwrap();
+        self.conn.send_to(&packet.serialize(), addr).map(|_| ())
+    }
+}
+
+#[derive(Debug)]
+pub enum RequestServerError {
+    Closed,
+    Io(IoError),
+}
+pub type ArcRequestServerError = Arc<RequestServerError>;
+
+impl From<IoError> for RequestServerError {
+    fn from(e: IoError) -> Self {
+        RequestServerError::Io(e)
+    }
+}
+
+impl fmt::Display for RequestServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestServerError::Closed => write!(f, "request server closed"),
+            RequestServerError::Io(ref e) => write!(f, "io error: {e}"),
+        }
+    }
+}
+
+impl Error for RequestServerError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::v1::store::MemoryStore;
+
+    #[test]
+    fn drop_stops_thread() {
+        let server = RequestServer::bind("127.0.0.1:0", SessionId::BLANK, MemoryStore::new())
+            .unwrap();
+        let weak = Arc::downgrade(&server.0);
+        drop(server);
+        thread::sleep(POLL_INTERVAL * 3);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn charges_only_for_sent() {
+        let session = SessionId::new_trunc("A");
+        let store = MemoryStore::new();
+        let blocks = [MessageBlock::from_data(b"a"), MessageBlock::from_data(b"b")];
+        store.insert_blocks(session, 1, &blocks).unwrap();
+        let server = RequestServer::options()
+            .with_session(session)
+            .with_rate_limit(5)
+            .bind("127.0.0.1:0", store)
+            .unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut b = [0u8; 1500];
+        for _ in 0..2 {
+            let req = RequestPacket::new(session, 1, 5);
+            client.send_to(req.as_slice(), server.local_addr().unwrap()).unwrap();
+            let n = client.recv(&mut b).unwrap();
+            let packet = DownstreamPacket::parse(&b[..n]).unwrap();
+            assert_eq!(packet.message_blocks().len(), 2);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// The result of pushing a message into a [`Sequencer`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Sequenced {
+    /// The message was the next expected and was delivered (possibly along with buffered
+    /// messages that followed it).
+    Delivered,
+    /// The message is ahead of the next expected and was buffered until the gap is filled.
+    Buffered,
+    /// The message was already delivered or buffered and was dropped.
+    Duplicate,
+}
+
+/// Puts messages back into sequence order. Messages that arrive ahead of the next expected
+/// sequence number are buffered until the gap before them is filled.
+pub struct Sequencer<T> {
+    next_seq_num: u64,
+    high_seq_num: u64,
+    pending: BTreeMap<u64, T>,
+}
+
+impl<T> Sequencer<T> {
+    /// Creates a sequencer that will deliver `next_seq_num` first.
+    pub fn new(next_seq_num: u64) -> Self {
+        Self {
+            next_seq_num,
+            high_seq_num: next_seq_num,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// The sequence number of the next message to be delivered.
+    pub fn next_seq_num(&self) -> u64 {
+        self.next_seq_num
+    }
+
+    /// One past the highest sequence number seen so far.
+    pub fn high_seq_num(&self) -> u64 {
+        self.high_seq_num
+    }
+
+    /// The number of messages buffered waiting on a gap.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn has_gap(&self) -> bool {
+        self.next_seq_num < self.high_seq_num
+    }
+
+    /// Whether the message has already been delivered or buffered, in which case pushing it
+    /// again would be a duplicate.
+    pub fn contains(&self, seq_num: u64) -> bool {
+        seq_num < self.next_seq_num || self.pending.contains_key(&seq_num)
+    }
+
+    /// Records that messages up to (but not including) `seq_num` exist without delivering
+    /// anything.
+    pub fn observe(&mut self, seq_num: u64) {
+        self.high_seq_num = self.high_seq_num.max(seq_num);
+    }
+
+    /// Pushes the message with the given sequence number, calling `deliver` for every message
+    /// that is now in order.
+    pub fn push(&mut self, seq_num: u64, item: T, mut deliver: impl FnMut(u64, T)) -> Sequenced {
+        if self.contains(seq_num) {
+            return Sequenced::Duplicate;
+        }
+        self.observe(seq_num.saturating_add(1));
+        if seq_num > self.next_seq_num {
+            self.pending.insert(seq_num, item);
+            return Sequenced::Buffered;
+        }
+        deliver(seq_num, item);
+        self.next_seq_num += 1;
+        self.drain(deliver);
+        Sequenced::Delivered
+    }
+
+    /// Skips ahead to `seq_num`, dropping anything buffered before it, and delivers anything
+    /// buffered that is now in order.
+    pub fn skip_to(&mut self, seq_num: u64, deliver: impl FnMut(u64, T)) {
+        if seq_num <= self.next_seq_num {
+            return;
+        }
+        self.pending = self.pending.split_off(&seq_num);
+        self.next_seq_num = seq_num;
+        self.observe(seq_num);
+        self.drain(deliver);
+    }
+
+    /// Gives up on the first gap, delivering the buffered messages after it up to the next gap.
+    /// Returns the sequence numbers skipped, or `None` if nothing is buffered.
+    pub fn skip_gap(&mut self, deliver: impl FnMut(u64, T)) -> Option<Range<u64>> {
+        let &first = self.pending.keys().next()?;
+        let skipped = self.next_seq_num..first;
+        self.skip_to(first, deliver);
+        Some(skipped)
+    }
+
+    /// Drops all state and starts over, expecting `next_seq_num` next.
+    pub fn reset(&mut self, next_seq_num: u64) {
+        self.next_seq_num = next_seq_num;
+        self.high_seq_num = next_seq_num;
+        self.pending.clear();
+    }
+
+    /// Returns the ranges of sequence numbers that are missing between the next expected and
+    /// the highest seen.
+    pub fn missing(&self) -> Vec<Range<u64>> {
+        let mut ranges = Vec::new();
+        let mut start = self.next_seq_num;
+        for &seq_num in self.pending.keys() {
+            if seq_num > start {
+                ranges.push(start..seq_num);
+            }
+            start = seq_num.saturating_add(1);
+        }
+        if start < self.high_seq_num {
+            ranges.push(start..self.high_seq_num);
+        }
+        ranges
+    }
+
+    fn drain(&mut self, mut deliver: impl FnMut(u64, T)) {
+        while let Some(item) = self.pending.remove(&self.next_seq_num) {
+            deliver(self.next_seq_num, item);
+            self.next_seq_num += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn push(seqr: &mut Sequencer<u64>, seq_num: u64, out: &mut Vec<u64>) -> Sequenced {
+        seqr.push(seq_num, seq_num, |_, item| out.push(item))
+    }
+
+    fn missing(seqr: &Sequencer<u64>) -> Vec<(u64, u64)> {
+        seqr.missing().into_iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn fills_gap() {
+        let mut seqr = Sequencer::new(1);
+        let mut out = Vec::new();
+        assert_eq!(push(&mut seqr, 1, &mut out), Sequenced::Delivered);
+        assert_eq!(push(&mut seqr, 4, &mut out), Sequenced::Buffered);
+        assert_eq!(push(&mut seqr, 3, &mut out), Sequenced::Buffered);
+        assert_eq!(push(&mut seqr, 4, &mut out), Sequenced::Duplicate);
+        assert_eq!(out, [1]);
+        assert!(seqr.has_gap());
+        assert_eq!(missing(&seqr), [(2, 3)]);
+        assert_eq!(seqr.pending_len(), 2);
+
+        assert_eq!(push(&mut seqr, 2, &mut out), Sequenced::Delivered);
+        assert_eq!(out, [1, 2, 3, 4]);
+        assert_eq!(push(&mut seqr, 2, &mut out), Sequenced::Duplicate);
+        assert!(!seqr.has_gap());
+        assert_eq!(seqr.next_seq_num(), 5);
+        assert_eq!(seqr.pending_len(), 0);
+    }
+
+    #[test]
+    fn missing_ranges() {
+        let mut seqr = Sequencer::new(1);
+        let mut out = Vec::new();
+        push(&mut seqr, 3, &mut out);
+        push(&mut seqr, 6, &mut out);
+        seqr.observe(10);
+        assert_eq!(missing(&seqr), [(1, 3), (4, 6), (7, 10)]);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn skip_gap() {
+        let mut seqr = Sequencer::new(1);
+        let mut out = Vec::new();
+        for seq_num in [3, 4, 7] {
+            push(&mut seqr, seq_num, &mut out);
+        }
+        assert_eq!(seqr.skip_gap(|_, item| out.push(item)), Some(1..3));
+        assert_eq!(out, [3, 4]);
+        assert_eq!(missing(&seqr), [(5, 7)]);
+        assert_eq!(seqr.skip_gap(|_, item| out.push(item)), Some(5..7));
+        assert_eq!(out, [3, 4, 7]);
+        assert_eq!(seqr.skip_gap(|_, item| out.push(item)), None);
+        assert_eq!(push(&mut seqr, 2, &mut out), Sequenced::Duplicate);
+    }
+
+    #[test]
+    fn skip_to_drops_earlier() {
+        let mut seqr = Sequencer::new(1);
+        let mut out = Vec::new();
+        push(&mut seqr, 2, &mut out);
+        push(&mut seqr, 5, &mut out);
+        seqr.skip_to(4, |_, item| out.push(item));
+        assert!(out.is_empty());
+        assert_eq!(missing(&seqr), [(4, 5)]);
+        assert_eq!(seqr.pending_len(), 1);
+        assert_eq!(push(&mut seqr, 4, &mut out), Sequenced::Delivered);
+        assert_eq!(out, [4, 5]);
+    }
+}
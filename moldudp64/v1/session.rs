@@ -0,0 +1,125 @@
+use super::types::SessionId;
+
+/// What a receiver does when it sees a session other than the one it's on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SessionPolicy {
+    /// Only the session in the options is accepted; anything else closes the receiver.
+    #[default]
+    Fixed,
+    /// The first session seen is adopted (unless one is set in the options) and anything else
+    /// afterwards closes the receiver.
+    AdoptFirst,
+    /// The first session seen is adopted, and whenever a new session shows up the receiver
+    /// switches to it, starting its sequencing over. An end of session doesn't close the
+    /// receiver.
+    Follow,
+}
+
+/// Where a receiver starts when it joins a stream (or a new session) that's already going.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum LateJoin {
+    /// Start from the options' sequence number (1 by default), re-requesting everything before
+    /// the live stream if re-requests are enabled.
+    #[default]
+    Recover,
+    /// Start from the sequence number of the first packet seen.
+    Live,
+}
+
+pub(crate) enum SessionCheck {
+    Accept,
+    /// The packet is from a session that was switched away from.
+    Ignore,
+    /// The receiver switched to the packet's session.
+    Changed { from: Option<SessionId> },
+    Unexpected { want: SessionId },
+}
+
+/// Tracks the session a receiver is on according to its policy.
+pub(crate) struct SessionState {
+    policy: SessionPolicy,
+    current: Option<SessionId>,
+    previous: Option<SessionId>,
+    ended: bool,
+}
+
+impl SessionState {
+    pub(crate) fn new(session: SessionId, policy: SessionPolicy) -> Self {
+        let current = match policy {
+            SessionPolicy::Fixed => Some(session),
+            _ if session.is_blank() => None,
+            _ => Some(session),
+        };
+        Self {
+            policy,
+            current,
+            previous: None,
+            ended: false,
+        }
+    }
+
+    pub(crate) fn current(&self) -> Option<SessionId> {
+        self.current
+    }
+
+    pub(crate) fn check(&mut self, got: SessionId) -> SessionCheck {
+        let from = match self.current {
+            Some(want) if want == got => return SessionCheck::Accept,
+            None => None,
+            Some(_) if self.previous == Some(got) => return SessionCheck::Ignore,
+            Some(want) if self.policy != SessionPolicy::Follow => {
+                return SessionCheck::Unexpected { want };
+            }
+            Some(want) => Some(want),
+        };
+        self.previous = from;
+        self.current = Some(got);
+        self.ended = false;
+        SessionCheck::Changed { from }
+    }
+
+    /// Marks the current session as ended, returning false if it already was.
+    pub(crate) fn end(&mut self) -> bool {
+        !std::mem::replace(&mut self.ended, true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fixed_and_adopt_first() {
+        let (a, b) = (SessionId::new_trunc("A"), SessionId::new_trunc("B"));
+        let mut fixed = SessionState::new(a, SessionPolicy::Fixed);
+        assert!(matches!(fixed.check(a), SessionCheck::Accept));
+        assert!(matches!(fixed.check(b), SessionCheck::Unexpected { want } if want == a));
+
+        // A blank session is only a wildcard for the adopting policies.
+        let mut blank = SessionState::new(SessionId::BLANK, SessionPolicy::Fixed);
+        assert_eq!(blank.current(), Some(SessionId::BLANK));
+        assert!(matches!(blank.check(a), SessionCheck::Unexpected { .. }));
+
+        let mut adopt = SessionState::new(SessionId::BLANK, SessionPolicy::AdoptFirst);
+        assert_eq!(adopt.current(), None);
+        assert!(matches!(adopt.check(a), SessionCheck::Changed { from: None }));
+        assert_eq!(adopt.current(), Some(a));
+        assert!(matches!(adopt.check(a), SessionCheck::Accept));
+        assert!(matches!(adopt.check(b), SessionCheck::Unexpected { want } if want == a));
+    }
+
+    #[test]
+    fn follow() {
+        let (a, b) = (SessionId::new_trunc("A"), SessionId::new_trunc("B"));
+        let mut state = SessionState::new(a, SessionPolicy::Follow);
+        assert!(matches!(state.check(a), SessionCheck::Accept));
+        assert!(state.end());
+        assert!(!state.end());
+
+        assert!(matches!(state.check(b), SessionCheck::Changed { from: Some(from) } if from == a));
+        assert_eq!(state.current(), Some(b));
+        // Stragglers from the old session are dropped rather than switching back.
+        assert!(matches!(state.check(a), SessionCheck::Ignore));
+        assert!(state.end());
+    }
+}
@@ -0,0 +1,240 @@
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+/// Options for the sockets used by receivers and transmitters.
+///
+/// If the address a receiver is given isn't a multicast group, it binds to that address and
+/// receives unicast packets without joining anything; likewise, a transmitter given a unicast
+/// address sends straight to it. This works without any multicast routing.
+#[derive(Clone, Debug)]
+pub struct SocketOptions {
+    reuse_addr: bool,
+    reuse_port: bool,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+    multicast_ttl: Option<u32>,
+    multicast_loop: Option<bool>,
+    multicast_interface_v4: Ipv4Addr,
+    multicast_interface_v6: u32,
+    bind_addr: Option<IpAddr>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            reuse_addr: false,
+            reuse_port: false,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            multicast_ttl: None,
+            multicast_loop: None,
+            multicast_interface_v4: Ipv4Addr::UNSPECIFIED,
+            multicast_interface_v6: 0,
+            bind_addr: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets SO_REUSEADDR so several receivers on one host can bind the same group and port.
+    pub fn with_reuse_addr(mut self, b: bool) -> Self {
+        self.reuse_addr = b;
+        self
+    }
+
+    /// Sets SO_REUSEPORT. Only has an effect on unix platforms.
+    pub fn with_reuse_port(mut self, b: bool) -> Self {
+        self.reuse_port = b;
+        self
+    }
+
+    /// Sets SO_RCVBUF. The OS may cap (or, on linux, double) the size.
+    pub fn with_recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets SO_SNDBUF.
+    pub fn with_send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// The TTL (or IPv6 hop limit) of multicast packets sent.
+    pub fn with_multicast_ttl(mut self, ttl: u32) -> Self {
+        self.multicast_ttl = Some(ttl);
+        self
+    }
+
+    /// Whether multicast packets sent are looped back to receivers on the same host.
+    pub fn with_multicast_loop(mut self, b: bool) -> Self {
+        self.multicast_loop = Some(b);
+        self
+    }
+
+    /// The interface IPv4 groups are joined on and multicast packets are sent from.
+    pub fn with_multicast_interface_v4(mut self, intf: Ipv4Addr) -> Self {
+        self.multicast_interface_v4 = intf;
+        self
+    }
+
+    /// The index of the interface IPv6 groups are joined on and multicast packets are sent from.
+    /// 0 lets the OS choose.
+    pub fn with_multicast_interface_v6(mut self, index: u32) -> Self {
+        self.multicast_interface_v6 = index;
+        self
+    }
+
+    /// The local address to bind to. By default, receivers bind to the unspecified address
+    /// (with the group's port) when receiving multicast, and transmitters bind to the
+    /// unspecified address with any port.
+    pub fn with_bind_addr(mut self, addr: IpAddr) -> Self {
+        self.bind_addr = Some(addr);
+        self
+    }
+
+    pub fn reuse_addr(&self) -> bool {
+        self.reuse_addr
+    }
+
+    pub fn reuse_port(&self) -> bool {
+        self.reuse_port
+    }
+
+    pub fn recv_buffer_size(&self) -> Option<usize> {
+        self.recv_buffer_size
+    }
+
+    pub fn send_buffer_size(&self) -> Option<usize> {
+        self.send_buffer_size
+    }
+
+    pub fn multicast_ttl(&self) -> Option<u32> {
+        self.multicast_ttl
+    }
+
+    pub fn multicast_loop(&self) -> Option<bool> {
+        self.multicast_loop
+    }
+
+    pub fn multicast_interface_v4(&self) -> Ipv4Addr {
+        self.multicast_interface_v4
+    }
+
+    pub fn multicast_interface_v6(&self) -> u32 {
+        self.multicast_interface_v6
+    }
+
+    pub fn bind_addr(&self) -> Option<IpAddr> {
+        self.bind_addr
+    }
+
+    /// Creates a socket to receive on `addr`. If `addr` is a multicast group, the group still
+    /// needs to be joined.
+    pub fn bind_receiver(&self, addr: SocketAddr) -> io::Result<UdpSocket> {
+        let sock = self.new_socket(addr.ip())?;
+        let ip = match self.bind_addr {
+            Some(ip) => ip,
+            None if addr.ip().is_multicast() => unspecified(addr.ip()),
+            None => addr.ip(),
+        };
+        sock.bind(&SocketAddr::new(ip, addr.port()).into())?;
+        Ok(sock.into())
+    }
+
+    /// Creates a socket connected to `addr` to transmit on.
+    pub fn connect_transmitter(&self, addr: SocketAddr) -> io::Result<UdpSocket> {
+        let sock = self.new_socket(addr.ip())?;
+        let ip = self.bind_addr.unwrap_or_else(|| unspecified(addr.ip()));
+        sock.bind(&SocketAddr::new(ip, 0).into())?;
+        sock.connect(&addr.into())?;
+        Ok(sock.into())
+    }
+
+    /// Joins the group on the configured interface. Does nothing if `group` isn't a multicast
+    /// address.
+    pub fn join<'s>(&self, sock: impl Into<SockRef<'s>>, group: IpAddr) -> io::Result<()> {
+        let sock = sock.into();
+        match group {
+            IpAddr::V4(g) if g.is_multicast() => {
+                sock.join_multicast_v4(&g, &self.multicast_interface_v4)
+            }
+            IpAddr::V6(g) if g.is_multicast() => {
+                sock.join_multicast_v6(&g, self.multicast_interface_v6)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Leaves the group joined with `join`.
+    pub fn leave<'s>(&self, sock: impl Into<SockRef<'s>>, group: IpAddr) -> io::Result<()> {
+        let sock = sock.into();
+        match group {
+            IpAddr::V4(g) if g.is_multicast() => {
+                sock.leave_multicast_v4(&g, &self.multicast_interface_v4)
+            }
+            IpAddr::V6(g) if g.is_multicast() => {
+                sock.leave_multicast_v6(&g, self.multicast_interface_v6)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn new_socket(&self, ip: IpAddr) -> io::Result<Socket> {
+        let domain = if ip.is_ipv4() { Domain::IPV4 } else { Domain::IPV6 };
+        let sock = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        if ip.is_ipv6() {
+            sock.set_only_v6(true)?;
+        }
+        if self.reuse_addr {
+            sock.set_reuse_address(true)?;
+        }
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        if self.reuse_port {
+            sock.set_reuse_port(true)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            sock.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            sock.set_send_buffer_size(size)?;
+        }
+        match ip {
+            IpAddr::V4(_) => {
+                if let Some(ttl) = self.multicast_ttl {
+                    sock.set_multicast_ttl_v4(ttl)?;
+                }
+                if let Some(b) = self.multicast_loop {
+                    sock.set_multicast_loop_v4(b)?;
+                }
+                if !self.multicast_interface_v4.is_unspecified() {
+                    sock.set_multicast_if_v4(&self.multicast_interface_v4)?;
+                }
+            }
+            IpAddr::V6(_) => {
+                if let Some(hops) = self.multicast_ttl {
+                    sock.set_multicast_hops_v6(hops)?;
+                }
+                if let Some(b) = self.multicast_loop {
+                    sock.set_multicast_loop_v6(b)?;
+                }
+                if self.multicast_interface_v6 != 0 {
+                    sock.set_multicast_if_v6(self.multicast_interface_v6)?;
+                }
+            }
+        }
+        Ok(sock)
+    }
+}
+
+fn unspecified(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// The number of most recent gap recovery times the latency percentiles are taken over.
+pub const RECOVERY_LATENCY_SAMPLES: usize = 1024;
+
+/// A snapshot of a receiver's counters, totaled over all of its lines.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReceiverStats {
+    /// Downstream packets received, including heartbeats.
+    pub packets: u64,
+    /// The total size of the downstream packets received.
+    pub bytes: u64,
+    /// Packets that couldn't be parsed and were dropped.
+    pub malformed: u64,
+    /// Messages passed to the handler.
+    pub messages: u64,
+    /// Messages dropped because they had already been received.
+    pub duplicates: u64,
+    /// Gaps seen in the sequence numbers, including those found from heartbeats.
+    pub gaps: u64,
+    /// Messages that arrived after later ones had, filling a gap.
+    pub recovered: u64,
+    /// Messages that were never received, either because re-requesting them failed or because
+    /// the session changed before they arrived.
+    pub lost: u64,
+    /// How long gaps took to be filled.
+    pub recovery_latency: LatencyPercentiles,
+    /// The sequence number of the next message that will be passed to the handler.
+    pub curr_seq_num: u64,
+    /// One past the highest sequence number the server is known to have sent.
+    pub next_expected_seq_num: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    /// The number of latencies the percentiles were taken over. Zero if there were none, in which
+    /// case the percentiles are all zero.
+    pub samples: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyPercentiles {
+    fn from_samples(samples: impl Iterator<Item = Duration>) -> Self {
+        let mut sorted = samples.collect::<Vec<_>>();
+        if sorted.is_empty() {
+            return Self::default();
+        }
+        sorted.sort_unstable();
+        let at = |pct: usize| sorted[(sorted.len() - 1) * pct / 100];
+        Self {
+            samples: sorted.len(),
+            p50: at(50),
+            p90: at(90),
+            p99: at(99),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+/// The counters behind [`ReceiverStats`] along with the gaps that haven't been filled yet.
+#[derive(Default)]
+pub(crate) struct StatsState {
+    counts: ReceiverStats,
+    latencies: VecDeque<Duration>,
+    /// The end of each open gap and when it was opened, by its start.
+    open_gaps: BTreeMap<u64, (u64, Instant)>,
+}
+
+impl StatsState {
+    pub(crate) fn packet(&mut self, len: usize) {
+        self.counts.packets += 1;
+        self.counts.bytes += len as u64;
+    }
+
+    pub(crate) fn malformed(&mut self) {
+        self.counts.malformed += 1;
+    }
+
+    pub(crate) fn delivered(&mut self) {
+        self.counts.messages += 1;
+    }
+
+    pub(crate) fn duplicate(&mut self) {
+        self.counts.duplicates += 1;
+    }
+
+    pub(crate) fn recovered(&mut self) {
+        self.counts.recovered += 1;
+    }
+
+    pub(crate) fn lost(&mut self, count: u64) {
+        self.counts.lost += count;
+    }
+
+    pub(crate) fn gap_opened(&mut self, gap: Range<u64>, now: Instant) {
+        self.counts.gaps += 1;
+        self.open_gaps.insert(gap.start, (gap.end, now));
+    }
+
+    /// Closes the gaps that have been delivered through now that the next message to deliver is
+    /// `next_seq_num`, calling `filled` with each and how long it took to fill.
+    pub(crate) fn gaps_filled(
+        &mut self,
+        next_seq_num: u64,
+        now: Instant,
+        mut filled: impl FnMut(Range<u64>, Duration),
+    ) {
+        // Gaps never overlap, so they end in the same order they start.
+        while let Some(entry) = self.open_gaps.first_entry() {
+            let (end, opened) = *entry.get();
+            if end > next_seq_num {
+                break;
+            }
+            let start = entry.remove_entry().0;
+            let latency = now.saturating_duration_since(opened);
+            if self.latencies.len() == RECOVERY_LATENCY_SAMPLES {
+                self.latencies.pop_front();
+            }
+            self.latencies.push_back(latency);
+            filled(start..end, latency);
+        }
+    }
+
+    /// Counts the skipped messages as lost and forgets the gaps they ended.
+    pub(crate) fn abandon_gap(&mut self, skipped: Range<u64>) {
+        self.counts.lost += skipped.end - skipped.start;
+        self.open_gaps.retain(|_, (end, _)| *end > skipped.end);
+    }
+
+    /// Forgets the open gaps, counting the messages still missing from them as lost.
+    pub(crate) fn abandon_gaps(&mut self, missing: &[Range<u64>]) {
+        self.counts.lost += missing.iter().map(|r| r.end - r.start).sum::<u64>();
+        self.open_gaps.clear();
+    }
+
+    pub(crate) fn snapshot(&self, curr_seq_num: u64, next_expected_seq_num: u64) -> ReceiverStats {
+        ReceiverStats {
+            recovery_latency: LatencyPercentiles::from_samples(self.latencies.iter().copied()),
+            curr_seq_num,
+            next_expected_seq_num,
+            ..self.counts.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gap_latency() {
+        let ms = Duration::from_millis;
+        let t0 = Instant::now();
+        let mut stats = StatsState::default();
+        stats.gap_opened(2..5, t0);
+        stats.gap_opened(7..9, t0 + ms(10));
+
+        let mut filled = Vec::new();
+        stats.gaps_filled(7, t0 + ms(30), |gap, latency| filled.push((gap, latency)));
+        assert_eq!(filled, vec![(2..5, ms(30))]);
+        stats.gaps_filled(9, t0 + ms(50), |gap, latency| filled.push((gap, latency)));
+        assert_eq!(filled, vec![(2..5, ms(30)), (7..9, ms(40))]);
+
+        let snapshot = stats.snapshot(9, 9);
+        assert_eq!(snapshot.gaps, 2);
+        assert_eq!(snapshot.recovery_latency.samples, 2);
+        assert_eq!(snapshot.recovery_latency.p50, ms(30));
+        assert_eq!(snapshot.recovery_latency.max, ms(40));
+
+        let latency = LatencyPercentiles::from_samples((1..=100).map(ms));
+        assert_eq!((latency.p50, latency.p90, latency.p99), (ms(50), ms(90), ms(99)));
+        assert_eq!(latency.max, ms(100));
+        assert_eq!(LatencyPercentiles::from_samples(std::iter::empty()), Default::default());
+    }
+
+    #[test]
+    fn lost_counts() {
+        let t0 = Instant::now();
+        let mut stats = StatsState::default();
+        stats.gap_opened(2..5, t0);
+        stats.gap_opened(7..9, t0);
+        stats.abandon_gap(2..5);
+        stats.lost(1);
+        assert_eq!(stats.snapshot(0, 0).lost, 4);
+
+        // An abandoned gap is never reported as filled.
+        let mut filled = Vec::new();
+        stats.gaps_filled(9, t0, |gap, _| filled.push(gap));
+        assert_eq!(filled, vec![7..9]);
+
+        stats.gap_opened(10..12, t0);
+        stats.abandon_gaps(&[10..12, 15..16]);
+        stats.gaps_filled(20, t0, |gap, _| filled.push(gap));
+        assert_eq!(filled, vec![7..9]);
+        assert_eq!(stats.snapshot(0, 0).lost, 7);
+        assert_eq!(stats.snapshot(0, 0).gaps, 3);
+    }
+}
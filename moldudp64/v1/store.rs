@@ -0,0 +1,116 @@
+use super::types::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Arc, RwLock};
+
+/// Storage for the messages of one or more sessions, keyed by session and sequence number.
+/// Transmitters record what they send in a store, request servers answer from one, and
+/// receivers can journal what they deliver to one.
+pub trait MessageStore: Send + Sync {
+    /// Stores the blocks with consecutive sequence numbers, the first having `start`.
+    fn insert_blocks(
+        &self,
+        session: SessionId,
+        start: u64,
+        blocks: &[MessageBlock],
+    ) -> io::Result<()>;
+
+    /// Returns up to `max` blocks with consecutive sequence numbers starting at `start`. Stops
+    /// early at the first sequence number that isn't in the store.
+    fn range(&self, session: SessionId, start: u64, max: usize) -> io::Result<Vec<MessageBlock>>;
+
+    fn get(&self, session: SessionId, seq_num: u64) -> io::Result<Option<MessageBlock>> {
+        Ok(self.range(session, seq_num, 1)?.pop())
+    }
+}
+
+pub type ArcMessageStore = Arc<dyn MessageStore>;
+
+impl<S: MessageStore + ?Sized> MessageStore for Arc<S> {
+    fn insert_blocks(
+        &self,
+        session: SessionId,
+        start: u64,
+        blocks: &[MessageBlock],
+    ) -> io::Result<()> {
+        (**self).insert_blocks(session, start, blocks)
+    }
+
+    fn range(&self, session: SessionId, start: u64, max: usize) -> io::Result<Vec<MessageBlock>> {
+        (**self).range(session, start, max)
+    }
+
+    fn get(&self, session: SessionId, seq_num: u64) -> io::Result<Option<MessageBlock>> {
+        (**self).get(session, seq_num)
+    }
+}
+
+/// An in-memory store of message blocks, keyed by session and sequence number. Clones share the
+/// same underlying store, so a `Transmitter` and a `RequestServer` can be given the same one.
+#[derive(Clone, Default)]
+pub struct MemoryStore(Arc<RwLock<HashMap<SessionId, BTreeMap<u64, MessageBlock>>>>);
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, session: SessionId, seq_num: u64, block: MessageBlock) {
+        self.0
+            .write()
+            .unwrap()
+            .entry(session)
+            .or_default()
+            .insert(seq_num, block);
+    }
+
+    /// The number of messages stored for the session.
+    pub fn len(&self, session: SessionId) -> usize {
+        self.0
+            .read()
+            .unwrap()
+            .get(&session)
+            .map(|msgs| msgs.len())
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self, session: SessionId) -> bool {
+        self.len(session) == 0
+    }
+}
+
+impl MessageStore for MemoryStore {
+    fn insert_blocks(
+        &self,
+        session: SessionId,
+        start: u64,
+        blocks: &[MessageBlock],
+    ) -> io::Result<()> {
+        let mut sessions = self.0.write().unwrap();
+        let msgs = sessions.entry(session).or_default();
+        for (seq_num, block) in (start..).zip(blocks) {
+            msgs.insert(seq_num, block.clone());
+        }
+        Ok(())
+    }
+
+    fn range(&self, session: SessionId, start: u64, max: usize) -> io::Result<Vec<MessageBlock>> {
+        let sessions = self.0.read().unwrap();
+        let Some(msgs) = sessions.get(&session) else {
+            return Ok(Vec::new());
+        };
+        Ok(msgs
+            .range(start..)
+            .zip(start..)
+            .take(max)
+            .take_while(|((&seq_num, _), want)| seq_num == *want)
+            .map(|((_, block), _)| block.clone())
+            .collect())
+    }
+
+    fn get(&self, session: SessionId, seq_num: u64) -> io::Result<Option<MessageBlock>> {
+        let sessions = self.0.read().unwrap();
+        Ok(sessions.get(&session).and_then(|msgs| msgs.get(&seq_num)).cloned())
+    }
+}
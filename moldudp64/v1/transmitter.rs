@@ -1,40 +1,707 @@
-use std::io;
-use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
-use std::sync::Arc;
+use super::pacing::*;
+use super::receiver::POLL_INTERVAL;
+use super::socket::SocketOptions;
+use super::store::{ArcMessageStore, MessageStore};
+use super::types::*;
 
-pub struct Transmitter {
-    conn: UdpSocket,
-    session: [u8; 10],
-    next_seq_num: AtomicU64,
+use jtutils::atomic_value::{Ordering, AAV};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Error as IoError, ErrorKind as IoErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(1);
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_END_SESSION_REPEATS: u32 = 3;
+pub const DEFAULT_END_SESSION_INTERVAL: Duration = Duration::from_millis(100);
+/// The most message blocks a packet can hold (0xFFFF is reserved for end of session).
+pub(crate) const MAX_PACKET_BLOCKS: usize = 0xFFFE;
+
+/// Called with events about sending that aren't errors.
+pub type TransmitterEventHandler = Arc<dyn Fn(TransmitterEvent) + Send + Sync + 'static>;
+
+#[derive(Clone, Debug)]
+pub enum TransmitterEvent {
+    /// A replayed message (the first of those queued together, with sequence number `seq_num`)
+    /// was queued `behind` later than its original timing called for, beyond the schedule
+    /// tolerance. Sent once when falling behind, not for every late message.
+    BehindSchedule { seq_num: u64, behind: Duration },
+    /// A replayed message was queued on schedule after falling behind.
+    BackOnSchedule { seq_num: u64 },
+}
+
+#[derive(Clone)]
+pub struct TransmitterOptions {
+    session: SessionId,
+    sequence_number: u64,
+    mtu: usize,
+    flush_interval: Duration,
+    heartbeat_interval: Duration,
+    end_session_repeats: u32,
+    end_session_interval: Duration,
+    store: Option<ArcMessageStore>,
+    socket: SocketOptions,
+    pacing: Pacing,
+    replay_speed: f64,
+    schedule_tolerance: Duration,
+    event_handler: Option<TransmitterEventHandler>,
 }
 
+impl Default for TransmitterOptions {
+    fn default() -> Self {
+        Self {
+            session: SessionId::default(),
+            sequence_number: 1,
+            mtu: DEFAULT_MTU,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            end_session_repeats: DEFAULT_END_SESSION_REPEATS,
+            end_session_interval: DEFAULT_END_SESSION_INTERVAL,
+            store: None,
+            socket: SocketOptions::default(),
+            pacing: Pacing::default(),
+            replay_speed: 0.0,
+            schedule_tolerance: DEFAULT_SCHEDULE_TOLERANCE,
+            event_handler: None,
+        }
+    }
+}
+
+impl TransmitterOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_session(mut self, session: SessionId) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// The sequence number given to the first message sent. Defaults to 1.
+    pub fn with_sequence_number(mut self, seq_num: u64) -> Self {
+        self.sequence_number = seq_num;
+        self
+    }
+
+    /// The maximum size of a packet, including the IP and UDP headers.
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    /// The longest a message waits for more messages to be batched with it before being sent.
+    /// A zero interval sends every call immediately, so only messages sent together share a
+    /// packet.
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// How long without sending anything before a heartbeat is sent. A zero interval disables
+    /// heartbeats.
+    pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// How many times the end of session packet is sent, and how long to wait in between.
+    pub fn with_end_session_repeats(mut self, repeats: u32, interval: Duration) -> Self {
+        self.end_session_repeats = repeats;
+        self.end_session_interval = interval;
+        self
+    }
+
+    /// Records every message sent in the store so it can be served by a `RequestServer`.
+    pub fn with_store(mut self, store: impl MessageStore + 'static) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Options for the socket created by `connect`.
+    pub fn with_socket_options(mut self, opts: SocketOptions) -> Self {
+        self.socket = opts;
+        self
+    }
+
+    /// Limits how fast packets are sent, so fast senders (like replays) don't overrun
+    /// receivers' socket buffers. Sending waits, holding up whoever is queueing messages, until
+    /// the limit allows the next packet. Defaults to `Pacing::Unlimited`.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Sends messages queued with a timestamp (`send_message_at`) with the same relative timing
+    /// they originally had, sped up by `speed` (2.0 is twice as fast). Timing starts with the
+    /// first such message. Zero (the default) ignores the timestamps.
+    pub fn with_replay_speed(mut self, speed: f64) -> Self {
+        self.replay_speed = speed;
+        self
+    }
+
+    /// How late a replayed message can be queued before `TransmitterEvent::BehindSchedule` is
+    /// sent.
+    pub fn with_schedule_tolerance(mut self, tolerance: Duration) -> Self {
+        self.schedule_tolerance = tolerance;
+        self
+    }
+
+    pub fn with_event_handler(mut self, handler: TransmitterEventHandler) -> Self {
+        self.event_handler = Some(handler);
+        self
+    }
+
+    pub fn session(&self) -> SessionId {
+        self.session
+    }
+
+    pub fn sequence_number(&self) -> u64 {
+        self.sequence_number
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.heartbeat_interval
+    }
+
+    pub fn end_session_repeats(&self) -> u32 {
+        self.end_session_repeats
+    }
+
+    pub fn end_session_interval(&self) -> Duration {
+        self.end_session_interval
+    }
+
+    pub fn store(&self) -> Option<&ArcMessageStore> {
+        self.store.as_ref()
+    }
+
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.socket
+    }
+
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    pub fn replay_speed(&self) -> f64 {
+        self.replay_speed
+    }
+
+    pub fn schedule_tolerance(&self) -> Duration {
+        self.schedule_tolerance
+    }
+
+    pub fn event_handler(&self) -> Option<&TransmitterEventHandler> {
+        self.event_handler.as_ref()
+    }
+
+    /// Sends to `addr`, which can be a multicast group or a unicast address.
+    pub fn connect(self, addr: impl ToSocketAddrs) -> Result<Transmitter, TransmitterError> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            IoError::new(IoErrorKind::InvalidInput, "no address to connect to")
+        })?;
+        let conn = self.socket.connect_transmitter(addr)?;
+        self.build_with_socket(conn)
+    }
+
+    /// The socket must already be connected to the address packets are sent to.
+    pub fn build_with_socket(self, conn: UdpSocket) -> Result<Transmitter, TransmitterError> {
+        if self.mtu < IP_UDP_HEADER_LEN + HEADER_LEN + 2 {
+            return Err(TransmitterError::Io(IoError::new(
+                IoErrorKind::InvalidInput,
+                "mtu too small to fit a message",
+            )));
+        }
+        let inner = Arc::new(InnerTransmitter {
+            conn,
+            batch: Mutex::new(Batch {
+                next_seq_num: self.sequence_number,
+                blocks: Vec::new(),
+                len: HEADER_LEN,
+                first_at: None,
+                last_sent: Instant::now(),
+                pacer: Pacer::new(self.pacing),
+                replay: ReplayClock::new(self.replay_speed, self.schedule_tolerance),
+            }),
+            cond: Condvar::new(),
+            opts: self,
+            close_err: AAV::empty(),
+        });
+        let weak = Arc::downgrade(&inner);
+        thread::spawn(move || InnerTransmitter::run(weak));
+        Ok(Transmitter(inner))
+    }
+}
+
+/// Sends messages as MoldUDP64 downstream packets. Messages are stamped with sequence numbers
+/// and batched into packets up to the MTU, and heartbeats are sent while idle.
+#[derive(Clone)]
+pub struct Transmitter(Arc<InnerTransmitter>);
+
 impl Transmitter {
-    pub fn new(addr: impl ToSocketAddrs, session: [u8; 10]) -> io::Result<Self> {
-        let conn = UdpSocket::bind("0.0.0.0:0")?;
-        conn.connect(addr)?;
-        Ok(Self { conn, session })
+    pub fn options() -> TransmitterOptions {
+        TransmitterOptions::new()
+    }
+
+    pub fn new(addr: impl ToSocketAddrs, session: SessionId) -> Result<Self, TransmitterError> {
+        Self::options().with_session(session).connect(addr)
+    }
+
+    /// Queues a message to be sent, returning the sequence number it was given.
+    pub fn send_message(&self, msg: impl Into<Vec<u8>>) -> Result<u64, TransmitterError> {
+        let block = MessageBlock::new(msg.into())
+            .map_err(|msg| TransmitterError::MessageTooLarge(msg.len()))?;
+        self.0.send_message_blocks(vec![block], None)
+    }
+
+    /// Queues a message originally sent at `at` (measured from any fixed point, like midnight),
+    /// first waiting until it's due if replaying. Otherwise the same as `send_message`.
+    pub fn send_message_at(
+        &self,
+        msg: impl Into<Vec<u8>>,
+        at: Duration,
+    ) -> Result<u64, TransmitterError> {
+        let block = MessageBlock::new(msg.into())
+            .map_err(|msg| TransmitterError::MessageTooLarge(msg.len()))?;
+        self.0.send_message_blocks(vec![block], Some(at))
+    }
+
+    /// Queues the message blocks to be sent in order, returning the sequence number given to
+    /// the first. If sending fails partway, `TransmitterError::Unsent` says how many were queued.
+    pub fn send_message_blocks(&self, blocks: Vec<MessageBlock>) -> Result<u64, TransmitterError> {
+        self.0.send_message_blocks(blocks, None)
     }
 
-    pub fn send(&self, packet: DownstreamPacket) -> io::Result<()> {
-        self.conn.send(&packet.serialize()).map(|_| ())
+    /// Queues message blocks originally sent at `at`, like `send_message_at`.
+    pub fn send_message_blocks_at(
+        &self,
+        blocks: Vec<MessageBlock>,
+        at: Duration,
+    ) -> Result<u64, TransmitterError> {
+        self.0.send_message_blocks(blocks, Some(at))
     }
 
-    pub fn send_message_blocks(&self, blocks: Vec<MessageBlocks>) -> io::Result<()> {
+    /// Sends anything that's been queued.
+    pub fn flush(&self) -> Result<(), TransmitterError> {
+        self.0.flush()
     }
 
-    pub fn send_heartbeat(&self) -> io::Result<()> {
-        // TODO
-        self.send(&DownstreamPacket::heartbeat(self.session)).map(|_| ())
+    pub fn send_heartbeat(&self) -> Result<(), TransmitterError> {
+        self.0.send_heartbeat()
     }
 
-    pub fn send_end_session(&self) -> io::Result<()> {
-        self.conn.send(&DownstreamPacket::end_session().serialize()).map(|_| ())
+    /// Flushes anything queued, sends the end of session packet (repeatedly, as configured), and
+    /// closes the transmitter.
+    pub fn send_end_session(&self) -> Result<(), TransmitterError> {
+        self.0.send_end_session()
     }
+
+    /// The sequence number the next message queued will be given.
+    pub fn next_seq_num(&self) -> u64 {
+        self.0.next_seq_num()
+    }
+
+    /// How late the last replayed message was queued compared to its original timing.
+    pub fn schedule_lag(&self) -> Duration {
+        let batch = self.0.batch.lock().unwrap();
+        batch.replay.as_ref().map(|r| r.lag()).unwrap_or_default()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.conn.local_addr()
+    }
+
+    pub fn opts(&self) -> &TransmitterOptions {
+        &self.0.opts
+    }
+
+    pub fn store(&self) -> Option<&ArcMessageStore> {
+        self.0.opts.store()
+    }
+
+    /// Flushes anything queued and stops the transmitter without ending the session.
+    pub fn close(&self) -> Result<(), TransmitterError> {
+        let res = self.0.flush();
+        self.0.close_with_err(TransmitterError::Closed);
+        res
+    }
+
+    pub fn close_err(&self) -> Option<ArcTransmitterError> {
+        self.0.close_err.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+}
+
+struct InnerTransmitter {
+    conn: UdpSocket,
+    opts: TransmitterOptions,
+    batch: Mutex<Batch>,
+    cond: Condvar,
+
+    close_err: AAV<TransmitterError>,
+}
+
+struct Batch {
+    /// The sequence number of the first block in `blocks`.
+    next_seq_num: u64,
+    blocks: Vec<MessageBlock>,
+    /// The length of the packet the blocks would be sent in.
+    len: usize,
+    /// When the first block in `blocks` was queued.
+    first_at: Option<Instant>,
+    last_sent: Instant,
+    pacer: Pacer,
+    /// Set if replaying.
+    replay: Option<ReplayClock>,
 }
 
-/*
-fn test(addr: impl ToSocketAddrs) {
-    let conn = UdpSocket::bind("0.0.0.0:0").expect("bad bind");
-    conn.join_multicast_v4(&Ipv4Addr::UNSPECIFIED).expect("bad join");
+impl InnerTransmitter {
+    fn max_packet_len(&self) -> usize {
+        self.opts.mtu - IP_UDP_HEADER_LEN
+    }
+
+    fn send_message_blocks(
+        &self,
+        blocks: Vec<MessageBlock>,
+        at: Option<Duration>,
+    ) -> Result<u64, TransmitterError> {
+        let max_len = self.max_packet_len();
+        if let Some(block) = blocks.iter().find(|b| HEADER_LEN + b.as_slice().len() > max_len) {
+            return Err(TransmitterError::MessageTooLarge(block.len()));
+        }
+        let mut batch = self.lock_open()?;
+        let due = match (at, &mut batch.replay) {
+            (Some(at), Some(replay)) => Some(replay.due(at, Instant::now())),
+            _ => None,
+        };
+        if let Some(due) = due {
+            let wait = due.saturating_duration_since(Instant::now());
+            if !wait.is_zero() {
+                // Anything queued was due earlier, so it shouldn't wait for the flush interval.
+                self.flush_locked(&mut batch)?;
+                drop(batch);
+                thread::sleep(wait);
+                batch = self.lock_open()?;
+            }
+        }
+        let first_seq_num = batch.next_seq_num + batch.blocks.len() as u64;
+        let count = blocks.len();
+        let unsent = |queued, e| TransmitterError::unsent(first_seq_num, queued, e);
+        for (queued, block) in blocks.into_iter().enumerate() {
+            let block_len = block.as_slice().len();
+            if batch.len + block_len > max_len || batch.blocks.len() >= MAX_PACKET_BLOCKS {
+                self.flush_locked(&mut batch).map_err(|e| unsent(queued, e))?;
+            }
+            if batch.first_at.is_none() {
+                batch.first_at = Some(Instant::now());
+                self.cond.notify_all();
+            }
+            batch.len += block_len;
+            batch.blocks.push(block);
+        }
+        if self.opts.flush_interval.is_zero() {
+            self.flush_locked(&mut batch).map_err(|e| unsent(count, e))?;
+        }
+        let event = match (due, &mut batch.replay) {
+            (Some(due), Some(replay)) => replay.queued(due, Instant::now(), first_seq_num),
+            _ => None,
+        };
+        drop(batch);
+        if let (Some(event), Some(handler)) = (event, &self.opts.event_handler) {
+            handler(event);
+        }
+        Ok(first_seq_num)
+    }
+
+    fn flush(&self) -> Result<(), TransmitterError> {
+        let mut batch = self.lock_open()?;
+        self.flush_locked(&mut batch)
+    }
+
+    fn flush_locked(&self, batch: &mut Batch) -> Result<(), TransmitterError> {
+        if batch.blocks.is_empty() {
+            return Ok(());
+        }
+        if let Some(store) = &self.opts.store {
+            store.insert_blocks(self.opts.session, batch.next_seq_num, &batch.blocks)?;
+        }
+        let blocks = std::mem::take(&mut batch.blocks);
+        let header = Header::new(self.opts.session, batch.next_seq_num, 0);
+        // The number of blocks is kept under the max when they're queued, so this can't fail.
+        let packet = DownstreamPacket::new(header, blocks).unwrap();
+        let res = self.send_packet(batch, &packet.serialize());
+        let (_, blocks) = packet.into_parts();
+        if let Err(e) = res {
+            // Left queued so they're sent again, with the same sequence numbers, next time.
+            batch.blocks = blocks;
+            return Err(e.into());
+        }
+        batch.next_seq_num += blocks.len() as u64;
+        batch.len = HEADER_LEN;
+        batch.first_at = None;
+        batch.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Sends the packet once pacing allows it.
+    fn send_packet(&self, batch: &mut Batch, packet: &[u8]) -> io::Result<()> {
+        let delay = batch.pacer.delay(packet.len(), Instant::now());
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+        batch.pacer.sent(packet.len(), Instant::now());
+        // NOTE: "partial write are not possible until buffer sizes above i32::MAX."
+        self.conn.send(packet)?;
+        Ok(())
+    }
+
+    fn send_heartbeat(&self) -> Result<(), TransmitterError> {
+        let mut batch = self.lock_open()?;
+        self.send_heartbeat_locked(&mut batch)
+    }
+
+    fn send_heartbeat_locked(&self, batch: &mut Batch) -> Result<(), TransmitterError> {
+        // Anything queued has to go out first, otherwise the heartbeat's sequence number would
+        // be behind.
+        self.flush_locked(batch)?;
+        let header = Header::heartbeat(self.opts.session, batch.next_seq_num);
+        batch.last_sent = Instant::now();
+        self.send_packet(batch, header.as_slice())?;
+        Ok(())
+    }
+
+    fn send_end_session(&self) -> Result<(), TransmitterError> {
+        let mut batch = self.lock_open()?;
+        self.flush_locked(&mut batch)?;
+        let header = Header::end_session(self.opts.session, batch.next_seq_num);
+        for i in 0..self.opts.end_session_repeats.max(1) {
+            if i != 0 {
+                thread::sleep(self.opts.end_session_interval);
+            }
+            self.send_packet(&mut batch, header.as_slice())?;
+        }
+        batch.last_sent = Instant::now();
+        self.close_with_err(TransmitterError::SessionEnded);
+        Ok(())
+    }
+
+    fn next_seq_num(&self) -> u64 {
+        let batch = self.batch.lock().unwrap();
+        batch.next_seq_num + batch.blocks.len() as u64
+    }
+
+    fn lock_open(&self) -> Result<MutexGuard<'_, Batch>, TransmitterError> {
+        let batch = self.batch.lock().unwrap();
+        if self.is_closed() {
+            return Err(TransmitterError::Closed);
+        }
+        Ok(batch)
+    }
+
+    fn is_closed(&self) -> bool {
+        !self.close_err.is_empty(Ordering::Relaxed)
+    }
+
+    fn close_with_err(&self, err: impl Into<TransmitterError>) -> ArcTransmitterError {
+        let _ = self.close_err.store_if_empty(err.into(), Ordering::Relaxed, Ordering::Relaxed);
+        self.cond.notify_all();
+        self.close_err.load(Ordering::Relaxed).unwrap()
+    }
+
+    /// Flushes batches that have waited long enough and sends heartbeats while idle, until the
+    /// transmitter is closed or dropped.
+    fn run(weak: Weak<Self>) {
+        loop {
+            let Some(inner) = weak.upgrade() else {
+                break;
+            };
+            let mut batch = inner.batch.lock().unwrap();
+            if inner.is_closed() {
+                break;
+            }
+            let (flush_interval, hb_interval) =
+                (inner.opts.flush_interval, inner.opts.heartbeat_interval);
+            let now = Instant::now();
+            let deadline = match batch.first_at {
+                Some(first_at) => {
+                    if now >= first_at + flush_interval {
+                        if let Err(e) = inner.flush_locked(&mut batch) {
+                            // Closes with the error, which `close_err` returns.
+                            inner.close_with_err(e);
+                            break;
+                        }
+                        continue;
+                    }
+                    Some(first_at + flush_interval)
+                }
+                None if !hb_interval.is_zero() => {
+                    if now >= batch.last_sent + hb_interval {
+                        if let Err(e) = inner.send_heartbeat_locked(&mut batch) {
+                            inner.close_with_err(e);
+                            break;
+                        }
+                        continue;
+                    }
+                    Some(batch.last_sent + hb_interval)
+                }
+                None => None,
+            };
+            // Wakes up at least every poll interval to notice the transmitter being dropped.
+            let timeout = deadline
+                .map(|deadline| deadline.saturating_duration_since(now))
+                .unwrap_or(POLL_INTERVAL)
+                .min(POLL_INTERVAL);
+            drop(inner.cond.wait_timeout(batch, timeout).unwrap());
+        }
+    }
+}
+
+impl Drop for InnerTransmitter {
+    fn drop(&mut self) {
+        // Anything still queued is sent rather than lost.
+        if !self.is_closed() {
+            let mut batch = self.batch.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = self.flush_locked(&mut batch);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransmitterError {
+    /// The message (of the given length) can't fit in a packet.
+    MessageTooLarge(usize),
+    SessionEnded,
+    Closed,
+    Io(IoError),
+    /// Sending failed after `queued` of the message blocks were queued, the first given
+    /// `first_seq_num`. Those stay queued to be sent with the next flush; the rest weren't.
+    Unsent {
+        first_seq_num: u64,
+        queued: usize,
+        err: Box<TransmitterError>,
+    },
+}
+pub type ArcTransmitterError = Arc<TransmitterError>;
+
+impl TransmitterError {
+    /// The error for a failed send once `queued` blocks from a call were queued, if any were.
+    pub(crate) fn unsent(first_seq_num: u64, queued: usize, err: TransmitterError) -> Self {
+        if queued == 0 {
+            return err;
+        }
+        TransmitterError::Unsent {
+            first_seq_num,
+            queued,
+            err: Box::new(err),
+        }
+    }
+}
+
+impl From<IoError> for TransmitterError {
+    fn from(e: IoError) -> Self {
+        TransmitterError::Io(e)
+    }
+}
+
+impl fmt::Display for TransmitterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransmitterError::MessageTooLarge(len) => {
+                write!(f, "message of {len} bytes too large to fit in a packet")
+            }
+            TransmitterError::SessionEnded => write!(f, "session ended"),
+            TransmitterError::Closed => write!(f, "transmitter closed"),
+            TransmitterError::Io(ref e) => write!(f, "io error: {e}"),
+            TransmitterError::Unsent {
+                first_seq_num,
+                queued,
+                ref err,
+            } => write!(
+                f,
+                "{err} after queueing {queued} messages from sequence number {first_seq_num}",
+            ),
+        }
+    }
+}
+
+impl Error for TransmitterError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drop_stops_thread_and_flushes() {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let tx = Transmitter::options()
+            .with_session(SessionId::new_trunc("A"))
+            .with_flush_interval(Duration::from_secs(60))
+            .connect(rx.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(tx.send_message(b"hello".to_vec()).unwrap(), 1);
+        let weak = Arc::downgrade(&tx.0);
+        drop(tx);
+
+        let mut b = [0u8; 1500];
+        let n = rx.recv(&mut b).unwrap();
+        let packet = DownstreamPacket::parse(&b[..n]).unwrap();
+        assert_eq!(packet.header().sequence_number(), 1);
+        assert_eq!(&*packet.message_blocks()[0], b"hello");
+
+        thread::sleep(POLL_INTERVAL * 3);
+        assert!(weak.upgrade().is_none());
+    }
+
+    /// Fails to store anything from `fail_from` on.
+    struct FailingStore {
+        fail_from: u64,
+    }
+
+    impl MessageStore for FailingStore {
+        fn insert_blocks(&self, _: SessionId, start: u64, _: &[MessageBlock]) -> io::Result<()> {
+            if start >= self.fail_from {
+                return Err(io::Error::other("full"));
+            }
+            Ok(())
+        }
+
+        fn range(&self, _: SessionId, _: u64, _: usize) -> io::Result<Vec<MessageBlock>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn reports_blocks_queued_before_failure() {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        // Room for two 10-byte messages per packet.
+        let tx = Transmitter::options()
+            .with_mtu(IP_UDP_HEADER_LEN + HEADER_LEN + 2 * 12)
+            .with_flush_interval(Duration::from_secs(60))
+            .with_store(FailingStore { fail_from: 3 })
+            .connect(rx.local_addr().unwrap())
+            .unwrap();
+        let blocks = (0..5).map(|_| MessageBlock::new(vec![0; 10]).unwrap()).collect();
+        let err = tx.send_message_blocks(blocks).unwrap_err();
+        let TransmitterError::Unsent { first_seq_num, queued, err } = err else {
+            panic!("{err:?}")
+        };
+        assert_eq!((first_seq_num, queued), (1, 4));
+        assert!(matches!(*err, TransmitterError::Io(_)));
+        assert_eq!(tx.next_seq_num(), 5);
+    }
 }
-*/
@@ -1,9 +1,16 @@
 use std::borrow::Borrow;
+use std::error::Error;
+use std::fmt::{self, Formatter};
 use std::ops::Deref;
 
 const SESSION_ID_LEN: usize = 10;
+pub const HEADER_LEN: usize = 20;
+/// The default maximum transmission unit (that of ethernet).
+pub const DEFAULT_MTU: usize = 1500;
+/// The length of the IPv4 and UDP headers, which count against the MTU.
+pub const IP_UDP_HEADER_LEN: usize = 28;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct SessionId([u8; SESSION_ID_LEN]);
 
 impl SessionId {
@@ -12,28 +19,27 @@ impl SessionId {
     /// Returns the value back if it's length is longer than the length of a session. If it is
     /// less, padding bytes are added to the beginning.
     pub fn new<T: AsRef<[u8]>>(slice_t: T) -> Result<Self, T> {
-        Self::from_slice(slice_t.as_ref()).map_err(|_| slice_t)
+        Self::from_slice(slice_t.as_ref()).ok_or(slice_t)
     }
 
     /// Same as SessionId::new but silently leaves out extra bytes.
     pub fn new_trunc(slice: impl AsRef<[u8]>) -> Self {
         let slice = slice.as_ref();
         if slice.len() <= SESSION_ID_LEN {
-            Self::from_slice(&slice).unwrap()
+            Self::from_slice(slice).unwrap()
         } else {
             Self::from_slice(&slice[..SESSION_ID_LEN]).unwrap()
         }
     }
 
     #[inline(always)]
-    fn from_slice(slice: &[u8]) -> Result<Self, ()> {
-        let slice = slice.as_ref();
+    fn from_slice(slice: &[u8]) -> Option<Self> {
         if slice.len() > SESSION_ID_LEN {
-            return Err(());
+            return None;
         }
         let mut arr = [b' '; SESSION_ID_LEN];
         arr[SESSION_ID_LEN - slice.len()..].copy_from_slice(slice);
-        Ok(Self(arr))
+        Some(Self(arr))
     }
 
     pub const fn into_inner(Self(inner): Self) -> [u8; SESSION_ID_LEN] {
@@ -77,66 +83,7 @@ impl fmt::Display for SessionId {
     }
 }
 
-/*
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub struct Header {
-    /// The session to which the packet belongs.
-    pub session: SessionId,
-    /// The sequence number of the first message of the packet.
-    pub sequence_number: u64,
-    /// The count of messages contained in this packet.
-    pub message_count: u16,
-}
-
-impl Header {
-    pub fn heartbeat() -> Self {
-        // TODO
-        Self {
-            session: [0u8; 10],
-            sequence_number: 0,
-            message_count: 0,
-        }
-    }
-
-    pub fn end_session() -> Self {
-        // TODO
-        Self {
-            session: [0u8; 10],
-            sequence_number: 0,
-            message_count: 0xFFFF,
-        }
-    }
-
-    pub fn parse(b: &[u8]) -> Result<Self, ()> {
-        if b.len() != 20 {
-            return Err(());
-        }
-        Ok(Self {
-            session: (&b[..10]).try_into().unwrap(),
-            sequence_number: u64::from_be_bytes((&b[10..18]).try_into().unwrap()),
-            message_count: u16::from_be_bytes((&b[18..]).try_into().unwrap()),
-        })
-    }
-
-    pub fn serialize(&self) -> [u8; 20] {
-        let mut res = [0u8; 20];
-        res[..10].copy_from_slice(&self.session);
-        res[10..10+8].copy_from_slice(&self.sequence_number.to_be_bytes());
-        res[10+8..].copy_from_slice(&self.message_count.to_be_bytes());
-        res
-    }
-
-    pub fn is_heartbeat(&self) -> bool {
-        self.message_count == 0
-    }
-
-    pub fn is_end_session(&self) -> bool {
-        self.message_count == 0xFFFF
-    }
-}
-*/
-
-#[derive(Clone)]
+#[derive(Clone, Copy)]
 pub struct Header([u8; 20]);
 
 impl Header {
@@ -156,8 +103,11 @@ impl Header {
         Self::new(session, next_seq_num, 0xFFFF)
     }
 
-    pub fn parse(b: &[u8]) -> Result<Self, ()> {
-        b.try_into().map_err(|_| ()).map(Self)
+    pub fn parse(b: &[u8]) -> Result<Self, ParseError> {
+        b.get(..HEADER_LEN)
+            .and_then(|b| b.try_into().ok())
+            .map(Self)
+            .ok_or(ParseError::ShortHeader(b.len()))
     }
 
     pub fn session(&self) -> SessionId {
@@ -188,10 +138,6 @@ impl Header {
         &self.0
     }
 
-    pub fn as_slice(&self) -> &[u8] {
-        &self.0
-    }
-
     pub fn is_heartbeat(&self) -> bool {
         self.message_count() == 0
     }
@@ -207,72 +153,140 @@ pub struct DownstreamPacket {
 }
 
 impl DownstreamPacket {
-    pub fn new(mut header: Header, message_blocks: Vec<MessageBlock>) -> Result<Self, ()> {
+    /// Sets the header's message count to the number of blocks. Fails if there are too many
+    /// blocks to count (0xFFFF is reserved for end of session).
+    pub fn new(
+        mut header: Header,
+        message_blocks: Vec<MessageBlock>,
+    ) -> Result<Self, TooManyBlocksError> {
         let l = message_blocks.len();
         if l >= 0xFFFF {
-            // TODO
+            return Err(TooManyBlocksError(l));
         }
         header.set_message_count(l as _);
         Ok(Self { header, message_blocks })
     }
 
-    pub fn heartbeat() -> Self {
+    pub fn heartbeat(session: SessionId, next_seq_num: u64) -> Self {
         Self {
-            header: Header::heartbeat(),
+            header: Header::heartbeat(session, next_seq_num),
             message_blocks: Vec::new(),
         }
     }
 
-    pub fn end_session() -> Self {
+    pub fn end_session(session: SessionId, next_seq_num: u64) -> Self {
         Self {
-            header: Header::end_session(),
+            header: Header::end_session(session, next_seq_num),
             message_blocks: Vec::new(),
         }
     }
 
-    pub fn parse(mut b: &[u8]) -> Result<Self, ()> {
-        if b.len() < 20 {
-            return Err(());
+    /// Copies every message block out of `b`. See `DownstreamPacketRef` to avoid the copies.
+    pub fn parse(b: &[u8]) -> Result<Self, ParseError> {
+        DownstreamPacketRef::parse(b).map(|p| p.to_owned())
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn message_blocks(&self) -> &[MessageBlock] {
+        &self.message_blocks
+    }
+
+    pub fn into_parts(self) -> (Header, Vec<MessageBlock>) {
+        (self.header, self.message_blocks)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut res = self.header.as_slice().to_vec();
+        for block in &self.message_blocks {
+            res.extend_from_slice(block.as_slice());
         }
+        res
+    }
+}
+
+/// A downstream packet that borrows the buffer it was parsed from.
+#[derive(Clone, Copy)]
+pub struct DownstreamPacketRef<'a> {
+    header: Header,
+    /// The message blocks (with their lengths), all of which have been checked to be complete.
+    blocks: &'a [u8],
+}
+
+impl<'a> DownstreamPacketRef<'a> {
+    /// Checks that every message block is complete. Anything after the last block is ignored.
+    pub fn parse(b: &'a [u8]) -> Result<Self, ParseError> {
         let header = Header::parse(b)?;
         if header.is_heartbeat() || header.is_end_session() {
-            return Ok(Self {
-                header,
-                message_blocks: Vec::new(),
-            });
+            return Ok(Self { header, blocks: &[] });
         }
-        b = &b[20..];
-        let mut message_blocks = Vec::with_capacity(header.message_count() as usize);
-        for _ in 0..header.message_count() as usize {
-            if b.len() < 2 {
-                return Err(todo!());
+        let body = &b[HEADER_LEN..];
+        let mut off = 0;
+        for index in 0..header.message_count() {
+            let Some(len) = body.get(off..off + 2) else {
+                return Err(ParseError::TruncatedLength { index });
+            };
+            let len = u16::from_be_bytes([len[0], len[1]]);
+            let remaining = body.len() - off - 2;
+            if remaining < len as usize {
+                return Err(ParseError::TruncatedMessage { index, len, remaining });
             }
-            let ml = u16::from_be_bytes(b[..2].try_into().unwrap()) as usize;
-            if b.len() < ml {
-                return Err(todo!());
-            }
-            /*
-            b = &b[2..];
-            message_blocks.push(MessageBlock {
-                message_len: ml as u16,
-                message_data: b[..ml].to_vec(),
-            });
-            */
-            message_blocks.push(MessageBlock(b[..2 + ml].to_vec()));
-            b = &b[2 + ml..];
+            off += 2 + len as usize;
         }
-        Ok(Self { header, message_blocks })
+        Ok(Self { header, blocks: &body[..off] })
     }
 
-    pub fn serialize(&self) -> Vec<u8> {
-        let mut res = self.header.as_slice().to_vec();
-        for block in &self.message_blocks {
-            res.extend_from_slice(block.as_slice());
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Iterates over the message data of each block (without the lengths).
+    pub fn message_blocks(&self) -> MessageBlocks<'a> {
+        MessageBlocks {
+            b: self.blocks,
+            remaining: if self.blocks.is_empty() { 0 } else { self.header.message_count() },
+        }
+    }
+
+    pub fn to_owned(&self) -> DownstreamPacket {
+        DownstreamPacket {
+            header: self.header,
+            message_blocks: self.message_blocks().map(MessageBlock::from_data).collect(),
         }
-        res
     }
 }
 
+/// An iterator over the message blocks of a `DownstreamPacketRef`.
+#[derive(Clone)]
+pub struct MessageBlocks<'a> {
+    b: &'a [u8],
+    remaining: u16,
+}
+
+impl<'a> Iterator for MessageBlocks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        // The lengths were all checked when the packet was parsed.
+        let len = u16::from_be_bytes([self.b[0], self.b[1]]) as usize;
+        let (data, rest) = self.b[2..].split_at(len);
+        self.b = rest;
+        Some(data)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl ExactSizeIterator for MessageBlocks<'_> {}
+
 #[derive(Clone)]
 pub struct MessageBlock(Vec<u8>);
 
@@ -285,13 +299,25 @@ impl MessageBlock {
         let mut v = Vec::with_capacity(2 + data.len());
         v.extend_from_slice(&(l as u16).to_be_bytes());
         v.append(&mut data);
-        Ok(Self(data))
+        Ok(Self(v))
+    }
+
+    /// Copies the data, which must be at most 0xFFFF bytes.
+    pub(crate) fn from_data(data: &[u8]) -> Self {
+        let mut v = Vec::with_capacity(2 + data.len());
+        v.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        v.extend_from_slice(data);
+        Self(v)
     }
 
     pub fn len(&self) -> usize {
         self.len_u16() as _
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn len_u16(&self) -> u16 {
         ((self.0[0] as u16) << 8) | (self.0[1] as u16)
     }
@@ -345,6 +371,10 @@ impl RequestPacket {
         pkt
     }
 
+    pub fn parse(b: &[u8]) -> Result<Self, ParseError> {
+        b.try_into().map(Self).map_err(|_| ParseError::BadRequestLength(b.len()))
+    }
+
     pub fn session(&self) -> SessionId {
         SessionId(*slice_byte_arr::<20, 0, SESSION_ID_LEN>(&self.0))
     }
@@ -374,6 +404,51 @@ impl RequestPacket {
     }
 }
 
+/// Why a packet couldn't be parsed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    /// The packet (of the given length) is shorter than a header.
+    ShortHeader(usize),
+    /// The length of the message block at the index was cut off.
+    TruncatedLength { index: u16 },
+    /// The message block at the index has a length longer than the bytes remaining.
+    TruncatedMessage { index: u16, len: u16, remaining: usize },
+    /// Request packets are exactly 20 bytes.
+    BadRequestLength(usize),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ParseError::ShortHeader(len) => write!(f, "packet of {len} bytes too short for header"),
+            ParseError::TruncatedLength { index } => {
+                write!(f, "length of message block {index} cut off")
+            }
+            ParseError::TruncatedMessage { index, len, remaining } => write!(
+                f,
+                "message block {index} has length {len} but only {remaining} bytes remain",
+            ),
+            ParseError::BadRequestLength(len) => {
+                write!(f, "request packet has {len} bytes, expected {HEADER_LEN}")
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// A packet can hold at most 0xFFFE message blocks.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TooManyBlocksError(pub usize);
+
+impl fmt::Display for TooManyBlocksError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} message blocks is too many for a packet", self.0)
+    }
+}
+
+impl Error for TooManyBlocksError {}
+
 fn slice_byte_arr<const N: usize, const START: usize, const LEN: usize>(
     bytes: &[u8; N],
 ) -> &[u8; LEN] {
@@ -381,7 +456,7 @@ fn slice_byte_arr<const N: usize, const START: usize, const LEN: usize>(
     // SAFETY:
     // Byte arrays cannot be misaligned and won't be reading past the length of the array as
     // checked by the assert above.
-    unsafe { &*(bytes as *const [u8; N]).add(START).cast::<[u8; LEN]>() }
+    unsafe { &*(bytes as *const [u8; N]).cast::<u8>().add(START).cast::<[u8; LEN]>() }
 }
 
 fn slice_byte_arr_mut<const N: usize, const START: usize, const LEN: usize>(
@@ -391,5 +466,55 @@ fn slice_byte_arr_mut<const N: usize, const START: usize, const LEN: usize>(
     // SAFETY:
     // Byte arrays cannot be misaligned and won't be reading past the length of the array as
     // checked by the assert above.
-    unsafe { &mut *(bytes as *mut [u8; N]).add(START).cast::<[u8; LEN]>() }
+    unsafe { &mut *(bytes as *mut [u8; N]).cast::<u8>().add(START).cast::<[u8; LEN]>() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet(msg_count: u16, body: &[u8]) -> Vec<u8> {
+        let mut b = Header::new(SessionId::new_trunc("A"), 5, msg_count).as_slice().to_vec();
+        b.extend_from_slice(body);
+        b
+    }
+
+    #[test]
+    fn parse_blocks() {
+        let b = packet(2, &[0, 1, b'a', 0, 2, b'b', b'c', 0xFF]);
+        let p = DownstreamPacketRef::parse(&b).unwrap();
+        assert_eq!(p.header().sequence_number(), 5);
+        let blocks: Vec<&[u8]> = p.message_blocks().collect();
+        assert_eq!(blocks, [&b"a"[..], &b"bc"[..]]);
+        let owned = p.to_owned();
+        assert_eq!(owned.serialize(), &b[..b.len() - 1]);
+        assert_eq!(DownstreamPacket::parse(&b).unwrap().message_blocks().len(), 2);
+
+        // Heartbeats and end of session packets have no blocks, whatever follows the header.
+        let (hb, end) = (packet(0, &[1, 2, 3]), packet(0xFFFF, &[]));
+        let hb = DownstreamPacketRef::parse(&hb).unwrap();
+        assert_eq!(hb.message_blocks().len(), 0);
+        let end = DownstreamPacketRef::parse(&end).unwrap();
+        assert!(end.header().is_end_session());
+        assert_eq!(end.message_blocks().len(), 0);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse = |b: &[u8]| DownstreamPacketRef::parse(b).err();
+        assert_eq!(parse(&[0; 19]), Some(ParseError::ShortHeader(19)));
+        assert_eq!(parse(&packet(1, &[])), Some(ParseError::TruncatedLength { index: 0 }));
+        assert_eq!(
+            parse(&packet(2, &[0, 1, b'a', 0])),
+            Some(ParseError::TruncatedLength { index: 1 }),
+        );
+        assert_eq!(
+            parse(&packet(2, &[0, 1, b'a', 0, 3, b'b'])),
+            Some(ParseError::TruncatedMessage { index: 1, len: 3, remaining: 1 }),
+        );
+        assert_eq!(
+            RequestPacket::parse(&[0; 21]).err(),
+            Some(ParseError::BadRequestLength(21)),
+        );
+    }
 }
@@ -0,0 +1,452 @@
+//! A builder for Enter Order messages.
+
+use super::*;
+use nasdaq::{Price, SignedPrice, Symbol};
+use std::error::Error;
+
+/// The most shares an order can be for.
+pub const MAX_ORDER_QUANTITY: u32 = 999_999;
+/// The most seconds past midnight an expire time can be.
+pub const MAX_EXPIRE_TIME: u32 = 86_399;
+
+impl EnterOrder {
+    /// Starts building a limit order. The UserRefNum is 0 and the ClOrdId blank unless set, since
+    /// sessions and generators usually fill them in.
+    pub fn limit(
+        symbol: Symbol,
+        side: OrderSide,
+        quantity: u32,
+        price: Price,
+    ) -> EnterOrderBuilder {
+        EnterOrderBuilder::new(symbol, side, quantity, Some(price))
+    }
+
+    /// Starts building a market order. The price is set on build: `Price::MARKET_CROSS` for the
+    /// opening and closing crosses, `Price::MARKET` otherwise.
+    pub fn market(symbol: Symbol, side: OrderSide, quantity: u32) -> EnterOrderBuilder {
+        EnterOrderBuilder::new(symbol, side, quantity, None)
+    }
+}
+
+/// Builds an `EnterOrder`, filling in the optional appendage. Options left at their
+/// `default_enter_order()` values aren't put in the appendage, so the port defaults apply.
+#[derive(Clone, Debug)]
+pub struct EnterOrderBuilder {
+    user_ref_num: UserRefNum,
+    side: OrderSide,
+    quantity: u32,
+    symbol: Symbol,
+    /// `None` for market orders.
+    price: Option<Price>,
+    time_in_force: TimeInForce,
+    display: Display,
+    capacity: Capacity,
+    imse: InterMarketSweepEligibility,
+    cross_type: CrossType,
+    cl_ord_id: ClOrdId,
+
+    firm: Option<Firm>,
+    min_qty: Option<u32>,
+    customer_type: CustomerType,
+    max_floor: Option<u32>,
+    price_type: PriceType,
+    peg_offset: Option<SignedPrice>,
+    discretion_price: Option<Price>,
+    post_only: PostOnly,
+    expire_time: Option<u32>,
+    handle_inst: HandleInst,
+    others: Vec<OptionValue>,
+}
+
+impl EnterOrderBuilder {
+    fn new(symbol: Symbol, side: OrderSide, quantity: u32, price: Option<Price>) -> Self {
+        Self {
+            user_ref_num: UserRefNum(0),
+            side,
+            quantity,
+            symbol,
+            price,
+            time_in_force: TimeInForce::Day,
+            display: Display::Visible,
+            capacity: Capacity::Agency,
+            imse: InterMarketSweepEligibility::NotEligible,
+            cross_type: CrossType::ContinuousMarket,
+            cl_ord_id: ClOrdId([b' '; 14]),
+            firm: None,
+            min_qty: None,
+            customer_type: CustomerType::default_enter_order(),
+            max_floor: None,
+            price_type: PriceType::default_enter_order(),
+            peg_offset: None,
+            discretion_price: None,
+            post_only: PostOnly::default_enter_order(),
+            expire_time: None,
+            handle_inst: HandleInst::default_enter_order(),
+            others: Vec::new(),
+        }
+    }
+
+    pub fn user_ref_num(mut self, urn: UserRefNum) -> Self {
+        self.user_ref_num = urn;
+        self
+    }
+
+    pub fn cl_ord_id(mut self, id: ClOrdId) -> Self {
+        self.cl_ord_id = id;
+        self
+    }
+
+    /// Defaults to `Day`.
+    pub fn tif(mut self, tif: TimeInForce) -> Self {
+        self.time_in_force = tif;
+        self
+    }
+
+    /// Defaults to `Visible`.
+    pub fn display(mut self, display: Display) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// Defaults to `Agency`.
+    pub fn capacity(mut self, capacity: Capacity) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Defaults to `NotEligible`.
+    pub fn imse(mut self, imse: InterMarketSweepEligibility) -> Self {
+        self.imse = imse;
+        self
+    }
+
+    /// Defaults to `ContinuousMarket`.
+    pub fn cross_type(mut self, cross_type: CrossType) -> Self {
+        self.cross_type = cross_type;
+        self
+    }
+
+    pub fn firm(mut self, firm: Firm) -> Self {
+        self.firm = Some(firm);
+        self
+    }
+
+    pub fn customer_type(mut self, customer_type: CustomerType) -> Self {
+        self.customer_type = customer_type;
+        self
+    }
+
+    pub fn post_only(mut self) -> Self {
+        self.post_only = PostOnly::PostOnly;
+        self
+    }
+
+    pub fn min_qty(mut self, qty: u32) -> Self {
+        self.min_qty = Some(qty);
+        self
+    }
+
+    /// The most shares to display at once (a reserve order).
+    pub fn max_floor(mut self, qty: u32) -> Self {
+        self.max_floor = Some(qty);
+        self
+    }
+
+    /// Defaults to `Limit`. Pegged orders need one of the peg types.
+    pub fn price_type(mut self, price_type: PriceType) -> Self {
+        self.price_type = price_type;
+        self
+    }
+
+    /// The offset from the peg. The price type must be a peg.
+    pub fn peg(mut self, offset: SignedPrice) -> Self {
+        self.peg_offset = Some(offset);
+        self
+    }
+
+    pub fn discretion(mut self, price: Price) -> Self {
+        self.discretion_price = Some(price);
+        self
+    }
+
+    pub fn handle_inst(mut self, handle_inst: HandleInst) -> Self {
+        self.handle_inst = handle_inst;
+        self
+    }
+
+    /// The time the order expires, in seconds past midnight. The time in force must be `GTT`.
+    pub fn expire_time(mut self, secs: u32) -> Self {
+        self.expire_time = Some(secs);
+        self
+    }
+
+    /// Adds an option without a method of its own. It's checked against the options an Enter
+    /// Order may have, and must not be one set by another method.
+    pub fn option(mut self, value: OptionValue) -> Self {
+        self.others.push(value);
+        self
+    }
+
+    pub fn build(self) -> Result<EnterOrder, OrderBuildError> {
+        use OrderBuildError as E;
+        let is_market = self.price.is_none();
+        if self.quantity == 0 || self.quantity > MAX_ORDER_QUANTITY {
+            return Err(E::InvalidQuantity(self.quantity));
+        }
+        if let Some(price) = self.price {
+            if price.to_raw() == 0 || price.to_raw() > Price::MAX_U64 {
+                return Err(E::InvalidPrice(price));
+            }
+        }
+        if let Some(min) = self.min_qty {
+            if min == 0 || min > self.quantity {
+                return Err(E::InvalidMinQty(min));
+            }
+        }
+        if let Some(floor) = self.max_floor {
+            if floor == 0 || floor > self.quantity {
+                return Err(E::InvalidMaxFloor(floor));
+            }
+        }
+        let is_peg = matches!(
+            self.price_type,
+            PriceType::MarketPeg
+                | PriceType::MidpointPeg
+                | PriceType::PrimaryPeg
+                | PriceType::MarketMakerPeg
+        );
+        if self.peg_offset.is_some() && !is_peg {
+            return Err(E::PegWithoutPegType(self.price_type));
+        }
+        if is_market {
+            if self.price_type != PriceType::Limit {
+                return Err(E::MarketWith("price type"));
+            }
+            if self.post_only == PostOnly::PostOnly {
+                return Err(E::MarketWith("post only"));
+            }
+            if self.discretion_price.is_some() {
+                return Err(E::MarketWith("discretion"));
+            }
+        }
+        match (self.time_in_force, self.expire_time) {
+            (TimeInForce::GTT, None) => return Err(E::MissingExpireTime),
+            (TimeInForce::GTT, Some(t)) if t > MAX_EXPIRE_TIME => {
+                return Err(E::InvalidExpireTime(t))
+            }
+            (TimeInForce::GTT, Some(_)) | (_, None) => (),
+            (tif, Some(_)) => return Err(E::ExpireTimeWithTif(tif)),
+        }
+
+        let mut opts = Vec::new();
+        if let Some(firm) = self.firm {
+            opts.push(OptionValue::Firm(firm));
+        }
+        if let Some(min) = self.min_qty {
+            opts.push(OptionValue::MinQty(min));
+        }
+        if self.customer_type != CustomerType::default_enter_order() {
+            opts.push(OptionValue::CustomerType(self.customer_type));
+        }
+        if let Some(floor) = self.max_floor {
+            opts.push(OptionValue::MaxFloor(floor));
+        }
+        if self.price_type != PriceType::default_enter_order() {
+            opts.push(OptionValue::PriceType(self.price_type));
+        }
+        if let Some(offset) = self.peg_offset {
+            opts.push(OptionValue::PegOffset(offset));
+        }
+        if let Some(price) = self.discretion_price {
+            opts.push(OptionValue::DiscretionPrice(price));
+        }
+        if self.post_only != PostOnly::default_enter_order() {
+            opts.push(OptionValue::PostOnly(self.post_only));
+        }
+        if let Some(t) = self.expire_time {
+            opts.push(OptionValue::ExpireTime(t));
+        }
+        if self.handle_inst != HandleInst::default_enter_order() {
+            opts.push(OptionValue::HandleInst(self.handle_inst));
+        }
+        for v in self.others {
+            let tag = OptionTag::from_u8(v.tag());
+            if !tag.is_some_and(|t| EnterOrder::OPTIONS.contains(&t)) {
+                return Err(E::InvalidOption(v.tag()));
+            }
+            if opts.iter().any(|o| o.tag() == v.tag()) {
+                return Err(E::DuplicateOption(v.tag()));
+            }
+            opts.push(v);
+        }
+        let optional_appendage =
+            OptionalAppendage::new(opts.into_iter().map(TagValue::new).collect::<Vec<_>>())
+                .map_err(|_| E::AppendageTooLong)?;
+
+        let price = match self.price {
+            Some(price) => price,
+            None if matches!(
+                self.cross_type,
+                CrossType::OpeningCross | CrossType::ClosingCross
+            ) =>
+            {
+                Price::MARKET_CROSS
+            }
+            None => Price::MARKET,
+        };
+        Ok(EnterOrder {
+            user_ref_num: self.user_ref_num,
+            side: self.side,
+            quantity: self.quantity,
+            symbol: self.symbol,
+            price,
+            time_in_force: self.time_in_force,
+            display: self.display,
+            capacity: self.capacity,
+            inter_market_sweep_eligibility: self.imse,
+            cross_type: self.cross_type,
+            cl_ord_id: self.cl_ord_id,
+            optional_appendage,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrderBuildError {
+    /// The quantity is 0 or more than `MAX_ORDER_QUANTITY`.
+    InvalidQuantity(u32),
+    /// The limit price is 0 or more than `Price::MAX`.
+    InvalidPrice(Price),
+    /// The min quantity is 0 or more than the quantity.
+    InvalidMinQty(u32),
+    /// The max floor is 0 or more than the quantity.
+    InvalidMaxFloor(u32),
+    /// A peg offset was given but the price type isn't a peg.
+    PegWithoutPegType(PriceType),
+    /// Market orders can't have the given option.
+    MarketWith(&'static str),
+    /// The time in force is GTT but there's no expire time.
+    MissingExpireTime,
+    InvalidExpireTime(u32),
+    /// An expire time was given but the time in force isn't GTT.
+    ExpireTimeWithTif(TimeInForce),
+    /// An option (by tag) Enter Orders can't have.
+    InvalidOption(u8),
+    /// An option (by tag) was given more than once.
+    DuplicateOption(u8),
+    AppendageTooLong,
+}
+
+impl fmt::Display for OrderBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderBuildError::InvalidQuantity(q) => write!(f, "invalid quantity: {q}"),
+            OrderBuildError::InvalidPrice(p) => write!(f, "invalid price: {p}"),
+            OrderBuildError::InvalidMinQty(q) => write!(f, "invalid min quantity: {q}"),
+            OrderBuildError::InvalidMaxFloor(q) => write!(f, "invalid max floor: {q}"),
+            OrderBuildError::PegWithoutPegType(pt) => {
+                write!(f, "peg offset with non-peg price type {pt:?}")
+            }
+            OrderBuildError::MarketWith(what) => write!(f, "market order with {what}"),
+            OrderBuildError::MissingExpireTime => write!(f, "GTT order without expire time"),
+            OrderBuildError::InvalidExpireTime(t) => write!(f, "invalid expire time: {t}"),
+            OrderBuildError::ExpireTimeWithTif(tif) => {
+                write!(f, "expire time with time in force {tif:?}")
+            }
+            OrderBuildError::InvalidOption(tag) => write!(f, "invalid option tag {tag}"),
+            OrderBuildError::DuplicateOption(tag) => write!(f, "duplicate option tag {tag}"),
+            OrderBuildError::AppendageTooLong => write!(f, "optional appendage too long"),
+        }
+    }
+}
+
+impl Error for OrderBuildError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limit(quantity: u32) -> EnterOrderBuilder {
+        EnterOrder::limit(sym(), OrderSide::Buy, quantity, px(1.0))
+    }
+
+    fn sym() -> Symbol {
+        Symbol::new_padded("AAPL").unwrap()
+    }
+
+    fn px(f: f64) -> Price {
+        Price::from_f64(f).unwrap()
+    }
+
+    #[test]
+    fn builds() {
+        let o = EnterOrder::limit(sym(), OrderSide::Buy, 300, px(10.0)).build().unwrap();
+        assert!(o.optional_appendage.is_empty());
+        assert_eq!(o.time_in_force, TimeInForce::Day);
+
+        let o = EnterOrder::limit(sym(), OrderSide::Sell, 300, px(10.0))
+            .post_only()
+            .min_qty(100)
+            .max_floor(100)
+            .discretion(px(10.1))
+            .handle_inst(HandleInst::ImbalanceOnly)
+            .tif(TimeInForce::GTT)
+            .expire_time(3600)
+            .option(OptionValue::RandomReserves(50))
+            .build()
+            .unwrap();
+        let tags: Vec<u8> = o.optional_appendage.iter().map(|t| t.option_value.tag()).collect();
+        assert_eq!(tags, [3, 5, 9, 12, 15, 17, 13]);
+        o.optional_appendage.validate(EnterOrder::TYPE, EnterOrder::OPTIONS).unwrap();
+        let enc = o.encode();
+        assert_eq!(enc.len(), EnterOrder::BASE_LEN + o.optional_appendage.encoded_len());
+
+        let p = EnterOrder::limit(sym(), OrderSide::Buy, 100, px(10.0))
+            .price_type(PriceType::PrimaryPeg)
+            .peg(SignedPrice::from_f64(-0.01).unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(p.optional_appendage.len(), 2);
+
+        let m = EnterOrder::market(sym(), OrderSide::Buy, 100).build().unwrap();
+        assert_eq!(m.price.to_raw(), Price::MARKET.to_raw());
+        let m = EnterOrder::market(sym(), OrderSide::Buy, 100)
+            .cross_type(CrossType::OpeningCross)
+            .build()
+            .unwrap();
+        assert!(m.price.is_market_cross());
+    }
+
+    #[test]
+    fn rejects() {
+        use OrderBuildError as E;
+        let e = |b: EnterOrderBuilder| b.build().unwrap_err();
+
+        assert_eq!(e(limit(0)), E::InvalidQuantity(0));
+        let zero = EnterOrder::limit(sym(), OrderSide::Buy, 1, Price::from_raw(0));
+        assert!(matches!(e(zero), E::InvalidPrice(_)));
+        assert_eq!(e(limit(10).min_qty(11)), E::InvalidMinQty(11));
+        assert_eq!(
+            e(limit(10).peg(SignedPrice::from_raw(1))),
+            E::PegWithoutPegType(PriceType::Limit)
+        );
+        assert_eq!(
+            e(EnterOrder::market(sym(), OrderSide::Buy, 10).post_only()),
+            E::MarketWith("post only")
+        );
+        assert_eq!(e(limit(10).tif(TimeInForce::GTT)), E::MissingExpireTime);
+        assert_eq!(e(limit(10).expire_time(5)), E::ExpireTimeWithTif(TimeInForce::Day));
+        assert_eq!(
+            e(limit(10).option(OptionValue::UserRefIdx(1)).option(OptionValue::UserRefIdx(2))),
+            E::DuplicateOption(28)
+        );
+        assert_eq!(
+            e(limit(10).option(OptionValue::DisplayPrice(px(1.0)))),
+            E::InvalidOption(23)
+        );
+        assert_eq!(
+            e(limit(10).min_qty(5).option(OptionValue::MinQty(3))),
+            E::DuplicateOption(3)
+        );
+    }
+}
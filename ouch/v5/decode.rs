@@ -0,0 +1,414 @@
+use super::*;
+
+use std::error::Error;
+
+/// Why a message couldn't be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Empty,
+    UnknownType(u8),
+    /// The message of type `msg_type` needed `need` bytes but only had `got`.
+    Truncated {
+        msg_type: u8,
+        need: usize,
+        got: usize,
+    },
+    /// An enum field had a value that isn't one of its variants.
+    InvalidEnum { field: &'static str, value: u16 },
+    /// A text field (like a symbol) had characters that aren't allowed.
+    InvalidText { field: &'static str },
+    /// There were bytes left after the message of type `msg_type`.
+    TrailingBytes { msg_type: u8, extra: usize },
+    /// The appendage was longer than `OptionalAppendage::MAX_LEN`.
+    AppendageTooLong(usize),
+    /// An option's value had the wrong length for its tag, or ran past the appendage.
+    OptionLength { tag: u8, len: usize },
+    /// A known option had a value that isn't valid for it.
+    InvalidOption { tag: u8 },
+    /// A message of type `msg_type` had an option it isn't allowed to have.
+    UnexpectedOption { msg_type: u8, tag: u8 },
+    /// A message of type `msg_type` had the same option twice.
+    DuplicateOption { msg_type: u8, tag: u8 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "empty message"),
+            DecodeError::UnknownType(t) => write!(f, "unknown message type {:?}", *t as char),
+            DecodeError::Truncated {
+                msg_type,
+                need,
+                got,
+            } => write!(
+                f,
+                "message type {:?} truncated: need {need} bytes, got {got}",
+                *msg_type as char,
+            ),
+            DecodeError::InvalidEnum { field, value } => {
+                write!(f, "invalid value {value:#x} for {field}")
+            }
+            DecodeError::InvalidText { field } => write!(f, "invalid text for {field}"),
+            DecodeError::TrailingBytes { msg_type, extra } => write!(
+                f,
+                "{extra} extra bytes after message type {:?}",
+                *msg_type as char,
+            ),
+            DecodeError::AppendageTooLong(len) => write!(f, "appendage too long: {len} bytes"),
+            DecodeError::OptionLength { tag, len } => {
+                write!(f, "invalid length {len} for option {tag}")
+            }
+            DecodeError::InvalidOption { tag } => write!(f, "invalid value for option {tag}"),
+            DecodeError::UnexpectedOption { msg_type, tag } => write!(
+                f,
+                "option {tag} not allowed on message type {:?}",
+                *msg_type as char,
+            ),
+            DecodeError::DuplicateOption { msg_type, tag } => write!(
+                f,
+                "option {tag} repeated on message type {:?}",
+                *msg_type as char,
+            ),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Reads the fields of a message in order, after its type byte.
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    msg_type: u8,
+}
+
+impl<'a> Reader<'a> {
+    /// Checks that the message is of type `msg_type`.
+    pub(crate) fn new(buf: &'a [u8], msg_type: u8) -> Result<Self, DecodeError> {
+        match buf.first() {
+            None => Err(DecodeError::Empty),
+            Some(&t) if t != msg_type => Err(DecodeError::UnknownType(t)),
+            Some(_) => Ok(Self {
+                buf,
+                pos: 1,
+                msg_type,
+            }),
+        }
+    }
+
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        let need = self.pos + n;
+        if need > self.buf.len() {
+            return Err(DecodeError::Truncated {
+                msg_type: self.msg_type,
+                need,
+                got: self.buf.len(),
+            });
+        }
+        let b = &self.buf[self.pos..need];
+        self.pos = need;
+        Ok(b)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, DecodeError> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.array().map(u64::from_be_bytes)
+    }
+
+    pub(crate) fn timestamp(&mut self) -> Result<i64, DecodeError> {
+        self.array().map(i64::from_be_bytes)
+    }
+
+    pub(crate) fn user_ref_num(&mut self) -> Result<UserRefNum, DecodeError> {
+        self.array().map(UserRefNum::from_bytes)
+    }
+
+    pub(crate) fn price(&mut self) -> Result<nasdaq::Price, DecodeError> {
+        self.array().map(nasdaq::Price::from_bytes)
+    }
+
+    pub(crate) fn side(&mut self, field: &'static str) -> Result<OrderSide, DecodeError> {
+        self.enum_u8(field, OrderSide::from_u8)
+    }
+
+    pub(crate) fn symbol(&mut self) -> Result<nasdaq::Symbol, DecodeError> {
+        let b = self.bytes(8)?;
+        nasdaq::Symbol::new(b).map_err(|_| DecodeError::InvalidText { field: "symbol" })
+    }
+
+    pub(crate) fn firm(&mut self, field: &'static str) -> Result<Firm, DecodeError> {
+        let b = self.bytes(4)?;
+        Firm::new(b).map_err(|_| DecodeError::InvalidText { field })
+    }
+
+    pub(crate) fn cl_ord_id(&mut self) -> Result<ClOrdId, DecodeError> {
+        let b = self.bytes(14)?;
+        ClOrdId::from_bytes(b).ok_or(DecodeError::InvalidText { field: "cl_ord_id" })
+    }
+
+    pub(crate) fn enum_u8<T>(
+        &mut self,
+        field: &'static str,
+        from_u8: impl FnOnce(u8) -> Option<T>,
+    ) -> Result<T, DecodeError> {
+        let b = self.u8()?;
+        from_u8(b).ok_or(DecodeError::InvalidEnum {
+            field,
+            value: b as u16,
+        })
+    }
+
+    /// Reads the appendage length and the appendage, checking its options against `allowed`.
+    pub(crate) fn appendage(
+        &mut self,
+        allowed: &[OptionTag],
+    ) -> Result<OptionalAppendage, DecodeError> {
+        let len = self.u16()? as usize;
+        let app = OptionalAppendage::decode(self.bytes(len)?)?;
+        app.validate(self.msg_type, allowed)?;
+        Ok(app)
+    }
+
+    /// Makes sure the whole message was read.
+    pub(crate) fn finish<T>(self, msg: T) -> Result<T, DecodeError> {
+        if self.pos != self.buf.len() {
+            return Err(DecodeError::TrailingBytes {
+                msg_type: self.msg_type,
+                extra: self.buf.len() - self.pos,
+            });
+        }
+        Ok(msg)
+    }
+}
+
+/// Any message sent by the exchange.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutboundMessage {
+    SystemEvent(SystemEvent),
+    OrderAccepted(OrderAccepted),
+    OrderReplaced(OrderReplaced),
+    OrderCanceled(OrderCanceled),
+    AiqCanceled(AiqCanceled),
+    OrderExecuted(OrderExecuted),
+    BrokenTrade(BrokenTrade),
+    Rejected(Rejected),
+    CancelPending(CancelPending),
+    CancelReject(CancelReject),
+    OrderPriorityUpdate(OrderPriorityUpdate),
+    OrderModified(OrderModified),
+    OrderRestated(OrderRestated),
+    MassCancelResponse(MassCancelResponse),
+    DisableOrderEntryResponse(DisableOrderEntryResponse),
+    EnableOrderEntryResponse(EnableOrderEntryResponse),
+    AccountQueryResponse(AccountQueryResponse),
+}
+
+impl OutboundMessage {
+    /// Decodes a whole message (the payload of a SoupBinTCP sequenced data packet), choosing the
+    /// message by its type byte.
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let Some(&msg_type) = buf.first() else {
+            return Err(DecodeError::Empty);
+        };
+        Ok(match msg_type {
+            SystemEvent::TYPE => Self::SystemEvent(SystemEvent::decode(buf)?),
+            OrderAccepted::TYPE => Self::OrderAccepted(OrderAccepted::decode(buf)?),
+            OrderReplaced::TYPE => Self::OrderReplaced(OrderReplaced::decode(buf)?),
+            OrderCanceled::TYPE => Self::OrderCanceled(OrderCanceled::decode(buf)?),
+            AiqCanceled::TYPE => Self::AiqCanceled(AiqCanceled::decode(buf)?),
+            OrderExecuted::TYPE => Self::OrderExecuted(OrderExecuted::decode(buf)?),
+            BrokenTrade::TYPE => Self::BrokenTrade(BrokenTrade::decode(buf)?),
+            Rejected::TYPE => Self::Rejected(Rejected::decode(buf)?),
+            CancelPending::TYPE => Self::CancelPending(CancelPending::decode(buf)?),
+            CancelReject::TYPE => Self::CancelReject(CancelReject::decode(buf)?),
+            OrderPriorityUpdate::TYPE => {
+                Self::OrderPriorityUpdate(OrderPriorityUpdate::decode(buf)?)
+            }
+            OrderModified::TYPE => Self::OrderModified(OrderModified::decode(buf)?),
+            OrderRestated::TYPE => Self::OrderRestated(OrderRestated::decode(buf)?),
+            MassCancelResponse::TYPE => Self::MassCancelResponse(MassCancelResponse::decode(buf)?),
+            DisableOrderEntryResponse::TYPE => {
+                Self::DisableOrderEntryResponse(DisableOrderEntryResponse::decode(buf)?)
+            }
+            EnableOrderEntryResponse::TYPE => {
+                Self::EnableOrderEntryResponse(EnableOrderEntryResponse::decode(buf)?)
+            }
+            AccountQueryResponse::TYPE => {
+                Self::AccountQueryResponse(AccountQueryResponse::decode(buf)?)
+            }
+            t => return Err(DecodeError::UnknownType(t)),
+        })
+    }
+
+    pub fn msg_type(&self) -> u8 {
+        match self {
+            Self::SystemEvent(_) => SystemEvent::TYPE,
+            Self::OrderAccepted(_) => OrderAccepted::TYPE,
+            Self::OrderReplaced(_) => OrderReplaced::TYPE,
+            Self::OrderCanceled(_) => OrderCanceled::TYPE,
+            Self::AiqCanceled(_) => AiqCanceled::TYPE,
+            Self::OrderExecuted(_) => OrderExecuted::TYPE,
+            Self::BrokenTrade(_) => BrokenTrade::TYPE,
+            Self::Rejected(_) => Rejected::TYPE,
+            Self::CancelPending(_) => CancelPending::TYPE,
+            Self::CancelReject(_) => CancelReject::TYPE,
+            Self::OrderPriorityUpdate(_) => OrderPriorityUpdate::TYPE,
+            Self::OrderModified(_) => OrderModified::TYPE,
+            Self::OrderRestated(_) => OrderRestated::TYPE,
+            Self::MassCancelResponse(_) => MassCancelResponse::TYPE,
+            Self::DisableOrderEntryResponse(_) => DisableOrderEntryResponse::TYPE,
+            Self::EnableOrderEntryResponse(_) => EnableOrderEntryResponse::TYPE,
+            Self::AccountQueryResponse(_) => AccountQueryResponse::TYPE,
+        }
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        match self {
+            Self::SystemEvent(m) => m.timestamp,
+            Self::OrderAccepted(m) => m.timestamp,
+            Self::OrderReplaced(m) => m.timestamp,
+            Self::OrderCanceled(m) => m.timestamp,
+            Self::AiqCanceled(m) => m.timestamp,
+            Self::OrderExecuted(m) => m.timestamp,
+            Self::BrokenTrade(m) => m.timestamp,
+            Self::Rejected(m) => m.timestamp,
+            Self::CancelPending(m) => m.timestamp,
+            Self::CancelReject(m) => m.timestamp,
+            Self::OrderPriorityUpdate(m) => m.timestamp,
+            Self::OrderModified(m) => m.timestamp,
+            Self::OrderRestated(m) => m.timestamp,
+            Self::MassCancelResponse(m) => m.timestamp,
+            Self::DisableOrderEntryResponse(m) => m.timestamp,
+            Self::EnableOrderEntryResponse(m) => m.timestamp,
+            Self::AccountQueryResponse(m) => m.timestamp,
+        }
+    }
+
+    /// The order (or request) the message is about. For replacements, this is the new order's.
+    /// `None` for system events and account query responses.
+    pub fn user_ref_num(&self) -> Option<UserRefNum> {
+        Some(match self {
+            Self::SystemEvent(_) | Self::AccountQueryResponse(_) => return None,
+            Self::OrderAccepted(m) => m.user_ref_num,
+            Self::OrderReplaced(m) => m.user_ref_num,
+            Self::OrderCanceled(m) => m.user_ref_num,
+            Self::AiqCanceled(m) => m.user_ref_num,
+            Self::OrderExecuted(m) => m.user_ref_num,
+            Self::BrokenTrade(m) => m.user_ref_num,
+            Self::Rejected(m) => m.user_ref_num,
+            Self::CancelPending(m) => m.user_ref_num,
+            Self::CancelReject(m) => m.user_ref_num,
+            Self::OrderPriorityUpdate(m) => m.user_ref_num,
+            Self::OrderModified(m) => m.user_ref_num,
+            Self::OrderRestated(m) => m.user_ref_num,
+            Self::MassCancelResponse(m) => m.user_ref_num,
+            Self::DisableOrderEntryResponse(m) => m.user_ref_num,
+            Self::EnableOrderEntryResponse(m) => m.user_ref_num,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::nasdaq::*;
+
+    fn accepted() -> Vec<u8> {
+        let mut b = vec![b'A'];
+        b.extend(42i64.to_be_bytes());
+        b.extend(7u32.to_be_bytes());
+        b.push(b'B');
+        b.extend(300u32.to_be_bytes());
+        b.extend(b"AAPL    ");
+        b.extend(1_872_500u64.to_be_bytes());
+        b.extend(b"0Y");
+        b.extend(99u64.to_be_bytes());
+        b.extend(b"ANNL");
+        b.extend(b"ABC123        ");
+        b.extend(0u16.to_be_bytes());
+        b
+    }
+
+    #[test]
+    fn order_accepted() {
+        let m = OutboundMessage::decode(&accepted()).unwrap();
+        let OutboundMessage::OrderAccepted(a) = &m else { panic!("{m:?}") };
+        assert_eq!(a.timestamp, 42);
+        assert_eq!(a.user_ref_num, UserRefNum(7));
+        assert_eq!(a.side, OrderSide::Buy);
+        assert_eq!(a.quantity, 300);
+        assert_eq!(a.symbol, Symbol::new_padded("AAPL").unwrap());
+        assert_eq!(a.order_reference_number, 99);
+        assert_eq!(a.order_state, OrderState::Live);
+        assert_eq!(m.user_ref_num(), Some(UserRefNum(7)));
+        assert_eq!(m.msg_type(), b'A');
+    }
+
+    #[test]
+    fn errors() {
+        let b = accepted();
+        assert_eq!(OutboundMessage::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(OutboundMessage::decode(b"Z"), Err(DecodeError::UnknownType(b'Z')));
+        assert!(matches!(
+            OutboundMessage::decode(&b[..20]),
+            Err(DecodeError::Truncated { msg_type: b'A', .. })
+        ));
+        let mut extra = b.clone();
+        extra.push(0);
+        assert_eq!(
+            OutboundMessage::decode(&extra),
+            Err(DecodeError::TrailingBytes { msg_type: b'A', extra: 1 })
+        );
+        let mut bad = b;
+        bad[13] = b'Q';
+        assert!(matches!(OutboundMessage::decode(&bad), Err(DecodeError::InvalidEnum { .. })));
+    }
+
+    #[test]
+    fn small_messages() {
+        let mut b = vec![b'J'];
+        b.extend(1i64.to_be_bytes());
+        b.extend(5u32.to_be_bytes());
+        b.extend(0x1au16.to_be_bytes());
+        b.extend(b"X             ");
+        b.extend(0u16.to_be_bytes());
+        let OutboundMessage::Rejected(r) = OutboundMessage::decode(&b).unwrap() else { panic!() };
+        assert_eq!(r.reason, RejectReason::RetailNotAllowed);
+
+        let mut b = vec![b'S'];
+        b.extend(1i64.to_be_bytes());
+        b.push(b'S');
+        assert!(matches!(OutboundMessage::decode(&b), Ok(OutboundMessage::SystemEvent(_))));
+
+        let mut b = vec![b'Q'];
+        b.extend(1i64.to_be_bytes());
+        b.extend(77u32.to_be_bytes());
+        b.extend(0u16.to_be_bytes());
+        let OutboundMessage::AccountQueryResponse(q) = OutboundMessage::decode(&b).unwrap() else {
+            panic!()
+        };
+        assert_eq!(q.next_user_ref_num, UserRefNum(77));
+
+        for v in 1..=50u16 {
+            assert_eq!(RejectReason::from_u16(v).unwrap().to_u16(), v);
+        }
+        assert_eq!(RejectReason::from_u16(0), None);
+        assert_eq!(RejectReason::from_u16(51), None);
+    }
+}
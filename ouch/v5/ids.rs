@@ -0,0 +1,389 @@
+//! UserRefNums and ClOrdIds that stay unique across restarts.
+//!
+//! Numbers are reserved in blocks, with the end of the block written (and synced) to a file
+//! before any number in it is handed out. A restarted process starts after the last reserved
+//! block, so numbers may be skipped but are never reused.
+
+use super::{ClOrdId, UserRefNum};
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The default number of IDs reserved with each write to the file.
+pub const DEFAULT_BLOCK_SIZE: u32 = 1024;
+
+/// Allocates strictly increasing UserRefNums for a day, persisted to a file so a restarted
+/// process never reuses one. The file holds the date and the first unreserved number; if its
+/// date isn't the one given, numbering starts again at 1.
+pub struct UserRefNumAllocator {
+    counter: Counter,
+}
+
+impl UserRefNumAllocator {
+    /// Opens the allocator backed by the file at `path`, which is created on the first
+    /// allocation if it doesn't exist. `date` is the trading day as YYYYMMDD (see `today`).
+    pub fn open(path: impl Into<PathBuf>, date: u32) -> Result<Self, IdError> {
+        Ok(Self {
+            counter: Counter::open(path.into(), date, 1, u32::MAX as u64)?,
+        })
+    }
+
+    /// Sets how many numbers are reserved with each write to the file.
+    pub fn set_block_size(&mut self, size: u32) {
+        self.counter.block = size.max(1) as u64;
+    }
+
+    /// Returns the next UserRefNum, marking it used.
+    pub fn allocate(&mut self) -> Result<UserRefNum, IdError> {
+        Ok(UserRefNum(self.counter.take()? as u32))
+    }
+
+    /// The UserRefNum `allocate` would return.
+    pub fn peek(&self) -> UserRefNum {
+        UserRefNum(self.counter.next.min(u32::MAX as u64) as u32)
+    }
+
+    /// Marks `urn` and everything before it as used, e.g., when syncing with the server.
+    pub fn claim(&mut self, urn: UserRefNum) -> Result<(), IdError> {
+        self.counter.claim(urn.0 as u64)
+    }
+
+    pub fn date(&self) -> u32 {
+        self.counter.date
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.counter.path
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ClOrdIdOptions {
+    prefix: String,
+    date: Option<u32>,
+    block: u32,
+}
+
+impl Default for ClOrdIdOptions {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            date: None,
+            block: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+impl ClOrdIdOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The alphanumeric characters every ID starts with.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// The date (as YYYYMMDD) to put after the prefix as YYMMDD. The counter starts again at 0
+    /// when the date changes. Without a date, the counter never starts again.
+    pub fn with_date(mut self, date: u32) -> Self {
+        self.date = Some(date);
+        self
+    }
+
+    /// How many IDs are reserved with each write to the file.
+    pub fn with_block_size(mut self, size: u32) -> Self {
+        self.block = size.max(1);
+        self
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn date(&self) -> Option<u32> {
+        self.date
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.block
+    }
+
+    /// Opens the generator backed by the file at `path`, which is created on the first ID if it
+    /// doesn't exist.
+    pub fn open(self, path: impl Into<PathBuf>) -> Result<ClOrdIdGenerator, IdError> {
+        if !self.prefix.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(IdError::Invalid(format!("bad prefix: {:?}", self.prefix)));
+        }
+        let mut head = self.prefix.as_bytes().to_vec();
+        if let Some(date) = self.date {
+            if !(10000101..=99991231).contains(&date) {
+                return Err(IdError::Invalid(format!("bad date: {date}")));
+            }
+            head.extend(format!("{:06}", date % 1_000_000).bytes());
+        }
+        if head.len() >= 14 {
+            return Err(IdError::Invalid("prefix and date leave no room for a counter".into()));
+        }
+        let width = 14 - head.len() as u32;
+        let max = 36u64.checked_pow(width).map_or(u64::MAX, |n| n - 1);
+        let mut counter = Counter::open(path.into(), self.date.unwrap_or(0), 0, max)?;
+        counter.block = self.block as u64;
+        Ok(ClOrdIdGenerator {
+            head,
+            width: width as usize,
+            counter,
+        })
+    }
+}
+
+/// Generates unique ClOrdIds made of a prefix, an optional date, and a base-36 counter filling
+/// the rest of the 14 bytes, persisted to a file so a restarted process never reuses one.
+pub struct ClOrdIdGenerator {
+    head: Vec<u8>,
+    width: usize,
+    counter: Counter,
+}
+
+impl ClOrdIdGenerator {
+    pub fn options() -> ClOrdIdOptions {
+        ClOrdIdOptions::new()
+    }
+
+    pub fn generate(&mut self) -> Result<ClOrdId, IdError> {
+        let mut n = self.counter.take()?;
+        let mut id = [b'0'; 14];
+        id[..self.head.len()].copy_from_slice(&self.head);
+        for b in id[self.head.len()..].iter_mut().rev() {
+            *b = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ"[(n % 36) as usize];
+            n /= 36;
+        }
+        Ok(ClOrdId(id))
+    }
+
+    /// The number of characters left for the counter.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.counter.path
+    }
+}
+
+/// The date (as YYYYMMDD) it is now, `utc_offset_secs` from UTC (e.g., -5 * 3600 for US
+/// Eastern Standard Time).
+pub fn today(utc_offset_secs: i32) -> u32 {
+    date_at(SystemTime::now(), utc_offset_secs)
+}
+
+/// The date (as YYYYMMDD) at `time`, `utc_offset_secs` from UTC.
+pub fn date_at(time: SystemTime, utc_offset_secs: i32) -> u32 {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let days = (secs + utc_offset_secs as i64).div_euclid(86400);
+    // Converts days since the epoch to a civil date (see Howard Hinnant's `civil_from_days`).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year * 10000 + month * 100 + day) as u32
+}
+
+/// A counter whose reserved range is persisted to a file.
+struct Counter {
+    path: PathBuf,
+    date: u32,
+    next: u64,
+    /// The first number not yet written to the file.
+    reserved: u64,
+    block: u64,
+    max: u64,
+}
+
+impl Counter {
+    /// Opens the counter, starting at `start` if the file doesn't exist or is for another date.
+    fn open(path: PathBuf, date: u32, start: u64, max: u64) -> Result<Self, IdError> {
+        let next = match fs::read_to_string(&path) {
+            Ok(s) => {
+                let bad = || IdError::Invalid(format!("bad id file {}", path.display()));
+                let (d, n) = s.trim().split_once(' ').ok_or_else(bad)?;
+                let d: u32 = d.parse().map_err(|_| bad())?;
+                let n: u64 = n.parse().map_err(|_| bad())?;
+                if d == date {
+                    n
+                } else {
+                    start
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => start,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            date,
+            next,
+            reserved: next,
+            block: DEFAULT_BLOCK_SIZE as u64,
+            max,
+        })
+    }
+
+    fn take(&mut self) -> Result<u64, IdError> {
+        let n = self.next;
+        if n > self.max {
+            return Err(IdError::Exhausted);
+        }
+        self.claim(n)?;
+        Ok(n)
+    }
+
+    fn claim(&mut self, n: u64) -> Result<(), IdError> {
+        if n > self.max {
+            return Err(IdError::Exhausted);
+        }
+        if n >= self.reserved {
+            let reserved = n.saturating_add(self.block);
+            self.persist(reserved)?;
+            self.reserved = reserved;
+        }
+        self.next = self.next.max(n + 1);
+        Ok(())
+    }
+
+    /// Writes the file by renaming a synced temporary file over it, so it's never left torn.
+    fn persist(&self, reserved: u64) -> io::Result<()> {
+        let mut tmp = OsString::from(self.path.as_os_str());
+        tmp.push(".tmp");
+        let mut f = File::create(&tmp)?;
+        writeln!(f, "{} {}", self.date, reserved)?;
+        f.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        // Sync the directory so the rename itself survives a crash.
+        if let Some(dir) = self.path.parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            if let Ok(d) = File::open(dir) {
+                let _ = d.sync_all();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum IdError {
+    Io(io::Error),
+    /// A bad option or file contents.
+    Invalid(String),
+    /// There are no more IDs left for the day.
+    Exhausted,
+}
+
+impl From<io::Error> for IdError {
+    fn from(e: io::Error) -> Self {
+        IdError::Io(e)
+    }
+}
+
+impl fmt::Display for IdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdError::Io(ref e) => write!(f, "io error: {e}"),
+            IdError::Invalid(ref msg) => write!(f, "{msg}"),
+            IdError::Exhausted => write!(f, "ids exhausted"),
+        }
+    }
+}
+
+impl Error for IdError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ouch-{name}-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn user_ref_nums_survive_reopen() {
+        let p = temp_path("urn");
+        let mut a = UserRefNumAllocator::open(&p, 20261018).unwrap();
+        a.set_block_size(4);
+        assert_eq!(a.allocate().unwrap(), UserRefNum(1));
+        assert_eq!(a.allocate().unwrap(), UserRefNum(2));
+        drop(a);
+        // The rest of the reserved block is skipped.
+        let mut a = UserRefNumAllocator::open(&p, 20261018).unwrap();
+        assert_eq!(a.allocate().unwrap(), UserRefNum(5));
+        a.claim(UserRefNum(5000)).unwrap();
+        assert_eq!(a.allocate().unwrap(), UserRefNum(5001));
+        drop(a);
+        let mut a = UserRefNumAllocator::open(&p, 20261018).unwrap();
+        assert!(a.peek().0 > 5001);
+        // A new day starts over.
+        let mut b = UserRefNumAllocator::open(&p, 20261019).unwrap();
+        assert_eq!(b.allocate().unwrap(), UserRefNum(1));
+        a.allocate().unwrap();
+        fs::remove_file(&p).unwrap();
+    }
+
+    #[test]
+    fn cl_ord_ids_survive_reopen() {
+        let p = temp_path("cl");
+        let opts = || ClOrdIdGenerator::options().with_prefix("AB").with_date(20261018);
+        let mut g = opts().with_block_size(1).open(&p).unwrap();
+        assert_eq!(g.generate().unwrap().to_string(), "AB261018000000");
+        assert_eq!(g.generate().unwrap().to_string(), "AB261018000001");
+        drop(g);
+        let mut g = opts().open(&p).unwrap();
+        assert_eq!(g.generate().unwrap().to_string(), "AB261018000002");
+        for _ in 0..33 {
+            g.generate().unwrap();
+        }
+        assert_eq!(g.generate().unwrap().to_string(), "AB261018000010");
+        fs::remove_file(&p).unwrap();
+    }
+
+    #[test]
+    fn cl_ord_id_options() {
+        let p = temp_path("cl-opts");
+        assert!(ClOrdIdGenerator::options().with_prefix("A-").open(&p).is_err());
+        let long = ClOrdIdGenerator::options().with_prefix("ABCDEFGH").with_date(20261018);
+        assert!(long.open(&p).is_err());
+        let mut g = ClOrdIdGenerator::options().with_prefix("ABCDEFGHIJKLM").open(&p).unwrap();
+        for _ in 0..36 {
+            g.generate().unwrap();
+        }
+        assert!(matches!(g.generate(), Err(IdError::Exhausted)));
+        let _ = fs::remove_file(&p);
+    }
+
+    #[test]
+    fn dates() {
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_208_000);
+        assert_eq!(date_at(UNIX_EPOCH, 0), 19700101);
+        assert_eq!(date_at(leap_day, 0), 20240229);
+        assert_eq!(date_at(leap_day, -13 * 3600), 20240228);
+        assert_eq!(date_at(UNIX_EPOCH - Duration::from_secs(1), 0), 19691231);
+    }
+}
@@ -0,0 +1,74 @@
+//! A switch to stop a strategy from entering orders.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// A switch that, once triggered, stops sessions it's given to from entering orders and has them
+/// send their kill actions (see `OuchSessionOptions::with_kill_switch`). Clones share the same
+/// switch, so one can be given to many sessions and triggered from anywhere.
+#[derive(Clone, Default)]
+pub struct KillSwitch(Arc<InnerKillSwitch>);
+
+#[derive(Default)]
+struct InnerKillSwitch {
+    triggered: AtomicBool,
+    /// The number of times the switch has gone from not triggered to triggered.
+    triggers: AtomicU64,
+    reason: Mutex<Option<String>>,
+    cond: Condvar,
+}
+
+impl KillSwitch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trigger(&self, reason: impl Into<String>) {
+        let mut r = self.0.reason.lock().unwrap();
+        if !self.0.triggered.swap(true, Ordering::SeqCst) {
+            self.0.triggers.fetch_add(1, Ordering::SeqCst);
+            *r = Some(reason.into());
+        }
+        self.0.cond.notify_all();
+    }
+
+    /// Lets orders be entered again. Sessions send their kill actions again if it's triggered
+    /// again.
+    pub fn reset(&self) {
+        let mut r = self.0.reason.lock().unwrap();
+        self.0.triggered.store(false, Ordering::SeqCst);
+        *r = None;
+        self.0.cond.notify_all();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.triggered.load(Ordering::SeqCst)
+    }
+
+    /// The reason given when the switch was triggered.
+    pub fn reason(&self) -> Option<String> {
+        self.0.reason.lock().unwrap().clone()
+    }
+
+    /// The number of times the switch has been triggered (not counting triggers while it
+    /// already was), which tells one trigger from the next.
+    pub(crate) fn triggers(&self) -> u64 {
+        self.0.triggers.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the switch is woken or the timeout passes, returning right away if whether
+    /// it's triggered isn't `triggered`.
+    pub(crate) fn wait(&self, triggered: bool, timeout: Duration) {
+        let r = self.0.reason.lock().unwrap();
+        if self.is_triggered() == triggered {
+            drop(self.0.cond.wait_timeout(r, timeout).unwrap());
+        }
+    }
+
+    /// Wakes anything waiting on the switch.
+    pub(crate) fn wake(&self) {
+        let _r = self.0.reason.lock().unwrap();
+        self.0.cond.notify_all();
+    }
+}
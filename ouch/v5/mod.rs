@@ -1,13 +1,33 @@
-// TODO: encoding/decoding
-
-// TODO: what combo of letters/spaces is valid/invalid? right-padded or left-padded for
-// Firm/Symbol/ect.
-use std::cmd::{PartialEq, PartialOrd};
+// Alpha fields (Firm/Symbol/etc.) are left-justified and padded on the right with spaces.
+use common::nasdaq::{self, OrderSide};
 use std::fmt;
+use std::io::{self, Write};
 use std::ops::{Deref, DerefMut};
 
+mod builder;
+pub use builder::*;
+mod decode;
+pub use decode::{DecodeError, OutboundMessage};
+use decode::Reader;
+mod ids;
+pub use ids::*;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "soupbintcp")]
+mod kill_switch;
+#[cfg(feature = "soupbintcp")]
+pub mod session;
+#[cfg(feature = "soupbintcp")]
+mod throttle;
+mod risk;
+pub use risk::*;
+mod text;
+mod tracker;
+pub use tracker::*;
+
 pub const REVISION: u8 = 4;
 
+/// A message, encoded big-endian as laid out in the spec.
 pub trait Message {
     const TYPE: u8;
     fn encode(&self) -> Vec<u8>;
@@ -19,25 +39,44 @@ pub trait Message {
             return None;
         }
         buf[..encoded.len()].copy_from_slice(&encoded);
-        encoded.len()
+        Some(encoded.len())
     }
     /// Write encoded to a writer.
-    fn encode_to<W: Write>(&self, w: W) -> io::Result<()> {
-        w.write(&self.encode())
+    fn encode_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(&self.encode())
     }
 }
 
 /* Inbound messages */
 
 #[repr(transparent)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct UserRefNum(pub u32);
 
 impl UserRefNum {
+    pub const fn from_bytes(bytes: [u8; 4]) -> Self {
+        Self(u32::from_be_bytes(bytes))
+    }
+
+    /// The big-endian encoding.
+    pub const fn to_bytes(self) -> [u8; 4] {
+        self.0.to_be_bytes()
+    }
+
     pub const fn incr(self) -> Self {
         Self(self.0 + 1)
     }
 
+    /// The next UserRefNum, or `None` if this is the last one.
+    pub const fn checked_incr(self) -> Option<Self> {
+        match self.0.checked_add(1) {
+            Some(n) => Some(Self(n)),
+            None => None,
+        }
+    }
+
     pub const fn add(self, n: u32) -> Self {
         Self(self.0 + n)
     }
@@ -53,11 +92,10 @@ impl ClOrdId {
             return None;
         }
         let mut arr = [0u8; 14];
-        for i in 0..bytes.len() {
-            let b = bytes[i];
+        for (i, &b) in bytes.iter().enumerate() {
             // FIXME: are there specific places spaces are/aren't allowed e.g., are spaces only
             // padding characers, can an ID be all spaces?
-            if !b.is_ascii_alphanumeric() || b == b' ' {
+            if !b.is_ascii_alphanumeric() && b != b' ' {
                 return None;
             }
             arr[i] = b;
@@ -73,12 +111,13 @@ impl ClOrdId {
 
 impl fmt::Display for ClOrdId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write(f, "{}", String::from_utf8_lossy(&self.0))
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TimeInForce {
     Day = b'0',
     IOC = b'3',
@@ -88,13 +127,25 @@ pub enum TimeInForce {
 }
 
 impl TimeInForce {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'0' => Some(TimeInForce::Day),
+            b'3' => Some(TimeInForce::IOC),
+            b'5' => Some(TimeInForce::GTX),
+            b'6' => Some(TimeInForce::GTT),
+            b'E' => Some(TimeInForce::AfterHours),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Display {
     Visible = b'Y',
     Hidden = b'N',
@@ -102,13 +153,23 @@ pub enum Display {
 }
 
 impl Display {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'Y' => Some(Display::Visible),
+            b'N' => Some(Display::Hidden),
+            b'A' => Some(Display::Attributable),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as u8
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Capacity {
     Agency = b'A',
     Principal = b'P',
@@ -117,13 +178,24 @@ pub enum Capacity {
 }
 
 impl Capacity {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'A' => Some(Capacity::Agency),
+            b'P' => Some(Capacity::Principal),
+            b'R' => Some(Capacity::Riskless),
+            b'O' => Some(Capacity::Other),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InterMarketSweepEligibility {
     Eligible = b'Y',
     NotEligible = b'N',
@@ -131,13 +203,22 @@ pub enum InterMarketSweepEligibility {
 pub type IMSE = InterMarketSweepEligibility;
 
 impl InterMarketSweepEligibility {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'Y' => Some(InterMarketSweepEligibility::Eligible),
+            b'N' => Some(InterMarketSweepEligibility::NotEligible),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CrossType {
     ContinuousMarket = b'N',
     OpeningCross = b'O',
@@ -150,13 +231,28 @@ pub enum CrossType {
 }
 
 impl CrossType {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'N' => Some(CrossType::ContinuousMarket),
+            b'O' => Some(CrossType::OpeningCross),
+            b'C' => Some(CrossType::ClosingCross),
+            b'H' => Some(CrossType::HaltIpo),
+            b'S' => Some(CrossType::Supplemental),
+            b'R' => Some(CrossType::Retail),
+            b'E' => Some(CrossType::ExtendedLife),
+            b'A' => Some(CrossType::AfterHoursClose),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CustomerType {
     RetailDesignatedOrder = b'R',
     #[default]
@@ -169,13 +265,23 @@ impl CustomerType {
         CustomerType::UsePortDefault
     }
 
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'R' => Some(CustomerType::RetailDesignatedOrder),
+            b'N' => Some(CustomerType::NotRetailDesignated),
+            b' ' => Some(CustomerType::UsePortDefault),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PriceType {
     #[default]
     Limit = b'L',
@@ -191,13 +297,26 @@ impl PriceType {
         PriceType::Limit
     }
 
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'L' => Some(PriceType::Limit),
+            b'P' => Some(PriceType::MarketPeg),
+            b'M' => Some(PriceType::MidpointPeg),
+            b'R' => Some(PriceType::PrimaryPeg),
+            b'Q' => Some(PriceType::MarketMakerPeg),
+            b'm' => Some(PriceType::Midpoint),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PostOnly {
     PostOnly = b'P',
     #[default]
@@ -209,13 +328,22 @@ impl PostOnly {
         PostOnly::No
     }
 
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'P' => Some(PostOnly::PostOnly),
+            b'N' => Some(PostOnly::No),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TradeNow {
     UsePortDefault = b' ',
     Yes = b'Y',
@@ -247,6 +375,15 @@ impl TradeNow {
         }
     }
 
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b' ' => Some(TradeNow::UsePortDefault),
+            b'Y' => Some(TradeNow::Yes),
+            b'N' => Some(TradeNow::No),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
@@ -271,7 +408,8 @@ impl From<TradeNow> for Option<bool> {
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SharesLocated {
     Yes = b'Y',
     #[default]
@@ -294,6 +432,14 @@ impl SharesLocated {
         }
     }
 
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'Y' => Some(SharesLocated::Yes),
+            b'N' => Some(SharesLocated::No),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
@@ -312,7 +458,8 @@ impl From<SharesLocated> for bool {
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Handle instructions.
 pub enum HandleInst {
     No = b' ',
@@ -331,13 +478,28 @@ impl HandleInst {
         HandleInst::No
     }
 
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b' ' => Some(HandleInst::No),
+            b'I' => Some(HandleInst::ImbalanceOnly),
+            b'O' => Some(HandleInst::RetailOrderType1),
+            b'T' => Some(HandleInst::RetailOrderType2),
+            b'Q' => Some(HandleInst::RetailPriceImprovement),
+            b'B' => Some(HandleInst::ExtendedLifeContinuous),
+            b'D' => Some(HandleInst::DirectListingCapitalRaise),
+            b'R' => Some(HandleInst::RPIHPII),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BboWeightIndicator {
     #[default]
     Unspecified = b' ',
@@ -350,6 +512,19 @@ pub enum BboWeightIndicator {
 }
 
 impl BboWeightIndicator {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b' ' => Some(BboWeightIndicator::Unspecified),
+            b'0' => Some(BboWeightIndicator::Pct0),
+            b'1' => Some(BboWeightIndicator::Pct1),
+            b'2' => Some(BboWeightIndicator::Pct2),
+            b'3' => Some(BboWeightIndicator::Pct3),
+            b'S' => Some(BboWeightIndicator::SetQbbo),
+            b'N' => Some(BboWeightIndicator::ImproveNbbo),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
@@ -360,22 +535,22 @@ impl BboWeightIndicator {
 pub struct BrokerCode([u8; 4]);
 
 impl BrokerCode {
-    // FIXME: is comment below correct?
-    // A valid new broker is one that is 4 bytes long and contains all caps or spaces.
-    pub fn new<B: AsRef<[u8]>(bar: B) -> Result<Self, B> {
+    /// A valid new broker is one that is 4 bytes long and contains all caps, left-justified and
+    /// padded on the right with spaces.
+    pub fn new<B: AsRef<[u8]>>(bar: B) -> Result<Self, B> {
         let bytes = bar.as_ref();
         if bytes.len() != 4 {
             return Err(bar);
         }
-        let mut in_letters = false;
-        for i in 0..4 {
-            match bytes[i] {
-                b'A'..=b'Z' => in_letters = true,
-                b' ' => {
-                    if in_letters {
+        let mut in_padding = false;
+        for &b in bytes {
+            match b {
+                b'A'..=b'Z' => {
+                    if in_padding {
                         return Err(bar);
                     }
-                },
+                }
+                b' ' => in_padding = true,
                 _ => return Err(bar),
             }
         }
@@ -389,6 +564,10 @@ impl BrokerCode {
     pub fn as_str(&self) -> Option<&str> {
         std::str::from_utf8(&self.0).ok()
     }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 /*
@@ -401,12 +580,180 @@ impl Default for BrokerCode {
 
 impl fmt::Display for BrokerCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!("{}", self.as_str().unwrap_or("????"))
+        write!(f, "{}", self.as_str().unwrap_or("????"))
+    }
+}
+
+/// The tags of the options defined in the spec.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OptionTag {
+    SecondaryOrdRefNum = 1,
+    Firm = 2,
+    MinQty = 3,
+    CustomerType = 4,
+    MaxFloor = 5,
+    PriceType = 6,
+    PegOffset = 7,
+    DiscretionPrice = 9,
+    DiscretionPriceType = 10,
+    DiscretionPegOffset = 11,
+    PostOnly = 12,
+    RandomReserves = 13,
+    Route = 14,
+    ExpireTime = 15,
+    TradeNow = 16,
+    HandleInst = 17,
+    BboWeightIndicator = 18,
+    DisplayQuantity = 22,
+    DisplayPrice = 23,
+    GroupId = 24,
+    SharesLocated = 25,
+    LocateBroker = 26,
+    Side = 27,
+    UserRefIdx = 28,
+}
+
+impl OptionTag {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        use OptionTag::*;
+        Some(match b {
+            1 => SecondaryOrdRefNum,
+            2 => Firm,
+            3 => MinQty,
+            4 => CustomerType,
+            5 => MaxFloor,
+            6 => PriceType,
+            7 => PegOffset,
+            9 => DiscretionPrice,
+            10 => DiscretionPriceType,
+            11 => DiscretionPegOffset,
+            12 => PostOnly,
+            13 => RandomReserves,
+            14 => Route,
+            15 => ExpireTime,
+            16 => TradeNow,
+            17 => HandleInst,
+            18 => BboWeightIndicator,
+            22 => DisplayQuantity,
+            23 => DisplayPrice,
+            24 => GroupId,
+            25 => SharesLocated,
+            26 => LocateBroker,
+            27 => Side,
+            28 => UserRefIdx,
+            _ => return None,
+        })
+    }
+
+    pub fn to_u8(self) -> u8 {
+        self as _
+    }
+
+    /// The size of the option's value.
+    pub fn size(self) -> usize {
+        use OptionTag::*;
+        match self {
+            SecondaryOrdRefNum | DiscretionPrice | DisplayPrice => 8,
+            Firm | MinQty | MaxFloor | PegOffset | DiscretionPegOffset | RandomReserves | Route
+            | ExpireTime | DisplayQuantity | LocateBroker => 4,
+            GroupId => 2,
+            CustomerType | PriceType | DiscretionPriceType | PostOnly | TradeNow | HandleInst
+            | BboWeightIndicator | SharesLocated | Side | UserRefIdx => 1,
+        }
     }
 }
 
+/// Options that can go on any order (in an Enter Order or echoed back in an Order Accepted).
+const ORDER_OPTIONS: &[OptionTag] = &[
+    OptionTag::Firm,
+    OptionTag::MinQty,
+    OptionTag::CustomerType,
+    OptionTag::MaxFloor,
+    OptionTag::PriceType,
+    OptionTag::PegOffset,
+    OptionTag::DiscretionPrice,
+    OptionTag::DiscretionPriceType,
+    OptionTag::DiscretionPegOffset,
+    OptionTag::PostOnly,
+    OptionTag::RandomReserves,
+    OptionTag::Route,
+    OptionTag::ExpireTime,
+    OptionTag::TradeNow,
+    OptionTag::HandleInst,
+    OptionTag::BboWeightIndicator,
+    OptionTag::GroupId,
+    OptionTag::SharesLocated,
+    OptionTag::LocateBroker,
+    OptionTag::UserRefIdx,
+];
+
+/// Options that can be changed by a Replace Order Request.
+const REPLACE_OPTIONS: &[OptionTag] = &[
+    OptionTag::MinQty,
+    OptionTag::CustomerType,
+    OptionTag::MaxFloor,
+    OptionTag::PriceType,
+    OptionTag::PegOffset,
+    OptionTag::DiscretionPrice,
+    OptionTag::DiscretionPriceType,
+    OptionTag::DiscretionPegOffset,
+    OptionTag::PostOnly,
+    OptionTag::RandomReserves,
+    OptionTag::ExpireTime,
+    OptionTag::TradeNow,
+    OptionTag::HandleInst,
+    OptionTag::BboWeightIndicator,
+    OptionTag::SharesLocated,
+    OptionTag::LocateBroker,
+    OptionTag::UserRefIdx,
+];
+
+/// Options echoed back on an order's acceptance or replacement.
+const ACCEPTED_OPTIONS: &[OptionTag] = &[
+    OptionTag::SecondaryOrdRefNum,
+    OptionTag::Firm,
+    OptionTag::MinQty,
+    OptionTag::CustomerType,
+    OptionTag::MaxFloor,
+    OptionTag::PriceType,
+    OptionTag::PegOffset,
+    OptionTag::DiscretionPrice,
+    OptionTag::DiscretionPriceType,
+    OptionTag::DiscretionPegOffset,
+    OptionTag::PostOnly,
+    OptionTag::RandomReserves,
+    OptionTag::Route,
+    OptionTag::ExpireTime,
+    OptionTag::TradeNow,
+    OptionTag::HandleInst,
+    OptionTag::BboWeightIndicator,
+    OptionTag::DisplayQuantity,
+    OptionTag::DisplayPrice,
+    OptionTag::GroupId,
+    OptionTag::SharesLocated,
+    OptionTag::LocateBroker,
+    OptionTag::UserRefIdx,
+];
+
+/// Options on messages about an order that don't otherwise say its side.
+const SIDE_OPTIONS: &[OptionTag] = &[OptionTag::Side, OptionTag::UserRefIdx];
+
+const GROUP_OPTIONS: &[OptionTag] = &[OptionTag::GroupId, OptionTag::UserRefIdx];
+
+const RESTATED_OPTIONS: &[OptionTag] = &[
+    OptionTag::SecondaryOrdRefNum,
+    OptionTag::DisplayQuantity,
+    OptionTag::DisplayPrice,
+    OptionTag::UserRefIdx,
+];
+
+const BASIC_OPTIONS: &[OptionTag] = &[OptionTag::UserRefIdx];
+
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OptionValue {
     // FIXME: numeric
     SecondaryOrdRefNum(u64) = 1,
@@ -433,97 +780,234 @@ pub enum OptionValue {
     DisplayPrice(nasdaq::Price) = 23,
     GroupId(u16) = 24,
     SharesLocated(SharesLocated) = 25,
-    LocateBroker(Broker) = 26,
-    Side(Side) = 27,
+    LocateBroker(BrokerCode) = 26,
+    Side(OrderSide) = 27,
     UserRefIdx(u8) = 28,
+    /// An option this version doesn't know about, kept as it was on the wire.
+    Unknown { tag: u8, value: Vec<u8> } = 255,
 }
 
+#[allow(deprecated)]
 impl OptionValue {
-    pub fn size(self) -> usize {
+    /// Decodes the value of the option with the given tag. Tags that aren't known are kept as
+    /// `Unknown`.
+    pub fn decode(tag: u8, value: &[u8]) -> Result<Self, DecodeError> {
+        let Some(t) = OptionTag::from_u8(tag) else {
+            return Ok(OptionValue::Unknown {
+                tag,
+                value: value.to_vec(),
+            });
+        };
+        if value.len() != t.size() {
+            return Err(DecodeError::OptionLength {
+                tag,
+                len: value.len(),
+            });
+        }
+        let invalid = || DecodeError::InvalidOption { tag };
+        let u32_val = || u32::from_be_bytes(value.try_into().unwrap());
+        let u64_val = || u64::from_be_bytes(value.try_into().unwrap());
+        let b = value[0];
+        Ok(match t {
+            OptionTag::SecondaryOrdRefNum => OptionValue::SecondaryOrdRefNum(u64_val()),
+            OptionTag::Firm => OptionValue::Firm(Firm::new(value).map_err(|_| invalid())?),
+            OptionTag::MinQty => OptionValue::MinQty(u32_val()),
+            OptionTag::CustomerType => {
+                OptionValue::CustomerType(CustomerType::from_u8(b).ok_or_else(invalid)?)
+            }
+            OptionTag::MaxFloor => OptionValue::MaxFloor(u32_val()),
+            OptionTag::PriceType => {
+                OptionValue::PriceType(PriceType::from_u8(b).ok_or_else(invalid)?)
+            }
+            OptionTag::PegOffset => OptionValue::PegOffset(nasdaq::SignedPrice::from_bytes(
+                value.try_into().unwrap(),
+            )),
+            OptionTag::DiscretionPrice => OptionValue::DiscretionPrice(
+                nasdaq::Price::from_bytes(value.try_into().unwrap()),
+            ),
+            OptionTag::DiscretionPriceType => {
+                OptionValue::DiscretionPriceType(PriceType::from_u8(b).ok_or_else(invalid)?)
+            }
+            OptionTag::DiscretionPegOffset => OptionValue::DiscretionPegOffset(
+                nasdaq::SignedPrice::from_bytes(value.try_into().unwrap()),
+            ),
+            OptionTag::PostOnly => OptionValue::PostOnly(PostOnly::from_u8(b).ok_or_else(invalid)?),
+            OptionTag::RandomReserves => OptionValue::RandomReserves(u32_val()),
+            OptionTag::Route => OptionValue::Route(u32_val()),
+            OptionTag::ExpireTime => OptionValue::ExpireTime(u32_val()),
+            OptionTag::TradeNow => OptionValue::TradeNow(TradeNow::from_u8(b).ok_or_else(invalid)?),
+            OptionTag::HandleInst => {
+                OptionValue::HandleInst(HandleInst::from_u8(b).ok_or_else(invalid)?)
+            }
+            OptionTag::BboWeightIndicator => OptionValue::BboWeightIndicator(
+                BboWeightIndicator::from_u8(b).ok_or_else(invalid)?,
+            ),
+            OptionTag::DisplayQuantity => OptionValue::DisplayQuantity(u32_val()),
+            OptionTag::DisplayPrice => {
+                OptionValue::DisplayPrice(nasdaq::Price::from_bytes(value.try_into().unwrap()))
+            }
+            OptionTag::GroupId => OptionValue::GroupId(u16::from_be_bytes([value[0], value[1]])),
+            OptionTag::SharesLocated => {
+                OptionValue::SharesLocated(SharesLocated::from_u8(b).ok_or_else(invalid)?)
+            }
+            OptionTag::LocateBroker => {
+                OptionValue::LocateBroker(BrokerCode::new(value).map_err(|_| invalid())?)
+            }
+            OptionTag::Side => OptionValue::Side(OrderSide::from_u8(b).ok_or_else(invalid)?),
+            OptionTag::UserRefIdx => OptionValue::UserRefIdx(b),
+        })
+    }
+
+    /// The tag identifying the option on the wire.
+    pub fn tag(&self) -> u8 {
         match self {
-            OptionTag::SecondaryOrdRefNum(_) => 8,
-            OptionTag::Firm(_) => 4,
-            OptionTag::MinQty(_) => 4,
-            OptionTag::CustomerType(_) => 1,
-            OptionTag::MaxFloor(_) => 4,
-            OptionTag::PriceType(_) => 1,
-            OptionTag::PegOffset(_) => 4,
-            OptionTag::DiscretionPrice(_) => 8,
-            OptionTag::DiscretionPriceType(_) => 1,
-            OptionTag::DiscretionPegOffset(_) => 4,
-            OptionTag::PostOnly(_) => 1,
-            OptionTag::RandomReserves(_) => 4,
-            OptionTag::Route(_) => 4,
-            OptionTag::ExpireTime(_) => 4,
-            OptionTag::TradeNow(_) => 1,
-            OptionTag::HandleInst(_) => 1,
-            OptionTag::BboWeightIndicator(_) => 1,
-            OptionTag::DisplayQuantity(_) => 4,
-            OptionTag::DisplayPrice(_) => 8,
-            OptionTag::GroupId(_) => 2,
-            OptionTag::SharesLocated(_) => 1,
-            OptionTag::LocateBroker(_) => 4,
-            OptionTag::Side(_) => 1,
-            OptionTag::UserRefIdx(_) => 1,
+            OptionValue::SecondaryOrdRefNum(_) => 1,
+            OptionValue::Firm(_) => 2,
+            OptionValue::MinQty(_) => 3,
+            OptionValue::CustomerType(_) => 4,
+            OptionValue::MaxFloor(_) => 5,
+            OptionValue::PriceType(_) => 6,
+            OptionValue::PegOffset(_) => 7,
+            OptionValue::DiscretionPrice(_) => 9,
+            OptionValue::DiscretionPriceType(_) => 10,
+            OptionValue::DiscretionPegOffset(_) => 11,
+            OptionValue::PostOnly(_) => 12,
+            OptionValue::RandomReserves(_) => 13,
+            OptionValue::Route(_) => 14,
+            OptionValue::ExpireTime(_) => 15,
+            OptionValue::TradeNow(_) => 16,
+            OptionValue::HandleInst(_) => 17,
+            OptionValue::BboWeightIndicator(_) => 18,
+            OptionValue::DisplayQuantity(_) => 22,
+            OptionValue::DisplayPrice(_) => 23,
+            OptionValue::GroupId(_) => 24,
+            OptionValue::SharesLocated(_) => 25,
+            OptionValue::LocateBroker(_) => 26,
+            OptionValue::Side(_) => 27,
+            OptionValue::UserRefIdx(_) => 28,
+            OptionValue::Unknown { tag, .. } => *tag,
         }
     }
 
-    fn append_to(self, buf: &mut Vec<u8>) {
+    /// The size of the encoded value, not including the tag.
+    pub fn size(&self) -> usize {
         match self {
-            OptionTag::SecondaryOrdRefNum(v) => buf.extend_from_slice(&v.to_be_bytes()),
-            OptionTag::Firm(v) => buf.extend_from_slice(&v.as_bytes()),
-            OptionTag::MinQty(v) => buf.extend_from_slice(&v.to_be_bytes()),
-            OptionTag::CustomerType(v) => buf.push(v.to_u8()),
-            OptionTag::MaxFloor(v) => buf.extend_from_slice(&v.to_be_bytes()),
-            OptionTag::PriceType(v) => buf.push(v.to_u8()),
-            OptionTag::PegOffset(v) => buf.extend_from_slice(&v.to_bytes()),
-            OptionTag::DiscretionPrice(v) => buf.extend_from_slice(&v.to_bytes()),
-            OptionTag::DiscretionPriceType(v) => buf.push(v.to_u8()),
-            OptionTag::DiscretionPegOffset(v) => buf.extend_from_slice(&v.to_bytes()),
-            OptionTag::PostOnly(v) => buf.push(v.to_u8()),
-            OptionTag::RandomReserves(v) => buf.extend_from_slice(&v.to_be_bytes()),
-            OptionTag::Route(v) => buf.extend_from_slice(&v.to_be_bytes()),
-            OptionTag::ExpireTime(v) => buf.extend_from_slice(&v.to_be_bytes()),
-            OptionTag::TradeNow(v) => buf.push(v.to_u8()),
-            OptionTag::HandleInst(v) => buf.push(v.to_u8()),
-            OptionTag::BboWeightIndicator(v) => buf.push(v.to_u8()),
-            OptionTag::DisplayQuantity(v) => buf.extend_from_slice(&v.to_be_bytes()),
-            OptionTag::DisplayPrice(v) => buf.extend_from_slice(&v.to_bytes()),
-            OptionTag::GroupId(v) => buf.extend_from_slice(&v.to_be_bytes()),
-            OptionTag::SharesLocated(v) => buf.push(v.to_u8()),
-            OptionTag::LocateBroker(v) => buf.extend_from_slice(&v.as_bytes()),
-            OptionTag::Side(v) => buf.push(v.to_u8()),
-            OptionTag::UserRefIdx(v) => buf.push(v),
+            OptionValue::SecondaryOrdRefNum(_) => 8,
+            OptionValue::Firm(_) => 4,
+            OptionValue::MinQty(_) => 4,
+            OptionValue::CustomerType(_) => 1,
+            OptionValue::MaxFloor(_) => 4,
+            OptionValue::PriceType(_) => 1,
+            OptionValue::PegOffset(_) => 4,
+            OptionValue::DiscretionPrice(_) => 8,
+            OptionValue::DiscretionPriceType(_) => 1,
+            OptionValue::DiscretionPegOffset(_) => 4,
+            OptionValue::PostOnly(_) => 1,
+            OptionValue::RandomReserves(_) => 4,
+            OptionValue::Route(_) => 4,
+            OptionValue::ExpireTime(_) => 4,
+            OptionValue::TradeNow(_) => 1,
+            OptionValue::HandleInst(_) => 1,
+            OptionValue::BboWeightIndicator(_) => 1,
+            OptionValue::DisplayQuantity(_) => 4,
+            OptionValue::DisplayPrice(_) => 8,
+            OptionValue::GroupId(_) => 2,
+            OptionValue::SharesLocated(_) => 1,
+            OptionValue::LocateBroker(_) => 4,
+            OptionValue::Side(_) => 1,
+            OptionValue::UserRefIdx(_) => 1,
+            OptionValue::Unknown { value, .. } => value.len(),
+        }
+    }
+
+    fn append_to(&self, buf: &mut Vec<u8>) {
+        match *self {
+            OptionValue::SecondaryOrdRefNum(v) => buf.extend_from_slice(&v.to_be_bytes()),
+            OptionValue::Firm(ref v) => buf.extend_from_slice(v.as_bytes()),
+            OptionValue::MinQty(v) => buf.extend_from_slice(&v.to_be_bytes()),
+            OptionValue::CustomerType(v) => buf.push(v.to_u8()),
+            OptionValue::MaxFloor(v) => buf.extend_from_slice(&v.to_be_bytes()),
+            OptionValue::PriceType(v) => buf.push(v.to_u8()),
+            OptionValue::PegOffset(v) => buf.extend_from_slice(&v.to_bytes()),
+            OptionValue::DiscretionPrice(v) => buf.extend_from_slice(&v.to_bytes()),
+            OptionValue::DiscretionPriceType(v) => buf.push(v.to_u8()),
+            OptionValue::DiscretionPegOffset(v) => buf.extend_from_slice(&v.to_bytes()),
+            OptionValue::PostOnly(v) => buf.push(v.to_u8()),
+            OptionValue::RandomReserves(v) => buf.extend_from_slice(&v.to_be_bytes()),
+            OptionValue::Route(v) => buf.extend_from_slice(&v.to_be_bytes()),
+            OptionValue::ExpireTime(v) => buf.extend_from_slice(&v.to_be_bytes()),
+            OptionValue::TradeNow(v) => buf.push(v.to_u8()),
+            OptionValue::HandleInst(v) => buf.push(v.to_u8()),
+            OptionValue::BboWeightIndicator(v) => buf.push(v.to_u8()),
+            OptionValue::DisplayQuantity(v) => buf.extend_from_slice(&v.to_be_bytes()),
+            OptionValue::DisplayPrice(v) => buf.extend_from_slice(&v.to_bytes()),
+            OptionValue::GroupId(v) => buf.extend_from_slice(&v.to_be_bytes()),
+            OptionValue::SharesLocated(v) => buf.push(v.to_u8()),
+            OptionValue::LocateBroker(ref v) => buf.extend_from_slice(v.as_bytes()),
+            OptionValue::Side(v) => buf.push(v.to_u8()),
+            OptionValue::UserRefIdx(v) => buf.push(v),
+            OptionValue::Unknown { ref value, .. } => buf.extend_from_slice(value),
         }
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct TagValue {
     pub option_value: OptionValue,
 }
 
 impl TagValue {
-    pub fn length(self) -> u8 {
-        self.option_value.size() as _
+    pub fn new(option_value: OptionValue) -> Self {
+        Self { option_value }
+    }
+
+    /// The value of the length field, which counts the tag and the value.
+    pub fn length(&self) -> u8 {
+        (1 + self.option_value.size()) as _
+    }
+
+    /// The number of bytes the tag value takes up, including the length field.
+    pub fn encoded_len(&self) -> usize {
+        2 + self.option_value.size()
+    }
+
+    fn append_to(&self, buf: &mut Vec<u8>) {
+        buf.push(self.length());
+        buf.push(self.option_value.tag());
+        self.option_value.append_to(buf);
+    }
+}
+
+impl From<OptionValue> for TagValue {
+    fn from(option_value: OptionValue) -> Self {
+        Self::new(option_value)
     }
 }
 
 #[repr(transparent)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct OptionalAppendage(Vec<TagValue>);
 
 impl OptionalAppendage {
+    /// The most bytes the encoded tag values can take up, since the length is 2 bytes.
     pub const MAX_LEN: usize = u16::MAX as usize;
 
     pub fn new(v: impl Into<Vec<TagValue>>) -> Result<Self, Vec<TagValue>> {
         let v = v.into();
-        if v.len() > Self::MAX_LEN {
+        if v.iter().map(|tv| tv.encoded_len()).sum::<usize>() > Self::MAX_LEN {
             return Err(v);
         }
         Ok(Self(v))
     }
 
+    pub const fn empty() -> Self {
+        Self(Vec::new())
+    }
+
     pub fn push(&mut self, tv: TagValue) -> Result<(), TagValue> {
-        if self.len() >= Self::MAX_LEN {
+        if self.encoded_len() + tv.encoded_len() > Self::MAX_LEN {
             return Err(tv);
         }
         self.0.push(tv);
@@ -531,7 +1015,7 @@ impl OptionalAppendage {
     }
 
     pub fn insert(&mut self, index: usize, tv: TagValue) -> Result<(), TagValue> {
-        if self.len() >= Self::MAX_LEN {
+        if self.encoded_len() + tv.encoded_len() > Self::MAX_LEN {
             return Err(tv);
         }
         self.0.insert(index, tv);
@@ -543,10 +1027,11 @@ impl OptionalAppendage {
     }
 
     pub fn remove(&mut self, index: usize) -> TagValue {
-        self.0.remove(index);
+        self.0.remove(index)
     }
 
-    pub fn into_iter(self) -> impl Iterator<Item=TagValue> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn into_iter(self) -> impl Iterator<Item = TagValue> {
         self.0.into_iter()
     }
 
@@ -558,13 +1043,69 @@ impl OptionalAppendage {
         self.0.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The number of bytes the tag values take up, not including the appendage length.
+    pub fn encoded_len(&self) -> usize {
+        self.0.iter().map(|tv| tv.encoded_len()).sum()
+    }
+
+    /// The value of the first option with the tag.
+    pub fn get(&self, tag: OptionTag) -> Option<&OptionValue> {
+        self.0
+            .iter()
+            .map(|tv| &tv.option_value)
+            .find(|v| v.tag() == tag.to_u8())
+    }
+
+    /// Decodes the tag values of an appendage (without the appendage length).
+    pub fn decode(mut buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() > Self::MAX_LEN {
+            return Err(DecodeError::AppendageTooLong(buf.len()));
+        }
+        let mut tvs = Vec::new();
+        while let [len, rest @ ..] = buf {
+            let len = *len as usize;
+            let tag = rest.first().copied().unwrap_or(0);
+            if len == 0 || len > rest.len() {
+                return Err(DecodeError::OptionLength {
+                    tag,
+                    len: len.saturating_sub(1),
+                });
+            }
+            let value = OptionValue::decode(tag, &rest[1..len])?;
+            tvs.push(TagValue::new(value));
+            buf = &rest[len..];
+        }
+        Ok(Self(tvs))
+    }
+
+    /// Checks that no tag appears twice and that every known tag is in `allowed` (the `OPTIONS`
+    /// of the message of type `msg_type`). Unknown tags are let through.
+    pub fn validate(&self, msg_type: u8, allowed: &[OptionTag]) -> Result<(), DecodeError> {
+        let mut seen = [false; 256];
+        for tv in &self.0 {
+            let tag = tv.option_value.tag();
+            if std::mem::replace(&mut seen[tag as usize], true) {
+                return Err(DecodeError::DuplicateOption { msg_type, tag });
+            }
+            let known = OptionTag::from_u8(tag);
+            if known.is_some_and(|t| !allowed.contains(&t)) {
+                return Err(DecodeError::UnexpectedOption { msg_type, tag });
+            }
+        }
+        Ok(())
+    }
+
     fn append_to(&self, buf: &mut Vec<u8>, encode_len: bool) {
-        let l = self.len().max(Self::MAX_LEN);
         if encode_len {
-            buf.extend_from_slice(l.to_be_bytes());
+            // The length is checked when adding values, so this can't truncate.
+            buf.extend_from_slice(&(self.encoded_len() as u16).to_be_bytes());
         }
-        for i in 0..l {
-            self[i].append_to(buf);
+        for tv in &self.0 {
+            tv.append_to(buf);
         }
     }
 }
@@ -588,21 +1129,22 @@ impl DerefMut for OptionalAppendage {
 pub struct Firm([u8; 4]);
 
 impl Firm {
-    /// A valid new firm is one that is 4 bytes long and contains all caps or spaces.
-    pub fn new<B: AsRef<[u8]>(bar: B) -> Result<Self, B> {
+    /// A valid new firm is one that is 4 bytes long and contains all caps, left-justified and
+    /// padded on the right with spaces.
+    pub fn new<B: AsRef<[u8]>>(bar: B) -> Result<Self, B> {
         let bytes = bar.as_ref();
         if bytes.len() != 4 {
             return Err(bar);
         }
-        let mut in_letters = false;
-        for i in 0..4 {
-            match bytes[i] {
-                b'A'..=b'Z' => in_letters = true,
-                b' ' => {
-                    if in_letters {
+        let mut in_padding = false;
+        for &b in bytes {
+            match b {
+                b'A'..=b'Z' => {
+                    if in_padding {
                         return Err(bar);
                     }
-                },
+                }
+                b' ' => in_padding = true,
                 _ => return Err(bar),
             }
         }
@@ -616,6 +1158,10 @@ impl Firm {
     pub fn as_str(&self) -> Option<&str> {
         std::str::from_utf8(&self.0).ok()
     }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 impl Default for Firm {
@@ -626,10 +1172,12 @@ impl Default for Firm {
 
 impl fmt::Display for Firm {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!("{}", self.as_str().unwrap_or("????"))
+        write!(f, "{}", self.as_str().unwrap_or("????"))
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnterOrder {
     pub user_ref_num: UserRefNum,
     pub side: OrderSide,
@@ -642,14 +1190,22 @@ pub struct EnterOrder {
     pub inter_market_sweep_eligibility: InterMarketSweepEligibility,
     pub cross_type: CrossType,
     pub cl_ord_id: ClOrdId,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
+impl EnterOrder {
+    /// The length of the message without the optional appendage.
+    pub const BASE_LEN: usize = 47;
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = ORDER_OPTIONS;
+}
+
 impl Message for EnterOrder {
-    pub const TYPE: u8 = b'O';
+    const TYPE: u8 = b'O';
 
     fn encode(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(47);
+        let mut buf = Vec::with_capacity(Self::BASE_LEN + self.optional_appendage.encoded_len());
         buf.push(Self::TYPE);
         buf.extend_from_slice(&self.user_ref_num.to_bytes());
         buf.push(self.side.to_u8());
@@ -663,9 +1219,12 @@ impl Message for EnterOrder {
         buf.push(self.cross_type.to_u8());
         buf.extend_from_slice(self.cl_ord_id.as_bytes());
         self.optional_appendage.append_to(&mut buf, true);
+        buf
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReplaceOrderRequest {
     pub orig_user_ref_num: UserRefNum,
     pub user_ref_num: UserRefNum,
@@ -675,103 +1234,261 @@ pub struct ReplaceOrderRequest {
     pub display: Display,
     pub inter_market_sweep_eligibility: InterMarketSweepEligibility,
     pub cl_ord_id: ClOrdId,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl ReplaceOrderRequest {
-    pub const TYPE: u8 = b'U';
+    /// The length of the message without the optional appendage.
+    pub const BASE_LEN: usize = 40;
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = REPLACE_OPTIONS;
 }
 
+impl Message for ReplaceOrderRequest {
+    const TYPE: u8 = b'U';
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::BASE_LEN + self.optional_appendage.encoded_len());
+        buf.push(Self::TYPE);
+        buf.extend_from_slice(&self.orig_user_ref_num.to_bytes());
+        buf.extend_from_slice(&self.user_ref_num.to_bytes());
+        buf.extend_from_slice(&self.quantity.to_be_bytes());
+        buf.extend_from_slice(&self.price.to_bytes());
+        buf.push(self.time_in_force.to_u8());
+        buf.push(self.display.to_u8());
+        buf.push(self.inter_market_sweep_eligibility.to_u8());
+        buf.extend_from_slice(self.cl_ord_id.as_bytes());
+        self.optional_appendage.append_to(&mut buf, true);
+        buf
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CancelOrderRequest {
     pub user_ref_num: UserRefNum,
+    /// The new intended order size. Zero cancels the order completely.
     pub quantity: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl CancelOrderRequest {
-    pub const TYPE: u8 = b'X';
+    /// The length of the message without the optional appendage.
+    pub const BASE_LEN: usize = 11;
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = BASIC_OPTIONS;
 }
 
+impl Message for CancelOrderRequest {
+    const TYPE: u8 = b'X';
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::BASE_LEN + self.optional_appendage.encoded_len());
+        buf.push(Self::TYPE);
+        buf.extend_from_slice(&self.user_ref_num.to_bytes());
+        buf.extend_from_slice(&self.quantity.to_be_bytes());
+        self.optional_appendage.append_to(&mut buf, true);
+        buf
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModifyOrderRequest {
     pub user_ref_num: UserRefNum,
-    pub side: Side,
+    pub side: OrderSide,
     pub quantity: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl ModifyOrderRequest {
-    pub const TYPE: u8 = b'M';
+    /// The length of the message without the optional appendage.
+    pub const BASE_LEN: usize = 12;
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = BASIC_OPTIONS;
+}
+
+impl Message for ModifyOrderRequest {
+    const TYPE: u8 = b'M';
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::BASE_LEN + self.optional_appendage.encoded_len());
+        buf.push(Self::TYPE);
+        buf.extend_from_slice(&self.user_ref_num.to_bytes());
+        buf.push(self.side.to_u8());
+        buf.extend_from_slice(&self.quantity.to_be_bytes());
+        self.optional_appendage.append_to(&mut buf, true);
+        buf
+    }
 }
 
-pub struct MassCancerRequest {
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MassCancelRequest {
     pub user_ref_num: UserRefNum,
     pub firm: Firm,
+    /// Left blank (all spaces) to cancel across all symbols.
     pub symbol: nasdaq::Symbol,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
-impl MassOrderRequest {
-    pub const TYPE: u8 = b'C';
+impl MassCancelRequest {
+    /// The length of the message without the optional appendage.
+    pub const BASE_LEN: usize = 19;
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = GROUP_OPTIONS;
 }
 
+impl Message for MassCancelRequest {
+    const TYPE: u8 = b'C';
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::BASE_LEN + self.optional_appendage.encoded_len());
+        buf.push(Self::TYPE);
+        buf.extend_from_slice(&self.user_ref_num.to_bytes());
+        buf.extend_from_slice(self.firm.as_bytes());
+        buf.extend_from_slice(self.symbol.as_bytes());
+        self.optional_appendage.append_to(&mut buf, true);
+        buf
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DisableOrderEntryRequest {
     pub user_ref_num: UserRefNum,
     pub firm: Firm,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl DisableOrderEntryRequest {
-    pub const TYPE: u8 = b'D';
+    /// The length of the message without the optional appendage.
+    pub const BASE_LEN: usize = 11;
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = BASIC_OPTIONS;
+}
+
+impl Message for DisableOrderEntryRequest {
+    const TYPE: u8 = b'D';
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::BASE_LEN + self.optional_appendage.encoded_len());
+        buf.push(Self::TYPE);
+        buf.extend_from_slice(&self.user_ref_num.to_bytes());
+        buf.extend_from_slice(self.firm.as_bytes());
+        self.optional_appendage.append_to(&mut buf, true);
+        buf
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnableOrderEntryRequest {
     pub user_ref_num: UserRefNum,
     pub firm: Firm,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl EnableOrderEntryRequest {
-    pub const TYPE: u8 = b'E';
+    /// The length of the message without the optional appendage.
+    pub const BASE_LEN: usize = 11;
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = BASIC_OPTIONS;
 }
 
+impl Message for EnableOrderEntryRequest {
+    const TYPE: u8 = b'E';
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::BASE_LEN + self.optional_appendage.encoded_len());
+        buf.push(Self::TYPE);
+        buf.extend_from_slice(&self.user_ref_num.to_bytes());
+        buf.extend_from_slice(self.firm.as_bytes());
+        self.optional_appendage.append_to(&mut buf, true);
+        buf
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AccountQueryRequest {
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl AccountQueryRequest {
-    pub const TYPE: u8 = b'Q';
+    /// The length of the message without the optional appendage.
+    pub const BASE_LEN: usize = 3;
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = BASIC_OPTIONS;
+}
+
+impl Message for AccountQueryRequest {
+    const TYPE: u8 = b'Q';
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::BASE_LEN + self.optional_appendage.encoded_len());
+        buf.push(Self::TYPE);
+        self.optional_appendage.append_to(&mut buf, true);
+        buf
+    }
 }
 
 /* Outbound messages */
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EventCode {
     StartOfDay = b'S',
     EndOfDay = b'E',
 }
 
 impl EventCode {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'S' => Some(EventCode::StartOfDay),
+            b'E' => Some(EventCode::EndOfDay),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OrderState {
     Live = b'L',
     Dead = b'D',
 }
 
 impl OrderState {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'L' => Some(OrderState::Live),
+            b'D' => Some(OrderState::Dead),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OrderCancelReason {
     /// This order cannot be executed because of a regulatory restriction (e.g.: trade through
     /// restrictions).
@@ -811,13 +1528,34 @@ pub enum OrderCancelReason {
 }
 
 impl OrderCancelReason {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'D' => Some(OrderCancelReason::RegulatoryRestriction),
+            b'E' => Some(OrderCancelReason::Closed),
+            b'F' => Some(OrderCancelReason::PostOnlyNms),
+            b'G' => Some(OrderCancelReason::PostOnlyContra),
+            b'H' => Some(OrderCancelReason::Halted),
+            b'I' => Some(OrderCancelReason::ImmediateOrCancel),
+            b'K' => Some(OrderCancelReason::MarketCollars),
+            b'Q' => Some(OrderCancelReason::SelfMatchPrevention),
+            b'S' => Some(OrderCancelReason::Supervisory),
+            b'T' => Some(OrderCancelReason::Timeout),
+            b'U' => Some(OrderCancelReason::UserRequested),
+            b'X' => Some(OrderCancelReason::OpenProtection),
+            b'Z' => Some(OrderCancelReason::SystemCancel),
+            b'e' => Some(OrderCancelReason::Exceeds),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LiquidityFlag {
     Added = b'A',
     ClosingCross = b'C',
@@ -855,19 +1593,50 @@ pub enum LiquidityFlag {
     /// Displayed, liquidity-adding order improves the NBBO.
     DisplayedNbbo = b'7',
     /// Displayed, liquidity-adding order sets the QBBO while joining the NBBO.
-    DisplayedQbbo = b'7',
+    DisplayedQbbo = b'8',
     /// RPI order provides liquidity, No RPII.
     RpiOrderNoRpii = b'1',
 }
 
 impl LiquidityFlag {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'A' => Some(LiquidityFlag::Added),
+            b'C' => Some(LiquidityFlag::ClosingCross),
+            b'e' => Some(LiquidityFlag::RetailDesigExec),
+            b'H' => Some(LiquidityFlag::HaltIpoCross),
+            b'i' => Some(LiquidityFlag::AfterHoursClosingCross),
+            b'J' => Some(LiquidityFlag::NonDisplayed),
+            b'j' => Some(LiquidityFlag::RpiOrder),
+            b'K' => Some(LiquidityFlag::HaltCross),
+            b'L' => Some(LiquidityFlag::ClosingCrossImbalanceOnly),
+            b'M' => Some(LiquidityFlag::OpeningCrossImbalanceOnly),
+            b'm' => Some(LiquidityFlag::RemovedLiquidity),
+            b'N' => Some(LiquidityFlag::PassiveMidpointExecution),
+            b'n' => Some(LiquidityFlag::MidpointExtended),
+            b'O' => Some(LiquidityFlag::OpeningCross),
+            b'p' => Some(LiquidityFlag::RemovedPrice),
+            b'q' => Some(LiquidityFlag::RmoRetailOrder),
+            b'R' => Some(LiquidityFlag::Removed),
+            b'r' => Some(LiquidityFlag::RetailOrderRpi),
+            b't' => Some(LiquidityFlag::RetailOrderPrice),
+            b'u' => Some(LiquidityFlag::ReserveOrder),
+            b'0' => Some(LiquidityFlag::SupplementalOrderExecution),
+            b'7' => Some(LiquidityFlag::DisplayedNbbo),
+            b'8' => Some(LiquidityFlag::DisplayedQbbo),
+            b'1' => Some(LiquidityFlag::RpiOrderNoRpii),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BrokenReason {
     Erroneuos = b'E',
     Consent = b'C',
@@ -876,13 +1645,24 @@ pub enum BrokenReason {
 }
 
 impl BrokenReason {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'E' => Some(BrokenReason::Erroneuos),
+            b'C' => Some(BrokenReason::Consent),
+            b'S' => Some(BrokenReason::Supervisory),
+            b'X' => Some(BrokenReason::External),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
 #[repr(u16)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RejectReason {
     QuoteUnavailable = 0x0001,
     DestinationClosed = 0x0002,
@@ -936,6 +1716,72 @@ pub enum RejectReason {
     RegShoStateNotAvailable = 0x0032,
 }
 
+impl RejectReason {
+    pub fn from_u16(v: u16) -> Option<Self> {
+        use RejectReason::*;
+        const ALL: [RejectReason; 50] = [
+            QuoteUnavailable,
+            DestinationClosed,
+            InvalidDisplay,
+            InvalidMaxFloor,
+            InvalidPegType,
+            FatFinger,
+            Halted,
+            IsoNotAllowed,
+            InvalidSlide,
+            ProcessingError,
+            CancelPending,
+            FirmNotAuthorized,
+            InvalidMinQuantity,
+            NoClosingReferencePrice,
+            Other,
+            CancelNotAllowed,
+            PeggingNotAllowed,
+            CrossedMarket,
+            InvalidQuantity,
+            InvalidCrossOrder,
+            ReplaceNotAllowed,
+            RoutingNotAllowed,
+            InvalidSymbol,
+            Test,
+            LateLocTooAggressive,
+            RetailNotAllowed,
+            InvalidMidpointPostOnlyPrice,
+            InvalidDestination,
+            InvalidPrice,
+            SharesExceedThreshold,
+            ExceedsMaximumAllowedNotionalValue,
+            RiskAggregateExposureExceeded,
+            RiskMarketImpact,
+            RiskRestrictedStock,
+            RiskShortSellRestricted,
+            RiskIsoNotAllowed,
+            RiskExceedsAdvLimit,
+            RiskFatFinger,
+            RiskLocateRequired,
+            RiskSymbolMessageRateRestriction,
+            RiskPortMessageRateRestriction,
+            RiskDuplicateMessageRateRestriction,
+            RiskShortSellNotAllowed,
+            RiskMarketOrderNotAllowed,
+            RiskPreMarketNotAllowed,
+            RiskPostMarketNotAllowed,
+            RiskShortSellExemptNotAllowed,
+            RiskSingleOrderNotionalExceeded,
+            RiskMaxQuantityExceeded,
+            RegShoStateNotAvailable,
+        ];
+        // The reasons are numbered from 1 with no gaps.
+        ALL.get((v as usize).checked_sub(1)?).copied()
+    }
+
+    pub fn to_u16(self) -> u16 {
+        self as _
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SystemEvent {
     pub timestamp: i64,
     pub event_code: EventCode,
@@ -943,15 +1789,26 @@ pub struct SystemEvent {
 
 impl SystemEvent {
     pub const TYPE: u8 = b'S';
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            event_code: r.enum_u8("event_code", EventCode::from_u8)?,
+        };
+        r.finish(msg)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderAccepted {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
-    pub side: Side,
-    pub quantity: u64,
+    pub side: OrderSide,
+    pub quantity: u32,
     pub symbol: nasdaq::Symbol,
-    pub price: Price,
+    pub price: nasdaq::Price,
     pub time_in_force: TimeInForce,
     pub display: Display,
     pub order_reference_number: u64,
@@ -960,19 +1817,46 @@ pub struct OrderAccepted {
     pub cross_type: CrossType,
     pub order_state: OrderState,
     pub cl_ord_id: ClOrdId,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl OrderAccepted {
     pub const TYPE: u8 = b'A';
-}
-
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = ACCEPTED_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            side: r.side("side")?,
+            quantity: r.u32()?,
+            symbol: r.symbol()?,
+            price: r.price()?,
+            time_in_force: r.enum_u8("time_in_force", TimeInForce::from_u8)?,
+            display: r.enum_u8("display", Display::from_u8)?,
+            order_reference_number: r.u64()?,
+            capacity: r.enum_u8("capacity", Capacity::from_u8)?,
+            inter_market_sweep_eligibility: r.enum_u8("imse", IMSE::from_u8)?,
+            cross_type: r.enum_u8("cross_type", CrossType::from_u8)?,
+            order_state: r.enum_u8("order_state", OrderState::from_u8)?,
+            cl_ord_id: r.cl_ord_id()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderReplaced {
     pub timestamp: i64,
     pub orig_user_ref_num: UserRefNum,
     pub user_ref_num: UserRefNum,
-    pub side: Side,
-    pub quantity: u64,
+    pub side: OrderSide,
+    pub quantity: u32,
     pub symbol: nasdaq::Symbol,
     pub price: nasdaq::Price,
     pub time_in_force: TimeInForce,
@@ -983,186 +1867,595 @@ pub struct OrderReplaced {
     pub cross_type: CrossType,
     pub order_state: OrderState,
     pub cl_ord_id: ClOrdId,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl OrderReplaced {
     pub const TYPE: u8 = b'U';
-}
-
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = ACCEPTED_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            orig_user_ref_num: r.user_ref_num()?,
+            user_ref_num: r.user_ref_num()?,
+            side: r.side("side")?,
+            quantity: r.u32()?,
+            symbol: r.symbol()?,
+            price: r.price()?,
+            time_in_force: r.enum_u8("time_in_force", TimeInForce::from_u8)?,
+            display: r.enum_u8("display", Display::from_u8)?,
+            order_reference_number: r.u64()?,
+            capacity: r.enum_u8("capacity", Capacity::from_u8)?,
+            inter_market_sweep_eligibility: r.enum_u8("imse", IMSE::from_u8)?,
+            cross_type: r.enum_u8("cross_type", CrossType::from_u8)?,
+            order_state: r.enum_u8("order_state", OrderState::from_u8)?,
+            cl_ord_id: r.cl_ord_id()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderCanceled {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
-    pub side: Side,
+    /// The number of shares just decremented from the order.
+    pub quantity: u32,
     pub reason: OrderCancelReason,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl OrderCanceled {
     pub const TYPE: u8 = b'C';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = SIDE_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            quantity: r.u32()?,
+            reason: r.enum_u8("reason", OrderCancelReason::from_u8)?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AiqCanceled {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
     pub decrement_shares: u32,
+    pub reason: OrderCancelReason,
     pub quantity_prevent_from_trading: u32,
     pub execution_price: nasdaq::Price,
-    pub aiq_strategy: todo!(), // TODO
+    pub liquidity_flag: LiquidityFlag,
+    pub aiq_strategy: u8,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl AiqCanceled {
     pub const TYPE: u8 = b'D';
-}
-
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = SIDE_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            decrement_shares: r.u32()?,
+            reason: r.enum_u8("reason", OrderCancelReason::from_u8)?,
+            quantity_prevent_from_trading: r.u32()?,
+            execution_price: r.price()?,
+            liquidity_flag: r.enum_u8("liquidity_flag", LiquidityFlag::from_u8)?,
+            aiq_strategy: r.u8()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderExecuted {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
-    pub quantity: u64,
+    pub quantity: u32,
     pub price: nasdaq::Price,
     pub liquidity_flag: LiquidityFlag,
     pub match_number: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl OrderExecuted {
     pub const TYPE: u8 = b'E';
-}
-
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = SIDE_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            quantity: r.u32()?,
+            price: r.price()?,
+            liquidity_flag: r.enum_u8("liquidity_flag", LiquidityFlag::from_u8)?,
+            match_number: r.u64()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BrokenTrade {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
     pub match_number: u64,
     pub reason: BrokenReason,
     pub cl_ord_id: ClOrdId,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl BrokenTrade {
     pub const TYPE: u8 = b'B';
-} 
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = SIDE_OPTIONS;
 
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            match_number: r.u64()?,
+            reason: r.enum_u8("reason", BrokenReason::from_u8)?,
+            cl_ord_id: r.cl_ord_id()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rejected {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
     pub reason: RejectReason,
     pub cl_ord_id: ClOrdId,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl Rejected {
     pub const TYPE: u8 = b'J';
-}
-
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = SIDE_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            reason: {
+                let v = r.u16()?;
+                RejectReason::from_u16(v).ok_or(DecodeError::InvalidEnum {
+                    field: "reason",
+                    value: v,
+                })?
+            },
+            cl_ord_id: r.cl_ord_id()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CancelPending {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl CancelPending {
     pub const TYPE: u8 = b'P';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = SIDE_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CancelReject {
+    pub timestamp: i64,
     pub user_ref_num: UserRefNum,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl CancelReject {
     pub const TYPE: u8 = b'I';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = SIDE_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderPriorityUpdate {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
     pub price: nasdaq::Price,
     pub display: Display,
     pub order_reference_number: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl OrderPriorityUpdate {
     pub const TYPE: u8 = b'T';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = SIDE_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            price: r.price()?,
+            display: r.enum_u8("display", Display::from_u8)?,
+            order_reference_number: r.u64()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderModified {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
-    pub side: Side,
+    pub side: OrderSide,
     pub quantity: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl OrderModified {
     pub const TYPE: u8 = b'M';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = BASIC_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            side: r.side("side")?,
+            quantity: r.u32()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
 }
 
 #[repr(u8)]
-#[derive(Cloone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RestateReason {
     RefreshOfDisplay = b'R',
     UpdateOfDisplayedPrice = b'P',
 }
 
 impl RestateReason {
+    pub fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            b'R' => Some(RestateReason::RefreshOfDisplay),
+            b'P' => Some(RestateReason::UpdateOfDisplayedPrice),
+            _ => None,
+        }
+    }
+
     pub fn to_u8(self) -> u8 {
         self as _
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderRestated {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
     pub reason: RestateReason,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl OrderRestated {
     pub const TYPE: u8 = b'R';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = RESTATED_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            reason: r.enum_u8("reason", RestateReason::from_u8)?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MassCancelResponse {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
     pub firm: Firm,
     pub symbol: nasdaq::Symbol,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl MassCancelResponse {
     pub const TYPE: u8 = b'X';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = GROUP_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            firm: r.firm("firm")?,
+            symbol: r.symbol()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DisableOrderEntryResponse {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
     pub firm: Firm,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl DisableOrderEntryResponse {
     pub const TYPE: u8 = b'G';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = BASIC_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            firm: r.firm("firm")?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnableOrderEntryResponse {
     pub timestamp: i64,
     pub user_ref_num: UserRefNum,
     pub firm: Firm,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl EnableOrderEntryResponse {
     pub const TYPE: u8 = b'K';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = BASIC_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            user_ref_num: r.user_ref_num()?,
+            firm: r.firm("firm")?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AccountQueryResponse {
     pub timestamp: i64,
     pub next_user_ref_num: UserRefNum,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub optional_appendage: OptionalAppendage,
 }
 
 impl AccountQueryResponse {
     pub const TYPE: u8 = b'Q';
+    /// The options the appendage may have.
+    pub const OPTIONS: &'static [OptionTag] = BASIC_OPTIONS;
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(buf, Self::TYPE)?;
+        let msg = Self {
+            timestamp: r.timestamp()?,
+            next_user_ref_num: r.user_ref_num()?,
+            optional_appendage: r.appendage(Self::OPTIONS)?,
+        };
+        r.finish(msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::nasdaq::*;
+
+    fn enter_order(optional_appendage: OptionalAppendage) -> EnterOrder {
+        EnterOrder {
+            user_ref_num: UserRefNum(7),
+            side: OrderSide::Buy,
+            quantity: 300,
+            symbol: Symbol::new_padded("AAPL").unwrap(),
+            price: Price::from_f64(187.25).unwrap(),
+            time_in_force: TimeInForce::Day,
+            display: Display::Visible,
+            capacity: Capacity::Agency,
+            inter_market_sweep_eligibility: IMSE::NotEligible,
+            cross_type: CrossType::ContinuousMarket,
+            cl_ord_id: ClOrdId::from_bytes(b"ABC123        ").unwrap(),
+            optional_appendage,
+        }
+    }
+
+    fn appendage(values: impl IntoIterator<Item = OptionValue>) -> OptionalAppendage {
+        let mut app = OptionalAppendage::empty();
+        for v in values {
+            app.push(v.into()).unwrap();
+        }
+        app
+    }
+
+    fn cancel_pending(app: &[u8]) -> Vec<u8> {
+        let mut b = vec![b'P'];
+        b.extend(1i64.to_be_bytes());
+        b.extend(5u32.to_be_bytes());
+        b.extend((app.len() as u16).to_be_bytes());
+        b.extend(app);
+        b
+    }
+
+    #[test]
+    fn encode_enter_order() {
+        let app = appendage([
+            OptionValue::MinQty(100),
+            OptionValue::PegOffset(SignedPrice::from_raw(-100)),
+        ]);
+        let b = enter_order(app).encode();
+        assert_eq!(b.len(), EnterOrder::BASE_LEN + 12);
+        assert_eq!(&b[..5], &[b'O', 0, 0, 0, 7]);
+        assert_eq!(b[5], b'B');
+        assert_eq!(&b[6..10], &300u32.to_be_bytes());
+        assert_eq!(&b[10..18], b"AAPL    ");
+        assert_eq!(&b[18..26], &1_872_500u64.to_be_bytes());
+        assert_eq!(&b[26..31], b"0YANN");
+        assert_eq!(&b[31..45], b"ABC123        ");
+        assert_eq!(&b[45..47], &12u16.to_be_bytes());
+        assert_eq!(&b[47..53], &[5, 3, 0, 0, 0, 100]);
+        assert_eq!(&b[53..59], &[5, 7, 0xff, 0xff, 0xff, 0x9c]);
+        assert_eq!(AccountQueryRequest::default().encode(), vec![b'Q', 0, 0]);
+    }
+
+    #[test]
+    fn appendage_round_trip() {
+        let app = appendage([
+            OptionValue::MinQty(100),
+            OptionValue::PegOffset(SignedPrice::from_raw(-100)),
+            OptionValue::Unknown { tag: 99, value: vec![1, 2, 3] },
+        ]);
+        let b = enter_order(app.clone()).encode();
+        let parsed = OptionalAppendage::decode(&b[EnterOrder::BASE_LEN..]).unwrap();
+        assert_eq!(parsed, app);
+        assert_eq!(parsed.get(OptionTag::MinQty), Some(&OptionValue::MinQty(100)));
+        assert!(matches!(
+            parsed.get(OptionTag::PegOffset),
+            Some(OptionValue::PegOffset(p)) if p.to_raw() == -100
+        ));
+        assert_eq!(parsed.get(OptionTag::Firm), None);
+        // Unknown tags are let through.
+        parsed.validate(EnterOrder::TYPE, EnterOrder::OPTIONS).unwrap();
+    }
+
+    #[test]
+    fn validate() {
+        let dup = appendage([OptionValue::MinQty(100), OptionValue::MinQty(200)]);
+        assert_eq!(
+            dup.validate(EnterOrder::TYPE, EnterOrder::OPTIONS),
+            Err(DecodeError::DuplicateOption { msg_type: b'O', tag: 3 })
+        );
+        let disallowed = appendage([OptionValue::MinQty(100)]);
+        assert_eq!(
+            disallowed.validate(CancelOrderRequest::TYPE, CancelOrderRequest::OPTIONS),
+            Err(DecodeError::UnexpectedOption { msg_type: b'X', tag: 3 })
+        );
+    }
+
+    #[test]
+    fn outbound_appendage() {
+        let m = OutboundMessage::decode(&cancel_pending(&[2, 28, 4, 3, 99, 1, 2])).unwrap();
+        let OutboundMessage::CancelPending(p) = m else { panic!() };
+        assert_eq!(p.optional_appendage.len(), 2);
+        assert_eq!(
+            p.optional_appendage.get(OptionTag::UserRefIdx),
+            Some(&OptionValue::UserRefIdx(4))
+        );
+
+        assert_eq!(
+            OutboundMessage::decode(&cancel_pending(&[2, 28, 4, 2, 28, 5])),
+            Err(DecodeError::DuplicateOption { msg_type: b'P', tag: 28 })
+        );
+        assert_eq!(
+            OutboundMessage::decode(&cancel_pending(&[5, 3, 0, 0, 0, 1])),
+            Err(DecodeError::UnexpectedOption { msg_type: b'P', tag: 3 })
+        );
+        assert_eq!(
+            OutboundMessage::decode(&cancel_pending(&[3, 28, 4, 4])),
+            Err(DecodeError::OptionLength { tag: 28, len: 2 })
+        );
+        assert_eq!(
+            OutboundMessage::decode(&cancel_pending(&[9, 28, 4])),
+            Err(DecodeError::OptionLength { tag: 28, len: 8 })
+        );
+        assert_eq!(
+            OutboundMessage::decode(&cancel_pending(&[2, 27, b'Z'])),
+            Err(DecodeError::InvalidOption { tag: 27 })
+        );
+    }
 }
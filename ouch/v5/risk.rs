@@ -0,0 +1,691 @@
+//! Pre-trade risk checks, run on orders before they're sent.
+
+use super::*;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The limits orders are checked against. Limits that aren't set aren't checked.
+///
+/// Limits can be loaded from a config file of `key = value` lines, where `#` starts a comment:
+///
+/// ```text
+/// max_shares = 10000
+/// # In dollars.
+/// max_notional = 250000
+/// # In percent of the reference price.
+/// price_collar = 5
+/// max_position = 20000
+/// position_limit.AAPL = 50000
+/// restricted = GME, AMC
+/// duplicate_window_ms = 500
+/// max_open_orders = 200
+/// max_open_orders_per_symbol = 20
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RiskLimits {
+    max_shares: Option<u32>,
+    max_notional: Option<f64>,
+    price_collar: Option<f64>,
+    max_position: Option<u64>,
+    position_limits: HashMap<nasdaq::Symbol, u64>,
+    restricted: HashSet<nasdaq::Symbol>,
+    duplicate_window: Option<Duration>,
+    max_open_orders: Option<usize>,
+    max_open_orders_per_symbol: Option<usize>,
+}
+
+impl RiskLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the limits from the config file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RiskConfigError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses the limits from the contents of a config file.
+    pub fn parse(s: &str) -> Result<Self, RiskConfigError> {
+        let mut limits = Self::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: &str| RiskConfigError::Invalid {
+                line: i + 1,
+                msg: msg.to_string(),
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(err("expected key = value"));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let symbol = |s: &str| nasdaq::Symbol::new_padded(s).map_err(|_| err("bad symbol"));
+            match key {
+                "max_shares" => {
+                    limits.max_shares = Some(value.parse().map_err(|_| err("bad number"))?)
+                }
+                "max_notional" => {
+                    limits.max_notional = Some(value.parse().map_err(|_| err("bad number"))?)
+                }
+                "price_collar" => {
+                    let pct: f64 = value.parse().map_err(|_| err("bad number"))?;
+                    limits.price_collar = Some(pct / 100.0);
+                }
+                "max_position" => {
+                    limits.max_position = Some(value.parse().map_err(|_| err("bad number"))?)
+                }
+                "restricted" => {
+                    for s in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                        limits.restricted.insert(symbol(s)?);
+                    }
+                }
+                "duplicate_window_ms" => {
+                    let ms = value.parse().map_err(|_| err("bad number"))?;
+                    limits.duplicate_window = Some(Duration::from_millis(ms));
+                }
+                "max_open_orders" => {
+                    limits.max_open_orders = Some(value.parse().map_err(|_| err("bad number"))?)
+                }
+                "max_open_orders_per_symbol" => {
+                    limits.max_open_orders_per_symbol =
+                        Some(value.parse().map_err(|_| err("bad number"))?)
+                }
+                _ => match key.strip_prefix("position_limit.") {
+                    Some(s) => {
+                        let limit = value.parse().map_err(|_| err("bad number"))?;
+                        limits.position_limits.insert(symbol(s)?, limit);
+                    }
+                    None => return Err(err("unknown key")),
+                },
+            }
+        }
+        Ok(limits)
+    }
+
+    /// The most shares an order can be for.
+    pub fn with_max_shares(mut self, max: u32) -> Self {
+        self.max_shares = Some(max);
+        self
+    }
+
+    /// The most an order can be worth, in dollars. Market orders are valued at the reference
+    /// price.
+    pub fn with_max_notional(mut self, max: f64) -> Self {
+        self.max_notional = Some(max);
+        self
+    }
+
+    /// How far (as a fraction, so 0.05 is 5%) a limit price can be from the symbol's reference
+    /// price. Orders in symbols without a reference price are rejected when this is set.
+    pub fn with_price_collar(mut self, frac: f64) -> Self {
+        self.price_collar = Some(frac);
+        self
+    }
+
+    /// The largest position, long or short, allowed in any symbol without its own limit. Open
+    /// orders count as if they were filled.
+    pub fn with_max_position(mut self, max: u64) -> Self {
+        self.max_position = Some(max);
+        self
+    }
+
+    /// The largest position allowed in the symbol, overriding the max position.
+    pub fn with_position_limit(mut self, symbol: nasdaq::Symbol, max: u64) -> Self {
+        self.position_limits.insert(symbol, max);
+        self
+    }
+
+    /// Adds a symbol no orders may be entered in.
+    pub fn with_restricted(mut self, symbol: nasdaq::Symbol) -> Self {
+        self.restricted.insert(symbol);
+        self
+    }
+
+    /// Rejects an order identical (in symbol, side, quantity and price) to one that passed
+    /// within the window.
+    pub fn with_duplicate_window(mut self, window: Duration) -> Self {
+        self.duplicate_window = Some(window);
+        self
+    }
+
+    /// The most orders that can be open (or pending) at once.
+    pub fn with_max_open_orders(mut self, max: usize) -> Self {
+        self.max_open_orders = Some(max);
+        self
+    }
+
+    /// The most orders that can be open (or pending) at once in a single symbol.
+    pub fn with_max_open_orders_per_symbol(mut self, max: usize) -> Self {
+        self.max_open_orders_per_symbol = Some(max);
+        self
+    }
+
+    pub fn max_shares(&self) -> Option<u32> {
+        self.max_shares
+    }
+
+    pub fn max_notional(&self) -> Option<f64> {
+        self.max_notional
+    }
+
+    pub fn price_collar(&self) -> Option<f64> {
+        self.price_collar
+    }
+
+    pub fn max_position(&self) -> Option<u64> {
+        self.max_position
+    }
+
+    /// The position limit for the symbol, which is its own or the max position.
+    pub fn position_limit(&self, symbol: nasdaq::Symbol) -> Option<u64> {
+        self.position_limits.get(&symbol).copied().or(self.max_position)
+    }
+
+    pub fn is_restricted(&self, symbol: nasdaq::Symbol) -> bool {
+        self.restricted.contains(&symbol)
+    }
+
+    pub fn duplicate_window(&self) -> Option<Duration> {
+        self.duplicate_window
+    }
+
+    pub fn max_open_orders(&self) -> Option<usize> {
+        self.max_open_orders
+    }
+
+    pub fn max_open_orders_per_symbol(&self) -> Option<usize> {
+        self.max_open_orders_per_symbol
+    }
+}
+
+/// Checks orders against `RiskLimits` before they're sent. Open orders and executions are taken
+/// from an `OrderTracker`, so orders that pass should be given to the tracker with `sent_order`
+/// or `sent_replace` before the next check.
+#[derive(Clone, Debug)]
+pub struct RiskChecker {
+    limits: RiskLimits,
+    reference_prices: HashMap<nasdaq::Symbol, nasdaq::Price>,
+    /// Positions from before the tracker's executions, like those carried over from a previous
+    /// day.
+    start_positions: HashMap<nasdaq::Symbol, i64>,
+    recent: VecDeque<(Instant, OrderKey)>,
+}
+
+type OrderKey = (nasdaq::Symbol, OrderSide, u32, u64);
+
+impl RiskChecker {
+    pub fn new(limits: RiskLimits) -> Self {
+        Self {
+            limits,
+            reference_prices: HashMap::new(),
+            start_positions: HashMap::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn limits(&self) -> &RiskLimits {
+        &self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: RiskLimits) {
+        self.limits = limits;
+    }
+
+    /// Sets the price collars and market orders are checked against for the symbol.
+    pub fn set_reference_price(&mut self, symbol: nasdaq::Symbol, price: nasdaq::Price) {
+        self.reference_prices.insert(symbol, price);
+    }
+
+    pub fn reference_price(&self, symbol: nasdaq::Symbol) -> Option<nasdaq::Price> {
+        self.reference_prices.get(&symbol).copied()
+    }
+
+    /// Sets the position held before any of the tracker's executions. Short positions are
+    /// negative.
+    pub fn set_start_position(&mut self, symbol: nasdaq::Symbol, position: i64) {
+        self.start_positions.insert(symbol, position);
+    }
+
+    /// The current position in the symbol: the start position plus the tracker's executions.
+    pub fn position(&self, symbol: nasdaq::Symbol, tracker: &OrderTracker) -> i64 {
+        let start = self.start_positions.get(&symbol).copied().unwrap_or(0);
+        tracker
+            .orders()
+            .filter(|o| o.symbol == symbol)
+            .fold(start, |pos, o| pos + signed(o.side, o.executed))
+    }
+
+    pub fn check_enter(
+        &mut self,
+        order: &EnterOrder,
+        tracker: &OrderTracker,
+    ) -> Result<(), RiskReject> {
+        self.check_enter_at(order, tracker, Instant::now())
+    }
+
+    /// Checks the order as if it were being sent at `now`.
+    pub fn check_enter_at(
+        &mut self,
+        order: &EnterOrder,
+        tracker: &OrderTracker,
+        now: Instant,
+    ) -> Result<(), RiskReject> {
+        let symbol = order.symbol;
+        if self.limits.is_restricted(symbol) {
+            return Err(RiskReject::Restricted(symbol));
+        }
+        self.check_size(symbol, order.quantity, order.price)?;
+        let key = (symbol, order.side, order.quantity, order.price.to_raw());
+        if let Some(window) = self.limits.duplicate_window {
+            while let Some(&(at, _)) = self.recent.front() {
+                if now.saturating_duration_since(at) < window {
+                    break;
+                }
+                self.recent.pop_front();
+            }
+            if self.recent.iter().any(|(_, k)| *k == key) {
+                return Err(RiskReject::Duplicate);
+            }
+        }
+        let open = tracker.orders().filter(|o| is_working(o));
+        let (total, in_symbol) = open.fold((0, 0), |(total, in_symbol), o| {
+            (total + 1, in_symbol + (o.symbol == symbol) as usize)
+        });
+        if let Some(max) = self.limits.max_open_orders {
+            if total >= max {
+                return Err(RiskReject::MaxOpenOrders { open: total, max });
+            }
+        }
+        if let Some(max) = self.limits.max_open_orders_per_symbol {
+            if in_symbol >= max {
+                return Err(RiskReject::MaxOpenOrdersPerSymbol {
+                    symbol,
+                    open: in_symbol,
+                    max,
+                });
+            }
+        }
+        self.check_position(symbol, order.side, order.quantity, None, tracker)?;
+        if self.limits.duplicate_window.is_some() {
+            self.recent.push_back((now, key));
+        }
+        Ok(())
+    }
+
+    /// Checks the replacement against the original order, which must be in the tracker.
+    pub fn check_replace(
+        &self,
+        req: &ReplaceOrderRequest,
+        tracker: &OrderTracker,
+    ) -> Result<(), RiskReject> {
+        let Some(orig) = tracker.get(req.orig_user_ref_num) else {
+            return Err(RiskReject::UnknownOrder(req.orig_user_ref_num));
+        };
+        if self.limits.is_restricted(orig.symbol) {
+            return Err(RiskReject::Restricted(orig.symbol));
+        }
+        self.check_size(orig.symbol, req.quantity, req.price)?;
+        self.check_position(orig.symbol, orig.side, req.quantity, Some(orig), tracker)
+    }
+
+    /// Checks the modification against the order, which must be in the tracker.
+    pub fn check_modify(
+        &self,
+        req: &ModifyOrderRequest,
+        tracker: &OrderTracker,
+    ) -> Result<(), RiskReject> {
+        let Some(order) = tracker.get(req.user_ref_num) else {
+            return Err(RiskReject::UnknownOrder(req.user_ref_num));
+        };
+        if let Some(max) = self.limits.max_shares {
+            if req.quantity > max {
+                return Err(RiskReject::MaxShares {
+                    quantity: req.quantity,
+                    max,
+                });
+            }
+        }
+        self.check_position(order.symbol, req.side, req.quantity, Some(order), tracker)
+    }
+
+    /// Checks the max shares, max notional and price collar.
+    fn check_size(
+        &self,
+        symbol: nasdaq::Symbol,
+        quantity: u32,
+        price: nasdaq::Price,
+    ) -> Result<(), RiskReject> {
+        if let Some(max) = self.limits.max_shares {
+            if quantity > max {
+                return Err(RiskReject::MaxShares { quantity, max });
+            }
+        }
+        let reference = self.reference_price(symbol);
+        let is_market = price.is_market() || price.is_market_cross();
+        if let Some(collar) = self.limits.price_collar.filter(|_| !is_market) {
+            let Some(reference) = reference else {
+                return Err(RiskReject::NoReferencePrice(symbol));
+            };
+            let (p, r) = (price.to_f64(), reference.to_f64());
+            if (p - r).abs() > r * collar {
+                return Err(RiskReject::PriceCollar { price, reference });
+            }
+        }
+        if let Some(max) = self.limits.max_notional {
+            let value = if is_market {
+                match reference {
+                    Some(r) => r.to_f64(),
+                    None => return Err(RiskReject::NoReferencePrice(symbol)),
+                }
+            } else {
+                price.to_f64()
+            };
+            let notional = value * quantity as f64;
+            if notional > max {
+                return Err(RiskReject::MaxNotional { notional, max });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the position, with every working order in the symbol filled, stays within
+    /// the limit if an order for `quantity` shares on `side` is added. `replacing` is an order
+    /// whose remaining shares are no longer counted.
+    fn check_position(
+        &self,
+        symbol: nasdaq::Symbol,
+        side: OrderSide,
+        quantity: u32,
+        replacing: Option<&TrackedOrder>,
+        tracker: &OrderTracker,
+    ) -> Result<(), RiskReject> {
+        let Some(limit) = self.limits.position_limit(symbol) else {
+            return Ok(());
+        };
+        let position = self.position(symbol, tracker);
+        let replaced = replacing.map(|o| o.user_ref_num);
+        let working = tracker
+            .orders()
+            .filter(|o| o.symbol == symbol && is_working(o))
+            .filter(|o| Some(o.user_ref_num) != replaced);
+        let (mut long, mut short) = (position, position);
+        for (side, shares) in working.map(|o| (o.side, o.leaves)).chain([(side, quantity)]) {
+            if side.is_buy() {
+                long += shares as i64;
+            } else {
+                short -= shares as i64;
+            }
+        }
+        for position in [long, short] {
+            if position.unsigned_abs() > limit {
+                return Err(RiskReject::PositionLimit {
+                    symbol,
+                    position,
+                    limit,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_working(o: &TrackedOrder) -> bool {
+    o.status == OrderStatus::Pending || o.status.is_open()
+}
+
+fn signed(side: OrderSide, quantity: u32) -> i64 {
+    if side.is_buy() {
+        quantity as i64
+    } else {
+        -(quantity as i64)
+    }
+}
+
+/// Why an order failed a risk check.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RiskReject {
+    MaxShares {
+        quantity: u32,
+        max: u32,
+    },
+    /// The order's value, in dollars, was over the max.
+    MaxNotional {
+        notional: f64,
+        max: f64,
+    },
+    /// The price was outside the collar around the reference price.
+    PriceCollar {
+        price: nasdaq::Price,
+        reference: nasdaq::Price,
+    },
+    /// A check needed the symbol's reference price but there wasn't one.
+    NoReferencePrice(nasdaq::Symbol),
+    /// The position (long or short) would go past the limit if every working order on that side
+    /// filled.
+    PositionLimit {
+        symbol: nasdaq::Symbol,
+        position: i64,
+        limit: u64,
+    },
+    Restricted(nasdaq::Symbol),
+    /// An identical order passed within the duplicate window.
+    Duplicate,
+    MaxOpenOrders {
+        open: usize,
+        max: usize,
+    },
+    MaxOpenOrdersPerSymbol {
+        symbol: nasdaq::Symbol,
+        open: usize,
+        max: usize,
+    },
+    /// The order being replaced or modified isn't tracked.
+    UnknownOrder(UserRefNum),
+}
+
+impl fmt::Display for RiskReject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RiskReject::MaxShares { quantity, max } => {
+                write!(f, "{quantity} shares is over the max of {max}")
+            }
+            RiskReject::MaxNotional { notional, max } => {
+                write!(f, "notional {notional:.2} is over the max of {max:.2}")
+            }
+            RiskReject::PriceCollar { price, reference } => {
+                write!(f, "price {price} is outside the collar around {reference}")
+            }
+            RiskReject::NoReferencePrice(symbol) => write!(f, "no reference price for {symbol}"),
+            RiskReject::PositionLimit {
+                symbol,
+                position,
+                limit,
+            } => write!(f, "position of {position} in {symbol} is over the limit of {limit}"),
+            RiskReject::Restricted(symbol) => write!(f, "{symbol} is restricted"),
+            RiskReject::Duplicate => write!(f, "duplicate order"),
+            RiskReject::MaxOpenOrders { open, max } => {
+                write!(f, "{open} open orders is at the max of {max}")
+            }
+            RiskReject::MaxOpenOrdersPerSymbol { symbol, open, max } => {
+                write!(f, "{open} open orders in {symbol} is at the max of {max}")
+            }
+            RiskReject::UnknownOrder(urn) => write!(f, "unknown order {}", urn.0),
+        }
+    }
+}
+
+impl Error for RiskReject {}
+
+#[derive(Debug)]
+pub enum RiskConfigError {
+    Io(io::Error),
+    /// The line (starting at 1) couldn't be parsed.
+    Invalid { line: usize, msg: String },
+}
+
+impl From<io::Error> for RiskConfigError {
+    fn from(e: io::Error) -> Self {
+        RiskConfigError::Io(e)
+    }
+}
+
+impl fmt::Display for RiskConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RiskConfigError::Io(ref e) => write!(f, "io error: {e}"),
+            RiskConfigError::Invalid { line, msg } => write!(f, "line {line}: {msg}"),
+        }
+    }
+}
+
+impl Error for RiskConfigError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::nasdaq::*;
+
+    fn sym(s: &str) -> Symbol {
+        Symbol::new_padded(s).unwrap()
+    }
+
+    fn enter(urn: u32, s: &str, side: OrderSide, qty: u32, price: f64) -> EnterOrder {
+        EnterOrder {
+            user_ref_num: UserRefNum(urn),
+            side,
+            quantity: qty,
+            symbol: sym(s),
+            price: Price::from_f64(price).unwrap(),
+            time_in_force: TimeInForce::Day,
+            display: Display::Visible,
+            capacity: Capacity::Agency,
+            inter_market_sweep_eligibility: IMSE::NotEligible,
+            cross_type: CrossType::ContinuousMarket,
+            cl_ord_id: ClOrdId::from_bytes(b"A             ").unwrap(),
+            optional_appendage: OptionalAppendage::empty(),
+        }
+    }
+
+    #[test]
+    fn config() {
+        let l = RiskLimits::parse(
+            "# risk\nmax_shares = 1000\nmax_notional = 50000 # dollars\nprice_collar = 5\n\
+             max_position = 2000\nposition_limit.AAPL = 3000\nrestricted = GME, AMC\n\
+             duplicate_window_ms = 100\nmax_open_orders = 3\nmax_open_orders_per_symbol = 2\n",
+        )
+        .unwrap();
+        assert_eq!(l.max_shares(), Some(1000));
+        assert_eq!(l.price_collar(), Some(0.05));
+        assert_eq!(l.position_limit(sym("AAPL")), Some(3000));
+        assert_eq!(l.position_limit(sym("MSFT")), Some(2000));
+        assert!(l.is_restricted(sym("AMC")));
+        assert_eq!(l.duplicate_window(), Some(Duration::from_millis(100)));
+        assert!(matches!(
+            RiskLimits::parse("max_shares = 1\nnope = 2"),
+            Err(RiskConfigError::Invalid { line: 2, .. })
+        ));
+        assert!(RiskLimits::parse("max_shares = x").is_err());
+    }
+
+    #[test]
+    fn rejects() {
+        use OrderSide::*;
+
+        let limits = RiskLimits::new()
+            .with_max_shares(1000)
+            .with_max_notional(50_000.0)
+            .with_price_collar(0.05)
+            .with_max_position(1500)
+            .with_restricted(sym("GME"))
+            .with_duplicate_window(Duration::from_millis(100))
+            .with_max_open_orders(3)
+            .with_max_open_orders_per_symbol(2);
+        let mut rc = RiskChecker::new(limits);
+        rc.set_reference_price(sym("AAPL"), Price::from_f64(40.0).unwrap());
+        rc.set_reference_price(sym("MSFT"), Price::from_f64(40.0).unwrap());
+        let mut t = OrderTracker::new();
+        let now = Instant::now();
+
+        let check = |rc: &mut RiskChecker, o: EnterOrder| rc.check_enter_at(&o, &t, now);
+        assert!(matches!(
+            check(&mut rc, enter(1, "AAPL", Buy, 2000, 40.0)),
+            Err(RiskReject::MaxShares { .. })
+        ));
+        assert!(matches!(
+            check(&mut rc, enter(1, "AAPL", Buy, 1000, 60.0)),
+            Err(RiskReject::PriceCollar { .. })
+        ));
+        assert!(matches!(
+            check(&mut rc, enter(1, "TSLA", Buy, 10, 60.0)),
+            Err(RiskReject::NoReferencePrice(_))
+        ));
+        assert_eq!(
+            check(&mut rc, enter(1, "GME", Buy, 10, 60.0)),
+            Err(RiskReject::Restricted(sym("GME")))
+        );
+        rc.set_reference_price(sym("AAPL"), Price::from_f64(60.0).unwrap());
+        assert!(matches!(
+            check(&mut rc, enter(1, "AAPL", Buy, 1000, 60.0)),
+            Err(RiskReject::MaxNotional { .. })
+        ));
+        rc.set_reference_price(sym("AAPL"), Price::from_f64(40.0).unwrap());
+
+        let o = enter(1, "AAPL", Buy, 1000, 40.0);
+        rc.check_enter_at(&o, &t, now).unwrap();
+        t.sent_order(&o);
+        let later = now + Duration::from_millis(50);
+        assert_eq!(rc.check_enter_at(&o, &t, later), Err(RiskReject::Duplicate));
+        // 1000 working + 600 > 1500
+        let later = now + Duration::from_millis(200);
+        assert!(matches!(
+            rc.check_enter_at(&enter(2, "AAPL", Buy, 600, 40.0), &t, later),
+            Err(RiskReject::PositionLimit { position: 1600, .. })
+        ));
+        // Sells don't add to the long side.
+        let o2 = enter(2, "AAPL", Sell, 1000, 40.0);
+        rc.check_enter_at(&o2, &t, now).unwrap();
+        t.sent_order(&o2);
+        assert!(matches!(
+            rc.check_enter_at(&enter(3, "AAPL", Buy, 10, 40.5), &t, now),
+            Err(RiskReject::MaxOpenOrdersPerSymbol { open: 2, .. })
+        ));
+        let o3 = enter(3, "MSFT", Buy, 10, 40.0);
+        rc.check_enter_at(&o3, &t, now).unwrap();
+        t.sent_order(&o3);
+        assert!(matches!(
+            rc.check_enter_at(&enter(4, "MSFT", Buy, 11, 40.0), &t, now),
+            Err(RiskReject::MaxOpenOrders { open: 3, max: 3 })
+        ));
+
+        // Replacing order 1 with 900 shares is fine, since its 1000 aren't counted.
+        let rep = ReplaceOrderRequest {
+            orig_user_ref_num: UserRefNum(1),
+            user_ref_num: UserRefNum(5),
+            quantity: 900,
+            price: Price::from_f64(40.0).unwrap(),
+            time_in_force: TimeInForce::Day,
+            display: Display::Visible,
+            inter_market_sweep_eligibility: IMSE::NotEligible,
+            cl_ord_id: ClOrdId::from_bytes(b"A             ").unwrap(),
+            optional_appendage: OptionalAppendage::empty(),
+        };
+        rc.check_replace(&rep, &t).unwrap();
+        rc.set_start_position(sym("AAPL"), 1000);
+        assert!(matches!(
+            rc.check_replace(&rep, &t),
+            Err(RiskReject::PositionLimit { position: 1900, .. })
+        ));
+        let m = ModifyOrderRequest {
+            user_ref_num: UserRefNum(9),
+            side: Sell,
+            quantity: 1,
+            optional_appendage: OptionalAppendage::empty(),
+        };
+        assert_eq!(rc.check_modify(&m, &t), Err(RiskReject::UnknownOrder(UserRefNum(9))));
+    }
+}
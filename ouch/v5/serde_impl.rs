@@ -0,0 +1,103 @@
+//! Alpha fields are (de)serialized as strings without padding. Everything else is derived.
+
+use super::{BrokerCode, ClOrdId, Firm, OptionalAppendage, TagValue};
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Pads the string with spaces to `N` bytes.
+fn padded<const N: usize, E: de::Error>(s: &str, what: &str) -> Result<[u8; N], E> {
+    if s.len() > N {
+        return Err(E::custom(format!("invalid {what}: {s:?}")));
+    }
+    let mut arr = [b' '; N];
+    arr[..s.len()].copy_from_slice(s.as_bytes());
+    Ok(arr)
+}
+
+macro_rules! impl_alpha {
+    ($ty:ident, $len:literal, $new:expr, $what:literal) => {
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                s.serialize_str(self.to_string().trim_end())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(d)?;
+                let arr = padded::<$len, D::Error>(&s, $what)?;
+                $new(&arr).ok_or_else(|| de::Error::custom(format!("invalid {}: {s:?}", $what)))
+            }
+        }
+    };
+}
+
+impl_alpha!(Firm, 4, |b: &[u8]| Firm::new(b).ok(), "firm");
+impl_alpha!(BrokerCode, 4, |b: &[u8]| BrokerCode::new(b).ok(), "broker");
+impl_alpha!(ClOrdId, 14, ClOrdId::from_bytes, "cl_ord_id");
+
+impl Serialize for OptionalAppendage {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for OptionalAppendage {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        OptionalAppendage::new(Vec::<TagValue>::deserialize(d)?)
+            .map_err(|_| de::Error::custom("optional appendage too long"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::*;
+    use nasdaq::{Price, Symbol};
+
+    fn executed() -> OutboundMessage {
+        let mut opts = OptionalAppendage::default();
+        opts.push(OptionValue::MinQty(100).into()).unwrap();
+        opts.push(OptionValue::Firm(Firm::new("AB  ").unwrap()).into()).unwrap();
+        OutboundMessage::OrderExecuted(OrderExecuted {
+            timestamp: 1000,
+            user_ref_num: UserRefNum(7),
+            quantity: 100,
+            price: Price::from_raw(1_872_500),
+            liquidity_flag: LiquidityFlag::Added,
+            match_number: 42,
+            optional_appendage: opts,
+        })
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = serde_json::to_string(&executed()).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"OrderExecuted":{"timestamp":1000,"user_ref_num":7,"quantity":100,"#,
+                r#""price":"187.2500","liquidity_flag":"Added","match_number":42,"#,
+                r#""optional_appendage":[{"MinQty":100},{"Firm":"AB"}]}}"#,
+            )
+        );
+        let back: OutboundMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, executed());
+    }
+
+    #[test]
+    fn alpha_padding() {
+        let firm = Firm::new("AB  ").unwrap();
+        assert_eq!(serde_json::to_string(&firm).unwrap(), r#""AB""#);
+        assert_eq!(serde_json::from_str::<Firm>(r#""AB""#).unwrap(), firm);
+
+        let cid = ClOrdId::from_bytes(b"ABC123        ").unwrap();
+        assert_eq!(serde_json::to_string(&cid).unwrap(), r#""ABC123""#);
+        assert_eq!(serde_json::from_str::<ClOrdId>(r#""ABC123""#).unwrap(), cid);
+        assert!(serde_json::from_str::<ClOrdId>(r#""ABCDEFGHIJKLMNO""#).is_err());
+        assert!(serde_json::from_str::<Firm>(r#""A-B""#).is_err());
+
+        let sym = Symbol::new_padded("AAPL").unwrap();
+        assert_eq!(serde_json::to_string(&sym).unwrap(), r#""AAPL""#);
+        assert_eq!(serde_json::from_str::<Symbol>(r#""AAPL""#).unwrap(), sym);
+    }
+}
@@ -0,0 +1,1022 @@
+//! An order-entry session, with SoupBinTCP as the transport.
+
+use super::throttle::Throttle;
+use super::*;
+
+pub use super::kill_switch::KillSwitch;
+pub use super::throttle::ThrottleMode;
+
+use jtutils::atomic_value::{Ordering, AAV};
+use soupbintcp::client::{ArcClientError, Client, ClientError, ClientOptions};
+use soupbintcp::{PacketType, Payload, SessionId};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default time to wait for the Account Query Response sent on login.
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(15);
+/// The default time between checks for the kill file.
+pub const DEFAULT_KILL_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// How long background threads wait before checking whether the session has closed.
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+#[derive(Clone)]
+pub struct OuchSessionOptions {
+    client: ClientOptions,
+    user_ref_num: UserRefNum,
+    allocator: Option<Arc<Mutex<UserRefNumAllocator>>>,
+    sync_user_ref_num: bool,
+    query_timeout: Duration,
+    max_messages_per_sec: u32,
+    burst: u32,
+    throttle_mode: ThrottleMode,
+    coalesce_cancels: bool,
+    kill_switch: Option<KillSwitch>,
+    kill_file: Option<PathBuf>,
+    kill_poll_interval: Duration,
+    kill_mass_cancels: Vec<(Firm, nasdaq::Symbol)>,
+    kill_disables: Vec<Firm>,
+}
+
+impl Default for OuchSessionOptions {
+    fn default() -> Self {
+        Self {
+            client: ClientOptions::default(),
+            user_ref_num: UserRefNum(1),
+            allocator: None,
+            sync_user_ref_num: true,
+            query_timeout: DEFAULT_QUERY_TIMEOUT,
+            max_messages_per_sec: 0,
+            burst: 0,
+            throttle_mode: ThrottleMode::default(),
+            coalesce_cancels: true,
+            kill_switch: None,
+            kill_file: None,
+            kill_poll_interval: DEFAULT_KILL_POLL_INTERVAL,
+            kill_mass_cancels: Vec::new(),
+            kill_disables: Vec::new(),
+        }
+    }
+}
+
+impl OuchSessionOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Options used to log into the SoupBinTCP server.
+    pub fn with_client_options(mut self, opts: ClientOptions) -> Self {
+        self.client = opts;
+        self
+    }
+
+    /// The first UserRefNum to assign. If the session is synced on login, the higher of this and
+    /// the one the server gives is used.
+    pub fn with_user_ref_num(mut self, urn: UserRefNum) -> Self {
+        self.user_ref_num = urn;
+        self
+    }
+
+    /// The allocator every UserRefNum sent is claimed from before it's sent, so a restarted
+    /// process never reuses one. The first UserRefNum assigned is at least the allocator's next.
+    pub fn with_user_ref_num_allocator(mut self, alloc: UserRefNumAllocator) -> Self {
+        self.allocator = Some(Arc::new(Mutex::new(alloc)));
+        self
+    }
+
+    /// Whether to send an Account Query Request on login and wait for the response to learn the
+    /// next UserRefNum. On by default.
+    pub fn with_sync_user_ref_num(mut self, b: bool) -> Self {
+        self.sync_user_ref_num = b;
+        self
+    }
+
+    /// How long to wait for the Account Query Response on login.
+    pub fn with_query_timeout(mut self, timeout: Duration) -> Self {
+        self.query_timeout = timeout;
+        self
+    }
+
+    /// The most messages to send per second. Zero (the default) is unlimited.
+    pub fn with_max_messages_per_sec(mut self, rate: u32) -> Self {
+        self.max_messages_per_sec = rate;
+        self
+    }
+
+    /// How many messages can be sent at once before the rate limit applies. Defaults to one.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// What to do with messages over the rate limit.
+    pub fn with_throttle_mode(mut self, mode: ThrottleMode) -> Self {
+        self.throttle_mode = mode;
+        self
+    }
+
+    /// Whether a queued cancel is replaced by a later cancel for the same order, rather than
+    /// both being sent. On by default.
+    pub fn with_coalesce_cancels(mut self, b: bool) -> Self {
+        self.coalesce_cancels = b;
+        self
+    }
+
+    /// The switch that stops the session from entering orders. Once triggered, Enter Order and
+    /// Replace Order Requests (including queued ones) aren't sent, and the kill actions are.
+    pub fn with_kill_switch(mut self, ks: KillSwitch) -> Self {
+        self.kill_switch = Some(ks);
+        self
+    }
+
+    /// A file whose existence triggers the kill switch.
+    pub fn with_kill_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.kill_file = Some(path.into());
+        self
+    }
+
+    /// How often to check for the kill file.
+    pub fn with_kill_poll_interval(mut self, interval: Duration) -> Self {
+        self.kill_poll_interval = interval;
+        self
+    }
+
+    /// Adds a Mass Cancel Request to send when the kill switch is triggered. A blank symbol
+    /// cancels across all symbols.
+    pub fn with_kill_mass_cancel(mut self, firm: Firm, symbol: nasdaq::Symbol) -> Self {
+        self.kill_mass_cancels.push((firm, symbol));
+        self
+    }
+
+    /// Adds a Disable Order Entry Request to send when the kill switch is triggered.
+    pub fn with_kill_disable_order_entry(mut self, firm: Firm) -> Self {
+        self.kill_disables.push(firm);
+        self
+    }
+
+    pub fn client_options(&self) -> &ClientOptions {
+        &self.client
+    }
+
+    pub fn max_messages_per_sec(&self) -> u32 {
+        self.max_messages_per_sec
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    pub fn throttle_mode(&self) -> ThrottleMode {
+        self.throttle_mode
+    }
+
+    pub fn coalesce_cancels(&self) -> bool {
+        self.coalesce_cancels
+    }
+
+    pub fn kill_switch(&self) -> Option<&KillSwitch> {
+        self.kill_switch.as_ref()
+    }
+
+    pub fn kill_file(&self) -> Option<&Path> {
+        self.kill_file.as_deref()
+    }
+
+    pub fn kill_poll_interval(&self) -> Duration {
+        self.kill_poll_interval
+    }
+
+    pub fn kill_mass_cancels(&self) -> &[(Firm, nasdaq::Symbol)] {
+        &self.kill_mass_cancels
+    }
+
+    pub fn kill_disables(&self) -> &[Firm] {
+        &self.kill_disables
+    }
+
+    pub fn user_ref_num(&self) -> UserRefNum {
+        self.user_ref_num
+    }
+
+    pub fn user_ref_num_allocator(&self) -> Option<&Mutex<UserRefNumAllocator>> {
+        self.allocator.as_deref()
+    }
+
+    pub fn sync_user_ref_num(&self) -> bool {
+        self.sync_user_ref_num
+    }
+
+    pub fn query_timeout(&self) -> Duration {
+        self.query_timeout
+    }
+
+    /// Logs into the server at `addr` and, if enabled, syncs the next UserRefNum. Messages read
+    /// while waiting for the Account Query Response are kept for `read_message`.
+    pub fn connect(self, addr: impl ToSocketAddrs) -> Result<OuchSession, SessionError> {
+        let client = self.client.clone().connect(addr, None)?;
+        let throttle = (self.max_messages_per_sec != 0)
+            .then(|| Mutex::new(Throttle::new(self.max_messages_per_sec, self.burst)));
+        let urn = match &self.allocator {
+            Some(alloc) => self.user_ref_num.max(alloc.lock().unwrap().peek()),
+            None => self.user_ref_num,
+        };
+        let inner = Arc::new(InnerSession {
+            next_seq_num: Mutex::new(client.sequence_number().to_u64().max(1)),
+            next_user_ref_num: Mutex::new(urn),
+            pending: Mutex::new(VecDeque::new()),
+            client,
+            throttle,
+            queue: Mutex::new(VecDeque::new()),
+            queue_cond: Condvar::new(),
+            kill_switch: self.kill_switch.clone().unwrap_or_default(),
+            killed: AtomicU64::new(0),
+            opts: self,
+            close_err: AAV::empty(),
+        });
+        if inner.opts.sync_user_ref_num {
+            if let Err(e) = inner.sync_user_ref_num() {
+                inner.close_with_err(SessionError::Closed);
+                return Err(e);
+            }
+        }
+        if inner.throttle.is_some() && inner.opts.throttle_mode == ThrottleMode::Queue {
+            let weak = Arc::downgrade(&inner);
+            thread::spawn(move || InnerSession::run_queue(weak));
+        }
+        if inner.opts.kill_switch.is_some() || inner.opts.kill_file.is_some() {
+            let weak = Arc::downgrade(&inner);
+            thread::spawn(move || InnerSession::watch_kill_switch(weak));
+        }
+        Ok(OuchSession(inner))
+    }
+}
+
+/// An OUCH 5.0 order-entry session. Inbound messages are sent as unsequenced data and the
+/// sequenced data from the server is decoded into `OutboundMessage`s.
+///
+/// UserRefNums are assigned by the session for every message that needs a new one, so they're
+/// strictly increasing on the wire. The session doesn't read on its own, so `read_message` must
+/// be called often enough to keep up with the server's heartbeats.
+///
+/// Messages can be rate limited, and a kill switch can stop new orders and cancel those open.
+/// The kill actions skip the queue of throttled messages.
+#[derive(Clone)]
+pub struct OuchSession(Arc<InnerSession>);
+
+impl OuchSession {
+    pub fn options() -> OuchSessionOptions {
+        OuchSessionOptions::new()
+    }
+
+    /// Enters the order, giving it the next UserRefNum, which is returned.
+    pub fn enter_order(&self, mut order: EnterOrder) -> Result<UserRefNum, ArcSessionError> {
+        self.0.check_killed()?;
+        self.0.send_with_urn(SendKind::Order, |urn| {
+            order.user_ref_num = urn;
+            order.encode()
+        })
+    }
+
+    /// Replaces the order `orig`, giving the replacement the next UserRefNum, which is returned.
+    /// The request's `orig_user_ref_num` is set to `orig`.
+    pub fn replace(
+        &self,
+        orig: UserRefNum,
+        mut req: ReplaceOrderRequest,
+    ) -> Result<UserRefNum, ArcSessionError> {
+        self.0.check_killed()?;
+        self.0.send_with_urn(SendKind::Order, |urn| {
+            req.orig_user_ref_num = orig;
+            req.user_ref_num = urn;
+            req.encode()
+        })
+    }
+
+    /// Reduces the order to `quantity` shares. Zero cancels it completely.
+    pub fn cancel(&self, urn: UserRefNum, quantity: u32) -> Result<(), ArcSessionError> {
+        let req = CancelOrderRequest {
+            user_ref_num: urn,
+            quantity,
+            optional_appendage: OptionalAppendage::empty(),
+        };
+        self.0.send_bytes(req.encode(), SendKind::Cancel(urn))
+    }
+
+    /// Changes the side of the order and reduces it to `quantity` shares.
+    pub fn modify(
+        &self,
+        urn: UserRefNum,
+        side: OrderSide,
+        quantity: u32,
+    ) -> Result<(), ArcSessionError> {
+        self.send(&ModifyOrderRequest {
+            user_ref_num: urn,
+            side,
+            quantity,
+            optional_appendage: OptionalAppendage::empty(),
+        })
+    }
+
+    /// Cancels all of the firm's orders in the symbol, or in all symbols if it's blank. Returns
+    /// the UserRefNum the request was sent with.
+    pub fn mass_cancel(
+        &self,
+        firm: Firm,
+        symbol: nasdaq::Symbol,
+    ) -> Result<UserRefNum, ArcSessionError> {
+        self.0.send_with_urn(SendKind::NewUrn, |urn| {
+            MassCancelRequest {
+                user_ref_num: urn,
+                firm,
+                symbol,
+                optional_appendage: OptionalAppendage::empty(),
+            }
+            .encode()
+        })
+    }
+
+    pub fn disable_order_entry(&self, firm: Firm) -> Result<UserRefNum, ArcSessionError> {
+        self.0.send_with_urn(SendKind::NewUrn, |urn| {
+            DisableOrderEntryRequest {
+                user_ref_num: urn,
+                firm,
+                optional_appendage: OptionalAppendage::empty(),
+            }
+            .encode()
+        })
+    }
+
+    pub fn enable_order_entry(&self, firm: Firm) -> Result<UserRefNum, ArcSessionError> {
+        self.0.send_with_urn(SendKind::NewUrn, |urn| {
+            EnableOrderEntryRequest {
+                user_ref_num: urn,
+                firm,
+                optional_appendage: OptionalAppendage::empty(),
+            }
+            .encode()
+        })
+    }
+
+    /// Asks for the next UserRefNum. The session syncs to the response when it's read.
+    pub fn account_query(&self) -> Result<(), ArcSessionError> {
+        self.send(&AccountQueryRequest::default())
+    }
+
+    /// Sends the message as is. Messages needing a new UserRefNum should be sent with the typed
+    /// methods instead.
+    pub fn send<M: Message>(&self, msg: &M) -> Result<(), ArcSessionError> {
+        let kind = match M::TYPE {
+            EnterOrder::TYPE | ReplaceOrderRequest::TYPE => {
+                self.0.check_killed()?;
+                SendKind::Order
+            }
+            MassCancelRequest::TYPE
+            | DisableOrderEntryRequest::TYPE
+            | EnableOrderEntryRequest::TYPE => SendKind::NewUrn,
+            _ => SendKind::Other,
+        };
+        self.0.send_bytes(msg.encode(), kind)
+    }
+
+    /// Triggers the kill switch and sends the kill actions, if they haven't been sent since it
+    /// was last triggered. Queued messages with new UserRefNums (like orders) are dropped.
+    pub fn kill(&self, reason: impl Into<String>) -> Result<(), ArcSessionError> {
+        self.0.kill_switch.trigger(reason);
+        self.0.run_kill_actions()
+    }
+
+    /// The session's kill switch, which is the one it was given or its own.
+    pub fn kill_switch(&self) -> &KillSwitch {
+        &self.0.kill_switch
+    }
+
+    /// The number of messages waiting to be sent because of the rate limit.
+    pub fn queued(&self) -> usize {
+        self.0.queue.lock().unwrap().len()
+    }
+
+    /// Reads the next message from the server. Returns `None` once the session is closed. A
+    /// message that can't be decoded is returned as an error without closing the session.
+    pub fn read_message(&self) -> Option<Result<OutboundMessage, ArcSessionError>> {
+        if let Some(msg) = self.0.pending.lock().unwrap().pop_front() {
+            return Some(Ok(msg));
+        }
+        self.0.read_message(None)
+    }
+
+    /// The UserRefNum the next new order or request will be given.
+    pub fn next_user_ref_num(&self) -> UserRefNum {
+        *self.0.next_user_ref_num.lock().unwrap()
+    }
+
+    /// The sequence number of the next message to be read from the server, which is the one to
+    /// log in with to pick up where this session left off.
+    pub fn next_seq_num(&self) -> u64 {
+        *self.0.next_seq_num.lock().unwrap()
+    }
+
+    pub fn session(&self) -> SessionId {
+        self.0.client.session()
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.0.client
+    }
+
+    pub fn opts(&self) -> &OuchSessionOptions {
+        &self.0.opts
+    }
+
+    pub fn logout(&self) -> Result<(), ArcSessionError> {
+        let res = self.0.client.logout();
+        self.0.close_with_err(SessionError::Closed);
+        res.map_err(|e| Arc::new(SessionError::Client(e)))
+    }
+
+    pub fn close_err(&self) -> Option<ArcSessionError> {
+        self.0.close_err.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.0.close_err.is_empty(Ordering::Relaxed)
+    }
+}
+
+struct InnerSession {
+    client: Client,
+    opts: OuchSessionOptions,
+    next_seq_num: Mutex<u64>,
+    /// Held while sending so UserRefNums go out in order.
+    next_user_ref_num: Mutex<UserRefNum>,
+    pending: Mutex<VecDeque<OutboundMessage>>,
+
+    throttle: Option<Mutex<Throttle>>,
+    /// Held while writing so messages go out in the order they were queued.
+    queue: Mutex<VecDeque<Queued>>,
+    queue_cond: Condvar,
+
+    kill_switch: KillSwitch,
+    /// The trigger of the kill switch (see `KillSwitch::triggers`) the kill actions were last
+    /// sent for.
+    killed: AtomicU64,
+
+    close_err: AAV<SessionError>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SendKind {
+    /// An Enter Order or Replace Order Request.
+    Order,
+    /// Any other request with a new UserRefNum.
+    NewUrn,
+    Cancel(UserRefNum),
+    /// Sent right away, skipping the queue.
+    Kill,
+    Other,
+}
+
+struct Queued {
+    bytes: Vec<u8>,
+    kind: SendKind,
+}
+
+impl InnerSession {
+    fn sync_user_ref_num(&self) -> Result<(), SessionError> {
+        let query = AccountQueryRequest::default().encode();
+        if let Err(e) = self.send_bytes(query, SendKind::Other) {
+            return Err(unshare(Some(e)));
+        }
+        let deadline = Instant::now() + self.opts.query_timeout;
+        loop {
+            if Instant::now() >= deadline {
+                return Err(SessionError::QueryTimedOut);
+            }
+            let msg = match self.read_message(Some(deadline)) {
+                Some(Ok(msg)) => msg,
+                Some(Err(e)) if matches!(*e, SessionError::QueryTimedOut) => {
+                    return Err(SessionError::QueryTimedOut);
+                }
+                // Messages that can't be decoded can't be the response.
+                Some(Err(e)) if matches!(*e, SessionError::Decode(_)) => continue,
+                Some(Err(e)) => return Err(unshare(Some(e))),
+                None => return Err(unshare(self.close_err.load(Ordering::Relaxed))),
+            };
+            let done = matches!(msg, OutboundMessage::AccountQueryResponse(_));
+            self.pending.lock().unwrap().push_back(msg);
+            if done {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send_with_urn(
+        &self,
+        kind: SendKind,
+        encode: impl FnOnce(UserRefNum) -> Vec<u8>,
+    ) -> Result<UserRefNum, ArcSessionError> {
+        let mut next = self.next_user_ref_num.lock().unwrap();
+        let urn = *next;
+        // The last UserRefNum is never sent so the next one can always be stored.
+        let Some(after) = urn.checked_incr() else {
+            return Err(Arc::new(SessionError::UserRefNum(IdError::Exhausted)));
+        };
+        if let Some(alloc) = &self.opts.allocator {
+            if let Err(e) = alloc.lock().unwrap().claim(urn) {
+                return Err(Arc::new(SessionError::UserRefNum(e)));
+            }
+        }
+        self.send_bytes(encode(urn), kind)?;
+        *next = after;
+        Ok(urn)
+    }
+
+    /// Sends the message, or queues it if it's over the rate limit.
+    fn send_bytes(&self, b: Vec<u8>, kind: SendKind) -> Result<(), ArcSessionError> {
+        if let Some(e) = self.close_err.load(Ordering::Relaxed) {
+            return Err(e);
+        }
+        let Some(throttle) = &self.throttle else {
+            return self.write(b);
+        };
+        let mut queue = self.queue.lock().unwrap();
+        let now = Instant::now();
+        if kind == SendKind::Kill {
+            throttle.lock().unwrap().spend(now);
+            return self.write(b);
+        }
+        if queue.is_empty() && throttle.lock().unwrap().try_take(now) {
+            return self.write(b);
+        }
+        if self.opts.throttle_mode == ThrottleMode::Reject {
+            return Err(Arc::new(SessionError::Throttled));
+        }
+        if matches!(kind, SendKind::Cancel(_)) && self.opts.coalesce_cancels {
+            if let Some(q) = queue.iter_mut().find(|q| q.kind == kind) {
+                q.bytes = b;
+                return Ok(());
+            }
+        }
+        queue.push_back(Queued { bytes: b, kind });
+        self.queue_cond.notify_all();
+        Ok(())
+    }
+
+    fn write(&self, b: Vec<u8>) -> Result<(), ArcSessionError> {
+        // Messages are far shorter than the maximum payload.
+        let payload = Payload::new(b).expect("message too long");
+        self.client
+            .send_unsequenced(payload)
+            .map_err(|e| self.close_with_err(SessionError::Client(e)))
+    }
+
+    /// Reads the next sequenced message. If the deadline passes first, `QueryTimedOut` is
+    /// returned without closing the session. Since reads block until a packet arrives, it's only
+    /// checked as heartbeats come in.
+    fn read_message(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Option<Result<OutboundMessage, ArcSessionError>> {
+        loop {
+            if !self.close_err.is_empty(Ordering::Relaxed) {
+                return None;
+            }
+            let packet = match self.client.read_packet() {
+                Some(Ok(packet)) => packet,
+                Some(Err(e)) => return Some(Err(self.close_with_err(SessionError::Client(e)))),
+                None => {
+                    let err = self
+                        .client
+                        .close_err()
+                        .unwrap_or_else(|| Arc::new(ClientError::LoggedOut));
+                    self.close_with_err(SessionError::Client(err));
+                    return None;
+                }
+            };
+            self.client.set_last_server_heartbeat(Instant::now());
+            match packet.packet_type() {
+                PacketType::SequencedData => {
+                    *self.next_seq_num.lock().unwrap() += 1;
+                    return Some(match OutboundMessage::decode(packet.payload()) {
+                        Ok(msg) => {
+                            self.saw(&msg);
+                            Ok(msg)
+                        }
+                        Err(e) => Err(Arc::new(SessionError::Decode(e))),
+                    });
+                }
+                PacketType::EndOfSession => {
+                    self.close_with_err(SessionError::SessionEnded);
+                    return None;
+                }
+                _ => (),
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Some(Err(Arc::new(SessionError::QueryTimedOut)));
+            }
+        }
+    }
+
+    /// Sends queued messages as the rate limit allows, until the session is closed or dropped.
+    fn run_queue(weak: Weak<InnerSession>) {
+        loop {
+            let Some(inner) = weak.upgrade() else {
+                break;
+            };
+            if !inner.close_err.is_empty(Ordering::Relaxed) {
+                break;
+            }
+            let mut queue = inner.queue.lock().unwrap();
+            if queue.is_empty() {
+                let _ = inner.queue_cond.wait_timeout(queue, IDLE_WAIT).unwrap();
+                continue;
+            }
+            let delay = {
+                let mut throttle = inner.throttle.as_ref().unwrap().lock().unwrap();
+                let delay = throttle.delay(Instant::now());
+                if delay.is_zero() {
+                    throttle.spend(Instant::now());
+                }
+                delay
+            };
+            if !delay.is_zero() {
+                drop(queue);
+                drop(inner);
+                thread::sleep(delay.min(IDLE_WAIT));
+                continue;
+            }
+            let q = queue.pop_front().unwrap();
+            if inner.write(q.bytes).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Watches for the kill switch to be triggered (or the kill file to appear) and sends the
+    /// kill actions when it is, until the session is closed or dropped.
+    fn watch_kill_switch(weak: Weak<InnerSession>) {
+        loop {
+            let Some(inner) = weak.upgrade() else {
+                break;
+            };
+            if !inner.close_err.is_empty(Ordering::Relaxed) {
+                break;
+            }
+            if let Some(path) = &inner.opts.kill_file {
+                if !inner.kill_switch.is_triggered() && path.exists() {
+                    inner.kill_switch.trigger(format!("kill file {}", path.display()));
+                }
+            }
+            let triggered = inner.kill_switch.is_triggered();
+            if triggered {
+                let _ = inner.run_kill_actions();
+            }
+            let timeout = match inner.opts.kill_file {
+                Some(_) => inner.opts.kill_poll_interval.min(IDLE_WAIT),
+                None => IDLE_WAIT,
+            };
+            let ks = inner.kill_switch.clone();
+            drop(inner);
+            ks.wait(triggered, timeout);
+        }
+    }
+
+    fn check_killed(&self) -> Result<(), ArcSessionError> {
+        if self.kill_switch.is_triggered() {
+            return Err(Arc::new(SessionError::Killed));
+        }
+        Ok(())
+    }
+
+    /// Drops queued messages with new UserRefNums and sends the kill actions, once per trigger
+    /// of the kill switch. The queued messages would be rejected anyway, as the kill actions
+    /// skip the queue with higher UserRefNums.
+    fn run_kill_actions(&self) -> Result<(), ArcSessionError> {
+        if !self.kill_switch.is_triggered() {
+            return Ok(());
+        }
+        let triggers = self.kill_switch.triggers();
+        if self.killed.swap(triggers, AtomicOrdering::SeqCst) == triggers {
+            return Ok(());
+        }
+        self.queue
+            .lock()
+            .unwrap()
+            .retain(|q| !matches!(q.kind, SendKind::Order | SendKind::NewUrn));
+        for &(firm, symbol) in &self.opts.kill_mass_cancels {
+            self.send_with_urn(SendKind::Kill, |urn| {
+                MassCancelRequest {
+                    user_ref_num: urn,
+                    firm,
+                    symbol,
+                    optional_appendage: OptionalAppendage::empty(),
+                }
+                .encode()
+            })?;
+        }
+        for &firm in &self.opts.kill_disables {
+            self.send_with_urn(SendKind::Kill, |urn| {
+                DisableOrderEntryRequest {
+                    user_ref_num: urn,
+                    firm,
+                    optional_appendage: OptionalAppendage::empty(),
+                }
+                .encode()
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the next UserRefNum past any the server has seen.
+    fn saw(&self, msg: &OutboundMessage) {
+        let urn = match msg {
+            OutboundMessage::AccountQueryResponse(resp) => resp.next_user_ref_num,
+            msg => match msg.user_ref_num() {
+                // The last UserRefNum is never sent, so staying on it means there are none left.
+                Some(urn) => urn.checked_incr().unwrap_or(UserRefNum(u32::MAX)),
+                None => return,
+            },
+        };
+        let mut next = self.next_user_ref_num.lock().unwrap();
+        *next = (*next).max(urn);
+    }
+
+    fn close_with_err(&self, err: SessionError) -> ArcSessionError {
+        let stored = self
+            .close_err
+            .store_if_empty(err, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok();
+        if stored {
+            if !self.client.is_closed() {
+                let _ = self.client.logout();
+            }
+            self.queue_cond.notify_all();
+            self.kill_switch.wake();
+        }
+        self.close_err.load(Ordering::Relaxed).unwrap()
+    }
+}
+
+/// Turns a shared close error into one that can be returned from `connect`.
+fn unshare(err: Option<ArcSessionError>) -> SessionError {
+    match err.as_deref() {
+        Some(SessionError::SessionEnded) => SessionError::SessionEnded,
+        Some(SessionError::Client(e)) => SessionError::Client(Arc::clone(e)),
+        _ => SessionError::Closed,
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    /// The server ended the session.
+    SessionEnded,
+    /// No Account Query Response came on login within the query timeout.
+    QueryTimedOut,
+    /// The message was over the rate limit and the throttle mode is `Reject`.
+    Throttled,
+    /// The kill switch is triggered, so no orders can be entered.
+    Killed,
+    Closed,
+    /// The UserRefNum couldn't be claimed from the allocator, or there are none left, so the
+    /// message wasn't sent.
+    UserRefNum(IdError),
+    Client(ArcClientError),
+    Decode(DecodeError),
+}
+pub type ArcSessionError = Arc<SessionError>;
+
+impl From<ClientError> for SessionError {
+    fn from(e: ClientError) -> Self {
+        SessionError::Client(Arc::new(e))
+    }
+}
+
+impl From<DecodeError> for SessionError {
+    fn from(e: DecodeError) -> Self {
+        SessionError::Decode(e)
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::SessionEnded => write!(f, "session ended"),
+            SessionError::QueryTimedOut => write!(f, "account query timed out"),
+            SessionError::Throttled => write!(f, "over the message rate limit"),
+            SessionError::Killed => write!(f, "kill switch triggered"),
+            SessionError::Closed => write!(f, "session closed"),
+            SessionError::UserRefNum(ref e) => write!(f, "user ref num error: {e}"),
+            SessionError::Client(ref e) => write!(f, "soupbintcp client error: {e}"),
+            SessionError::Decode(ref e) => write!(f, "decode error: {e}"),
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soupbintcp::{Packet, SequenceNumber};
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+
+    /// Accepts a login, runs `script`, then returns the first `n` unsequenced messages sent
+    /// before ending the session.
+    fn serve(
+        n: usize,
+        script: impl FnOnce(&mut TcpStream) + Send + 'static,
+    ) -> (SocketAddr, thread::JoinHandle<Vec<Vec<u8>>>) {
+        let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = ln.local_addr().unwrap();
+        let h = thread::spawn(move || {
+            let (mut s, _) = ln.accept().unwrap();
+            Packet::read_from(&mut s).unwrap();
+            let accepted =
+                Packet::login_accepted(SessionId::new_trunc("SESS"), SequenceNumber::from_u64(1));
+            s.write_all(accepted.as_slice()).unwrap();
+            script(&mut s);
+            let mut got = Vec::new();
+            while got.len() < n {
+                let Ok(p) = Packet::read_from(&mut s) else { break };
+                if p.packet_type() == PacketType::UnsequencedData {
+                    got.push(p.payload().to_vec());
+                }
+            }
+            let _ = s.write_all(Packet::end_of_session().as_slice());
+            got
+        });
+        (addr, h)
+    }
+
+    fn sequenced(s: &mut TcpStream, msg: Vec<u8>) {
+        let p = Packet::sequenced_data(Payload::new(msg).unwrap());
+        s.write_all(p.as_slice()).unwrap();
+    }
+
+    fn rejected(urn: u32) -> Vec<u8> {
+        let mut b = vec![b'J'];
+        b.extend(1i64.to_be_bytes());
+        b.extend(urn.to_be_bytes());
+        b.extend(0x1au16.to_be_bytes());
+        b.extend(b"X             ");
+        b.extend(0u16.to_be_bytes());
+        b
+    }
+
+    fn query_response(urn: u32) -> Vec<u8> {
+        let mut b = vec![b'Q'];
+        b.extend(2i64.to_be_bytes());
+        b.extend(urn.to_be_bytes());
+        b.extend(0u16.to_be_bytes());
+        b
+    }
+
+    fn order() -> EnterOrder {
+        let symbol = nasdaq::Symbol::new_padded("AAPL").unwrap();
+        let price = nasdaq::Price::from_f64(187.25).unwrap();
+        EnterOrder::limit(symbol, OrderSide::Buy, 100, price).build().unwrap()
+    }
+
+    #[test]
+    fn kill_drops_queued_and_reruns() {
+        let (addr, server) = serve(3, |_| {});
+        let firm = Firm::new("ABCD").unwrap();
+        let sess = OuchSession::options()
+            .with_sync_user_ref_num(false)
+            .with_max_messages_per_sec(1)
+            .with_kill_mass_cancel(firm, nasdaq::Symbol::empty())
+            .connect(addr)
+            .unwrap();
+        assert_eq!(sess.enter_order(order()).unwrap(), UserRefNum(1));
+        // Over the rate limit, so these are queued.
+        assert_eq!(sess.mass_cancel(firm, nasdaq::Symbol::empty()).unwrap(), UserRefNum(2));
+        assert_eq!(sess.enter_order(order()).unwrap(), UserRefNum(3));
+        assert_eq!(sess.queued(), 2);
+
+        sess.kill("test").unwrap();
+        assert_eq!(sess.queued(), 0);
+        assert!(matches!(*sess.enter_order(order()).unwrap_err(), SessionError::Killed));
+        // Without a watcher thread, the actions are still sent again for a new trigger.
+        sess.kill_switch().reset();
+        sess.kill("again").unwrap();
+
+        let got = server.join().unwrap();
+        assert_eq!((got[0][0], &got[0][1..5]), (b'O', &1u32.to_be_bytes()[..]));
+        assert_eq!((got[1][0], &got[1][1..5]), (b'C', &4u32.to_be_bytes()[..]));
+        assert_eq!((got[2][0], &got[2][1..5]), (b'C', &5u32.to_be_bytes()[..]));
+    }
+
+    #[test]
+    fn login_syncs_user_ref_num() {
+        let (addr, server) = serve(2, |s| {
+            sequenced(s, rejected(41));
+            sequenced(s, query_response(100));
+        });
+        let sess = OuchSession::options().connect(addr).unwrap();
+        assert_eq!(sess.next_user_ref_num(), UserRefNum(100));
+        assert_eq!(sess.next_seq_num(), 3);
+        // Messages read while waiting for the response are still handed out.
+        let msg = sess.read_message().unwrap().unwrap();
+        assert!(matches!(msg, OutboundMessage::Rejected(r) if r.user_ref_num == UserRefNum(41)));
+        let msg = sess.read_message().unwrap().unwrap();
+        assert!(matches!(msg, OutboundMessage::AccountQueryResponse(_)));
+        assert_eq!(sess.enter_order(order()).unwrap(), UserRefNum(100));
+
+        let got = server.join().unwrap();
+        assert_eq!(got[0][0], b'Q');
+        assert_eq!((got[1][0], &got[1][1..5]), (b'O', &100u32.to_be_bytes()[..]));
+        assert!(sess.read_message().is_none());
+        assert!(matches!(*sess.close_err().unwrap(), SessionError::SessionEnded));
+    }
+
+    #[test]
+    fn query_times_out_despite_heartbeats() {
+        let (addr, _server) = serve(0, |s| {
+            while s.write_all(Packet::server_heartbeat().as_slice()).is_ok() {
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        let opts = OuchSession::options().with_query_timeout(Duration::from_millis(100));
+        assert!(matches!(opts.connect(addr), Err(SessionError::QueryTimedOut)));
+    }
+
+    #[test]
+    fn last_user_ref_num_is_not_sent() {
+        let (addr, server) = serve(0, |s| sequenced(s, rejected(u32::MAX)));
+        let sess = OuchSession::options().with_sync_user_ref_num(false).connect(addr).unwrap();
+        assert!(sess.read_message().unwrap().is_ok());
+        assert_eq!(sess.next_user_ref_num(), UserRefNum(u32::MAX));
+        let err = sess.enter_order(order()).unwrap_err();
+        assert!(matches!(*err, SessionError::UserRefNum(IdError::Exhausted)));
+        assert!(server.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn throttle_reject() {
+        let (addr, server) = serve(1, |_| {});
+        let sess = OuchSession::options()
+            .with_sync_user_ref_num(false)
+            .with_max_messages_per_sec(1)
+            .with_throttle_mode(ThrottleMode::Reject)
+            .connect(addr)
+            .unwrap();
+        assert_eq!(sess.enter_order(order()).unwrap(), UserRefNum(1));
+        assert!(matches!(*sess.enter_order(order()).unwrap_err(), SessionError::Throttled));
+        // The rejected order doesn't use up a UserRefNum.
+        assert_eq!(sess.next_user_ref_num(), UserRefNum(2));
+        assert_eq!(sess.queued(), 0);
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn queued_cancels_coalesce() {
+        let (addr, server) = serve(3, |_| {});
+        let sess = OuchSession::options()
+            .with_sync_user_ref_num(false)
+            .with_max_messages_per_sec(2)
+            .connect(addr)
+            .unwrap();
+        assert_eq!(sess.enter_order(order()).unwrap(), UserRefNum(1));
+        sess.cancel(UserRefNum(1), 0).unwrap();
+        sess.cancel(UserRefNum(7), 0).unwrap();
+        sess.cancel(UserRefNum(1), 50).unwrap();
+        assert_eq!(sess.queued(), 2);
+
+        let got = server.join().unwrap();
+        let cancel = |b: &[u8]| {
+            let num = |i: usize| u32::from_be_bytes(b[i..i + 4].try_into().unwrap());
+            (b[0], num(1), num(5))
+        };
+        // The later cancel for 1 took the place of the first.
+        assert_eq!(cancel(&got[1]), (b'X', 1, 50));
+        assert_eq!(cancel(&got[2]), (b'X', 7, 0));
+    }
+
+    #[test]
+    fn kill_file_sends_mass_cancel() {
+        let path = std::env::temp_dir().join(format!("ouch-kill-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (addr, server) = serve(1, |_| {});
+        let firm = Firm::new("ABCD").unwrap();
+        let sess = OuchSession::options()
+            .with_sync_user_ref_num(false)
+            .with_kill_file(&path)
+            .with_kill_poll_interval(Duration::from_millis(1))
+            .with_kill_mass_cancel(firm, nasdaq::Symbol::empty())
+            .connect(addr)
+            .unwrap();
+        std::fs::write(&path, b"").unwrap();
+
+        let got = server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((got[0][0], &got[0][1..5]), (b'C', &1u32.to_be_bytes()[..]));
+        assert!(sess.kill_switch().is_triggered());
+        assert!(matches!(*sess.enter_order(order()).unwrap_err(), SessionError::Killed));
+    }
+}
@@ -0,0 +1,331 @@
+//! A compact one-line text format for logs, e.g.,
+//! `OrderExecuted ts=1000 urn=7 qty=100 px=187.2500 liq=Added match=42`. Alpha fields
+//! are written without padding and options are written after the fields as
+//! `opts=[MinQty=100,PostOnly=PostOnly]`.
+
+use super::*;
+use nasdaq::{Price, SignedPrice, Symbol};
+
+/// Writes a field's value in the text format.
+trait Text {
+    fn text(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+macro_rules! text_display {
+    ($($ty:ty),* $(,)?) => {$(
+        impl Text for $ty {
+            fn text(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{self}")
+            }
+        }
+    )*};
+}
+
+macro_rules! text_trimmed {
+    ($($ty:ty),* $(,)?) => {$(
+        impl Text for $ty {
+            fn text(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(self.to_string().trim_end())
+            }
+        }
+    )*};
+}
+
+/// Enums are written by name.
+macro_rules! text_debug {
+    ($($ty:ty),* $(,)?) => {$(
+        impl Text for $ty {
+            fn text(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{self:?}")
+            }
+        }
+    )*};
+}
+
+text_display!(u8, u16, u32, u64, i64, Price, SignedPrice);
+text_trimmed!(Symbol, Firm, BrokerCode, ClOrdId);
+text_debug!(
+    OrderSide,
+    TimeInForce,
+    Display,
+    Capacity,
+    InterMarketSweepEligibility,
+    CrossType,
+    CustomerType,
+    PriceType,
+    PostOnly,
+    TradeNow,
+    SharesLocated,
+    HandleInst,
+    BboWeightIndicator,
+    EventCode,
+    OrderState,
+    OrderCancelReason,
+    LiquidityFlag,
+    BrokenReason,
+    RejectReason,
+    RestateReason,
+);
+
+impl Text for UserRefNum {
+    fn text(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[allow(deprecated)]
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, value): (&str, &dyn Text) = match self {
+            OptionValue::SecondaryOrdRefNum(v) => ("SecondaryOrdRefNum", v),
+            OptionValue::Firm(v) => ("Firm", v),
+            OptionValue::MinQty(v) => ("MinQty", v),
+            OptionValue::CustomerType(v) => ("CustomerType", v),
+            OptionValue::MaxFloor(v) => ("MaxFloor", v),
+            OptionValue::PriceType(v) => ("PriceType", v),
+            OptionValue::PegOffset(v) => ("PegOffset", v),
+            OptionValue::DiscretionPrice(v) => ("DiscretionPrice", v),
+            OptionValue::DiscretionPriceType(v) => ("DiscretionPriceType", v),
+            OptionValue::DiscretionPegOffset(v) => ("DiscretionPegOffset", v),
+            OptionValue::PostOnly(v) => ("PostOnly", v),
+            OptionValue::RandomReserves(v) => ("RandomReserves", v),
+            OptionValue::Route(v) => ("Route", v),
+            OptionValue::ExpireTime(v) => ("ExpireTime", v),
+            OptionValue::TradeNow(v) => ("TradeNow", v),
+            OptionValue::HandleInst(v) => ("HandleInst", v),
+            OptionValue::BboWeightIndicator(v) => ("BboWeightIndicator", v),
+            OptionValue::DisplayQuantity(v) => ("DisplayQuantity", v),
+            OptionValue::DisplayPrice(v) => ("DisplayPrice", v),
+            OptionValue::GroupId(v) => ("GroupId", v),
+            OptionValue::SharesLocated(v) => ("SharesLocated", v),
+            OptionValue::LocateBroker(v) => ("LocateBroker", v),
+            OptionValue::Side(v) => ("Side", v),
+            OptionValue::UserRefIdx(v) => ("UserRefIdx", v),
+            OptionValue::Unknown { tag, value } => {
+                write!(f, "Unknown{tag}=")?;
+                for b in value {
+                    write!(f, "{b:02x}")?;
+                }
+                return Ok(());
+            }
+        };
+        write!(f, "{name}=")?;
+        value.text(f)
+    }
+}
+
+impl fmt::Display for OptionalAppendage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("[")?;
+        for (i, tv) in self.iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", tv.option_value)?;
+        }
+        f.write_str("]")
+    }
+}
+
+/// Implements `Display` for messages, writing the name, then each field as `key=value`, then
+/// the options, if there are any.
+macro_rules! text_message {
+    ($($msg:ident { $($field:ident => $key:literal),* $(,)? })*) => {$(
+        impl fmt::Display for $msg {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(stringify!($msg))?;
+                $(
+                    f.write_str(concat!(" ", $key, "="))?;
+                    self.$field.text(f)?;
+                )*
+                if !self.optional_appendage.is_empty() {
+                    write!(f, " opts={}", self.optional_appendage)?;
+                }
+                Ok(())
+            }
+        }
+    )*};
+}
+
+text_message! {
+    EnterOrder {
+        user_ref_num => "urn",
+        side => "side",
+        quantity => "qty",
+        symbol => "sym",
+        price => "px",
+        time_in_force => "tif",
+        display => "display",
+        capacity => "capacity",
+        inter_market_sweep_eligibility => "imse",
+        cross_type => "cross",
+        cl_ord_id => "cl_ord_id",
+    }
+    ReplaceOrderRequest {
+        orig_user_ref_num => "orig_urn",
+        user_ref_num => "urn",
+        quantity => "qty",
+        price => "px",
+        time_in_force => "tif",
+        display => "display",
+        inter_market_sweep_eligibility => "imse",
+        cl_ord_id => "cl_ord_id",
+    }
+    CancelOrderRequest { user_ref_num => "urn", quantity => "qty" }
+    ModifyOrderRequest { user_ref_num => "urn", side => "side", quantity => "qty" }
+    MassCancelRequest { user_ref_num => "urn", firm => "firm", symbol => "sym" }
+    DisableOrderEntryRequest { user_ref_num => "urn", firm => "firm" }
+    EnableOrderEntryRequest { user_ref_num => "urn", firm => "firm" }
+    AccountQueryRequest {  }
+    OrderAccepted {
+        timestamp => "ts",
+        user_ref_num => "urn",
+        side => "side",
+        quantity => "qty",
+        symbol => "sym",
+        price => "px",
+        time_in_force => "tif",
+        display => "display",
+        order_reference_number => "order_ref",
+        capacity => "capacity",
+        inter_market_sweep_eligibility => "imse",
+        cross_type => "cross",
+        order_state => "state",
+        cl_ord_id => "cl_ord_id",
+    }
+    OrderReplaced {
+        timestamp => "ts",
+        orig_user_ref_num => "orig_urn",
+        user_ref_num => "urn",
+        side => "side",
+        quantity => "qty",
+        symbol => "sym",
+        price => "px",
+        time_in_force => "tif",
+        display => "display",
+        order_reference_number => "order_ref",
+        capacity => "capacity",
+        inter_market_sweep_eligibility => "imse",
+        cross_type => "cross",
+        order_state => "state",
+        cl_ord_id => "cl_ord_id",
+    }
+    OrderCanceled {
+        timestamp => "ts",
+        user_ref_num => "urn",
+        quantity => "qty",
+        reason => "reason",
+    }
+    AiqCanceled {
+        timestamp => "ts",
+        user_ref_num => "urn",
+        decrement_shares => "decrement",
+        reason => "reason",
+        quantity_prevent_from_trading => "qty_prevented",
+        execution_price => "exec_px",
+        liquidity_flag => "liq",
+        aiq_strategy => "aiq_strategy",
+    }
+    OrderExecuted {
+        timestamp => "ts",
+        user_ref_num => "urn",
+        quantity => "qty",
+        price => "px",
+        liquidity_flag => "liq",
+        match_number => "match",
+    }
+    BrokenTrade {
+        timestamp => "ts",
+        user_ref_num => "urn",
+        match_number => "match",
+        reason => "reason",
+        cl_ord_id => "cl_ord_id",
+    }
+    Rejected {
+        timestamp => "ts",
+        user_ref_num => "urn",
+        reason => "reason",
+        cl_ord_id => "cl_ord_id",
+    }
+    CancelPending { timestamp => "ts", user_ref_num => "urn" }
+    CancelReject { timestamp => "ts", user_ref_num => "urn" }
+    OrderPriorityUpdate {
+        timestamp => "ts",
+        user_ref_num => "urn",
+        price => "px",
+        display => "display",
+        order_reference_number => "order_ref",
+    }
+    OrderModified { timestamp => "ts", user_ref_num => "urn", side => "side", quantity => "qty" }
+    OrderRestated { timestamp => "ts", user_ref_num => "urn", reason => "reason" }
+    MassCancelResponse { timestamp => "ts", user_ref_num => "urn", firm => "firm", symbol => "sym" }
+    DisableOrderEntryResponse { timestamp => "ts", user_ref_num => "urn", firm => "firm" }
+    EnableOrderEntryResponse { timestamp => "ts", user_ref_num => "urn", firm => "firm" }
+    AccountQueryResponse { timestamp => "ts", next_user_ref_num => "next_urn" }
+}
+
+impl fmt::Display for SystemEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SystemEvent ts={} event={:?}", self.timestamp, self.event_code)
+    }
+}
+
+impl fmt::Display for OutboundMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SystemEvent(m) => write!(f, "{m}"),
+            Self::OrderAccepted(m) => write!(f, "{m}"),
+            Self::OrderReplaced(m) => write!(f, "{m}"),
+            Self::OrderCanceled(m) => write!(f, "{m}"),
+            Self::AiqCanceled(m) => write!(f, "{m}"),
+            Self::OrderExecuted(m) => write!(f, "{m}"),
+            Self::BrokenTrade(m) => write!(f, "{m}"),
+            Self::Rejected(m) => write!(f, "{m}"),
+            Self::CancelPending(m) => write!(f, "{m}"),
+            Self::CancelReject(m) => write!(f, "{m}"),
+            Self::OrderPriorityUpdate(m) => write!(f, "{m}"),
+            Self::OrderModified(m) => write!(f, "{m}"),
+            Self::OrderRestated(m) => write!(f, "{m}"),
+            Self::MassCancelResponse(m) => write!(f, "{m}"),
+            Self::DisableOrderEntryResponse(m) => write!(f, "{m}"),
+            Self::EnableOrderEntryResponse(m) => write!(f, "{m}"),
+            Self::AccountQueryResponse(m) => write!(f, "{m}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn executed() {
+        let mut msg = OrderExecuted {
+            timestamp: 1000,
+            user_ref_num: UserRefNum(7),
+            quantity: 100,
+            price: Price::from_raw(1_872_500),
+            liquidity_flag: LiquidityFlag::Added,
+            match_number: 42,
+            optional_appendage: OptionalAppendage::default(),
+        };
+        let text = "OrderExecuted ts=1000 urn=7 qty=100 px=187.2500 liq=Added match=42";
+        assert_eq!(msg.to_string(), text);
+
+        msg.optional_appendage.push(OptionValue::MinQty(100).into()).unwrap();
+        msg.optional_appendage.push(OptionValue::PostOnly(PostOnly::PostOnly).into()).unwrap();
+        let msg = OutboundMessage::OrderExecuted(msg);
+        assert_eq!(msg.to_string(), format!("{text} opts=[MinQty=100,PostOnly=PostOnly]"));
+    }
+
+    #[test]
+    fn padding_trimmed() {
+        let msg = MassCancelRequest {
+            user_ref_num: UserRefNum(3),
+            firm: Firm::new("AB  ").unwrap(),
+            symbol: Symbol::new_padded("ZVZZT").unwrap(),
+            optional_appendage: OptionalAppendage::default(),
+        };
+        assert_eq!(msg.to_string(), "MassCancelRequest urn=3 firm=AB sym=ZVZZT");
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+/// What a session does with messages sent over its rate limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThrottleMode {
+    /// Queue the messages and send them as the limit allows. Calls to send return as soon as the
+    /// message is queued.
+    #[default]
+    Queue,
+    /// Return `SessionError::Throttled` without sending.
+    Reject,
+}
+
+/// A token bucket with a token per message.
+pub(crate) struct Throttle {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    pub(crate) fn new(rate: u32, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+        Self {
+            rate: rate as f64,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = self.last.max(now);
+    }
+
+    /// Takes a token if there is one.
+    pub(crate) fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Takes a token even if there isn't one, so the messages after wait longer.
+    pub(crate) fn spend(&mut self, now: Instant) {
+        self.refill(now);
+        self.tokens -= 1.0;
+    }
+
+    /// How long until a token is available.
+    pub(crate) fn delay(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn try_take_and_delay() {
+        let ms = Duration::from_millis;
+        let mut t = Throttle::new(4, 2);
+        let start = t.last;
+        assert!(t.try_take(start));
+        assert!(t.try_take(start));
+        assert!(!t.try_take(start));
+        assert_eq!(t.delay(start), ms(250));
+        assert_eq!(t.delay(start + ms(125)), ms(125));
+        // Going back in time doesn't take tokens away.
+        assert_eq!(t.delay(start), ms(125));
+        assert!(t.try_take(start + ms(250)));
+
+        // Only refills up to the burst.
+        let later = start + Duration::from_secs(10);
+        assert!(t.try_take(later));
+        assert!(t.try_take(later));
+        assert!(!t.try_take(later));
+
+        // Spending without a token makes the next wait longer.
+        t.spend(later);
+        assert_eq!(t.delay(later), ms(500));
+    }
+
+    #[test]
+    fn burst_at_least_one() {
+        let mut t = Throttle::new(1, 0);
+        let start = t.last;
+        assert!(t.try_take(start));
+        assert!(!t.try_take(start));
+        assert_eq!(t.delay(start), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,734 @@
+//! Keeping the state of orders up to date from the messages the exchange sends.
+
+use super::*;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+/// Where an order is in its life.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OrderStatus {
+    /// Sent but not yet accepted.
+    Pending,
+    Live,
+    /// Replaced by another order, given by `TrackedOrder::replaced_by`.
+    Replaced,
+    CancelPending,
+    Canceled,
+    Filled,
+    Rejected,
+}
+
+impl OrderStatus {
+    /// Whether the order can still trade.
+    pub fn is_open(self) -> bool {
+        matches!(self, OrderStatus::Live | OrderStatus::CancelPending)
+    }
+
+    /// Whether nothing more should happen to the order, other than broken trades.
+    pub fn is_done(self) -> bool {
+        matches!(
+            self,
+            OrderStatus::Replaced
+                | OrderStatus::Canceled
+                | OrderStatus::Filled
+                | OrderStatus::Rejected
+        )
+    }
+}
+
+/// An execution against an order.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Fill {
+    pub match_number: u64,
+    pub quantity: u32,
+    pub price: nasdaq::Price,
+    pub liquidity_flag: LiquidityFlag,
+    /// Set when a Broken Trade for the match is received.
+    pub broken: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackedOrder {
+    pub user_ref_num: UserRefNum,
+    pub status: OrderStatus,
+    pub side: OrderSide,
+    pub symbol: nasdaq::Symbol,
+    pub price: nasdaq::Price,
+    /// The quantity the order was entered (or replaced) with.
+    pub quantity: u32,
+    /// The shares still open.
+    pub leaves: u32,
+    /// The shares executed, less those of broken trades.
+    pub executed: u32,
+    pub cl_ord_id: ClOrdId,
+    /// Set once the order is accepted.
+    pub order_reference_number: Option<u64>,
+    /// The displayed quantity and price given by the last Order Restated, if any.
+    pub display_quantity: Option<u32>,
+    pub display_price: Option<nasdaq::Price>,
+    /// The order this one replaced.
+    pub replaces: Option<UserRefNum>,
+    /// The order that replaced this one.
+    pub replaced_by: Option<UserRefNum>,
+    pub fills: Vec<Fill>,
+    /// The sum of quantity times raw price of the unbroken fills.
+    notional: u128,
+}
+
+impl TrackedOrder {
+    fn new(
+        user_ref_num: UserRefNum,
+        side: OrderSide,
+        symbol: nasdaq::Symbol,
+        price: nasdaq::Price,
+        quantity: u32,
+        cl_ord_id: ClOrdId,
+    ) -> Self {
+        Self {
+            user_ref_num,
+            status: OrderStatus::Pending,
+            side,
+            symbol,
+            price,
+            quantity,
+            leaves: quantity,
+            executed: 0,
+            cl_ord_id,
+            order_reference_number: None,
+            display_quantity: None,
+            display_price: None,
+            replaces: None,
+            replaced_by: None,
+            fills: Vec::new(),
+            notional: 0,
+        }
+    }
+
+    /// The average price of the unbroken fills, or `None` if there are none.
+    pub fn avg_price(&self) -> Option<nasdaq::Price> {
+        if self.executed == 0 {
+            return None;
+        }
+        let avg = self.notional / self.executed as u128;
+        Some(nasdaq::Price::from_raw(avg as u64))
+    }
+}
+
+/// Keeps a table of orders, keyed by UserRefNum, up to date from the outbound messages of a
+/// session.
+///
+/// Orders can be added as they're sent with `sent_order` and `sent_replace`, so rejections can
+/// be matched up, but orders first seen in an Order Accepted or Order Replaced are tracked too.
+/// Messages that don't make sense for what's known about an order are flagged with an error, but
+/// are still applied as far as they can be.
+#[derive(Clone, Debug, Default)]
+pub struct OrderTracker {
+    orders: HashMap<UserRefNum, TrackedOrder>,
+}
+
+impl OrderTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, urn: UserRefNum) -> Option<&TrackedOrder> {
+        self.orders.get(&urn)
+    }
+
+    pub fn orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders.values()
+    }
+
+    /// The orders that can still trade.
+    pub fn open_orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders.values().filter(|o| o.status.is_open())
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    /// Follows the chain of replacements from `urn` to the latest order in it. If the chain loops
+    /// (which only bad messages can cause), the walk stops before coming back around.
+    pub fn latest(&self, urn: UserRefNum) -> Option<&TrackedOrder> {
+        let mut order = self.orders.get(&urn)?;
+        let mut seen = HashSet::from([urn]);
+        while let Some(next) = order.replaced_by {
+            match self.orders.get(&next) {
+                Some(o) if seen.insert(next) => order = o,
+                _ => break,
+            }
+        }
+        Some(order)
+    }
+
+    /// The chain of replacements `urn` is in, from the original order to the latest. Like
+    /// `latest`, each order is only included once.
+    pub fn chain(&self, urn: UserRefNum) -> Vec<UserRefNum> {
+        let mut first = urn;
+        let mut seen = HashSet::from([urn]);
+        while let Some(prev) = self.orders.get(&first).and_then(|o| o.replaces) {
+            if !self.orders.contains_key(&prev) || !seen.insert(prev) {
+                break;
+            }
+            first = prev;
+        }
+        let mut chain = vec![first];
+        let mut seen = HashSet::from([first]);
+        let mut cur = first;
+        while let Some(next) = self.orders.get(&cur).and_then(|o| o.replaced_by) {
+            if !seen.insert(next) {
+                break;
+            }
+            chain.push(next);
+            cur = next;
+        }
+        chain
+    }
+
+    /// Removes the orders that are done (see `OrderStatus::is_done`).
+    pub fn remove_done(&mut self) {
+        self.orders.retain(|_, o| !o.status.is_done());
+    }
+
+    /// Tracks an order that was sent, as pending.
+    pub fn sent_order(&mut self, order: &EnterOrder) {
+        let tracked = TrackedOrder::new(
+            order.user_ref_num,
+            order.side,
+            order.symbol,
+            order.price,
+            order.quantity,
+            order.cl_ord_id,
+        );
+        self.orders.insert(order.user_ref_num, tracked);
+    }
+
+    /// Tracks the replacement that was sent, as pending. The original order is left alone until
+    /// the replace is accepted.
+    pub fn sent_replace(&mut self, req: &ReplaceOrderRequest) {
+        let (side, symbol) = match self.orders.get(&req.orig_user_ref_num) {
+            Some(orig) => (orig.side, orig.symbol),
+            None => (OrderSide::Buy, nasdaq::Symbol::empty()),
+        };
+        let mut tracked = TrackedOrder::new(
+            req.user_ref_num,
+            side,
+            symbol,
+            req.price,
+            req.quantity,
+            req.cl_ord_id,
+        );
+        tracked.replaces = Some(req.orig_user_ref_num);
+        self.orders.insert(req.user_ref_num, tracked);
+    }
+
+    /// Applies the message to the order(s) it's about. Messages not about an order are ignored.
+    pub fn apply(&mut self, msg: &OutboundMessage) -> Result<(), TrackerError> {
+        use OutboundMessage as OM;
+        match msg {
+            OM::OrderAccepted(m) => self.accepted(m),
+            OM::OrderReplaced(m) => self.replaced(m),
+            OM::OrderCanceled(m) => self.canceled(m.user_ref_num, m.quantity, msg),
+            OM::AiqCanceled(m) => self.canceled(m.user_ref_num, m.decrement_shares, msg),
+            OM::OrderExecuted(m) => self.executed(m),
+            OM::BrokenTrade(m) => self.broken(m),
+            OM::Rejected(m) => {
+                let order = self.order(m.user_ref_num)?;
+                check(order, msg.msg_type(), order.status == OrderStatus::Pending)?;
+                order.status = OrderStatus::Rejected;
+                order.leaves = 0;
+                Ok(())
+            }
+            OM::CancelPending(m) => {
+                let order = self.order(m.user_ref_num)?;
+                check(order, msg.msg_type(), order.status.is_open())?;
+                order.status = OrderStatus::CancelPending;
+                Ok(())
+            }
+            OM::CancelReject(m) => {
+                let order = self.order(m.user_ref_num)?;
+                check(order, msg.msg_type(), order.status.is_open())?;
+                order.status = OrderStatus::Live;
+                Ok(())
+            }
+            OM::OrderPriorityUpdate(m) => {
+                let order = self.order(m.user_ref_num)?;
+                let res = check(order, msg.msg_type(), order.status.is_open());
+                order.price = m.price;
+                order.order_reference_number = Some(m.order_reference_number);
+                res
+            }
+            OM::OrderModified(m) => {
+                let order = self.order(m.user_ref_num)?;
+                check(order, msg.msg_type(), order.status.is_open())?;
+                let res = check_quantity(order, msg.msg_type(), m.quantity);
+                order.side = m.side;
+                order.leaves = m.quantity.min(order.leaves);
+                if order.leaves == 0 {
+                    order.status = OrderStatus::Canceled;
+                }
+                res
+            }
+            OM::OrderRestated(m) => {
+                let order = self.order(m.user_ref_num)?;
+                let res = check(order, msg.msg_type(), order.status.is_open());
+                for tv in m.optional_appendage.iter() {
+                    match tv.option_value {
+                        OptionValue::DisplayQuantity(q) => order.display_quantity = Some(q),
+                        OptionValue::DisplayPrice(p) => order.display_price = Some(p),
+                        OptionValue::SecondaryOrdRefNum(orn) => {
+                            order.order_reference_number = Some(orn)
+                        }
+                        _ => (),
+                    }
+                }
+                res
+            }
+            OM::SystemEvent(_)
+            | OM::MassCancelResponse(_)
+            | OM::DisableOrderEntryResponse(_)
+            | OM::EnableOrderEntryResponse(_)
+            | OM::AccountQueryResponse(_) => Ok(()),
+        }
+    }
+
+    fn order(&mut self, urn: UserRefNum) -> Result<&mut TrackedOrder, TrackerError> {
+        self.orders
+            .get_mut(&urn)
+            .ok_or(TrackerError::UnknownOrder(urn))
+    }
+
+    fn accepted(&mut self, m: &OrderAccepted) -> Result<(), TrackerError> {
+        let order = self.orders.entry(m.user_ref_num).or_insert_with(|| {
+            TrackedOrder::new(
+                m.user_ref_num,
+                m.side,
+                m.symbol,
+                m.price,
+                m.quantity,
+                m.cl_ord_id,
+            )
+        });
+        let res = if order.status == OrderStatus::Pending {
+            Ok(())
+        } else {
+            Err(bad_transition(order, OrderAccepted::TYPE))
+        };
+        order.side = m.side;
+        order.symbol = m.symbol;
+        order.price = m.price;
+        order.quantity = m.quantity;
+        order.leaves = m.quantity;
+        order.order_reference_number = Some(m.order_reference_number);
+        order.status = match m.order_state {
+            OrderState::Live => OrderStatus::Live,
+            OrderState::Dead => OrderStatus::Canceled,
+        };
+        res
+    }
+
+    fn replaced(&mut self, m: &OrderReplaced) -> Result<(), TrackerError> {
+        let mut res = Ok(());
+        match self.orders.get_mut(&m.orig_user_ref_num) {
+            Some(orig) => {
+                if !orig.status.is_open() {
+                    res = Err(bad_transition(orig, OrderReplaced::TYPE));
+                }
+                orig.status = OrderStatus::Replaced;
+                orig.leaves = 0;
+                orig.replaced_by = Some(m.user_ref_num);
+            }
+            None => res = Err(TrackerError::UnknownOrder(m.orig_user_ref_num)),
+        }
+        let order = self.orders.entry(m.user_ref_num).or_insert_with(|| {
+            TrackedOrder::new(
+                m.user_ref_num,
+                m.side,
+                m.symbol,
+                m.price,
+                m.quantity,
+                m.cl_ord_id,
+            )
+        });
+        if res.is_ok() && order.status != OrderStatus::Pending {
+            res = Err(bad_transition(order, OrderReplaced::TYPE));
+        }
+        order.side = m.side;
+        order.symbol = m.symbol;
+        order.price = m.price;
+        order.quantity = m.quantity;
+        order.leaves = m.quantity;
+        order.cl_ord_id = m.cl_ord_id;
+        order.order_reference_number = Some(m.order_reference_number);
+        order.replaces = Some(m.orig_user_ref_num);
+        order.status = match m.order_state {
+            OrderState::Live => OrderStatus::Live,
+            OrderState::Dead => OrderStatus::Canceled,
+        };
+        res
+    }
+
+    fn canceled(
+        &mut self,
+        urn: UserRefNum,
+        decrement: u32,
+        msg: &OutboundMessage,
+    ) -> Result<(), TrackerError> {
+        let order = self.order(urn)?;
+        check(order, msg.msg_type(), order.status.is_open())?;
+        let res = check_quantity(order, msg.msg_type(), decrement);
+        order.leaves = order.leaves.saturating_sub(decrement);
+        order.status = if order.leaves == 0 {
+            OrderStatus::Canceled
+        } else {
+            OrderStatus::Live
+        };
+        res
+    }
+
+    fn executed(&mut self, m: &OrderExecuted) -> Result<(), TrackerError> {
+        let order = self.order(m.user_ref_num)?;
+        let mut res = check_quantity(order, OrderExecuted::TYPE, m.quantity);
+        if res.is_ok() && !order.status.is_open() {
+            res = Err(bad_transition(order, OrderExecuted::TYPE));
+        }
+        order.leaves = order.leaves.saturating_sub(m.quantity);
+        order.executed += m.quantity;
+        order.notional += m.quantity as u128 * m.price.to_raw() as u128;
+        order.fills.push(Fill {
+            match_number: m.match_number,
+            quantity: m.quantity,
+            price: m.price,
+            liquidity_flag: m.liquidity_flag,
+            broken: false,
+        });
+        if order.leaves == 0 {
+            order.status = OrderStatus::Filled;
+        }
+        res
+    }
+
+    fn broken(&mut self, m: &BrokenTrade) -> Result<(), TrackerError> {
+        let order = self.order(m.user_ref_num)?;
+        let fill = order
+            .fills
+            .iter_mut()
+            .find(|f| f.match_number == m.match_number && !f.broken);
+        let Some(fill) = fill else {
+            return Err(TrackerError::UnknownMatch {
+                user_ref_num: m.user_ref_num,
+                match_number: m.match_number,
+            });
+        };
+        // The broken shares don't go back on the order.
+        fill.broken = true;
+        order.executed -= fill.quantity;
+        order.notional -= fill.quantity as u128 * fill.price.to_raw() as u128;
+        Ok(())
+    }
+}
+
+fn bad_transition(order: &TrackedOrder, msg_type: u8) -> TrackerError {
+    TrackerError::BadTransition {
+        user_ref_num: order.user_ref_num,
+        status: order.status,
+        msg_type,
+    }
+}
+
+fn check(order: &TrackedOrder, msg_type: u8, ok: bool) -> Result<(), TrackerError> {
+    if ok {
+        Ok(())
+    } else {
+        Err(bad_transition(order, msg_type))
+    }
+}
+
+/// Checks that `quantity` shares can come off the order.
+fn check_quantity(order: &TrackedOrder, msg_type: u8, quantity: u32) -> Result<(), TrackerError> {
+    if quantity <= order.leaves {
+        return Ok(());
+    }
+    Err(TrackerError::BadQuantity {
+        user_ref_num: order.user_ref_num,
+        msg_type,
+        quantity,
+        leaves: order.leaves,
+    })
+}
+
+/// A message that doesn't fit what's known about an order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackerError {
+    /// The message is about an order that isn't tracked.
+    UnknownOrder(UserRefNum),
+    /// The message of type `msg_type` can't happen to an order with the status.
+    BadTransition {
+        user_ref_num: UserRefNum,
+        status: OrderStatus,
+        msg_type: u8,
+    },
+    /// The message took off more shares than the order had left.
+    BadQuantity {
+        user_ref_num: UserRefNum,
+        msg_type: u8,
+        quantity: u32,
+        leaves: u32,
+    },
+    /// A Broken Trade was for a match the order doesn't have (or was already broken).
+    UnknownMatch {
+        user_ref_num: UserRefNum,
+        match_number: u64,
+    },
+}
+
+impl fmt::Display for TrackerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrackerError::UnknownOrder(urn) => write!(f, "unknown order {}", urn.0),
+            TrackerError::BadTransition {
+                user_ref_num,
+                status,
+                msg_type,
+            } => write!(
+                f,
+                "message type {:?} not expected for order {} in status {status:?}",
+                *msg_type as char, user_ref_num.0,
+            ),
+            TrackerError::BadQuantity {
+                user_ref_num,
+                msg_type,
+                quantity,
+                leaves,
+            } => write!(
+                f,
+                "message type {:?} for {quantity} shares of order {} with {leaves} left",
+                *msg_type as char, user_ref_num.0,
+            ),
+            TrackerError::UnknownMatch {
+                user_ref_num,
+                match_number,
+            } => write!(f, "unknown match {match_number} for order {}", user_ref_num.0),
+        }
+    }
+}
+
+impl Error for TrackerError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::nasdaq::*;
+
+    fn app() -> OptionalAppendage {
+        OptionalAppendage::empty()
+    }
+
+    fn cid() -> ClOrdId {
+        ClOrdId::from_bytes(b"A             ").unwrap()
+    }
+
+    fn enter(urn: u32) -> EnterOrder {
+        EnterOrder {
+            user_ref_num: UserRefNum(urn),
+            side: OrderSide::Buy,
+            quantity: 300,
+            symbol: Symbol::new_padded("AAPL").unwrap(),
+            price: Price::from_raw(1_000_000),
+            time_in_force: TimeInForce::Day,
+            display: Display::Visible,
+            capacity: Capacity::Agency,
+            inter_market_sweep_eligibility: IMSE::NotEligible,
+            cross_type: CrossType::ContinuousMarket,
+            cl_ord_id: cid(),
+            optional_appendage: app(),
+        }
+    }
+
+    fn accepted(urn: u32, qty: u32) -> OutboundMessage {
+        OutboundMessage::OrderAccepted(OrderAccepted {
+            timestamp: 0,
+            user_ref_num: UserRefNum(urn),
+            side: OrderSide::Buy,
+            quantity: qty,
+            symbol: Symbol::new_padded("AAPL").unwrap(),
+            price: Price::from_raw(1_000_000),
+            time_in_force: TimeInForce::Day,
+            display: Display::Visible,
+            order_reference_number: 55,
+            capacity: Capacity::Agency,
+            inter_market_sweep_eligibility: IMSE::NotEligible,
+            cross_type: CrossType::ContinuousMarket,
+            order_state: OrderState::Live,
+            cl_ord_id: cid(),
+            optional_appendage: app(),
+        })
+    }
+
+    fn exec(urn: u32, qty: u32, price: u64, mn: u64) -> OutboundMessage {
+        OutboundMessage::OrderExecuted(OrderExecuted {
+            timestamp: 0,
+            user_ref_num: UserRefNum(urn),
+            quantity: qty,
+            price: Price::from_raw(price),
+            liquidity_flag: LiquidityFlag::Added,
+            match_number: mn,
+            optional_appendage: app(),
+        })
+    }
+
+    #[test]
+    fn lifecycle() {
+        let mut t = OrderTracker::new();
+        t.sent_order(&enter(1));
+        assert_eq!(t.get(UserRefNum(1)).unwrap().status, OrderStatus::Pending);
+        t.apply(&accepted(1, 300)).unwrap();
+        let o = t.get(UserRefNum(1)).unwrap();
+        assert_eq!(o.status, OrderStatus::Live);
+        assert_eq!((o.leaves, o.order_reference_number), (300, Some(55)));
+
+        t.apply(&exec(1, 100, 1_000_000, 7)).unwrap();
+        t.apply(&exec(1, 100, 1_010_000, 8)).unwrap();
+        let o = t.get(UserRefNum(1)).unwrap();
+        assert_eq!((o.leaves, o.executed), (100, 200));
+        assert_eq!(o.avg_price().unwrap().to_raw(), 1_005_000);
+
+        t.apply(&OutboundMessage::BrokenTrade(BrokenTrade {
+            timestamp: 0,
+            user_ref_num: UserRefNum(1),
+            match_number: 8,
+            reason: BrokenReason::Erroneuos,
+            cl_ord_id: cid(),
+            optional_appendage: app(),
+        }))
+        .unwrap();
+        let o = t.get(UserRefNum(1)).unwrap();
+        assert_eq!((o.leaves, o.executed), (100, 100));
+        assert_eq!(o.avg_price().unwrap().to_raw(), 1_000_000);
+        assert!(o.fills[1].broken);
+
+        // Replace 1 with 2.
+        t.apply(&OutboundMessage::OrderReplaced(OrderReplaced {
+            timestamp: 0,
+            orig_user_ref_num: UserRefNum(1),
+            user_ref_num: UserRefNum(2),
+            side: OrderSide::Buy,
+            quantity: 50,
+            symbol: Symbol::new_padded("AAPL").unwrap(),
+            price: Price::from_raw(990_000),
+            time_in_force: TimeInForce::Day,
+            display: Display::Visible,
+            order_reference_number: 56,
+            capacity: Capacity::Agency,
+            inter_market_sweep_eligibility: IMSE::NotEligible,
+            cross_type: CrossType::ContinuousMarket,
+            order_state: OrderState::Live,
+            cl_ord_id: cid(),
+            optional_appendage: app(),
+        }))
+        .unwrap();
+        assert_eq!(t.get(UserRefNum(1)).unwrap().status, OrderStatus::Replaced);
+        assert_eq!(t.latest(UserRefNum(1)).unwrap().user_ref_num, UserRefNum(2));
+        assert_eq!(t.chain(UserRefNum(2)), vec![UserRefNum(1), UserRefNum(2)]);
+
+        let mut restate = app();
+        restate.push(OptionValue::DisplayQuantity(10).into()).unwrap();
+        t.apply(&OutboundMessage::OrderRestated(OrderRestated {
+            timestamp: 0,
+            user_ref_num: UserRefNum(2),
+            reason: RestateReason::RefreshOfDisplay,
+            optional_appendage: restate,
+        }))
+        .unwrap();
+        assert_eq!(t.get(UserRefNum(2)).unwrap().display_quantity, Some(10));
+
+        // Execution on the replaced order is flagged.
+        assert_eq!(
+            t.apply(&exec(1, 1, 1, 9)),
+            Err(TrackerError::BadQuantity {
+                user_ref_num: UserRefNum(1),
+                msg_type: OrderExecuted::TYPE,
+                quantity: 1,
+                leaves: 0,
+            })
+        );
+        t.apply(&OutboundMessage::OrderCanceled(OrderCanceled {
+            timestamp: 0,
+            user_ref_num: UserRefNum(2),
+            quantity: 50,
+            reason: OrderCancelReason::UserRequested,
+            optional_appendage: app(),
+        }))
+        .unwrap();
+        assert_eq!(t.get(UserRefNum(2)).unwrap().status, OrderStatus::Canceled);
+        assert_eq!(t.open_orders().count(), 0);
+
+        assert_eq!(
+            t.apply(&exec(9, 1, 1, 1)),
+            Err(TrackerError::UnknownOrder(UserRefNum(9)))
+        );
+        assert!(matches!(t.apply(&exec(2, 1, 1, 1)), Err(TrackerError::BadQuantity { .. })));
+        t.remove_done();
+        assert!(t.is_empty());
+    }
+
+    #[test]
+    fn rejected_and_overfill() {
+        let mut t = OrderTracker::new();
+        t.sent_order(&enter(1));
+        t.apply(&OutboundMessage::Rejected(Rejected {
+            timestamp: 0,
+            user_ref_num: UserRefNum(1),
+            reason: RejectReason::Halted,
+            cl_ord_id: cid(),
+            optional_appendage: app(),
+        }))
+        .unwrap();
+        assert_eq!(t.get(UserRefNum(1)).unwrap().status, OrderStatus::Rejected);
+        assert!(matches!(t.apply(&accepted(1, 300)), Err(TrackerError::BadTransition { .. })));
+
+        t.apply(&accepted(3, 10)).unwrap();
+        assert!(matches!(t.apply(&exec(3, 20, 1, 1)), Err(TrackerError::BadQuantity { .. })));
+        assert_eq!(t.get(UserRefNum(3)).unwrap().status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn replace_cycle() {
+        let replaced = |orig: u32, urn: u32| {
+            OutboundMessage::OrderReplaced(OrderReplaced {
+                timestamp: 0,
+                orig_user_ref_num: UserRefNum(orig),
+                user_ref_num: UserRefNum(urn),
+                side: OrderSide::Buy,
+                quantity: 10,
+                symbol: Symbol::new_padded("AAPL").unwrap(),
+                price: Price::from_raw(990_000),
+                time_in_force: TimeInForce::Day,
+                display: Display::Visible,
+                order_reference_number: 56,
+                capacity: Capacity::Agency,
+                inter_market_sweep_eligibility: IMSE::NotEligible,
+                cross_type: CrossType::ContinuousMarket,
+                order_state: OrderState::Live,
+                cl_ord_id: cid(),
+                optional_appendage: app(),
+            })
+        };
+        let mut t = OrderTracker::new();
+        t.apply(&accepted(1, 300)).unwrap();
+        t.apply(&replaced(1, 2)).unwrap();
+        t.apply(&replaced(2, 3)).unwrap();
+        // Bad, but still applied, leaving 2 and 3 replacing each other.
+        assert!(t.apply(&replaced(3, 2)).is_err());
+        assert_eq!(t.latest(UserRefNum(1)).unwrap().user_ref_num, UserRefNum(3));
+        assert_eq!(t.latest(UserRefNum(2)).unwrap().user_ref_num, UserRefNum(3));
+        assert_eq!(t.chain(UserRefNum(1)), vec![UserRefNum(1), UserRefNum(2), UserRefNum(3)]);
+        assert_eq!(t.chain(UserRefNum(2)), vec![UserRefNum(3), UserRefNum(2)]);
+    }
+}
@@ -104,7 +104,7 @@ impl ClientOptions {
             self.session,
             self.sequence_number,
         );
-        stream.write_all(&packet).await?;
+        stream.write_all(packet.as_slice()).await?;
 
         let packet = read_packet_from(&mut stream).await?;
         match packet.packet_type() {
@@ -116,6 +116,11 @@ impl ClientOptions {
             },
             _ => return Err(ClientError::UnexpectedPacket(packet)),
         };
+        let (Some(session), Some(sequence_number)) =
+            (packet.session(), packet.sequence_number())
+        else {
+            return Err(ClientError::UnexpectedPacket(packet));
+        };
 
         let (read, write) = stream.into_split();
         let now = Instant::now();
@@ -128,7 +133,8 @@ impl ClientOptions {
             last_server_heartbeat: NEAV::new(now),
             last_client_heartbeat: NEAV::new(now),
 
-            session_number: (),
+            session,
+            sequence_number,
 
             close_err: AAV::empty(),
         });
@@ -192,6 +198,17 @@ impl Client {
         self.0.handler()
     }
 
+    /// The session the server accepted the login for.
+    pub fn session(&self) -> SessionId {
+        self.0.session
+    }
+
+    /// The sequence number of the first sequenced message the server sends, as given when the
+    /// login was accepted.
+    pub fn sequence_number(&self) -> SequenceNumber {
+        self.0.sequence_number
+    }
+
     pub fn last_client_heartbeat(&self) -> Instant {
         self.0.last_client_heartbeat()
     }
@@ -214,8 +231,8 @@ struct InnerClient {
     last_client_heartbeat: NEAV<Instant>,
     last_server_heartbeat: NEAV<Instant>,
 
-    #[allow(dead_code)]
-    session_number: (),
+    session: SessionId,
+    sequence_number: SequenceNumber,
 
     close_err: AAV<ClientError>,
 }
@@ -265,7 +282,7 @@ impl InnerClient {
     }
 
     fn is_closed(&self) -> bool {
-        self.close_err.is_empty(Ordering::Relaxed)
+        !self.close_err.is_empty(Ordering::Relaxed)
     }
 
     fn opts(&self) -> &ClientOptions {
@@ -302,7 +319,7 @@ impl InnerClient {
             }
         };
         // TODO: close?
-        if let Err(e) = write_half.write(&packet).await {
+        if let Err(e) = write_half.write_all(packet.as_slice()).await {
             return Err(self.close_with_err(e));
         }
         self.last_client_heartbeat
@@ -376,7 +393,7 @@ impl InnerClient {
 async fn read_packet_from<R: AsyncRead + Unpin>(r: &mut R) -> Result<Packet, PacketParseError> {
     let mut buf = [0u8; 3];
     r.read_exact(&mut buf).await?;
-    let payload_len = match (buf[0] as usize) | ((buf[1] as usize) << 8) {
+    let payload_len = match ((buf[0] as usize) << 8) | (buf[1] as usize) {
         0 => return Err(PacketParseError::MismatchLen { want: 1, got: 0 }),
         pl => pl - 1,
     };
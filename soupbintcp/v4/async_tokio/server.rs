@@ -390,7 +390,7 @@ async fn try_read_packet_from_as<R: AsyncRead + Unpin>(
     let mut buf = [0u8; 3];
     r.read_exact(&mut buf)?;
     // TODO: check payload len to make sure it's at most max?
-    let payload_len = match (buf[0] as usize) | ((buf[1] as usize) << 8) {
+    let payload_len = match ((buf[0] as usize) << 8) | (buf[1] as usize) {
         0 => return Err(PacketParseError::MismatchLen { want: 1, got: 0 }),
         pl => pl - 1,
     };
@@ -184,7 +184,7 @@ impl ClientOptions {
         if self.deadline.is_some() {
             stream.set_write_timeout(map_deadline(self.deadline))?;
         }
-        stream.write_all(&packet)?;
+        stream.write_all(packet.as_slice())?;
 
         if self.deadline.is_some() {
             stream.set_read_timeout(map_deadline(self.deadline))?;
@@ -198,6 +198,11 @@ impl ClientOptions {
             },
             _ => return Err(ClientError::UnexpectedPacket(packet)),
         };
+        let (Some(session), Some(sequence_number)) =
+            (packet.session(), packet.sequence_number())
+        else {
+            return Err(ClientError::UnexpectedPacket(packet));
+        };
 
         if self.deadline.is_some() {
             stream.set_read_timeout(None)?;
@@ -214,6 +219,9 @@ impl ClientOptions {
             last_server_heartbeat: NEAV::new(now),
             last_client_heartbeat: NEAV::new(now),
 
+            session,
+            sequence_number,
+
             close_err: AAV::empty(),
         });
         if inner.handler.is_some() {
@@ -276,6 +284,17 @@ impl Client {
         self.0.handler()
     }
 
+    /// The session the server accepted the login for.
+    pub fn session(&self) -> SessionId {
+        self.0.session
+    }
+
+    /// The sequence number of the first sequenced message the server sends, as given when the
+    /// login was accepted.
+    pub fn sequence_number(&self) -> SequenceNumber {
+        self.0.sequence_number
+    }
+
     pub fn last_client_heartbeat(&self) -> Instant {
         self.0.last_client_heartbeat()
     }
@@ -298,6 +317,9 @@ struct InnerClient {
     last_client_heartbeat: NEAV<Instant>,
     last_server_heartbeat: NEAV<Instant>,
 
+    session: SessionId,
+    sequence_number: SequenceNumber,
+
     close_err: AAV<ClientError>,
 }
 
@@ -348,7 +370,7 @@ impl InnerClient {
     }
 
     fn is_closed(&self) -> bool {
-        self.close_err.is_empty(Ordering::Relaxed)
+        !self.close_err.is_empty(Ordering::Relaxed)
     }
 
     fn opts(&self) -> &ClientOptions {
@@ -385,7 +407,7 @@ impl InnerClient {
             }
         };
         // TODO: close?
-        if let Err(e) = (&**write_half).write(&packet) {
+        if let Err(e) = (&**write_half).write_all(packet.as_slice()) {
             return Err(self.close_with_err(e));
         }
         self.last_client_heartbeat
@@ -459,7 +481,7 @@ impl InnerClient {
                             continue;
                         }
                     }
-                    packet_len = (buf[0] as usize) | ((buf[1] as usize) << 8);
+                    packet_len = ((buf[0] as usize) << 8) | (buf[1] as usize);
                     if buf.len() < 2 + packet_len {
                         buf.resize(2 + packet_len, 0);
                     }
@@ -505,7 +527,8 @@ impl InnerClient {
                     continue;
                 }
 
-                let packet = match Packet::parse(&buf) {
+                buf_pos = 0;
+                let packet = match Packet::parse(&buf[..2 + packet_len]) {
                     Ok(packet) => packet,
                     Err(e) => {
                         self.close_with_err(e);
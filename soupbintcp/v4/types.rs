@@ -604,14 +604,14 @@ impl Packet {
     }
 
     pub fn parse_as(pt: PacketType, b: &[u8]) -> Result<Self, PacketParseError> {
-        Self::try_read_from_as(pt, &mut Cursor::new(b))
+        Self::try_read_from_as(&mut Cursor::new(b), pt)
     }
 
     pub fn read_from<R: Read>(r: &mut R) -> Result<Self, PacketParseError> {
         let mut buf = [0u8; 3];
         r.read_exact(&mut buf)?;
         // TODO: check payload len to make sure it's at most max?
-        let payload_len = match (buf[0] as usize) | ((buf[1] as usize) << 8) {
+        let payload_len = match ((buf[0] as usize) << 8) | (buf[1] as usize) {
             0 => return Err(PacketParseError::MismatchLen { want: 1, got: 0 }),
             pl => pl - 1,
         };
@@ -641,7 +641,7 @@ impl Packet {
         let mut buf = [0u8; 3];
         r.read_exact(&mut buf)?;
         // TODO: check payload len to make sure it's at most max?
-        let payload_len = match (buf[0] as usize) | ((buf[1] as usize) << 8) {
+        let payload_len = match ((buf[0] as usize) << 8) | (buf[1] as usize) {
             0 => return Err(PacketParseError::MismatchLen { want: 1, got: 0 }),
             pl => pl - 1,
         };